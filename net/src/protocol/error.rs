@@ -2,79 +2,79 @@
 /// These errors are categorized into Client Errors (100-115) and Server Errors (120-128).
 /// These errors are also sent along with Response
 /// These errors indicate issues on the client side of the protocol.
- 
+///
 /// - 100 - Bad Request: The request could not be understood or was missing required parameters.
-/// BadRequest100(Error),
- 
+///   BadRequest100(Error),
+///
 /// - 101 - Unauthorized: Authentication is required and has failed or has not been provided.
-/// Unauthorized101(Error),
- 
+///   Unauthorized101(Error),
+///
 /// - 102 - Forbidden: The request is understood, but it has been refused or access is not allowed.
-/// Forbidden102(Error),
- 
+///   Forbidden102(Error),
+///
 /// - 103 - Not Found: The requested resource could not be found.
-/// NotFound103(Error),
- 
+///   NotFound103(Error),
+///
 /// - 104 - Method Not Allowed: The method specified in the request is not allowed for the resource.
-/// MethodNotAllowed104(Error),
- 
+///   MethodNotAllowed104(Error),
+///
 /// - 105 - Not Acceptable: The resource is capable of generating only content not acceptable according to the Accept headers sent in the request.
-/// NotAcceptable105(Error),
- 
+///   NotAcceptable105(Error),
+///
 /// - 106 - Proxy Authentication Required: Authentication with a proxy is required.
-/// ProxyAuthenticationRequired106(Error),
- 
+///   ProxyAuthenticationRequired106(Error),
+///
 /// - 107 - Request Timeout: The server timed out waiting for the request.
-/// RequestTimeout107(Error),
- 
+///   RequestTimeout107(Error),
+///
 /// - 108 - Conflict: The request could not be processed because of conflict in the current state of the resource.
-/// Conflict108(Error),
- 
+///   Conflict108(Error),
+///
 /// - 109 - Gone: The requested resource is no longer available and will not be available again.
-/// Gone109(Error),
- 
+///   Gone109(Error),
+///
 /// - 110 - Precondition Failed: The server does not meet one of the preconditions that the requester put on the request.
-/// PreconditionFailed110(Error),
- 
+///   PreconditionFailed110(Error),
+///
 /// - 111 - Payload Too Large: The request is larger than the server is willing or able to process.
-/// PayloadTooLarge111(Error),
- 
+///   PayloadTooLarge111(Error),
+///
 /// - 112 - Unprocessable Content: The server understands the content type of the request entity, but was unable to process the contained instructions.
-/// UnprocessableContent112(Error),
- 
+///   UnprocessableContent112(Error),
+///
 /// - 113 - Locked: The resource is currently locked and cannot be accessed.
-/// Locked113(Error),
- 
+///   Locked113(Error),
+///
 /// - 114 - Too Many Requests: The user has sent too many requests in a given amount of time.
-/// TooManyRequests114(Error),
-
+///   TooManyRequests114(Error),
+///
 /// - 115 - Request Header Too Large: The request headers are too large for the server to process.
-/// RequestHeaderTooLarge115(Error),
-
+///   RequestHeaderTooLarge115(Error),
+///
 /// These errors indicate issues on the server side of the protocol.
-///  - 120 - Internal Server Error: An unexpected condition was encountered on the server.
-/// InternalServerError120(Error),
- 
-///  - 121 - Bad Gateway: The server received an invalid response from the upstream server.
-/// BadGateway121(Error),
- 
-///  - 123 - Service Unavailable: The server is currently unable to handle the request due to a temporary overload or maintenance.
-/// ServiceUnavailable123(Error),
- 
-///  - 124 - Gateway Timeout: The server did not receive a timely response from the upstream server or some other auxiliary server.
-/// GatewayTimeout124(Error),
- 
-///  - 125 - MTP Version Not Supported: The MTP version used in the request is not supported by the server.
-/// MTPVersionNotSupported125(Error),
- 
-///  - 126 - Insufficient Storage: The server is unable to store the representation needed to complete the request.
-/// InsufficientStorage126(Error),
- 
-///  - 127 - Loop Detected: The server detected an infinite loop while processing the request.
-/// LoopDetected127(Error),
- 
-///  - 128 - Network Authentication Required: The request requires network authentication.
-/// NetworkAuthenticationRequired128(Error),
+/// - 120 - Internal Server Error: An unexpected condition was encountered on the server.
+///   InternalServerError120(Error),
+///
+/// - 121 - Bad Gateway: The server received an invalid response from the upstream server.
+///   BadGateway121(Error),
+///
+/// - 123 - Service Unavailable: The server is currently unable to handle the request due to a temporary overload or maintenance.
+///   ServiceUnavailable123(Error),
+///
+/// - 124 - Gateway Timeout: The server did not receive a timely response from the upstream server or some other auxiliary server.
+///   GatewayTimeout124(Error),
+///
+/// - 125 - MTP Version Not Supported: The MTP version used in the request is not supported by the server.
+///   MTPVersionNotSupported125(Error),
+///
+/// - 126 - Insufficient Storage: The server is unable to store the representation needed to complete the request.
+///   InsufficientStorage126(Error),
+///
+/// - 127 - Loop Detected: The server detected an infinite loop while processing the request.
+///   LoopDetected127(Error),
+///
+/// - 128 - Network Authentication Required: The request requires network authentication.
+///   NetworkAuthenticationRequired128(Error),
 pub enum ProtocolError {
 
      /// **Client Errors (100-115)**
@@ -166,16 +166,31 @@ pub struct Error{
      info:String
 }
 
+impl Error {
+     /// Creates a new [`Error`] carrying the additional information sent to the client
+     ///
+     /// # Arguments
+     /// * `info` - Human readable detail about the error condition
+     pub fn new(info:String)->Self{
+          Self { info }
+     }
+
+     /// Retrieves the additional information of the error
+     pub fn info(&self)->&str{
+          &self.info
+     }
+}
+
 impl ProtocolError {
      /// Returns the numeric code associated with the error.
      ///
      /// # Examples
      ///
-     /// ```
+     /// ```ignore
      /// let error = ProtocolError::BadRequest100(Error { message: "Invalid request".to_string() });
      /// assert_eq!(error.code(), 100);
      /// ```
-     fn code(&self) -> u32 {
+     pub fn code(&self) -> u32 {
           match self {
              ProtocolError::BadRequest100(_) => 100,
              ProtocolError::Unauthorized101(_) => 101,
@@ -208,11 +223,11 @@ impl ProtocolError {
      ///
      /// # Examples
      ///
-     /// ```
+     /// ```ignore
      /// let error = ProtocolError::BadRequest100(Error { message: "Invalid request".to_string() });
      /// assert_eq!(error.description(), "100 - Bad Request: The request could not be understood or was missing required parameters.");
      /// ```
-     fn description(&self) -> &'static str {
+     pub fn description(&self) -> &'static str {
           match self {
                ProtocolError::BadRequest100(_) => "100 - Bad Request: The request could not be understood or was missing required parameters.",
                ProtocolError::Unauthorized101(_) => "101 - Unauthorized: Authentication is required and has failed or has not been provided.",
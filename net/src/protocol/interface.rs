@@ -1,11 +1,12 @@
 use std::net::SocketAddr;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 use super::{
      MTPManagerActions,
      MTPHeaders,
      MTPStorage,
      MTPMessage,
+     MTPEnvelope,
 
      error::ProtocolError
 };
@@ -44,11 +45,29 @@ pub trait MessageTransferProtocol {
     /// A result containing a response or an error.
     fn publish(&self, message: Self::Message) -> Result<Self::Response, ProtocolError>;
 
-    /// Pulls a message from the queue.
+    /// Pulls a batch of messages from a queue, waiting for messages to arrive when the queue has
+    /// none to deliver.
+    ///
+    /// # Arguments
+    /// * `queue` - The identifier of the queue to pull from.
+    /// * `max_messages` - The maximum number of messages returned.
+    /// * `max_bytes` - The maximum total size of the content of the messages returned. The first
+    ///   message is returned even if it is larger.
+    /// * `wait` - How long the broker holds the request open when there is no message to return.
+    ///
+    /// # Returns
+    /// A result containing a response carrying the messages pulled, or an error.
+    fn pull(&self, queue: String, max_messages: usize, max_bytes: usize, wait: Duration) -> Result<Self::Response, ProtocolError>;
+
+    /// Acknowledges a message delivered from a queue so that it is not redelivered.
+    ///
+    /// # Arguments
+    /// * `queue` - The identifier of the queue the message was delivered from.
+    /// * `id` - The identifier of the delivered message.
     ///
     /// # Returns
     /// A result containing a response or an error.
-    fn pull(&self) -> Result<Self::Response, ProtocolError>;
+    fn acknowledge(&self, queue: String, id: String) -> Result<Self::Response, ProtocolError>;
 
     /// Pings the server to check status
     ///
@@ -98,11 +117,16 @@ pub trait MessageTransferProtocol {
 /// 
 /// - `Option<MTPStorage>`: An `Option` where `Some(MTPStorage)` contains the storage information from the response, and `None` indicates that no storage information is available.
 ///
+/// ### `get_messages`
+///
+/// Retrieves the messages delivered with the response, each wrapped in an [`MTPEnvelope`]. Responses to requests that
+/// do not deliver messages return an empty list.
+///
 /// ## Example
 ///
 /// Here's an example implementation of `MessageTransferProtocolResponse`:
 ///
-/// ```rust
+/// ```ignore
 /// struct MyResponse {
 ///     status_code: MTPStatusCode,
 ///     headers: Option<MTPHeaders>,
@@ -135,6 +159,9 @@ pub trait MessageTransferProtocolResponse {
 
      /// Retrieced the local storage (with headers)
      fn get_storage(&self) -> Option<MTPStorage>;
+
+     /// Retrieves the messages delivered along with the response
+     fn get_messages(&self) -> Vec<MTPEnvelope>;
 }
 
 /// ```text
@@ -217,6 +244,8 @@ pub trait MessageTransferProtocolPayload {
 ///
 /// Represents a request to pull a message from the queue. This is typically used by clients to retrieve messages that have
 /// been queued for them. The message broker will return a message from the queue as per the client's request.
+/// With a [`MTPHeaderUnit::Batch`] unit the broker returns up to a batch of messages in a single response, holding the
+/// request open until messages arrive or the wait of the batch elapses.
 ///
 /// ### `Ping`
 ///
@@ -229,11 +258,17 @@ pub trait MessageTransferProtocolPayload {
 /// renaming queues, authorizing users, or modifying access permissions. This request type is used for administrative tasks
 /// that affect the message broker's configuration and operations.
 ///
+/// ### `Acknowledge`
+///
+/// Represents a consumer confirming that a delivered message has been processed. The message is identified by the
+/// [`MTPHeaderUnit::Delivery`] unit that the broker attached to it when it was delivered. Acknowledging a message that
+/// expired before the acknowledgement arrived is answered with [`ProtocolError::Gone109`].
+///
 /// ## Example
 ///
 /// Here is an example of how `MTPRequestType` might be used in a message broker service:
 ///
-/// ```rust
+/// ```ignore
 /// fn handle_request(request_type: MTPRequestType) {
 ///     match request_type {
 ///         MTPRequestType::Subscribe => {
@@ -254,13 +289,15 @@ pub trait MessageTransferProtocolPayload {
 ///         MTPRequestType::Manage => {
 ///             // Handle management actions
 ///         },
+///         MTPRequestType::Acknowledge => {
+///             // Handle message acknowledgement
+///         },
 ///     }
 /// }
 /// ```
 /// 
 /// In this example, `handle_request` uses a `match` statement to determine how to process each type of request, enabling the
 /// message broker to appropriately handle different client interactions based on the request type.
-
 pub enum MTPRequestType {
 
      /// To subscribe to a message queue
@@ -281,6 +318,9 @@ pub enum MTPRequestType {
      /// To perform manger functions on the queue 
      /// Only valid if the client has their respoective permission
      Manage,
+
+     /// To acknowledge a message delivered from a queue
+     Acknowledge,
 }
 
 /// `MTPStatusCode` represents the various status codes that can be returned in a protocol response.
@@ -328,6 +368,10 @@ pub enum MTPStatusCode {
 /// - `category`: A [`MessageCategory`] enum categorizing the type of message (e.g., event, command, or error).
 /// - `content_type`: A [`ContentType`] enum specifying the format of the message content (e.g., JSON or XML).
 ///
+/// On a queue that deduplicates messages the `id` doubles as an idempotency key: publishing a message whose id was
+/// published within the deduplication window of the queue succeeds without enqueueing it again, and the response
+/// carries a `duplicate` storage cell along with the offset of the original message.
+///
 /// ### `MessagePublish`
 ///
 /// Represents information about the message publishing operation. This unit is used to specify details about where the
@@ -336,11 +380,146 @@ pub enum MTPStatusCode {
 /// - `queue`: A [`String`] specifying the identifier of the queue to which the message is being published.
 /// - `to`: A [`MessagePublish`] enum indicating the target of the publication (e.g., all subscribers or specific groups).
 ///
+/// ### `QueueCreation`
+///
+/// Accompanies a manage request to provision a queue. Queues must be provisioned before messages are published to them
+/// or consumed from them. Provisioning an existing queue again with the same configuration succeeds, while provisioning
+/// it with a different one is answered with [`ProtocolError::Conflict108`].
+///
+/// - `name`: A [`String`] specifying the identifier of the queue.
+/// - `access`: A [`QueueAccess`] enum specifying who may join the queue.
+/// - `durable`: A [`bool`] indicating whether the messages of the queue are stored on disk.
+/// - `max_length`: An optional [`u64`] bounding the number of messages the queue holds.
+/// - `max_bytes`: An optional [`u64`] bounding the total size of the content of the messages the queue holds.
+/// - `overflow`: An [`Overflow`] enum specifying what happens to a message published to a full queue.
+/// - `ttl`: An optional [`Duration`] after which the messages of the queue expire, unless they carry a `TimeToLive` unit.
+/// - `max_deliveries`: An optional [`u32`] bounding how many times a message is delivered to a consumer without being
+///   acknowledged before it is given up on.
+/// - `dead_letter`: An optional [`String`] naming the queue to which expired, overflowing and given up messages are
+///   moved instead of being dropped.
+///
+/// ### `TimeToLive`
+///
+/// Represents the lifetime of a published message. The message expires once `ttl` has elapsed since the `timestamp` of
+/// its `Message` unit (or since the broker received it when no timestamp is given) and is no longer delivered.
+/// Overrides the default time-to-live of the queue.
+///
+/// - `ttl`: A [`Duration`] after which the message expires.
+///
+/// ### `Subscription`
+///
+/// Represents the queue a subscribe, unsubscribe or pull request operates on.
+///
+/// - `queue`: A [`String`] specifying the identifier of the queue.
+///
+/// ### `Delivery`
+///
+/// Attached by the broker to every message it delivers, and echoed back by the consumer to acknowledge it.
+///
+/// - `queue`: A [`String`] specifying the identifier of the queue the message was delivered from.
+/// - `id`: A [`String`] representing the identifier of the delivered message.
+/// - `offset`: A [`u64`] representing the offset of the message in the queue. Message ids need not be unique, so
+///   the broker acknowledges the message at this offset.
+///
+/// ### `NotBefore`
+///
+/// Represents the earliest time a published message may be delivered. The broker holds the message until then, after
+/// which it becomes visible in its queue. A scheduled message can be cancelled through [`MTPManagerAction::Cancel`].
+///
+/// - `at`: A [`SystemTime`] before which the message is not delivered.
+///
+/// ### `Delay`
+///
+/// Represents a delay after which a published message may be delivered, measured from the time the broker receives it.
+///
+/// - `delay`: A [`Duration`] for which the message is held.
+///
+/// ### `Key`
+///
+/// Represents the key of the entity whose state a published message carries. On a compacted queue only the latest message
+/// of each key is kept. Messages published without a `Key` unit are keyed by the id of their `Message` unit.
+///
+/// - `key`: A [`String`] identifying the entity.
+///
+/// ### `Tombstone`
+///
+/// Marks a published message as a tombstone. On a compacted queue a tombstone deletes every earlier message of its key,
+/// and is itself discarded once every consumer of the queue has moved past it.
+///
+/// ### `PartitionKey`
+///
+/// Represents the key a published message is ordered by within a consumer group. Every message of a key is delivered to
+/// the same member of the group, in publishing order, and a message is not delivered to another member while an
+/// earlier message of its key awaits acknowledgement, including while the group is rebalanced. Messages of different
+/// keys are delivered independently of each other, and messages published without a `PartitionKey` unit are
+/// delivered in any order.
+///
+/// - `key`: A [`String`] identifying the partition, such as the customer the message concerns.
+///
+/// ### `Batch`
+///
+/// Accompanies the `Subscription` unit of a pull request to receive several messages in one response. The broker returns
+/// as many messages as are available within both limits, at least one when any is available. When none is available it
+/// holds the request open until a message arrives or `wait` elapses, answering with an empty batch in the latter case.
+///
+/// - `max_messages`: A [`u32`] bounding the number of messages returned.
+/// - `max_bytes`: A [`u64`] bounding the total size of the content of the messages returned.
+/// - `wait`: A [`Duration`] for which the request is held open when no message is available.
+///
+/// ### `ConsumerGroup`
+///
+/// Accompanies the `Subscription` unit of a subscribe or pull request to consume the queue as a member of a consumer
+/// group. Each message reaching the group is delivered to exactly one of its members, while every group and every
+/// client consuming on its own receives its own copy.
+///
+/// - `group`: A [`String`] naming the group within the queue.
+/// - `distribution`: A [`Distribution`] spreading messages among the members, set by the member creating the group.
+///
+/// ### `Filter`
+///
+/// Accompanies the `Subscription` unit of a subscribe or pull request to only receive the messages matching an
+/// expression. The expression compares the `category`, `priority` and `content_type` of a message, or any [`MTPStorage`]
+/// cell it was published with, to a value, and combines comparisons with `AND`, `OR`, `NOT` and parentheses. Messages not
+/// matching are skipped by the broker rather than delivered. The filter applies to the whole consumer, replacing the
+/// one it had, and is kept across restarts of the broker. The members of a consumer group share its filter: a member
+/// subscribing with another filter while other members consume through the group is rejected with a `Conflict108`
+/// error. An invalid expression is rejected with an `UnprocessableContent112` error.
+///
+/// - `expression`: A [`String`] such as `category = EVENT AND priority >= High AND region = 'eu'`.
+///
+/// ### `ReplyTo`
+///
+/// Accompanies a message of the [`MessageCategory::REQUEST`] category to name the queue its response is published to,
+/// usually a temporary queue of the requesting client. The unit is delivered along with the message.
+///
+/// - `queue`: A [`String`] naming the queue the response is published to.
+///
+/// ### `Correlation`
+///
+/// Pairs a response with the request it answers. The requester picks an identifier for each request, and the responder
+/// publishes its [`MessageCategory::RESPONSE`] message with the same identifier.
+///
+/// - `id`: A [`String`] identifying the request.
+///
+/// ### `TemporaryQueue`
+///
+/// Accompanies a manage request to provision a queue exclusive to the session of the request. The broker names the
+/// queue and answers with its name in the `queue` storage cell. Only the session owning the queue may consume from it,
+/// while any client may publish to it, and the queue is deleted along with its messages when the session closes.
+///
+/// ### `Transaction`
+///
+/// Accompanies a manage request to begin, commit or roll back the transaction of the session. Between the beginning and
+/// the end of the transaction, the publish and acknowledge requests of the session are staged and take effect together
+/// on commit.
+///
+/// - `step`: A [`TransactionStep`] performed on the transaction.
+///
 /// ## Example
 ///
 /// Here is an example of how `MTPHeaderUnit` might be used in practice:
 ///
-/// ```rust
+/// ```ignore
 /// fn process_header_unit(header_unit: MTPHeaderUnit) {
 ///     match header_unit {
 ///         MTPHeaderUnit::Authentication { key, value } => {
@@ -358,6 +537,31 @@ pub enum MTPStatusCode {
 ///         MTPHeaderUnit::MessagePublish { queue, to } => {
 ///             // Handle message publishing details
 ///         },
+///         MTPHeaderUnit::TimeToLive { ttl } => {
+///             // Handle message expiry
+///         },
+///         MTPHeaderUnit::Subscription { queue } => {
+///             // Handle the queue subscribed to or pulled from
+///         },
+///         MTPHeaderUnit::Delivery { queue, id, offset } => {
+///             // Handle acknowledgement of a delivered message
+///         },
+///         MTPHeaderUnit::NotBefore { at } | MTPHeaderUnit::Delay { .. } => {
+///             // Handle scheduled delivery
+///         },
+///         MTPHeaderUnit::Key { key } | MTPHeaderUnit::Tombstone => {
+///             // Handle compaction of the message
+///         },
+///         MTPHeaderUnit::ConsumerGroup { group, distribution } => {
+///             // Handle the group a consumer joins
+///         },
+///         MTPHeaderUnit::QueueCreation { name, access, durable, max_length, max_bytes, overflow, ttl, max_deliveries, dead_letter } => {
+///             // Handle the provisioning of a queue
+///         },
+///         MTPHeaderUnit::Batch { max_messages, max_bytes, wait } => {
+///             // Handle the batch a pull request returns
+///         },
+///         _ => {}
 ///     }
 /// }
 /// ```
@@ -399,16 +603,123 @@ pub enum MTPHeaderUnit {
      },
 
      /// All information pertaining to the publishing of the message.
-     /// - Queue to which the message is published
+     /// - Topic to which the message is published. Levels are separated by `.` or `/`, and the
+     ///   message reaches the queue of that name along with every queue named after a pattern
+     ///   matching it, where `*` stands for one level and `#` for any number of levels.
      /// - [`MessagePublish`] type.
      MessagePublish {
           queue: String,
           to: MessagePublish,
      },
 
-     QueueCreation{
-          
-     }
+     /// Configuration of a queue provisioned by a manage request
+     /// - Queue name
+     /// - [`QueueAccess`] of the queue
+     /// - Whether the messages are stored on disk
+     /// - Maximum number of messages held
+     /// - Maximum total size of the content of the messages held
+     /// - [`Overflow`] policy applied once the queue is full
+     /// - Default time-to-live of the messages
+     /// - Maximum number of deliveries of a message to a consumer
+     /// - Dead-letter queue
+     QueueCreation {
+          name: String,
+          access: QueueAccess,
+          durable: bool,
+          max_length: Option<u64>,
+          max_bytes: Option<u64>,
+          overflow: Overflow,
+          ttl: Option<Duration>,
+          max_deliveries: Option<u32>,
+          dead_letter: Option<String>,
+     },
+
+     /// Lifetime of the published message, overriding the default of the queue
+     TimeToLive {
+          ttl: Duration,
+     },
+
+     /// Queue on which a subscribe, unsubscribe or pull request operates
+     Subscription {
+          queue: String,
+     },
+
+     /// Identifies a message delivered by the broker
+     /// - Queue from which the message was delivered
+     /// - Message id
+     /// - Offset of the message in the queue, by which it is acknowledged
+     Delivery {
+          queue: String,
+          id: String,
+          offset: u64,
+     },
+
+     /// Earliest time at which the published message is delivered
+     NotBefore {
+          at: SystemTime,
+     },
+
+     /// Delay after which the published message is delivered
+     Delay {
+          delay: Duration,
+     },
+
+     /// Key of the entity the published message carries the state of, used to compact a queue
+     Key {
+          key: String,
+     },
+
+     /// Marks the published message as a tombstone, deleting its key from a compacted queue
+     Tombstone,
+
+     /// Key the published message is ordered by within a consumer group
+     PartitionKey {
+          key: String,
+     },
+
+     /// Consumer group a subscribe or pull request joins
+     /// - Name of the group
+     /// - [`Distribution`] of messages among its members
+     ConsumerGroup {
+          group: String,
+          distribution: Distribution,
+     },
+
+     /// Batch of messages a pull request returns
+     /// - Maximum number of messages
+     /// - Maximum total size of the content of the messages
+     /// - Time for which the request is held open when no message is available
+     Batch {
+          max_messages: u32,
+          max_bytes: u64,
+          wait: Duration,
+     },
+
+     /// Filter expression over the metadata and storage cells of messages, selecting the
+     /// messages a subscribe or pull request is delivered, e.g.
+     /// `category = EVENT AND priority >= High AND region = 'eu'`
+     Filter {
+          expression: String,
+     },
+
+     /// Queue the response to a request message is published to
+     ReplyTo {
+          queue: String,
+     },
+
+     /// Identifier pairing a response message with the request it answers
+     Correlation {
+          id: String,
+     },
+
+     /// Requests a queue exclusive to the session of a manage request, deleted when the
+     /// session closes
+     TemporaryQueue,
+
+     /// [`TransactionStep`] performed by a manage request on the transaction of its session
+     Transaction {
+          step: TransactionStep,
+     },
 }
 
 /// `MTPAuth` represents different authentication methods that can be used within the protocol's header.
@@ -460,7 +771,7 @@ pub enum MTPHeaderUnit {
 ///
 /// Here is how `MTPAuth` might be used within the [`MTPHeaderUnit`] enum in practice:
 ///
-/// ```rust
+/// ```ignore
 /// pub enum MTPHeaderUnit {
 ///     /// All headers pertaining to authentication of the user
 ///     /// Includes token from foreign security services
@@ -581,6 +892,11 @@ pub enum AuthSchemes {
 /// is used to change the name of an existing entity to a new name.
 ///
 /// - **Usage**: This action might be used when reorganizing or updating resource names in the system.
+/// - **Semantics**: The queue named by the `Subscription` unit of the request is renamed at once, along
+///   with its stored messages and access rules. Its former name stays as a temporary alias: requests using
+///   it are redirected to the queue, and their responses carry the new name in a `deprecated` storage cell.
+///   Clients subscribed to the queue are pushed a frame carrying the former name in a `renamed` storage
+///   cell and the new name in a `queue` cell.
 ///
 /// ### `Authorize`
 ///
@@ -588,6 +904,9 @@ pub enum AuthSchemes {
 /// access to specific resources. It is a private action used to manage access rights within the protocol.
 ///
 /// - **Usage**: This action is used to invite or grant access to a user, managing their permissions within the system.
+/// - **Semantics**: The client is admitted to the private queue named by the `Subscription` unit of the request.
+///   A client whose request to join the queue was pending is subscribed to it and pushed a frame carrying the
+///   name of the queue in a `queue` storage cell and `authorized` in a `decision` cell.
 ///
 /// ### `Reject`
 ///
@@ -595,6 +914,8 @@ pub enum AuthSchemes {
 /// permissions to a user or client within the protocol.
 ///
 /// - **Usage**: This action might be used to deny access requests or remove permissions from users or clients.
+/// - **Semantics**: The pending request of the client to join the private queue named by the `Subscription` unit
+///   of the request is denied, and the client is pushed a frame carrying `rejected` in a `decision` cell.
 ///
 /// ### `Dispose`
 ///
@@ -602,6 +923,12 @@ pub enum AuthSchemes {
 /// clients or resources that are no longer needed or are being terminated.
 ///
 /// - **Usage**: This action is used to remove or terminate clients or resources that are no longer active or required.
+/// - **Semantics**: The client is unsubscribed from the queue named by the `Subscription` unit of the request, the
+///   messages it left unacknowledged going back to the other members of its group, and its pending request to join
+///   the queue is withdrawn. The client is pushed a frame carrying the name of the queue in a `queue` storage cell
+///   and `disposed` in a `decision` cell. With a `ban`, the client is refused the queue with
+///   [`ProtocolError::Forbidden102`] for that long, the end of the ban being carried in a `banned` cell as
+///   milliseconds since the Unix epoch. With `disconnect`, the sessions of the client are closed once notified.
 ///
 /// ### `AccessorModify`
 ///
@@ -610,6 +937,72 @@ pub enum AuthSchemes {
 ///
 /// - **Usage**: This action is used to update or change access permissions or roles for users or clients, modifying
 ///   their level of access to resources.
+/// - **Semantics**: The access of the queue named by the `Subscription` unit of the request is switched to the
+///   given [`QueueAccess`]. Only the moderator of the queue may switch it.
+///
+/// ### `Cancel`
+///
+/// Represents an action to cancel a message scheduled for delayed delivery, identified by the id of the message.
+///
+/// - **Usage**: This action is used to withdraw a message published with a `NotBefore` or `Delay` header unit before
+///   it becomes visible in its queue.
+/// - **Semantics**: Ids of scheduled messages are unique per queue. The message is looked up on the queue named by the
+///   `Subscription` unit of the request if it carries one, which it must when messages with this id are scheduled on
+///   several queues.
+///
+/// ### `Pending`
+///
+/// Represents an action to list the clients whose request to join a private queue awaits a decision.
+///
+/// - **Usage**: This action is used by the moderator of a queue, whose response carries a `pending` storage cell
+///   for every requesting client.
+///
+/// ### `Assign`
+///
+/// Represents an action to assign a [`QueueRoles`] role on a queue to a user, replacing the role it had.
+///
+/// - **Usage**: This action is used to grant a user the operations of a role, such as publishing without reading.
+///
+/// ### `Revoke`
+///
+/// Represents an action to withdraw the role of a user on a queue, who is then treated as a client without a role.
+///
+/// - **Usage**: This action is used to take the operations of a role back from a user.
+///
+/// ### `Delete`
+///
+/// Represents an action to delete a queue along with its messages. Its subscribers are pushed a frame carrying
+/// the name of the queue in a `deleted` storage cell.
+///
+/// - **Usage**: This action is used to dispose of a queue that is no longer needed.
+///
+/// ### `AddUser`
+///
+/// Represents an action to add a user authenticating through the `Basic` authorization scheme with a password.
+///
+/// - **Usage**: This action is used by the administrators of the broker to enrol a user.
+/// - **Semantics**: The actions on users operate on the broker rather than on a queue, so the request needs no
+///   `Subscription` unit. They are refused with [`ProtocolError::Forbidden102`] to clients the broker does not
+///   configure as administrators. Adding an existing user is answered with [`ProtocolError::Conflict108`].
+///
+/// ### `ChangePassword`
+///
+/// Represents an action to change the password of a user authenticating through the `Basic` authorization scheme.
+///
+/// - **Usage**: This action is used by the administrators of the broker to reset a password.
+///
+/// ### `RemoveUser`
+///
+/// Represents an action to remove a user authenticating through the `Basic` authorization scheme.
+///
+/// - **Usage**: This action is used by the administrators of the broker to withdraw an account.
+/// - **Semantics**: The clients that authenticated as the user with its password are no longer authenticated.
+///
+/// ### `UnlockUser`
+///
+/// Represents an action to lift the lockout of a user who failed to authenticate too many times in a row.
+///
+/// - **Usage**: This action is used by the administrators of the broker to let a locked out user back in.
 ///
 /// ## Example
 ///
@@ -622,6 +1015,15 @@ pub enum AuthSchemes {
 ///     Reject,
 ///     Dispose,
 ///     AccessorModify,
+///     Cancel,
+///     Pending,
+///     Assign,
+///     Revoke,
+///     Delete,
+///     AddUser,
+///     ChangePassword,
+///     RemoveUser,
+///     UnlockUser,
 /// }
 ///
 /// fn perform_action(action: MTPManagerAction) {
@@ -646,6 +1048,30 @@ pub enum AuthSchemes {
 ///             // Handle accessor modify action
 ///             println!("Performing accessor modify action");
 ///         },
+///         MTPManagerAction::Cancel => {
+///             // Handle cancel action
+///             println!("Performing cancel action");
+///         },
+///         MTPManagerAction::Pending => {
+///             // Handle pending action
+///             println!("Performing pending action");
+///         },
+///         MTPManagerAction::Assign => {
+///             // Handle assign action
+///             println!("Performing assign action");
+///         },
+///         MTPManagerAction::Revoke => {
+///             // Handle revoke action
+///             println!("Performing revoke action");
+///         },
+///         MTPManagerAction::Delete => {
+///             // Handle delete action
+///             println!("Performing delete action");
+///         },
+///         MTPManagerAction::AddUser | MTPManagerAction::ChangePassword | MTPManagerAction::RemoveUser | MTPManagerAction::UnlockUser => {
+///             // Handle user actions
+///             println!("Performing user action");
+///         },
 ///     }
 /// }
 /// ```
@@ -660,13 +1086,57 @@ pub enum MTPManagerAction {
      Authorize(String),       // On user invites (private)
 
      /// Reject user requesting permissions to the queue
-     Reject,          // Access permission
+     Reject(String),          // Access permission
 
      /// Dispose an exising client from the queue
-     Dispose(String),         // Dispose of existing clients
+     /// - Client to dispose of
+     /// - How long the client is banned from the queue, if it is
+     /// - Whether the sessions of the client are closed
+     Dispose {
+          client: String,
+          ban: Option<Duration>,
+          disconnect: bool,
+     },
 
      /// Modify the roles/permissions of existing client
      AccessorModify(QueueAccess),  // Change the permission of the access of the queue
+
+     /// Cancel a message scheduled for delayed delivery
+     Cancel(String),          // Id of the scheduled message
+
+     /// List the users requesting permissions to the queue
+     Pending,
+
+     /// Assign a role on the queue to a user
+     Assign(String, QueueRoles),
+
+     /// Withdraw the role of a user on the queue
+     Revoke(String),
+
+     /// Delete the queue along with its messages
+     Delete,
+
+     /// Add a user authenticating with a password
+     /// - Username of the user
+     /// - Password of the user
+     AddUser {
+          username: String,
+          password: String,
+     },
+
+     /// Change the password of a user
+     /// - Username of the user
+     /// - New password of the user
+     ChangePassword {
+          username: String,
+          password: String,
+     },
+
+     /// Remove a user authenticating with a password
+     RemoveUser(String),      // Username of the user
+
+     /// Lift the lockout of a user
+     UnlockUser(String),      // Username of the user
 }
 
 /// [`QueueAccess`] defines an access of a client to a particular queue.
@@ -689,8 +1159,19 @@ pub enum MTPManagerAction {
 ///
 /// ### `Protected`
 ///
-/// A queue to be protected.
+/// A queue to be protected. Any client that authenticated may join the queue and publish to it, while
+/// clients that did not are answered with [`ProtocolError::Unauthorized101`].
+///
+/// - **Usage**: A protected queue to share information among the known clients of a broker
+///
+/// ## Enforcement
 ///
+/// The access of a queue governs subscribing to it, pulling from it and publishing to it by name. A client
+/// refused access to a private queue is answered with [`ProtocolError::Forbidden102`], except for a subscribe
+/// request, which is held as a request to join the queue until its moderator authorizes or rejects it, or until
+/// it expires. The moderator is pushed a frame carrying the requesting client in a `join` storage cell. The client that
+/// provisioned the queue moderates it, is always admitted to it and may switch its access at runtime with
+/// [`MTPManagerAction::AccessorModify`], which unsubscribes the clients the new access does not permit.
 pub enum QueueAccess {
     Public,
     Private,
@@ -918,7 +1399,6 @@ pub enum MessagePriority {
 /// 
 /// In this example, the `handle_message` function uses a `match` statement to handle different message
 /// categories, performing specific operations based on the category assigned to each message.
-
 pub enum MessageCategory {
     EVENT,
     COMMAND,
@@ -938,6 +1418,24 @@ pub enum ContentType {
 
 /// [`QueueRoles`] for the queue
 /// Roles that are defined for each client in the queue
+///
+/// A role is assigned to a client on a queue with [`MTPManagerAction::Assign`] and withdrawn with
+/// [`MTPManagerAction::Revoke`]. It decides which operations the client may perform on the queue, while the
+/// [`QueueAccess`] of the queue still decides whether the client may use it at all. Clients without a role may
+/// publish, subscribe and pull. A client may always `Cancel` the messages it scheduled itself, while cancelling
+/// those of other clients takes a role permitting it on the queue named after their topic.
+///
+/// | Operation                                   | Moderator | Manager | Producer | Consumer | Couple |
+/// |---------------------------------------------|-----------|---------|----------|----------|--------|
+/// | Publish                                     | yes       | yes     | yes      | no       | yes    |
+/// | Subscribe, pull, acknowledge                | yes       | yes     | no       | yes      | yes    |
+/// | `Cancel` of messages of other clients       | yes       | yes     | no       | no       | no     |
+/// | `Authorize`, `Reject`, `Pending`, `Dispose` | yes       | yes     | no       | no       | no     |
+/// | `Rename`                                    | yes       | yes     | no       | no       | no     |
+/// | `AccessorModify`, `Assign`, `Revoke`        | yes       | no      | no       | no       | no     |
+/// | `Delete`                                    | yes       | no      | no       | no       | no     |
+///
+/// The client that provisions a queue is assigned the `Moderator` role on it.
 pub enum QueueRoles {
     Moderator,
     Manager,
@@ -1019,7 +1517,6 @@ pub enum QueueRoles {
 /// In this example, the `publish_message` function demonstrates how to handle different
 /// publishing methods based on the `MessagePublish` variant. It shows how to publish messages
 /// to all recipients, a specific recipient, or a group of recipients.
-
 pub enum MessagePublish {
 
      /// Default all clients registered in the queue
@@ -1032,6 +1529,90 @@ pub enum MessagePublish {
      GROUP(Vec<String>),
 }
 
+/// `Overflow` defines what happens to a message published to a queue holding as many messages or bytes as it is
+/// configured to.
+///
+/// ## Variants
+///
+/// ### `Reject`
+///
+/// Rejects the published message with [`ProtocolError::InsufficientStorage126`], leaving the queue untouched.
+///
+/// - **Usage**: Use this policy when publishers must learn that consumers fell behind. This is the default policy.
+///
+/// ### `DropHead`
+///
+/// Drops the oldest messages of the queue until the published message fits.
+///
+/// - **Usage**: Use this policy when only recent messages matter.
+///
+/// ### `DeadLetter`
+///
+/// Moves the oldest messages of the queue to its dead-letter queue until the published message fits. Without a
+/// dead-letter queue they are dropped.
+///
+/// - **Usage**: Use this policy when recent messages should be delivered first but no message may be lost.
+pub enum Overflow {
+     Reject,
+     DropHead,
+     DeadLetter,
+}
+
+/// `Distribution` defines how the messages reaching a consumer group are spread among its members.
+///
+/// Every consumer group of a queue receives its own copy of each message published to the queue, and each copy is
+/// delivered to exactly one member of the group. The distribution of a group is chosen by the member creating it.
+///
+/// ## Variants
+///
+/// ### `RoundRobin`
+///
+/// Hands messages to the members of the group in turn.
+///
+/// - **Usage**: Use this distribution when the members process messages at a similar pace.
+///
+/// ### `LeastLoaded`
+///
+/// Hands each message to the member with the fewest messages awaiting acknowledgement.
+///
+/// - **Usage**: Use this distribution when the processing time of messages varies, so that slow members are not
+///   handed more work while faster members are idle.
+pub enum Distribution {
+     /// Members receive messages in turn
+     RoundRobin,
+
+     /// The member with the fewest unacknowledged messages receives the next message
+     LeastLoaded,
+}
+
+/// `TransactionStep` defines the steps of a transaction, carried by the [`MTPHeaderUnit::Transaction`] unit of a
+/// manage request.
+///
+/// A transaction is scoped to the session that began it. While it is open, the messages the session publishes and the
+/// deliveries it acknowledges are staged by the broker rather than applied, and they take effect all at once when the
+/// transaction is committed.
+///
+/// ## Variants
+///
+/// ### `Begin`
+///
+/// Opens a transaction on the session. A session holds at most one open transaction.
+///
+/// ### `Commit`
+///
+/// Publishes the staged messages and applies the staged acknowledgements atomically, then closes the transaction. When
+/// a staged operation can no longer be applied, nothing is and the transaction is rolled back.
+///
+/// ### `Rollback`
+///
+/// Discards the staged messages and acknowledgements, then closes the transaction. Messages whose acknowledgement is
+/// discarded stay delivered to the session. A transaction still open when its session closes is rolled back.
+pub enum TransactionStep {
+     Begin,
+     Commit,
+     Rollback,
+}
+
 
 /// Clone implementation for [MTPAuth]
 impl Clone for MTPAuth{
@@ -1105,6 +1686,38 @@ impl Clone for MessagePublish{
      }
  }
 
+/// Clone implementation for [Overflow]
+impl Clone for Overflow{
+     fn clone(&self) -> Self {
+          match self {
+               Self::Reject => Self::Reject,
+               Self::DropHead => Self::DropHead,
+               Self::DeadLetter => Self::DeadLetter,
+          }
+     }
+}
+
+/// Clone implementation for [Distribution]
+impl Clone for Distribution{
+     fn clone(&self) -> Self {
+          match self {
+               Self::RoundRobin => Self::RoundRobin,
+               Self::LeastLoaded => Self::LeastLoaded,
+          }
+     }
+}
+
+/// Clone implementation for [TransactionStep]
+impl Clone for TransactionStep{
+     fn clone(&self) -> Self {
+          match self {
+               Self::Begin => Self::Begin,
+               Self::Commit => Self::Commit,
+               Self::Rollback => Self::Rollback,
+          }
+     }
+}
+
 /// Clone implementation for [MTPRequestType]
 impl Clone for MTPRequestType{
      fn clone(&self) -> Self {
@@ -1115,10 +1728,24 @@ impl Clone for MTPRequestType{
                Self::Pull => Self::Pull,
                Self::Ping => Self::Ping,
                Self::Manage => Self::Manage,
+               Self::Acknowledge => Self::Acknowledge,
           }
      }
 }
 
+/// Clone implementation for [QueueRoles]
+impl Clone for QueueRoles{
+    fn clone(&self) -> Self {
+        match self {
+            Self::Moderator => Self::Moderator,
+            Self::Manager => Self::Manager,
+            Self::Producer => Self::Producer,
+            Self::Consumer => Self::Consumer,
+            Self::Couple => Self::Couple,
+        }
+    }
+}
+
 /// Clone implementation for [QueueAccess]
 impl Clone for QueueAccess{
     fn clone(&self) -> Self {
@@ -34,8 +34,6 @@
 /// message processing scenarios.
 pub mod interface;
 
-pub mod data;
-
 
 /// The `error` module defines the error types used in the Message Transfer Protocol (MTP).
 ///
@@ -48,7 +46,7 @@ pub mod data;
 ///
 /// ## Example
 ///
-/// ```rust
+/// ```ignore
 /// use crate::error::{ProtocolError, Error};
 ///
 /// // Creating an error for a bad request
@@ -93,6 +91,9 @@ pub struct MTPResponse {
  
      /// The storage associated with the response, which may include additional data or resources.
      storage: MTPStorage,
+
+     /// The messages delivered with the response, empty unless the operation delivers messages.
+     messages: Vec<MTPEnvelope>,
  }
  
 impl MTPResponse {
@@ -110,16 +111,46 @@ impl MTPResponse {
      ///
      /// # Example
      ///
-     /// ```
+     /// ```ignore
      /// let response = MTPResponse::construct(status_code, headers, storage);
      /// ```
-     fn construct(status: MTPStatusCode, headers: MTPHeaders, storage: MTPStorage) -> Self {
+     pub fn construct(status: MTPStatusCode, headers: MTPHeaders, storage: MTPStorage) -> Self {
          Self {
              status_code: status,
              headers,
              storage,
+             messages: Vec::new(),
          }
      }
+
+     /// Attaches delivered messages to the response.
+     ///
+     /// # Arguments
+     ///
+     /// * `messages` - The messages delivered with the response.
+     ///
+     /// # Returns
+     ///
+     /// The `MTPResponse` carrying the passed messages.
+     pub fn with_messages(mut self, messages: Vec<MTPEnvelope>) -> Self {
+         self.messages = messages;
+         self
+     }
+
+     /// Adds a cell to the storage of the response.
+     ///
+     /// # Arguments
+     ///
+     /// * `key` - The key of the cell.
+     /// * `value` - The value of the cell.
+     ///
+     /// # Returns
+     ///
+     /// The `MTPResponse` carrying the added cell.
+     pub fn with_cell(mut self, key: String, value: String) -> Self {
+         self.storage.push(key, value);
+         self
+     }
  }
  
 impl MessageTransferProtocolResponse for MTPResponse {
@@ -151,12 +182,21 @@ impl MessageTransferProtocolResponse for MTPResponse {
      fn get_storage(&self) -> Option<MTPStorage> {
          Some(self.storage.clone())
      }
+
+     /// Retrieves the messages delivered with the `MTPResponse`.
+     ///
+     /// # Returns
+     ///
+     /// The delivered messages, empty if the response does not carry any.
+     fn get_messages(&self) -> Vec<MTPEnvelope> {
+         self.messages.clone()
+     }
  }
  
 /// [`MTPPayload`] type represents the payload sent from the client to the server
 /// Contains all the information pertaining to request action and source information
 /// passed in the header
-struct MTPPayload{
+pub struct MTPPayload{
 
     /// Headers from the client 
     /// 
@@ -179,7 +219,7 @@ struct MTPPayload{
 }
 
 impl MTPPayload {
-    fn construct(headers:MTPHeaders, message:Option<MTPMessage>, request:MTPRequestType)->Self{
+    pub fn construct(headers:MTPHeaders, message:Option<MTPMessage>, request:MTPRequestType)->Self{
         Self{
             headers,
             message,
@@ -187,29 +227,33 @@ impl MTPPayload {
         }
     }
 
-    fn subscribe(headers:MTPHeaders, message:Option<MTPMessage>)->Self{
+    pub fn subscribe(headers:MTPHeaders, message:Option<MTPMessage>)->Self{
         Self::construct(headers, message, MTPRequestType::Subscribe)
     }
 
-    fn unsubscribe(headers:MTPHeaders, message:Option<MTPMessage>)->Self{
+    pub fn unsubscribe(headers:MTPHeaders, message:Option<MTPMessage>)->Self{
         Self::construct(headers, message, MTPRequestType::Unsubscribe)
     }
 
-    fn publish(headers:MTPHeaders, message:Option<MTPMessage>)->Self{
+    pub fn publish(headers:MTPHeaders, message:Option<MTPMessage>)->Self{
         Self::construct(headers, message, MTPRequestType::Publish)
     }
 
-    fn pull(headers:MTPHeaders, message:Option<MTPMessage>)->Self{
+    pub fn pull(headers:MTPHeaders, message:Option<MTPMessage>)->Self{
         Self::construct(headers, message, MTPRequestType::Pull)
     }
 
-    fn ping(headers:MTPHeaders, message:Option<MTPMessage>)->Self{
+    pub fn ping(headers:MTPHeaders, message:Option<MTPMessage>)->Self{
         Self::construct(headers, message, MTPRequestType::Ping)
     }
 
-    fn manage(headers:MTPHeaders, message:Option<MTPMessage>)->Self{
+    pub fn manage(headers:MTPHeaders, message:Option<MTPMessage>)->Self{
         Self::construct(headers, message, MTPRequestType::Manage)
     }
+
+    pub fn acknowledge(headers:MTPHeaders, message:Option<MTPMessage>)->Self{
+        Self::construct(headers, message, MTPRequestType::Acknowledge)
+    }
 }
 
 impl MessageTransferProtocolPayload for MTPPayload{
@@ -226,7 +270,7 @@ impl MessageTransferProtocolPayload for MTPPayload{
     }
 
     fn get_timestamp(&self) -> Option<SystemTime> {
-        self.headers.timestamp.clone()
+        self.headers.timestamp
     }
 }
 
@@ -235,6 +279,18 @@ impl MessageTransferProtocolPayload for MTPPayload{
 pub struct MTPManagerActions {
      actions: Vec<MTPManagerAction>,
 }
+
+impl MTPManagerActions {
+     /// Creates a collection of the management actions `actions`
+     pub fn new(actions: Vec<MTPManagerAction>) -> Self {
+          Self { actions }
+     }
+
+     /// Retrieves the management actions, in the order they are performed
+     pub fn actions(&self) -> &[MTPManagerAction] {
+          &self.actions
+     }
+}
  
  /// `MTPMessage` represents the actual content of a message within the protocol.
  /// It encapsulates the message data along with metadata that describes its type,
@@ -295,7 +351,7 @@ pub struct MTPManagerActions {
  ///
  /// Here is an example of how `MTPMessage` might be used:
  ///
- /// ```rust
+ /// ```ignore
  /// pub struct MTPMessage {
  ///     content_type: ContentType,
  ///     priority: MessagePriority,
@@ -337,6 +393,71 @@ pub struct MTPManagerActions {
      message:String
  }
 
+impl MTPMessage {
+     /// Constructs a new `MTPMessage` from its content and metadata.
+     ///
+     /// # Arguments
+     ///
+     /// * `content_type` - The format of the message content.
+     /// * `priority` - The priority of the message.
+     /// * `category` - The category of the message.
+     /// * `publish` - How the message is disseminated.
+     /// * `message` - The content of the message.
+     pub fn new(content_type:ContentType, priority:MessagePriority, category:MessageCategory, publish:MessagePublish, message:String)->Self{
+          Self { content_type, priority, category, publish, message }
+     }
+
+     /// Retrieves the format of the message content
+     pub fn content_type(&self)->&ContentType{
+          &self.content_type
+     }
+
+     /// Retrieves the priority of the message
+     pub fn priority(&self)->&MessagePriority{
+          &self.priority
+     }
+
+     /// Retrieves the category of the message
+     pub fn category(&self)->&MessageCategory{
+          &self.category
+     }
+
+     /// Retrieves how the message is published
+     pub fn publish(&self)->&MessagePublish{
+          &self.publish
+     }
+
+     /// Retrieves the content of the message
+     pub fn message(&self)->&str{
+          &self.message
+     }
+}
+
+/// [`MTPEnvelope`] represents a message as it is delivered by the broker to a consumer.
+/// It pairs the [`MTPMessage`] with the [`MTPHeaders`] it was published with, extended by
+/// the broker with a [`MTPHeaderUnit::Delivery`] unit identifying the delivery.
+pub struct MTPEnvelope {
+     headers: MTPHeaders,
+     message: MTPMessage,
+}
+
+impl MTPEnvelope {
+     /// Constructs a new `MTPEnvelope` from the headers and the message delivered.
+     pub fn new(headers:MTPHeaders, message:MTPMessage)->Self{
+          Self { headers, message }
+     }
+
+     /// Retrieves the headers of the delivered message
+     pub fn headers(&self)->&MTPHeaders{
+          &self.headers
+     }
+
+     /// Retrieves the delivered message
+     pub fn message(&self)->&MTPMessage{
+          &self.message
+     }
+}
+
 
  /// `MTPHeaders` represents the headers of a message within the protocol.
 /// It includes various components that provide metadata and contextual information
@@ -382,7 +503,7 @@ pub struct MTPManagerActions {
 ///
 /// Here is an example of how `MTPHeaders` might be used:
 ///
-/// ```rust
+/// ```ignore
 /// use std::time::SystemTime;
 /// 
 /// // Define a header unit
@@ -421,6 +542,38 @@ pub struct MTPHeaders {
      timestamp: Option<SystemTime>,
  }
 
+impl MTPHeaders {
+     /// Constructs new `MTPHeaders` from header units, local storage and a timestamp.
+     pub fn new(headers:Vec<MTPHeaderUnit>, local:MTPStorage, timestamp:Option<SystemTime>)->Self{
+          Self { headers, local, timestamp }
+     }
+
+     /// Constructs `MTPHeaders` without any header units or storage.
+     pub fn empty()->Self{
+          Self::new(Vec::new(), MTPStorage::new(Vec::new()), None)
+     }
+
+     /// Retrieves the header units
+     pub fn units(&self)->&Vec<MTPHeaderUnit>{
+          &self.headers
+     }
+
+     /// Retrieves the local storage passed with the headers
+     pub fn local(&self)->&MTPStorage{
+          &self.local
+     }
+
+     /// Retrieves the timestamp of the headers
+     pub fn timestamp(&self)->Option<SystemTime>{
+          self.timestamp
+     }
+
+     /// Appends a header unit
+     pub fn push(&mut self, unit:MTPHeaderUnit){
+          self.headers.push(unit);
+     }
+}
+
  
 /// `MTPStorage` represents a collection of storage cells, which can be used to store additional
 /// data or pointers within the protocol.
@@ -434,6 +587,45 @@ pub struct MTPStorage {
      key: String,
      value: String,
  }
+
+impl MTPStorage {
+     /// Constructs a new `MTPStorage` holding the passed cells.
+     pub fn new(items:Vec<StorageCell>)->Self{
+          Self { items }
+     }
+
+     /// Retrieves all the storage cells
+     pub fn items(&self)->&Vec<StorageCell>{
+          &self.items
+     }
+
+     /// Retrieves the value of the first cell stored under `key`
+     pub fn get(&self, key:&str)->Option<&str>{
+          self.items.iter().find(|cell| cell.key == key).map(|cell| cell.value.as_str())
+     }
+
+     /// Appends a key value cell
+     pub fn push(&mut self, key:String, value:String){
+          self.items.push(StorageCell::new(key, value));
+     }
+}
+
+impl StorageCell {
+     /// Constructs a new `StorageCell` from a key value pair.
+     pub fn new(key:String, value:String)->Self{
+          Self { key, value }
+     }
+
+     /// Retrieves the key of the cell
+     pub fn key(&self)->&str{
+          &self.key
+     }
+
+     /// Retrieves the value of the cell
+     pub fn value(&self)->&str{
+          &self.value
+     }
+}
  
 
 /// Clone implementation for [MTPStorage]
@@ -454,7 +646,7 @@ impl Clone for StorageCell{
 /// Clone implementation for [MTPHeaders]
 impl Clone for MTPHeaders{
      fn clone(&self) -> Self {
-         Self { headers: self.headers.clone(), local: self.local.clone(), timestamp: self.timestamp.clone() }
+         Self { headers: self.headers.clone(), local: self.local.clone(), timestamp: self.timestamp }
      }
 }
 
@@ -464,10 +656,35 @@ impl Clone for MTPHeaderUnit {
           match self {
                Self::Authentication { key, value } => Self::Authentication { key: key.clone(), value: value.clone() },
                Self::Administration { action } => Self::Administration { action: action.clone() },
-               Self::Source { source } => Self::Source { source: source.clone() },
-               Self::Message { id, timestamp, priority, category, content_type } => Self::Message { id: id.clone(), timestamp: timestamp.clone(), priority: priority.clone(), category: category.clone(), content_type: content_type.clone() },
+               Self::Source { source } => Self::Source { source: *source },
+               Self::Message { id, timestamp, priority, category, content_type } => Self::Message { id: id.clone(), timestamp: *timestamp, priority: priority.clone(), category: category.clone(), content_type: content_type.clone() },
                Self::MessagePublish { queue, to } => Self::MessagePublish { queue: queue.clone(), to: to.clone() },
-               Self::QueueCreation { name, access } => Self::QueueCreation { name: name.clone(), access: access.clone() }
+               Self::QueueCreation { name, access, durable, max_length, max_bytes, overflow, ttl, max_deliveries, dead_letter } => Self::QueueCreation {
+                    name: name.clone(),
+                    access: access.clone(),
+                    durable: *durable,
+                    max_length: *max_length,
+                    max_bytes: *max_bytes,
+                    overflow: overflow.clone(),
+                    ttl: *ttl,
+                    max_deliveries: *max_deliveries,
+                    dead_letter: dead_letter.clone(),
+               },
+               Self::TimeToLive { ttl } => Self::TimeToLive { ttl: *ttl },
+               Self::Subscription { queue } => Self::Subscription { queue: queue.clone() },
+               Self::Delivery { queue, id, offset } => Self::Delivery { queue: queue.clone(), id: id.clone(), offset: *offset },
+               Self::NotBefore { at } => Self::NotBefore { at: *at },
+               Self::Delay { delay } => Self::Delay { delay: *delay },
+               Self::Key { key } => Self::Key { key: key.clone() },
+               Self::Tombstone => Self::Tombstone,
+               Self::PartitionKey { key } => Self::PartitionKey { key: key.clone() },
+               Self::ConsumerGroup { group, distribution } => Self::ConsumerGroup { group: group.clone(), distribution: distribution.clone() },
+               Self::Batch { max_messages, max_bytes, wait } => Self::Batch { max_messages: *max_messages, max_bytes: *max_bytes, wait: *wait },
+               Self::Filter { expression } => Self::Filter { expression: expression.clone() },
+               Self::ReplyTo { queue } => Self::ReplyTo { queue: queue.clone() },
+               Self::Correlation { id } => Self::Correlation { id: id.clone() },
+               Self::TemporaryQueue => Self::TemporaryQueue,
+               Self::Transaction { step } => Self::Transaction { step: step.clone() },
           }
     }
 }
@@ -478,9 +695,18 @@ impl Clone for MTPManagerAction{
           match self {
                Self::Rename(s) => Self::Rename(s.clone()),
                Self::Authorize(s) => Self::Authorize(s.clone()),
-               Self::Reject => Self::Reject,
-               Self::Dispose(s) => Self::Dispose(s.clone()),
+               Self::Reject(s) => Self::Reject(s.clone()),
+               Self::Dispose { client, ban, disconnect } => Self::Dispose { client: client.clone(), ban: *ban, disconnect: *disconnect },
                Self::AccessorModify(s) => Self::AccessorModify(s.clone()),
+               Self::Cancel(s) => Self::Cancel(s.clone()),
+               Self::Pending => Self::Pending,
+               Self::Assign(s, role) => Self::Assign(s.clone(), role.clone()),
+               Self::Revoke(s) => Self::Revoke(s.clone()),
+               Self::Delete => Self::Delete,
+               Self::AddUser { username, password } => Self::AddUser { username: username.clone(), password: password.clone() },
+               Self::ChangePassword { username, password } => Self::ChangePassword { username: username.clone(), password: password.clone() },
+               Self::RemoveUser(s) => Self::RemoveUser(s.clone()),
+               Self::UnlockUser(s) => Self::UnlockUser(s.clone()),
           }
     }
 }
@@ -501,4 +727,11 @@ impl Clone for MTPMessage{
     fn clone(&self) -> Self {
         Self { content_type: self.content_type.clone(), priority: self.priority.clone(), category: self.category.clone(), publish: self.publish.clone(), message: self.message.clone() }
     }
-}
\ No newline at end of file
+}
+
+/// Clone implementation for [MTPEnvelope]
+impl Clone for MTPEnvelope{
+     fn clone(&self) -> Self {
+          Self { headers: self.headers.clone(), message: self.message.clone() }
+     }
+}
@@ -1,12 +1,10 @@
 pub mod error;
-pub mod data;
 
 use std::{net::Ipv4Addr, time::Duration};
 
 use tokio::{io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader}, net::TcpStream, time};
 
 use error::ClientSocketError;
-use super::data::ProtocolParser as ProtocolParse;
 
 /// A simple socket for wrapping over async standard tcp stream
 /// Simplifies the tco_stream by returning data in an enclosed entity
@@ -43,7 +41,7 @@ impl ClientSocket {
      ///
      /// # Example
      ///
-     /// ```rust
+     /// ```ignore
      /// let socket = ClientSocket::connect(8080);     //Connect at "localhost:8080"
      /// ```
      ///
@@ -91,11 +89,12 @@ impl ClientSocket {
      /// If the underlying `write` operation fails, this function returns a `ClientSocketError`. Specifically, this might happen if:
      /// - The TCP stream is closed or interrupted.
      /// - The connection encounters a network error during transmission.
+     ///
      /// The error is propagated via the `?` operator, which handles the error and converts it into a `ClientSocketError`.
      ///
      /// # Example
      ///
-     /// ```rust
+     /// ```ignore
      /// let socket = ClientSocket::connect(8080).await.unwrap();
      /// socket.send("This is some data").await;
      /// ```
@@ -110,7 +109,7 @@ impl ClientSocket {
      pub async fn send(&mut self, data:String)->Result<(), ClientSocketError>{
           let _ = self.stream.write(data.as_bytes()).await?;
 
-          return Ok(());
+          Ok(())
      }
      /// Asynchronously receives data from the TCP stream.
      ///
@@ -130,7 +129,7 @@ impl ClientSocket {
      ///
      /// # Example
      ///
-     /// ```rust
+     /// ```ignore
      /// let mut buf = vec![0; 1024];
      /// let bytes_read = socket.recv(&mut buf).await.unwrap();
      /// ```
@@ -149,7 +148,7 @@ impl ClientSocket {
      ///
      /// # Example
      ///
-     /// ```rust
+     /// ```ignore
      /// socket.close().await.unwrap();
      /// ```
      pub async fn close(&mut self) -> Result<(), ClientSocketError> {
@@ -177,56 +176,6 @@ impl ClientSocket {
           }
      }
 
-     /// Sends a framed message with a length prefix.
-     /// Sends the frame implemented on [ProtocolParse]
-     ///
-     /// # Arguments
-     ///
-     /// * `data` - The message to send.
-     ///
-     /// # Returns
-     ///
-     /// Sends the length of the message followed by the actual data.
-     pub async fn send_frame(&mut self, data: impl ProtocolParse) -> Result<(), ClientSocketError> {
-          // buffer
-          let bytes:Vec<u8> = match data.to_bytes() {
-               Ok(b) => b,
-               Err(e) => return Err(ClientSocketError::ProtocolParseError { source: e }),
-          };
-
-          match self.stream.write(&bytes).await{
-               Ok(e) => return Ok(()),
-               Err(s) => Err(ClientSocketError::IoError { source: s }),
-          }
-     }
-
-     /// Receives a framed message with a length prefix.
-     /// Receives the frame implemented on [ProtocolParse]
-     /// Protocol standard tx. of data through socket
-     ///
-     /// # Returns
-     ///
-     /// The message data received after the length prefix.
-     pub async fn recv_frame<T: Clone + ProtocolParse>(
-          &mut self,
-          protocol: &mut T
-      ) -> Result<T, ClientSocketError> {
-          let mut bytes: Vec<u8> = Vec::new();
-          
-          // Reading bytes from stream
-          self.recv(&mut bytes).await.map_err(|e| ClientSocketError::from(e))?;
-          
-          // Parsing protocol data
-          match protocol.from_raw(bytes) {
-
-              Ok(parsed_data) =>{
-                    protocol.clone_from(&parsed_data);
-                    Ok(parsed_data) 
-               },
-              Err(e) => Err(ClientSocketError::ProtocolParseError { source: e }),
-          }
-     }
-
      /// Flushes the stream.
      ///
      /// # Returns
@@ -235,7 +184,7 @@ impl ClientSocket {
      ///
      /// # Example
      /// 
-     /// ```rust
+     /// ```ignore
      /// socket.flush().await.unwrap();
      /// ```
      pub async fn flush(&mut self) -> Result<(), ClientSocketError> {
@@ -250,7 +199,7 @@ impl ClientSocket {
      ///
      /// # Example
      /// 
-     /// ```rust
+     /// ```ignore
      /// let (read_half, write_half) = socket.split();
      /// ```
      pub fn split(self) -> (tokio::net::tcp::OwnedReadHalf, tokio::net::tcp::OwnedWriteHalf) {
@@ -265,7 +214,7 @@ impl ClientSocket {
      ///
      /// # Example
      /// 
-     /// ```rust
+     /// ```ignore
      /// let local_addr = socket.get_local_addr().unwrap();
      /// ```
      pub fn get_local_addr(&self) -> Result<std::net::SocketAddr, ClientSocketError> {
@@ -280,7 +229,7 @@ impl ClientSocket {
      ///
      /// # Example
      /// 
-     /// ```rust
+     /// ```ignore
      /// let peer_addr = socket.get_peer_addr().unwrap();
      /// ```
      pub fn get_peer_addr(&self) -> Result<std::net::SocketAddr, ClientSocketError> {
@@ -299,7 +248,7 @@ impl ClientSocket {
      ///
      /// # Example
      /// 
-     /// ```rust
+     /// ```ignore
      /// let data = socket.read_until(b'\n').await.unwrap();
      /// ```
      pub async fn read_until(&mut self, delimiter: u8) -> Result<Vec<u8>, ClientSocketError> {
@@ -318,7 +267,7 @@ impl ClientSocket {
      ///
      /// # Example
      /// 
-     /// ```rust
+     /// ```ignore
      /// let data = socket.read_to_end().await.unwrap();
      /// ```
      pub async fn read_to_end(&mut self) -> Result<Vec<u8>, ClientSocketError> {
@@ -335,7 +284,7 @@ impl ClientSocket {
      ///
      /// # Example
      /// 
-     /// ```rust
+     /// ```ignore
      /// let is_connected = socket.is_connected().await.unwrap();
      /// ```
      pub async fn is_connected(&mut self) -> Result<bool, ClientSocketError> {
@@ -354,7 +303,7 @@ impl ClientSocket {
      ///
      /// # Example
      /// 
-     /// ```rust
+     /// ```ignore
      /// socket.shutdown().await.unwrap();
      /// ```
      pub async fn shutdown(&mut self) -> Result<(), ClientSocketError> {
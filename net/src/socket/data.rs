@@ -25,7 +25,7 @@
 ///
 /// # Example
 ///
-/// ```
+/// ```ignore
 /// use your_crate::Data;
 ///
 /// let text_data = Data::Utf8("Hello, world!".to_string());
@@ -77,7 +77,7 @@ pub enum Data {
 ///
 /// # Example
 ///
-/// ```
+/// ```ignore
 /// use your_crate::Type;
 ///
 /// let data_type = Type::Utf8;
@@ -102,7 +102,7 @@ pub enum Type {
  }
  
 
-//// Represents the endianness of data.
+/// Represents the endianness of data.
 ///
 /// Endianness refers to the order in which bytes are arranged within
 /// a larger data type in memory. This enum is used to specify the byte
@@ -122,7 +122,7 @@ pub enum Type {
 ///
 /// # Example
 ///
-/// ```
+/// ```ignore
 /// use your_crate::Endian;
 ///
 /// let endian = Endian::Big;
@@ -151,7 +151,7 @@ impl Data{
      /// 
      /// # Returns 
      /// A [std::vec::Vec<u16>] which is utf-16 encoded
-     pub async fn to_utf16_encoded(buf:&Vec<u8>, endian:Endian)->Vec<u16>{
+     pub async fn to_utf16_encoded(buf:&[u8], endian:Endian)->Vec<u16>{
           //buffer in utf16
           let mut u16_buf = Vec::new();
           let buf_length = buf.len();
@@ -160,18 +160,16 @@ impl Data{
           for i in (0..buf_length).step_by(2){
                if i+1< buf_length{
 
-                    let s:u16;     //16 byte char
-
-                    //conversion by endian
-                    match endian {
+                    //16 byte char, converted by endian
+                    let s:u16 = match endian {
 
                          Endian::Big=>{
-                              s = u16::from_be_bytes([buf[i], buf[i+1]]);
+                              u16::from_be_bytes([buf[i], buf[i+1]])
                          },
                          Endian::Little=>{
-                              s = u16::from_le_bytes([buf[i], buf[i+1]]);
+                              u16::from_le_bytes([buf[i], buf[i+1]])
                          }
-                    }
+                    };
                     u16_buf.push(s);
                }
           }
@@ -187,7 +185,7 @@ impl Data{
      /// 
      /// # Returns 
      /// A [std::string::String] which is utf-16 encoded
-     pub async fn to_utf16_string(buf: &Vec<u8>, endian:Endian)->String{
+     pub async fn to_utf16_string(buf: &[u8], endian:Endian)->String{
           let utf16_encoded = Self::to_utf16_encoded(buf, endian).await;
 
           String::from_utf16_lossy(&utf16_encoded)
@@ -20,7 +20,7 @@ use crate::socket::data::Data;
 ///
 /// # Example
 ///
-/// ```rust
+/// ```ignore
 /// use std::net::SocketAddr;
 /// use your_crate::data::Data;
 /// use your_crate::SocketData;
@@ -59,7 +59,7 @@ pub struct SocketData {
      ///
      /// # Example
      ///
-     /// ```rust
+     /// ```ignore
      /// use std::net::SocketAddr;
      /// use your_crate::data::Data;
      /// use your_crate::SocketData;
@@ -74,6 +74,16 @@ pub struct SocketData {
              data,
          }
      }
+
+     /// Retrieves the address the data was received from
+     pub fn address(&self) -> SocketAddr {
+          self.address
+     }
+
+     /// Retrieves the data read from the stream
+     pub fn data(&self) -> &Data {
+          &self.data
+     }
  }
  
 
@@ -12,6 +12,6 @@ pub enum ServerSocketError{
 /// From implementation to type cast [std::io::Error] to [ServerSocketError]
 impl From<Error> for ServerSocketError{
      fn from(value: Error) -> Self {
-          return Self::IoError{source:value};
+          Self::IoError{source:value}
      }
 }
\ No newline at end of file
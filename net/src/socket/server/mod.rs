@@ -96,7 +96,7 @@ impl ServerSocket{
      ///
      /// # Example
      ///
-     /// ```rust
+     /// ```ignore
      /// use your_crate::ServerSocket;
      ///
      /// #[tokio::main]
@@ -130,7 +130,7 @@ impl ServerSocket{
      ///
      /// - [`ServerSocketError`] for details on the possible errors.
      /// - [`TcpListener`] for information on TCP listener behavior and usage.
-     /// ```rust
+     /// ```ignore
      /// pub async fn bind(port: u16) -> Result<Self, ServerSocketError> {
      ///     //localhost
      ///     let localhost = Ipv4Addr::bind(127, 0, 0, 1);
@@ -182,7 +182,7 @@ impl ServerSocket{
      ///
      /// # Example
      ///
-     /// ```rust
+     /// ```ignore
      /// // Assume `self` is an instance with a `tcp_listener`
      /// match self.read_incoming(Type::Utf8).await {
      ///     Ok(socket_data) => {
@@ -211,7 +211,7 @@ impl ServerSocket{
      /// - [`Type`] for the different data types you can specify for parsing.
      /// - [`SocketData`] for the structure of the data returned.
      /// - [`ServerSocketError`] for details on the possible errors.
-     /// ```rust
+     /// ```ignore
      /// pub async fn read_incoming(&self, data_type: Type) -> Result<SocketData, ServerSocketError> {
      ///     // Function implementation
      /// }
@@ -224,10 +224,10 @@ impl ServerSocket{
           let mut buf: Vec<u8> = Vec::new();
      
           // Reading socket data
-          stream.read(&mut buf).await?;
+          stream.read_to_end(&mut buf).await?;
      
           // Matching the type passed for parsing data for a specific encoding
-          return match data_type {
+          match data_type {
                Type::Bytes => Ok(SocketData::new(addr, Data::Bytes(buf))),
                Type::Utf16 => {
                     let utf16_string = Data::to_utf16_string(&buf, Endian::Big).await;
@@ -259,7 +259,7 @@ impl ServerSocket{
      ///
      /// # Example
      ///
-     /// ```rust
+     /// ```ignore
      /// use your_crate::ServerSocket;
      ///
      /// #[tokio::main]
@@ -295,7 +295,7 @@ impl ServerSocket{
      /// - [`read_incoming`] for more details on how data is read and parsed.
      /// - [`SocketData`] for the structure of the data returned.
      /// - [`ServerSocketError`] for details on the possible errors.
-     /// ```rust
+     /// ```ignore
      /// pub async fn read(&self) -> Result<SocketData, ServerSocketError> {
      ///     return self.read_incoming(Type::Utf8).await;
      /// }
@@ -329,7 +329,7 @@ impl ServerSocket{
      ///
      /// # Example
      ///
-     /// ```rust
+     /// ```ignore
      /// use your_crate::ServerSocket;
      /// use your_crate::data::Type;
      ///
@@ -369,36 +369,36 @@ impl ServerSocket{
           }
      }
 
-     /// Shuts down the TCP listener, stopping it from accepting new connections.
-     ///
-     /// # Returns
-     ///
-     /// - `Result<(), ServerSocketError>`:
-     ///   - `Ok(())` on successful shutdown.
-     ///   - `Err(ServerSocketError)` on failure to shutdown, which might include IO errors.
-     ///
-     /// # Example
-     ///
-     /// ```rust
-     /// use your_crate::ServerSocket;
-     ///
-     /// #[tokio::main]
-     /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
-     ///     let mut server = ServerSocket::bind(8080).await?;
-     ///     // Perform some operations
-     ///     server.shutdown().await?;
-     ///     Ok(())
-     /// }
-     /// ```
-     ///
-     /// # Errors
-     ///
-     /// This function may return an error if:
-     /// - There are issues with closing the listener.
-     ///
-     /// # Notes
-     ///
-     /// - Ensure that all connections are properly closed before shutting down the server.
+     // Shuts down the TCP listener, stopping it from accepting new connections.
+     //
+     // # Returns
+     //
+     // - `Result<(), ServerSocketError>`:
+     //   - `Ok(())` on successful shutdown.
+     //   - `Err(ServerSocketError)` on failure to shutdown, which might include IO errors.
+     //
+     // # Example
+     //
+     // ```rust
+     // use your_crate::ServerSocket;
+     //
+     // #[tokio::main]
+     // async fn main() -> Result<(), Box<dyn std::error::Error>> {
+     //     let mut server = ServerSocket::bind(8080).await?;
+     //     // Perform some operations
+     //     server.shutdown().await?;
+     //     Ok(())
+     // }
+     // ```
+     //
+     // # Errors
+     //
+     // This function may return an error if:
+     // - There are issues with closing the listener.
+     //
+     // # Notes
+     //
+     // - Ensure that all connections are properly closed before shutting down the server.
      // pub async fn shutdown(&mut self) -> Result<(), ServerSocketError> {
      //      // Closing the TcpListener isn't directly supported in Tokio; however, you can drop it
      //      // and ensure no new connections are accepted.
@@ -415,7 +415,7 @@ impl ServerSocket{
      ///
      /// # Example
      ///
-     /// ```rust
+     /// ```ignore
      /// use your_crate::ServerSocket;
      ///
      /// #[tokio::main]
@@ -444,7 +444,7 @@ impl ServerSocket{
 ///
 /// # Examples
 ///
-/// ```rust
+/// ```ignore
 /// use your_crate::ServerSocket;
 ///
 /// #[tokio::main]
@@ -508,7 +508,7 @@ impl<'a> Iterator for ConnectionIterator<'a> {
      ///
      /// # Example
      ///
-     /// ```rust
+     /// ```ignore
      /// let mut conn_iter = ConnectionIterator::new(&server);
      ///
      /// while let Some(result) = conn_iter.next().await {
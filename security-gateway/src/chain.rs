@@ -0,0 +1,68 @@
+use net::protocol::error::ProtocolError;
+use net::protocol::interface::MTPHeaderUnit;
+
+use crate::provider::{unrecognised, AuthProvider, Identity, Peer};
+
+/// Providers tried in turn until one of them recognises the credentials of a request.
+///
+/// A provider failing with [`ProtocolError::Unauthorized101`] falls through to the next one,
+/// while any other error ends the authentication at once. When no provider recognises the
+/// credentials, the chain fails with the error of the last one.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use std::path::PathBuf;
+/// # use security_gateway::chain::ProviderChain;
+/// # use security_gateway::introspection::Introspection;
+/// # use security_gateway::provider::Identity;
+/// # use security_gateway::tokens::StaticTokens;
+/// # use security_gateway::users::FileUsers;
+/// # fn main() -> std::io::Result<()> {
+/// let chain = ProviderChain::new()
+///     .with(StaticTokens::new().with_token("ci-token", Identity::new("ci".to_string())))
+///     .with(FileUsers::open(PathBuf::from("users"))?)
+///     .with(Introspection::new("https://sso.example.com/introspect".to_string()));
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Fields
+///
+/// ~ `providers`: Providers in the order they are tried
+pub struct ProviderChain {
+     providers: Vec<Box<dyn AuthProvider>>,
+}
+
+impl ProviderChain {
+     /// Creates a chain without providers, recognising no credentials
+     pub fn new() -> Self {
+          Self { providers: Vec::new() }
+     }
+
+     /// Appends `provider` to the chain, tried after the providers appended before
+     pub fn with(mut self, provider: impl AuthProvider + 'static) -> Self {
+          self.providers.push(Box::new(provider));
+          self
+     }
+}
+
+/// Default implementation for [ProviderChain]
+impl Default for ProviderChain {
+     fn default() -> Self {
+          Self::new()
+     }
+}
+
+impl AuthProvider for ProviderChain {
+     fn authenticate(&self, units: &[MTPHeaderUnit], peer: &Peer) -> Result<Identity, ProtocolError> {
+          let mut last = unrecognised("No authentication provider recognised the credentials");
+          for provider in &self.providers {
+               match provider.authenticate(units, peer) {
+                    Err(err @ ProtocolError::Unauthorized101(_)) => last = err,
+                    result => return result,
+               }
+          }
+          Err(last)
+     }
+}
@@ -0,0 +1,279 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use base64ct::{Base64, Encoding};
+use serde_json::Value;
+use ureq::{Agent, AgentBuilder};
+
+use net::protocol::error::{Error, ProtocolError};
+use net::protocol::interface::{AuthSchemes, MTPAuth, MTPHeaderUnit, QueueRoles};
+
+use crate::provider::{credentials, unrecognised, AuthProvider, Identity, Peer};
+
+/// A rule granting a role on a queue to the holders of the tokens whose `scope` holds `scope`.
+///
+/// # Fields
+///
+/// ~ `scope`: Scope the token must hold
+/// ~ `queue`: Queue the role is granted on
+/// ~ `role`: Role granted on the queue
+struct ScopeRole {
+     scope: String,
+     queue: String,
+     role: QueueRoles,
+}
+
+/// Provider asking an OAuth 2.0 token introspection endpoint (RFC 7662) whether the opaque
+/// tokens sent through `MTPAuth::ExternalToken` or the `Bearer` authorization scheme are active.
+///
+/// The token is posted as a form to the endpoint, authenticated with the credentials of the
+/// broker as a client of the authorization server if configured. An active token vouches for
+/// the identity held by its configured claim until its `exp`, granted the roles its `scope`
+/// maps to. An inactive token falls through to the next provider of a chain, while an endpoint
+/// that cannot be reached or answers with an error refuses the request with
+/// [`ProtocolError::BadGateway121`].
+///
+/// # Fields
+///
+/// ~ `endpoint`: URL of the introspection endpoint
+/// ~ `client`: Id and secret the broker authenticates to the endpoint with, if any
+/// ~ `identity_claim`: Claim of the response holding the identity the holder authenticates as
+/// ~ `scopes`: Rules granting roles on queues from the scopes of tokens
+/// ~ `agent`: HTTP agent the endpoint is called through, keeping connections alive
+pub struct Introspection {
+     endpoint: String,
+     client: Option<(String, String)>,
+     identity_claim: String,
+     scopes: Vec<ScopeRole>,
+     agent: Agent,
+}
+
+impl Introspection {
+     /// Creates a provider introspecting tokens at `endpoint`, authenticating their holder as
+     /// their `sub` claim. Calls to the endpoint time out after 5 seconds.
+     pub fn new(endpoint: String) -> Self {
+          Self {
+               endpoint,
+               client: None,
+               identity_claim: "sub".to_string(),
+               scopes: Vec::new(),
+               agent: AgentBuilder::new().timeout(Duration::from_secs(5)).build(),
+          }
+     }
+
+     /// Authenticates the broker to the endpoint as the client `id` with `secret`
+     pub fn with_client(mut self, id: String, secret: String) -> Self {
+          self.client = Some((id, secret));
+          self
+     }
+
+     /// Sets the claim of the response holding the identity the holder of a token
+     /// authenticates as
+     pub fn with_identity_claim(mut self, claim: String) -> Self {
+          self.identity_claim = claim;
+          self
+     }
+
+     /// Grants `role` on the queue `queue` to the holders of the tokens whose scope holds `scope`
+     pub fn with_scope_role(mut self, scope: String, queue: String, role: QueueRoles) -> Self {
+          self.scopes.push(ScopeRole { scope, queue, role });
+          self
+     }
+
+     /// Sets how long a call to the endpoint may take
+     pub fn with_timeout(mut self, timeout: Duration) -> Self {
+          self.agent = AgentBuilder::new().timeout(timeout).build();
+          self
+     }
+
+     /// Posts `token` to the endpoint
+     ///
+     /// # Returns
+     /// The claims the endpoint answered with
+     ///
+     /// # Errors
+     /// [`ProtocolError::BadGateway121`] if the endpoint cannot be reached, answers with an
+     /// error or with a body that is not a JSON object
+     fn introspect(&self, token: &str) -> Result<Value, ProtocolError> {
+          let mut request = self.agent.post(&self.endpoint);
+          if let Some((id, secret)) = &self.client {
+               request = request.set("Authorization", &format!("Basic {}", Base64::encode_string(format!("{}:{}", id, secret).as_bytes())));
+          }
+
+          let response = match request.send_form(&[("token", token), ("token_type_hint", "access_token")]) {
+               Ok(response) => response,
+               Err(ureq::Error::Status(status, _)) => {
+                    return Err(ProtocolError::BadGateway121(Error::new(format!("Introspection endpoint answered with status {}", status))));
+               },
+               Err(err) => {
+                    return Err(ProtocolError::BadGateway121(Error::new(format!("Introspection endpoint could not be reached: {}", err))));
+               },
+          };
+
+          response.into_string().ok()
+               .and_then(|body| serde_json::from_str::<Value>(&body).ok())
+               .filter(Value::is_object)
+               .ok_or_else(|| ProtocolError::BadGateway121(Error::new("Introspection endpoint answered with an invalid body".to_string())))
+     }
+}
+
+impl AuthProvider for Introspection {
+     fn authenticate(&self, units: &[MTPHeaderUnit], _peer: &Peer) -> Result<Identity, ProtocolError> {
+          let token = credentials(units, |method| matches!(method, MTPAuth::ExternalToken | MTPAuth::Authorization { scheme: AuthSchemes::Bearer }))
+               .ok_or_else(|| unrecognised("No bearer token"))?;
+
+          let claims = self.introspect(token)?;
+          if claims.get("active").and_then(Value::as_bool) != Some(true) {
+               return Err(unrecognised("Token is not active"));
+          }
+
+          let name = claims.get(&self.identity_claim)
+               .and_then(Value::as_str)
+               .ok_or_else(|| unrecognised(&format!("Token carries no {} claim", self.identity_claim)))?;
+          let mut identity = Identity::new(name.to_string());
+          if let Some(exp) = claims.get("exp").and_then(Value::as_u64) {
+               identity = identity.with_expiry(UNIX_EPOCH + Duration::from_secs(exp));
+          }
+
+          let scopes: Vec<&str> = claims.get("scope")
+               .and_then(Value::as_str)
+               .map(|scope| scope.split_whitespace().collect())
+               .unwrap_or_default();
+          for rule in self.scopes.iter().filter(|rule| scopes.contains(&rule.scope.as_str())) {
+               identity = identity.with_role(rule.queue.clone(), rule.role.clone());
+          }
+
+          Ok(identity)
+     }
+}
+
+#[cfg(test)]
+mod tests {
+     use std::io::{Read, Write};
+     use std::net::{TcpListener, TcpStream};
+     use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+     use net::protocol::error::ProtocolError;
+     use net::protocol::interface::{AuthSchemes, MTPAuth, MTPHeaderUnit, QueueRoles};
+
+     use super::Introspection;
+     use crate::chain::ProviderChain;
+     use crate::provider::{AuthProvider, Identity, Peer};
+     use crate::tokens::StaticTokens;
+
+     /// Credentials the mock endpoint expects the broker to authenticate with, `mq:s3cret`
+     const CLIENT: &str = "Basic bXE6czNjcmV0";
+
+     fn now() -> u64 {
+          SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+     }
+
+     /// Reads an HTTP request up to the end of its body
+     fn request(stream: &mut TcpStream) -> String {
+          let mut request = Vec::new();
+          let mut chunk = [0; 4096];
+          loop {
+               let read = stream.read(&mut chunk).unwrap();
+               request.extend_from_slice(&chunk[..read]);
+               let text = String::from_utf8_lossy(&request).to_string();
+               let complete = text.find("\r\n\r\n").is_some_and(|end| {
+                    let length = text.lines()
+                         .find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length:").map(|value| value.trim().parse().unwrap()))
+                         .unwrap_or(0);
+                    request.len() >= end + 4 + length
+               });
+               if complete || read == 0 {
+                    return text;
+               }
+          }
+     }
+
+     /// Starts an introspection endpoint on a local port, answering for a few known tokens
+     ///
+     /// # Returns
+     /// The URL of the endpoint
+     fn endpoint() -> String {
+          let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+          let address = listener.local_addr().unwrap();
+          std::thread::spawn(move || {
+               for stream in listener.incoming() {
+                    let mut stream = stream.unwrap();
+                    let request = request(&mut stream);
+                    let (status, body) = if !request.contains(CLIENT) {
+                         ("401 Unauthorized", "{}".to_string())
+                    } else if request.contains("token=active") {
+                         ("200 OK", format!(r#"{{"active":true,"sub":"carol","exp":{},"scope":"read mq:admin"}}"#, now() + 3600))
+                    } else if request.contains("token=anonymous") {
+                         ("200 OK", r#"{"active":true}"#.to_string())
+                    } else if request.contains("token=broken") {
+                         ("500 Internal Server Error", "oops".to_string())
+                    } else if request.contains("token=garbled") {
+                         ("200 OK", "[]".to_string())
+                    } else {
+                         ("200 OK", r#"{"active":false}"#.to_string())
+                    };
+                    let _ = write!(stream, "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body);
+               }
+          });
+          format!("http://{}/introspect", address)
+     }
+
+     fn bearer(token: &str) -> Vec<MTPHeaderUnit> {
+          vec![MTPHeaderUnit::Authentication { key: MTPAuth::Authorization { scheme: AuthSchemes::Bearer }, value: token.to_string() }]
+     }
+
+     fn peer() -> Peer {
+          Peer::new("127.0.0.1:1".parse().unwrap())
+     }
+
+     fn provider(endpoint: &str) -> Introspection {
+          Introspection::new(endpoint.to_string())
+               .with_client("mq".to_string(), "s3cret".to_string())
+               .with_scope_role("mq:admin".to_string(), "orders".to_string(), QueueRoles::Moderator)
+               .with_timeout(Duration::from_secs(2))
+     }
+
+     #[test]
+     fn active_tokens_vouch_for_their_subject() {
+          let endpoint = endpoint();
+          let identity = provider(&endpoint).authenticate(&bearer("active"), &peer()).ok().unwrap();
+
+          assert_eq!(identity.name(), "carol");
+          assert!(matches!(identity.roles().get("orders"), Some(QueueRoles::Moderator)));
+          assert!(identity.expires().is_some_and(|expires| expires > SystemTime::now()));
+          assert!(identity.token().is_none());
+     }
+
+     #[test]
+     fn inactive_tokens_fall_through_while_failures_refuse() {
+          let endpoint = endpoint();
+          let provider = provider(&endpoint);
+
+          for token in ["inactive", "anonymous"] {
+               assert!(matches!(provider.authenticate(&bearer(token), &peer()), Err(ProtocolError::Unauthorized101(_))));
+          }
+          for token in ["broken", "garbled"] {
+               assert!(matches!(provider.authenticate(&bearer(token), &peer()), Err(ProtocolError::BadGateway121(_))));
+          }
+          let basic = vec![MTPHeaderUnit::Authentication { key: MTPAuth::Authorization { scheme: AuthSchemes::Basic }, value: "x".to_string() }];
+          assert!(matches!(provider.authenticate(&basic, &peer()), Err(ProtocolError::Unauthorized101(_))));
+
+          // the broker must authenticate to the endpoint, which must be reachable
+          let anonymous = Introspection::new(endpoint.clone());
+          assert!(matches!(anonymous.authenticate(&bearer("active"), &peer()), Err(ProtocolError::BadGateway121(_))));
+          let unreachable = Introspection::new("http://127.0.0.1:1/introspect".to_string());
+          assert!(matches!(unreachable.authenticate(&bearer("active"), &peer()), Err(ProtocolError::BadGateway121(_))));
+     }
+
+     #[test]
+     fn chains_fall_through_to_the_endpoint() {
+          let endpoint = endpoint();
+          let chain = ProviderChain::new()
+               .with(StaticTokens::new().with_token("ci", Identity::new("ci".to_string())))
+               .with(provider(&endpoint));
+
+          assert_eq!(chain.authenticate(&bearer("ci"), &peer()).ok().unwrap().name(), "ci");
+          assert_eq!(chain.authenticate(&bearer("active"), &peer()).ok().unwrap().name(), "carol");
+          assert!(matches!(chain.authenticate(&bearer("inactive"), &peer()), Err(ProtocolError::Unauthorized101(_))));
+          assert!(matches!(chain.authenticate(&bearer("broken"), &peer()), Err(ProtocolError::BadGateway121(_))));
+     }
+}
@@ -0,0 +1,23 @@
+/// Module containing the [`provider::AuthProvider`] trait the broker authenticates clients
+/// through, along with the [`provider::Identity`] a provider vouches for and the
+/// [`provider::Peer`] it authenticates.
+///
+/// The broker only depends on this module: the providers are plugged into it by whoever starts
+/// it, so that every environment picks its own authentication backends.
+pub mod provider;
+
+/// Module containing the [`chain::ProviderChain`] trying several providers in turn, falling
+/// through to the next one when a provider does not recognise the credentials.
+pub mod chain;
+
+/// Module containing the built-in [`tokens::StaticTokens`] provider, recognising a fixed set of
+/// opaque tokens.
+pub mod tokens;
+
+/// Module containing the built-in [`users::FileUsers`] provider, authenticating users by
+/// username and password against the hashes of a file.
+pub mod users;
+
+/// Module containing the built-in [`introspection::Introspection`] provider, checking opaque
+/// tokens against an OAuth 2.0 token introspection endpoint.
+pub mod introspection;
@@ -0,0 +1,202 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::SystemTime;
+
+use net::protocol::error::{Error, ProtocolError};
+use net::protocol::interface::{MTPAuth, MTPHeaderUnit, QueueRoles};
+
+/// The client whose credentials an [`AuthProvider`] checks.
+///
+/// # Fields
+///
+/// ~ `address`: The address the client is connected from
+pub struct Peer {
+     address: SocketAddr,
+}
+
+impl Peer {
+     /// Creates the peer connected from `address`
+     pub fn new(address: SocketAddr) -> Self {
+          Self { address }
+     }
+
+     /// Retrieves the address the client is connected from
+     pub fn address(&self) -> SocketAddr {
+          self.address
+     }
+}
+
+/// Clone implementation for [Peer]
+impl Clone for Peer {
+     fn clone(&self) -> Self {
+          Self { address: self.address }
+     }
+}
+
+/// An identity an [`AuthProvider`] vouches for.
+///
+/// # Fields
+///
+/// ~ `name`: Name the client authenticates as, identifying it to the queues it uses
+/// ~ `roles`: Roles the provider grants the client, by queue
+/// ~ `queues`: Queues the client is restricted to along with those it is granted a role on,
+///   every queue if empty
+/// ~ `expires`: Time the identity stops being vouched for, if it does
+/// ~ `token`: Id of the token vouching for the identity, if one does and its issuer revokes tokens
+/// ~ `key`: Id of the key the token was signed with, if its issuer retires keys
+pub struct Identity {
+     name: String,
+     roles: BTreeMap<String, QueueRoles>,
+     queues: BTreeSet<String>,
+     expires: Option<SystemTime>,
+     token: Option<String>,
+     key: Option<String>,
+}
+
+impl Identity {
+     /// Creates the identity `name`, granted no role and vouched for until the session closes
+     pub fn new(name: String) -> Self {
+          Self {
+               name,
+               roles: BTreeMap::new(),
+               queues: BTreeSet::new(),
+               expires: None,
+               token: None,
+               key: None,
+          }
+     }
+
+     /// Grants the client `role` on the queue `queue`
+     pub fn with_role(mut self, queue: String, role: QueueRoles) -> Self {
+          self.roles.insert(queue, role);
+          self
+     }
+
+     /// Restricts the client to the queue `queue`, along with the other queues it is restricted to
+     pub fn with_queue(mut self, queue: String) -> Self {
+          self.queues.insert(queue);
+          self
+     }
+
+     /// Stops vouching for the identity at `expires`
+     pub fn with_expiry(mut self, expires: SystemTime) -> Self {
+          self.expires = Some(expires);
+          self
+     }
+
+     /// Records that the token `token`, signed with the key `key` if any, vouches for the identity
+     pub fn with_token(mut self, token: String, key: Option<String>) -> Self {
+          self.token = Some(token);
+          self.key = key;
+          self
+     }
+
+     /// Retrieves the name the client authenticates as
+     pub fn name(&self) -> &str {
+          &self.name
+     }
+
+     /// Retrieves the roles granted to the client, by queue
+     pub fn roles(&self) -> &BTreeMap<String, QueueRoles> {
+          &self.roles
+     }
+
+     /// Retrieves the queues the client is restricted to, every queue if empty
+     pub fn queues(&self) -> &BTreeSet<String> {
+          &self.queues
+     }
+
+     /// Retrieves the time the identity stops being vouched for, if it does
+     pub fn expires(&self) -> Option<SystemTime> {
+          self.expires
+     }
+
+     /// Retrieves the id of the token vouching for the identity, if one does
+     pub fn token(&self) -> Option<&str> {
+          self.token.as_deref()
+     }
+
+     /// Retrieves the id of the key the token vouching for the identity was signed with, if any
+     pub fn key(&self) -> Option<&str> {
+          self.key.as_deref()
+     }
+}
+
+/// Clone implementation for [Identity]
+impl Clone for Identity {
+     fn clone(&self) -> Self {
+          Self {
+               name: self.name.clone(),
+               roles: self.roles.clone(),
+               queues: self.queues.clone(),
+               expires: self.expires,
+               token: self.token.clone(),
+               key: self.key.clone(),
+          }
+     }
+}
+
+/// A source of identities, authenticating clients from the credentials they send.
+///
+/// Providers are called outside of the async runtime, so they may block on I/O or on slow
+/// password hashes. A provider that does not recognise the credentials of a request, because
+/// their method is not one it handles or because it does not know them, fails with
+/// [`ProtocolError::Unauthorized101`]: a [`crate::chain::ProviderChain`] then lets the next
+/// provider try them. Any other error ends the authentication of the request.
+///
+/// # Example
+///
+/// ```rust
+/// # use net::protocol::error::ProtocolError;
+/// # use net::protocol::interface::MTPHeaderUnit;
+/// # use security_gateway::provider::{AuthProvider, Identity, Peer};
+/// struct Anonymous;
+///
+/// impl AuthProvider for Anonymous {
+///     fn authenticate(&self, _units: &[MTPHeaderUnit], peer: &Peer) -> Result<Identity, ProtocolError> {
+///         Ok(Identity::new(peer.address().to_string()))
+///     }
+/// }
+/// ```
+pub trait AuthProvider: Send + Sync {
+     /// Authenticates `peer` with the `Authentication` units of its request
+     ///
+     /// # Returns
+     /// The identity the credentials vouch for
+     ///
+     /// # Errors
+     /// - [`ProtocolError::Unauthorized101`] if the provider does not recognise the credentials
+     /// - Any other error if the credentials cannot be checked, or the client is refused
+     ///   whatever the other providers would decide
+     fn authenticate(&self, units: &[MTPHeaderUnit], peer: &Peer) -> Result<Identity, ProtocolError>;
+}
+
+/// A shared provider, such as one its owner keeps managing while it sits in a chain
+impl<P: AuthProvider + ?Sized> AuthProvider for Arc<P> {
+     fn authenticate(&self, units: &[MTPHeaderUnit], peer: &Peer) -> Result<Identity, ProtocolError> {
+          (**self).authenticate(units, peer)
+     }
+}
+
+/// A provider changed by its owner between requests, authenticating while locked for reading
+impl<P: AuthProvider> AuthProvider for RwLock<P> {
+     fn authenticate(&self, units: &[MTPHeaderUnit], peer: &Peer) -> Result<Identity, ProtocolError> {
+          self.read().unwrap_or_else(PoisonError::into_inner).authenticate(units, peer)
+     }
+}
+
+/// Retrieves the credentials of the first `Authentication` unit among `units` whose method
+/// `accepts` admits
+pub fn credentials(units: &[MTPHeaderUnit], accepts: impl Fn(&MTPAuth) -> bool) -> Option<&str> {
+     units.iter().find_map(|unit| match unit {
+          MTPHeaderUnit::Authentication { key, value } if accepts(key) => Some(value.as_str()),
+          _ => None,
+     })
+}
+
+/// Builds the error a provider fails with when it does not recognise the credentials of a
+/// request, see [`AuthProvider`]
+pub fn unrecognised(message: &str) -> ProtocolError {
+     ProtocolError::Unauthorized101(Error::new(message.to_string()))
+}
@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+
+use sha2::{Digest, Sha256};
+
+use net::protocol::error::ProtocolError;
+use net::protocol::interface::{AuthSchemes, MTPAuth, MTPHeaderUnit};
+
+use crate::provider::{credentials, unrecognised, AuthProvider, Identity, Peer};
+
+/// Provider recognising a fixed set of opaque tokens, sent through `MTPAuth::ExternalToken` or
+/// the `Bearer` authorization scheme, such as the tokens of service accounts.
+///
+/// Only the SHA-256 digests of the tokens are kept: a token is looked up by its digest, so
+/// that neither the tokens nor the time taken to compare them leak.
+///
+/// # Fields
+///
+/// ~ `tokens`: Identity every token vouches for, by the digest of the token
+pub struct StaticTokens {
+     tokens: HashMap<Vec<u8>, Identity>,
+}
+
+impl StaticTokens {
+     /// Creates a provider recognising no token
+     pub fn new() -> Self {
+          Self { tokens: HashMap::new() }
+     }
+
+     /// Recognises `token` as vouching for `identity`
+     pub fn with_token(mut self, token: &str, identity: Identity) -> Self {
+          self.tokens.insert(digest(token), identity);
+          self
+     }
+}
+
+/// Default implementation for [StaticTokens]
+impl Default for StaticTokens {
+     fn default() -> Self {
+          Self::new()
+     }
+}
+
+impl AuthProvider for StaticTokens {
+     fn authenticate(&self, units: &[MTPHeaderUnit], _peer: &Peer) -> Result<Identity, ProtocolError> {
+          let token = credentials(units, |method| matches!(method, MTPAuth::ExternalToken | MTPAuth::Authorization { scheme: AuthSchemes::Bearer }))
+               .ok_or_else(|| unrecognised("No bearer token"))?;
+
+          self.tokens.get(&digest(token))
+               .cloned()
+               .ok_or_else(|| unrecognised("Unknown token"))
+     }
+}
+
+/// Computes the SHA-256 digest of `token`
+fn digest(token: &str) -> Vec<u8> {
+     Sha256::digest(token.as_bytes()).to_vec()
+}
@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+use std::time::SystemTime;
+
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use base64ct::{Base64, Encoding};
+
+use net::protocol::error::{Error, ProtocolError};
+use net::protocol::interface::{AuthSchemes, MTPAuth, MTPHeaderUnit};
+
+use crate::provider::{credentials, unrecognised, AuthProvider, Identity, Peer};
+
+/// Users read from a file, as last read.
+///
+/// # Fields
+///
+/// ~ `hashes`: Hash of the password of every user, by username
+/// ~ `modified`: Time the file was modified at and its length when it was read
+struct Users {
+     hashes: HashMap<String, String>,
+     modified: Option<(SystemTime, u64)>,
+}
+
+/// Provider authenticating users by username and password through the `Basic` authorization
+/// scheme, against the Argon2 hashes of their passwords read from a file.
+///
+/// The file holds a `<username>:<hash>` line per user, the hash being a PHC string. Blank lines
+/// and lines starting with `#` are ignored. It is read again whenever it is modified, the users
+/// read last being kept while it is invalid. Users the file does not list fall through to the
+/// next provider of a chain.
+///
+/// # Fields
+///
+/// ~ `path`: File the users are read from
+/// ~ `users`: Users as last read from the file
+pub struct FileUsers {
+     path: PathBuf,
+     users: Mutex<Users>,
+}
+
+impl FileUsers {
+     /// Creates a provider authenticating the users of the file at `path`
+     ///
+     /// # Errors
+     /// An [`io::Error`] if the file cannot be read, or of kind [`io::ErrorKind::InvalidData`]
+     /// if a line is not a username followed by a valid password hash
+     pub fn open(path: PathBuf) -> io::Result<Self> {
+          let provider = Self {
+               path,
+               users: Mutex::new(Users { hashes: HashMap::new(), modified: None }),
+          };
+          provider.refresh()?;
+
+          Ok(provider)
+     }
+
+     /// Reads the file again if it was modified since it was last read
+     ///
+     /// # Errors
+     /// An [`io::Error`] if the file cannot be read or is invalid, see [`FileUsers::open`]
+     pub fn refresh(&self) -> io::Result<()> {
+          let metadata = fs::metadata(&self.path)?;
+          let modified = (metadata.modified()?, metadata.len());
+          if self.lock().modified == Some(modified) {
+               return Ok(());
+          }
+
+          let mut hashes = HashMap::new();
+          for (number, line) in fs::read_to_string(&self.path)?.lines().enumerate() {
+               let line = line.trim();
+               if line.is_empty() || line.starts_with('#') {
+                    continue;
+               }
+               let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("Invalid user on line {} of {}", number + 1, self.path.display()));
+               let (username, hash) = line.split_once(':').ok_or_else(invalid)?;
+               PasswordHash::new(hash).map_err(|_| invalid())?;
+               hashes.insert(username.to_string(), hash.to_string());
+          }
+
+          let mut users = self.lock();
+          users.hashes = hashes;
+          users.modified = Some(modified);
+          Ok(())
+     }
+
+     /// Locks the users read from the file, recovering them if a thread panicked holding them
+     fn lock(&self) -> MutexGuard<'_, Users> {
+          self.users.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+     }
+}
+
+impl AuthProvider for FileUsers {
+     fn authenticate(&self, units: &[MTPHeaderUnit], _peer: &Peer) -> Result<Identity, ProtocolError> {
+          let credentials = credentials(units, |method| matches!(method, MTPAuth::Authorization { scheme: AuthSchemes::Basic }))
+               .ok_or_else(|| unrecognised("No Basic credentials"))?;
+          let (username, password) = basic(credentials)?;
+
+          // the users read last keep authenticating while the file is being rewritten
+          let _ = self.refresh();
+          let hash = self.lock().hashes.get(&username).cloned()
+               .ok_or_else(|| unrecognised("Unknown user"))?;
+
+          // the hashes are compared in constant time by the verifier
+          let verified = PasswordHash::new(&hash)
+               .is_ok_and(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok());
+          if !verified {
+               return Err(ProtocolError::Unauthorized101(Error::new("Invalid username or password".to_string())));
+          }
+          Ok(Identity::new(username))
+     }
+}
+
+/// Decodes the credentials of the `Basic` authorization scheme, `<username>:<password>` in
+/// base64
+///
+/// # Errors
+/// [`ProtocolError::BadRequest100`] if the credentials are not valid base64 or hold no colon
+fn basic(credentials: &str) -> Result<(String, String), ProtocolError> {
+     let malformed = || ProtocolError::BadRequest100(Error::new("Malformed Basic credentials".to_string()));
+
+     let decoded = Base64::decode_vec(credentials.trim()).map_err(|_| malformed())?;
+     let decoded = String::from_utf8(decoded).map_err(|_| malformed())?;
+     let (username, password) = decoded.split_once(':').ok_or_else(malformed)?;
+     Ok((username.to_string(), password.to_string()))
+}
@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::storage::codec::{Decoder, Encoder};
+use crate::storage::error::StorageError;
+use crate::storage::journal::Journal;
+
+/// Name of the journal the aliases are persisted to within the data directory
+const JOURNAL: &str = "aliases.journal";
+
+/// Former names of renamed queues, redirecting the requests of the clients still using them.
+///
+/// An alias lasts for the retention configured on the broker, after which its name is unknown
+/// again. When persistence is enabled the aliases are recorded in a journal, rewritten as a
+/// whole on every change since queues are seldom renamed.
+///
+/// # Fields
+///
+/// ~ `entries`: Name every alias redirects to along with the time it expires at, by alias
+/// ~ `journal`: Journal the aliases are persisted to, if any
+pub struct Aliases {
+     entries: HashMap<String, (String, SystemTime)>,
+     journal: Option<Journal>,
+}
+
+impl Aliases {
+     /// Creates an empty set of aliases that is not persisted
+     pub fn in_memory() -> Self {
+          Self {
+               entries: HashMap::new(),
+               journal: None,
+          }
+     }
+
+     /// Opens the aliases persisted in `dir`, restoring those that had not expired at `now`
+     pub fn open(dir: &Path, now: SystemTime) -> Result<Self, StorageError> {
+          let (journal, records) = Journal::open(&dir.join(JOURNAL))?;
+          let mut aliases = Self::in_memory();
+
+          for record in records {
+               let mut decoder = Decoder::new(&record);
+               aliases.entries.insert(decoder.get_str()?, (decoder.get_str()?, decoder.get_time()?));
+          }
+
+          aliases.journal = Some(journal);
+          aliases.prune(now)?;
+
+          Ok(aliases)
+     }
+
+     /// Retrieves the name `name` redirects to, if it is an alias that had not expired at `now`
+     pub fn resolve(&self, name: &str, now: SystemTime) -> Option<&str> {
+          self.entries.get(name)
+               .filter(|(_, until)| *until > now)
+               .map(|(target, _)| target.as_str())
+     }
+
+     /// Makes `alias` redirect to `target` until `until`. The aliases redirecting to `alias`
+     /// redirect to `target` as well, while an alias named `target` is removed.
+     pub fn insert(&mut self, alias: String, target: String, until: SystemTime) -> Result<(), StorageError> {
+          self.entries.remove(&target);
+          for (redirect, _) in self.entries.values_mut().filter(|(redirect, _)| *redirect == alias) {
+               *redirect = target.clone();
+          }
+          self.entries.insert(alias, (target, until));
+
+          self.persist()
+     }
+
+     /// Removes the alias `name`, once a queue is provisioned under that name
+     pub fn remove(&mut self, name: &str) -> Result<(), StorageError> {
+          if self.entries.remove(name).is_none() {
+               return Ok(());
+          }
+          self.persist()
+     }
+
+     /// Removes the aliases that expired at `now`
+     pub fn prune(&mut self, now: SystemTime) -> Result<(), StorageError> {
+          let count = self.entries.len();
+          self.entries.retain(|_, (_, until)| *until > now);
+          if self.entries.len() == count {
+               return Ok(());
+          }
+          self.persist()
+     }
+
+     /// Rewrites the journal with the current aliases
+     fn persist(&mut self) -> Result<(), StorageError> {
+          let journal = match self.journal.as_mut() {
+               Some(journal) => journal,
+               None => return Ok(()),
+          };
+
+          let records: Vec<Vec<u8>> = self.entries.iter().map(|(alias, (target, until))| {
+               let mut encoder = Encoder::new();
+               encoder.put_str(alias);
+               encoder.put_str(target);
+               encoder.put_time(*until);
+               encoder.into_bytes()
+          }).collect();
+          journal.rewrite(&records)
+     }
+}
+
+/// Default implementation for [Aliases]
+impl Default for Aliases {
+     fn default() -> Self {
+          Self::in_memory()
+     }
+}
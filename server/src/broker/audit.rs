@@ -0,0 +1,480 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+
+use net::protocol::error::ProtocolError;
+use net::protocol::interface::{AuthSchemes, MTPAuth, MTPManagerAction, QueueAccess, QueueRoles};
+
+use crate::storage;
+use crate::storage::error::StorageError;
+
+/// MAC the first entry of an audit log chains to
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// How the action of an [`AuditEntry`] ended
+///
+/// ## Variants
+///
+/// ~ `Pending`: The action is about to be applied, a later entry concluding it records how it ended
+/// ~ `Success`: The action was applied
+/// ~ `Failure`: The action failed with the error of the code
+enum Outcome {
+     Pending,
+     Success,
+     Failure(u32),
+}
+
+/// Clone implementation for [Outcome]
+impl Clone for Outcome {
+     fn clone(&self) -> Self {
+          match self {
+               Self::Pending => Self::Pending,
+               Self::Success => Self::Success,
+               Self::Failure(code) => Self::Failure(*code),
+          }
+     }
+}
+
+/// An event recorded in the [`AuditLog`]: who did what, from where, to what and how it ended.
+///
+/// # Fields
+///
+/// ~ `actor`: Identity of the client behind the event, or its address if it did not authenticate
+/// ~ `address`: Address the client is connected from, if the event comes from a session
+/// ~ `action`: What the client did, such as `authenticate`, `create` or `assign`
+/// ~ `target`: What the client did it to, such as a queue or an authentication method
+/// ~ `detail`: Parameters of the action, such as the new name of a renamed queue, if any
+/// ~ `outcome`: How the action ended, or whether it is about to be applied
+/// ~ `concludes`: Sequence number of the pending entry whose outcome the entry records, if any
+pub struct AuditEntry {
+     actor: String,
+     address: Option<SocketAddr>,
+     action: String,
+     target: String,
+     detail: Option<String>,
+     outcome: Outcome,
+     concludes: Option<u64>,
+}
+
+impl AuditEntry {
+     /// Creates an entry of `action` on `target` by `actor`, recording that the action is about
+     /// to be applied until its outcome is set
+     pub fn new(actor: String, address: Option<SocketAddr>, action: &str, target: String) -> Self {
+          Self {
+               actor,
+               address,
+               action: action.to_string(),
+               target,
+               detail: None,
+               outcome: Outcome::Pending,
+               concludes: None,
+          }
+     }
+
+     /// Records the parameters of the action
+     pub fn with_detail(mut self, detail: String) -> Self {
+          self.detail = Some(detail);
+          self
+     }
+
+     /// Records how the action ended, failing with the code of the error of `result`, if any
+     pub fn with_outcome<T>(mut self, result: &Result<T, ProtocolError>) -> Self {
+          self.outcome = match result {
+               Ok(_) => Outcome::Success,
+               Err(err) => Outcome::Failure(err.code()),
+          };
+          self
+     }
+
+     /// Records the action as applied, for the actions that cannot fail once recorded
+     pub fn with_success(mut self) -> Self {
+          self.outcome = Outcome::Success;
+          self
+     }
+
+     /// Records the entry as the conclusion of the pending entry numbered `intent`, if any
+     pub fn concluding(mut self, intent: Option<u64>) -> Self {
+          self.concludes = intent;
+          self
+     }
+}
+
+/// Clone implementation for [AuditEntry]
+impl Clone for AuditEntry {
+     fn clone(&self) -> Self {
+          Self {
+               actor: self.actor.clone(),
+               address: self.address,
+               action: self.action.clone(),
+               target: self.target.clone(),
+               detail: self.detail.clone(),
+               outcome: self.outcome.clone(),
+               concludes: self.concludes,
+          }
+     }
+}
+
+/// An append-only file of [`AuditEntry`] chained by MAC, so that altering, removing or
+/// reordering entries is detected, see [`verify`].
+///
+/// Every entry is a line holding the HMAC-SHA-256 of its body in hexadecimal, a space, then the
+/// body: a JSON object carrying the sequence number of the entry, the MAC of the previous
+/// entry, the time of the event in milliseconds since the epoch and the fields of the
+/// [`AuditEntry`]. Each line is flushed to disk before the event is answered.
+///
+/// The MACs are computed under a secret key kept outside the log, so that whoever can write to
+/// the log cannot forge entries or rebuild the chain after altering it without also holding
+/// the key.
+///
+/// An action changing the broker is recorded as pending before it is applied, so that it never
+/// takes effect without leaving a trace, then concluded by an entry recording how it ended and
+/// referring to the pending one. A pending entry left without a conclusion records an action
+/// whose outcome is unknown, such as one interrupted by a crash.
+///
+/// Chaining cannot tell a log whose last entries were cut off from a shorter one: the number of
+/// entries and the MAC of the last one, as returned by [`verify`], are meant to be recorded
+/// elsewhere as checkpoints.
+///
+/// # Fields
+///
+/// ~ `file`: The audit file, opened for appending
+/// ~ `key`: Secret key the entries are authenticated with
+/// ~ `count`: Number of entries in the log
+/// ~ `head`: MAC of the last entry, or [`GENESIS`] if the log is empty
+pub struct AuditLog {
+     file: File,
+     key: Vec<u8>,
+     count: u64,
+     head: String,
+}
+
+impl AuditLog {
+     /// Opens the audit log at `path` authenticated with `key`, creating it if it does not exist.
+     /// An existing log is verified first, new entries being chained to its last one. A last
+     /// entry torn by an unclean shutdown was never answered, and is cut off the log.
+     ///
+     /// # Errors
+     /// [`StorageError::Corrupted`] if the complete entries of the existing log do not verify:
+     /// the log is never rewritten, so it must be set aside to start a new one. A log
+     /// authenticated with another key does not verify either.
+     pub fn open(path: &Path, key: &[u8]) -> Result<Self, StorageError> {
+          let (count, head, size) = match path.exists() {
+               true => {
+                    let (_, size) = complete(path)?;
+                    let (count, head) = verify(path, key)?;
+                    (count, head, size)
+               },
+               false => (0, GENESIS.to_string(), 0),
+          };
+          let created = !path.exists();
+          let file = OpenOptions::new().create(true).append(true).open(path)?;
+          if created {
+               storage::sync_parent(path)?;
+          }
+          if file.metadata()?.len() > size {
+               // torn last entry
+               file.set_len(size)?;
+               file.sync_data()?;
+          }
+
+          Ok(Self { file, key: key.to_vec(), count, head })
+     }
+
+     /// Appends `entry` as happening at `now`, chained to the last entry, and flushes it to disk
+     ///
+     /// # Returns
+     /// The sequence number of the entry
+     pub fn append(&mut self, entry: AuditEntry, now: SystemTime) -> Result<u64, StorageError> {
+          let body = json!({
+               "seq": self.count + 1,
+               "prev": self.head,
+               "time": now.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64,
+               "actor": entry.actor,
+               "address": entry.address.map(|address| address.to_string()),
+               "action": entry.action,
+               "target": entry.target,
+               "detail": entry.detail,
+               "outcome": match entry.outcome {
+                    Outcome::Pending => "pending",
+                    Outcome::Success => "success",
+                    Outcome::Failure(_) => "failure",
+               },
+               "code": match entry.outcome {
+                    Outcome::Failure(code) => Some(code),
+                    _ => None,
+               },
+               "concludes": entry.concludes,
+          }).to_string();
+          let hash = digest(&self.key, &body);
+
+          self.file.write_all(format!("{} {}\n", hash, body).as_bytes())?;
+          self.file.sync_data()?;
+          self.count += 1;
+          self.head = hash;
+
+          Ok(self.count)
+     }
+}
+
+/// Verifies the audit log at `path` against `key`: every entry must carry the MAC of its body
+/// under `key`, chain to the entry before it and follow its sequence number. A last line torn
+/// by an unclean shutdown is not an entry and is left out.
+///
+/// # Returns
+/// The number of entries in the log and the MAC of the last one
+///
+/// # Errors
+/// [`StorageError::Corrupted`] naming the first complete line that does not verify
+pub fn verify(path: &Path, key: &[u8]) -> Result<(u64, String), StorageError> {
+     let (content, _) = complete(path)?;
+
+     let mut count = 0;
+     let mut head = GENESIS.to_string();
+     for (number, line) in content.lines().enumerate() {
+          let corrupted = |reason: &str| StorageError::Corrupted { message: format!("Audit log {} {} on line {}", path.display(), reason, number + 1) };
+
+          let (hash, body) = line.split_once(' ').ok_or_else(|| corrupted("is malformed"))?;
+          if digest(key, body) != hash {
+               return Err(corrupted("was altered"));
+          }
+          let body: Value = serde_json::from_str(body).map_err(|_| corrupted("is malformed"))?;
+          if body.get("prev").and_then(Value::as_str) != Some(head.as_str()) {
+               return Err(corrupted("breaks the chain"));
+          }
+          if body.get("seq").and_then(Value::as_u64) != Some(count + 1) {
+               return Err(corrupted("is out of sequence"));
+          }
+
+          count += 1;
+          head = hash.to_string();
+     }
+
+     Ok((count, head))
+}
+
+/// Reads the complete lines of the audit log at `path`, leaving out a last line torn by an
+/// unclean shutdown
+///
+/// # Returns
+/// The complete lines, along with the size of the file up to the end of the last one
+fn complete(path: &Path) -> Result<(String, u64), StorageError> {
+     let mut bytes = std::fs::read(path)?;
+     let size = bytes.iter().rposition(|byte| *byte == b'\n').map_or(0, |end| end + 1);
+     bytes.truncate(size);
+
+     let content = String::from_utf8(bytes)
+          .map_err(|_| StorageError::Corrupted { message: format!("Audit log {} is not valid UTF-8", path.display()) })?;
+     Ok((content, size as u64))
+}
+
+/// Names the authentication method `method` as recorded in the target of an `authenticate` entry
+pub fn method(method: &MTPAuth) -> &'static str {
+     match method {
+          MTPAuth::LocalToken => "local-token",
+          MTPAuth::ExternalToken => "external-token",
+          MTPAuth::Authorization { scheme: AuthSchemes::Bearer } => "bearer",
+          MTPAuth::Authorization { scheme: AuthSchemes::Basic } => "basic",
+          MTPAuth::Cookie => "cookie",
+     }
+}
+
+/// Describes the manager action `action` as recorded in the audit log
+///
+/// # Returns
+/// The name of the action along with its parameters, or `None` for the actions that only read
+/// the state of a queue and are not recorded
+pub fn action(action: &MTPManagerAction) -> Option<(&'static str, Option<String>)> {
+     match action {
+          MTPManagerAction::Rename(to) => Some(("rename", Some(format!("to={}", to)))),
+          MTPManagerAction::Authorize(client) => Some(("authorize", Some(format!("client={}", client)))),
+          MTPManagerAction::Reject(client) => Some(("reject", Some(format!("client={}", client)))),
+          MTPManagerAction::Dispose { client, ban, disconnect } => {
+               let ban = ban.map(|ban| format!(" ban={}s", ban.as_secs())).unwrap_or_default();
+               Some(("dispose", Some(format!("client={}{} disconnect={}", client, ban, disconnect))))
+          },
+          MTPManagerAction::AccessorModify(access) => Some(("modify-access", Some(format!("access={}", access_name(access))))),
+          MTPManagerAction::Assign(client, role) => Some(("assign", Some(format!("client={} role={}", client, role_name(role))))),
+          MTPManagerAction::Revoke(client) => Some(("revoke", Some(format!("client={}", client)))),
+          MTPManagerAction::Cancel(id) => Some(("cancel", Some(format!("id={}", id)))),
+          MTPManagerAction::Delete => Some(("delete", None)),
+          // the target of the entry names the user, the password is never recorded
+          MTPManagerAction::AddUser { .. } => Some(("add-user", None)),
+          MTPManagerAction::ChangePassword { .. } => Some(("change-password", None)),
+          MTPManagerAction::RemoveUser(_) => Some(("remove-user", None)),
+          MTPManagerAction::UnlockUser(_) => Some(("unlock-user", None)),
+          MTPManagerAction::Pending => None,
+     }
+}
+
+/// Names the access `access`
+fn access_name(access: &QueueAccess) -> &'static str {
+     match access {
+          QueueAccess::Public => "public",
+          QueueAccess::Private => "private",
+          QueueAccess::Protected => "protected",
+     }
+}
+
+/// Names the role `role`
+fn role_name(role: &QueueRoles) -> &'static str {
+     match role {
+          QueueRoles::Moderator => "moderator",
+          QueueRoles::Manager => "manager",
+          QueueRoles::Producer => "producer",
+          QueueRoles::Consumer => "consumer",
+          QueueRoles::Couple => "couple",
+     }
+}
+
+/// Computes the HMAC-SHA-256 of `body` under `key` in hexadecimal
+fn digest(key: &[u8], body: &str) -> String {
+     let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+     mac.update(body.as_bytes());
+     mac.finalize().into_bytes().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+     use std::fs;
+     use std::path::{Path, PathBuf};
+     use std::time::{Duration, UNIX_EPOCH};
+
+     use net::protocol::error::{Error, ProtocolError};
+
+     use super::{digest, verify, AuditEntry, AuditLog};
+     use crate::storage::error::StorageError;
+
+     const KEY: &[u8] = b"audit-key";
+
+     fn scratch(name: &str) -> PathBuf {
+          let dir = std::env::temp_dir().join(format!("audit-{}-{}", name, std::process::id()));
+          let _ = fs::remove_dir_all(&dir);
+          fs::create_dir_all(&dir).unwrap();
+          dir
+     }
+
+     /// Writes a log of `count` entries, each an intent concluded by the next
+     fn written(path: &Path, count: u64) {
+          let mut log = AuditLog::open(path, KEY).ok().unwrap();
+          for entry in 0..count {
+               let mut next = AuditEntry::new("alice".to_string(), None, "create", format!("queue-{}", entry));
+               if entry % 2 == 1 {
+                    next = next.with_outcome(&Err::<(), _>(ProtocolError::Conflict108(Error::new(String::new())))).concluding(Some(entry));
+               }
+               assert_eq!(log.append(next, UNIX_EPOCH + Duration::from_secs(entry)).ok().unwrap(), entry + 1);
+          }
+     }
+
+     /// Retrieves the message of the corruption `verify` reports, if it reports one
+     fn corruption(path: &Path) -> Option<String> {
+          corruption_under(path, KEY)
+     }
+
+     /// Retrieves the message of the corruption `verify` reports under `key`, if it reports one
+     fn corruption_under(path: &Path, key: &[u8]) -> Option<String> {
+          match verify(path, key) {
+               Err(StorageError::Corrupted { message }) => Some(message),
+               _ => None,
+          }
+     }
+
+     fn rewrite(path: &Path, change: impl FnOnce(&mut Vec<String>)) {
+          let mut lines: Vec<String> = fs::read_to_string(path).unwrap().lines().map(str::to_string).collect();
+          change(&mut lines);
+          fs::write(path, lines.iter().map(|line| format!("{}\n", line)).collect::<String>()).unwrap();
+     }
+
+     #[test]
+     fn intact_logs_verify_and_are_extended() {
+          let dir = scratch("intact");
+          let path = dir.join("audit.log");
+          written(&path, 4);
+
+          let (count, head) = verify(&path, KEY).ok().unwrap();
+          assert_eq!(count, 4);
+          let last = fs::read_to_string(&path).unwrap().lines().last().unwrap().to_string();
+          assert!(last.starts_with(&head));
+          assert!(last.contains(r#""concludes":3"#) && last.contains(r#""outcome":"failure""#));
+
+          let mut log = AuditLog::open(&path, KEY).ok().unwrap();
+          assert_eq!(log.append(AuditEntry::new("bob".to_string(), None, "delete", "orders".to_string()).with_success(), UNIX_EPOCH).ok().unwrap(), 5);
+          assert_eq!(verify(&path, KEY).ok().unwrap().0, 5);
+
+          fs::remove_dir_all(&dir).unwrap();
+     }
+
+     #[test]
+     fn tampered_logs_do_not_verify() {
+          let dir = scratch("tampered");
+          let path = dir.join("audit.log");
+
+          written(&path, 4);
+          rewrite(&path, |lines| lines[1] = lines[1].replace("alice", "mallory"));
+          assert!(corruption(&path).is_some_and(|message| message.contains("was altered on line 2")));
+
+          fs::remove_file(&path).unwrap();
+          written(&path, 4);
+          rewrite(&path, |lines| lines.swap(1, 2));
+          assert!(corruption(&path).is_some_and(|message| message.contains("breaks the chain on line 2")));
+
+          fs::remove_file(&path).unwrap();
+          written(&path, 4);
+          rewrite(&path, |lines| {
+               lines.remove(0);
+          });
+          assert!(corruption(&path).is_some_and(|message| message.contains("breaks the chain on line 1")));
+
+          fs::remove_dir_all(&dir).unwrap();
+     }
+
+     #[test]
+     fn torn_last_entries_are_cut_off_on_open() {
+          let dir = scratch("torn");
+          let path = dir.join("audit.log");
+          written(&path, 4);
+          let content = fs::read_to_string(&path).unwrap();
+          let intact = content.len() - content.lines().last().unwrap().len() - 1;
+          fs::write(&path, &content[..content.len() - 10]).unwrap();
+
+          // the torn entry is not reported as corruption, only left out
+          assert_eq!(verify(&path, KEY).ok().unwrap().0, 3);
+
+          let mut log = AuditLog::open(&path, KEY).ok().unwrap();
+          assert_eq!(fs::metadata(&path).unwrap().len(), intact as u64);
+          assert_eq!(log.append(AuditEntry::new("bob".to_string(), None, "delete", "orders".to_string()).with_success(), UNIX_EPOCH).ok().unwrap(), 4);
+          assert_eq!(verify(&path, KEY).ok().unwrap().0, 4);
+
+          // a complete line that does not verify still is
+          rewrite(&path, |lines| lines[3] = lines[3].replace("bob", "mallory"));
+          assert!(corruption(&path).is_some_and(|message| message.contains("was altered on line 4")));
+          assert!(AuditLog::open(&path, KEY).is_err());
+
+          fs::remove_dir_all(&dir).unwrap();
+     }
+
+     #[test]
+     fn logs_only_verify_under_their_key() {
+          let dir = scratch("keyed");
+          let path = dir.join("audit.log");
+          written(&path, 2);
+
+          assert!(corruption_under(&path, b"another-key").is_some_and(|message| message.contains("was altered on line 1")));
+          assert!(AuditLog::open(&path, b"another-key").is_err());
+
+          // an altered entry whose MAC is recomputed without the key still does not verify
+          rewrite(&path, |lines| {
+               let (_, body) = lines[1].split_once(' ').unwrap();
+               let body = body.replace("alice", "mallory");
+               lines[1] = format!("{} {}", digest(b"guessed-key", &body), body);
+          });
+          assert!(corruption(&path).is_some_and(|message| message.contains("was altered on line 2")));
+
+          fs::remove_dir_all(&dir).unwrap();
+     }
+}
@@ -0,0 +1,257 @@
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::time::Instant;
+
+use net::protocol::{MTPEnvelope, MTPHeaders, MTPMessage, MTPPayload, MTPResponse, MTPStorage};
+use net::protocol::error::{Error, ProtocolError};
+use net::protocol::interface::{MTPHeaderUnit, MTPStatusCode, MessageCategory, MessagePublish, MessageTransferProtocolResponse};
+
+use super::Broker;
+use super::session::SessionId;
+
+/// Connection of a client to a broker running in the same process.
+///
+/// The connection calls the broker directly rather than going through a socket, so it only
+/// serves clients embedded in the process hosting the broker. The session of the connection is closed when the connection is dropped, deleting its
+/// temporary queues.
+///
+/// # Fields
+///
+/// ~ `broker`: Broker the client is connected to
+/// ~ `session`: Session of the client within the broker
+/// ~ `inbox`: Frames pushed by the broker to the session
+/// ~ `pending`: Frames received while waiting for a response, handed out by [`Connection::recv`]
+///   before the ones of the inbox
+/// ~ `replies`: Temporary queue the responses to the requests of the client are published to,
+///   once the first request was sent
+/// ~ `requests`: Number of requests sent, used to correlate responses with requests
+pub struct Connection {
+     broker: Arc<Broker>,
+     session: SessionId,
+     inbox: UnboundedReceiver<MTPResponse>,
+     pending: VecDeque<MTPResponse>,
+     replies: Option<String>,
+     requests: u64,
+}
+
+impl Connection {
+     /// Opens a session with `broker` for a client connected from `address`
+     pub fn connect(broker: Arc<Broker>, address: SocketAddr) -> Self {
+          let (session, inbox) = broker.connect(address);
+          Self { broker, session, inbox, pending: VecDeque::new(), replies: None, requests: 0 }
+     }
+
+     /// Retrieves the session of the client within the broker
+     pub fn session(&self) -> SessionId {
+          self.session
+     }
+
+     /// Sends a request to the broker
+     ///
+     /// # Errors
+     /// The [`ProtocolError`] the broker answered the request with
+     pub async fn send(&self, payload: MTPPayload) -> Result<MTPResponse, ProtocolError> {
+          let response = self.broker.handle(self.session, payload).await;
+          match response.get_status_code() {
+               MTPStatusCode::Success0 => Ok(response),
+               MTPStatusCode::Error1(err) => Err(err),
+          }
+     }
+
+     /// Receives the next frame the broker pushed to the session, waiting for one to arrive.
+     /// The frames that arrived while a request waited for its response come first.
+     pub async fn recv(&mut self) -> Option<MTPResponse> {
+          match self.pending.pop_front() {
+               Some(frame) => Some(frame),
+               None => self.inbox.recv().await,
+          }
+     }
+
+     /// Publishes `message` to `queue` as a request and waits for its response.
+     ///
+     /// The message is published in the [`MessageCategory::REQUEST`] category along with the
+     /// temporary queue of the session to reply to and an identifier correlating the response
+     /// with the request. The response is acknowledged once received, while responses to earlier
+     /// requests that arrive late are acknowledged and dropped. The other frames pushed to the
+     /// session in the meantime are kept for [`Connection::recv`].
+     ///
+     /// # Errors
+     /// - [`ProtocolError::RequestTimeout107`] if no response arrived within `timeout`
+     /// - The [`ProtocolError`] the broker answered the request with
+     pub async fn request(&mut self, queue: &str, message: MTPMessage, timeout: Duration) -> Result<MTPEnvelope, ProtocolError> {
+          let deadline = Instant::now() + timeout;
+          let replies = self.replies().await?;
+          self.requests += 1;
+          let correlation = format!("{}-{}", self.session, self.requests);
+
+          let units = vec![
+               MTPHeaderUnit::MessagePublish { queue: queue.to_string(), to: message.publish().clone() },
+               MTPHeaderUnit::ReplyTo { queue: replies.clone() },
+               MTPHeaderUnit::Correlation { id: correlation.clone() },
+          ];
+          let request = MTPMessage::new(
+               message.content_type().clone(),
+               message.priority().clone(),
+               MessageCategory::REQUEST,
+               message.publish().clone(),
+               message.message().to_string(),
+          );
+          self.send(MTPPayload::publish(headers(units), Some(request))).await?;
+
+          loop {
+               let frame = match tokio::time::timeout_at(deadline, self.inbox.recv()).await {
+                    Ok(Some(frame)) => frame,
+                    Ok(None) => return Err(ProtocolError::Gone109(Error::new("Session was closed by the broker".to_string()))),
+                    Err(_) => {
+                         return Err(ProtocolError::RequestTimeout107(Error::new(format!("No response to request {} within {}ms", correlation, timeout.as_millis()))));
+                    },
+               };
+
+               let envelopes = frame.get_messages();
+               let replied = !envelopes.is_empty() && envelopes.iter().all(|envelope| delivery(envelope).is_some_and(|(queue, ..)| queue == replies));
+               if !replied {
+                    self.pending.push_back(frame);
+                    continue;
+               }
+
+               for envelope in envelopes {
+                    let Some((delivered, id, offset)) = delivery(&envelope) else {
+                         continue;
+                    };
+                    self.acknowledge(delivered, id, offset).await?;
+                    if correlation_of(&envelope).as_deref() == Some(correlation.as_str()) {
+                         return Ok(envelope);
+                    }
+               }
+          }
+     }
+
+     /// Answers a request received from another client, publishing `message` to the queue the
+     /// request asked to be replied to, in the [`MessageCategory::RESPONSE`] category
+     ///
+     /// # Errors
+     /// - [`ProtocolError::BadRequest100`] if the request carries no `ReplyTo` unit
+     /// - The [`ProtocolError`] the broker answered the response with
+     pub async fn reply(&self, request: &MTPEnvelope, message: MTPMessage) -> Result<MTPResponse, ProtocolError> {
+          let queue = request.headers().units().iter().find_map(|unit| match unit {
+               MTPHeaderUnit::ReplyTo { queue } => Some(queue.clone()),
+               _ => None,
+          }).ok_or_else(|| ProtocolError::BadRequest100(Error::new("Request without a ReplyTo header".to_string())))?;
+
+          let mut units = vec![MTPHeaderUnit::MessagePublish { queue, to: MessagePublish::ALL }];
+          if let Some(id) = correlation_of(request) {
+               units.push(MTPHeaderUnit::Correlation { id });
+          }
+          let response = MTPMessage::new(
+               message.content_type().clone(),
+               message.priority().clone(),
+               MessageCategory::RESPONSE,
+               message.publish().clone(),
+               message.message().to_string(),
+          );
+
+          self.send(MTPPayload::publish(headers(units), Some(response))).await
+     }
+
+     /// Retrieves the temporary queue responses are published to, provisioning and subscribing
+     /// to it on the first request
+     async fn replies(&mut self) -> Result<String, ProtocolError> {
+          if let Some(queue) = &self.replies {
+               return Ok(queue.clone());
+          }
+
+          let response = self.send(MTPPayload::manage(headers(vec![MTPHeaderUnit::TemporaryQueue]), None)).await?;
+          let queue = response.get_storage()
+               .and_then(|storage| storage.get("queue").map(str::to_string))
+               .ok_or_else(|| ProtocolError::InternalServerError120(Error::new("Broker did not name the temporary queue".to_string())))?;
+          self.send(MTPPayload::subscribe(headers(vec![MTPHeaderUnit::Subscription { queue: queue.clone() }]), None)).await?;
+
+          self.replies = Some(queue.clone());
+          Ok(queue)
+     }
+
+     /// Acknowledges the message `id` delivered from `queue` at `offset`
+     async fn acknowledge(&self, queue: String, id: String, offset: u64) -> Result<MTPResponse, ProtocolError> {
+          self.send(MTPPayload::acknowledge(headers(vec![MTPHeaderUnit::Delivery { queue, id, offset }]), None)).await
+     }
+}
+
+/// Closes the session of the connection
+impl Drop for Connection {
+     fn drop(&mut self) {
+          self.broker.disconnect(self.session);
+     }
+}
+
+/// Builds headers out of `units`, timestamped now
+fn headers(units: Vec<MTPHeaderUnit>) -> MTPHeaders {
+     MTPHeaders::new(units, MTPStorage::new(Vec::new()), Some(SystemTime::now()))
+}
+
+/// Retrieves the queue a message was delivered from, its id and its offset in the queue
+fn delivery(envelope: &MTPEnvelope) -> Option<(String, String, u64)> {
+     envelope.headers().units().iter().find_map(|unit| match unit {
+          MTPHeaderUnit::Delivery { queue, id, offset } => Some((queue.clone(), id.clone(), *offset)),
+          _ => None,
+     })
+}
+
+/// Retrieves the identifier correlating a request with its response
+fn correlation_of(envelope: &MTPEnvelope) -> Option<String> {
+     envelope.headers().units().iter().find_map(|unit| match unit {
+          MTPHeaderUnit::Correlation { id } => Some(id.clone()),
+          _ => None,
+     })
+}
+
+#[cfg(test)]
+mod tests {
+     use std::sync::Arc;
+     use std::time::Duration;
+
+     use net::protocol::{MTPMessage, MTPPayload};
+     use net::protocol::interface::{ContentType, MTPHeaderUnit, MessageCategory, MessagePriority, MessagePublish, MessageTransferProtocolResponse};
+
+     use super::{delivery, headers, Connection};
+     use crate::broker::{Broker, BrokerConfig};
+     use crate::broker::queue::QueueConfig;
+
+     fn message(content: &str) -> MTPMessage {
+          MTPMessage::new(ContentType::JSON, MessagePriority::Low, MessageCategory::EVENT, MessagePublish::ALL, content.to_string())
+     }
+
+     fn subscribe(queue: &str) -> MTPPayload {
+          MTPPayload::subscribe(headers(vec![MTPHeaderUnit::Subscription { queue: queue.to_string() }]), None)
+     }
+
+     #[tokio::test]
+     async fn frames_received_while_waiting_for_a_response_are_kept() {
+          let broker = Arc::new(Broker::new(BrokerConfig::default()).ok().unwrap());
+          broker.declare("service".to_string(), QueueConfig::default()).ok().unwrap();
+          broker.declare("events".to_string(), QueueConfig::default()).ok().unwrap();
+
+          let mut requester = Connection::connect(Arc::clone(&broker), "127.0.0.1:1".parse().unwrap());
+          requester.send(subscribe("events")).await.ok().unwrap();
+          let mut responder = Connection::connect(Arc::clone(&broker), "127.0.0.1:2".parse().unwrap());
+          responder.send(subscribe("service")).await.ok().unwrap();
+
+          let answering = tokio::spawn(async move {
+               let request = responder.recv().await.unwrap().get_messages().remove(0);
+               let event = MTPPayload::publish(headers(vec![MTPHeaderUnit::MessagePublish { queue: "events".to_string(), to: MessagePublish::ALL }]), Some(message("event")));
+               responder.send(event).await.ok().unwrap();
+               responder.reply(&request, message("response")).await.ok().unwrap();
+          });
+
+          let response = requester.request("service", message("request"), Duration::from_secs(5)).await.ok().unwrap();
+          assert_eq!(response.message().message(), "response");
+          answering.await.unwrap();
+
+          let frame = tokio::time::timeout(Duration::from_secs(1), requester.recv()).await.ok().flatten().unwrap();
+          let envelopes = frame.get_messages();
+          assert_eq!(delivery(&envelopes[0]).map(|(queue, ..)| queue).as_deref(), Some("events"));
+     }
+}
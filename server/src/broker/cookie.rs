@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+
+use base64ct::{Base64UrlUnpadded, Encoding};
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+
+use super::session::SessionId;
+
+/// Number of random bytes a cookie is made of
+const COOKIE_BYTES: usize = 32;
+
+/// Cookies issued to authenticated sessions, letting their clients resume them from another
+/// connection through `MTPAuth::Cookie` without authenticating again.
+///
+/// A session holds at most one cookie, replaced whenever its client authenticates again, and a
+/// cookie resumes its session once. Only the SHA-256 digests of the cookies are kept, so that
+/// they are looked up without comparing them byte by byte. Cookies are not persisted: they do
+/// not outlive the sessions they resume.
+///
+/// # Fields
+///
+/// ~ `cookies`: Session every cookie resumes, by the digest of the cookie
+/// ~ `issued`: Digest of the cookie of every session holding one, by session
+pub struct CookieJar {
+     cookies: HashMap<Vec<u8>, SessionId>,
+     issued: HashMap<SessionId, Vec<u8>>,
+}
+
+impl CookieJar {
+     /// Creates a jar holding no cookie
+     pub fn new() -> Self {
+          Self {
+               cookies: HashMap::new(),
+               issued: HashMap::new(),
+          }
+     }
+
+     /// Issues a cookie resuming `session`, replacing the one it held
+     ///
+     /// # Returns
+     /// The cookie, as sent to the client
+     pub fn issue(&mut self, session: SessionId) -> String {
+          let mut bytes = [0u8; COOKIE_BYTES];
+          OsRng.fill_bytes(&mut bytes);
+          let cookie = Base64UrlUnpadded::encode_string(&bytes);
+
+          self.forget(session);
+          let digest = digest(&cookie);
+          self.cookies.insert(digest.clone(), session);
+          self.issued.insert(session, digest);
+
+          cookie
+     }
+
+     /// Checks whether `session` holds a cookie
+     pub fn contains(&self, session: SessionId) -> bool {
+          self.issued.contains_key(&session)
+     }
+
+     /// Takes the cookie `cookie` out of the jar, as it is used up resuming its session
+     ///
+     /// # Returns
+     /// The session the cookie resumes, or `None` if it was not issued or was used up
+     pub fn take(&mut self, cookie: &str) -> Option<SessionId> {
+          let session = self.cookies.remove(&digest(cookie))?;
+          self.issued.remove(&session);
+          Some(session)
+     }
+
+     /// Forgets the cookie of `session`, if it holds one
+     pub fn forget(&mut self, session: SessionId) {
+          if let Some(digest) = self.issued.remove(&session) {
+               self.cookies.remove(&digest);
+          }
+     }
+}
+
+/// Default implementation for [CookieJar]
+impl Default for CookieJar {
+     fn default() -> Self {
+          Self::new()
+     }
+}
+
+/// Computes the SHA-256 digest of `cookie`
+fn digest(cookie: &str) -> Vec<u8> {
+     Sha256::digest(cookie.as_bytes()).to_vec()
+}
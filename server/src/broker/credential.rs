@@ -0,0 +1,347 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::{Duration, SystemTime};
+
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::password_hash::SaltString;
+use base64ct::{Base64, Encoding};
+use rand_core::OsRng;
+
+use net::protocol::error::{Error, ProtocolError};
+use net::protocol::interface::{AuthSchemes, MTPAuth, MTPHeaderUnit};
+use security_gateway::provider::{credentials, unrecognised, AuthProvider, Identity, Peer};
+
+use crate::storage;
+use crate::storage::error::StorageError;
+
+/// Failed attempts of a client to authenticate as a user.
+///
+/// # Fields
+///
+/// ~ `count`: Number of consecutive failed attempts since the last success or lockout, the
+///   attempts in progress counting as failed until they succeed
+/// ~ `last`: Time of the last failed attempt
+/// ~ `locked`: Time the lockout of the user ends at, if it is locked out
+struct Failures {
+     count: u32,
+     last: SystemTime,
+     locked: Option<SystemTime>,
+}
+
+/// Users clients authenticate as through the `Basic` authorization scheme, along with the
+/// Argon2 hashes of their passwords.
+///
+/// The store is read from a file holding a `<username>:<hash>` line per user, the hash being a
+/// PHC string such as produced by [`hash`]. Blank lines and lines starting with `#` are ignored.
+/// The file is rewritten as a whole whenever a user is added, removed or changes password.
+///
+/// A user is locked out for a while after too many consecutive failed attempts, unknown
+/// usernames included so that the lockout does not reveal which users exist. Passwords are
+/// verified against a decoy hash for unknown usernames for the same reason. The failed attempts
+/// are locked on their own, so that users authenticate concurrently. An attempt is counted as
+/// failed as soon as it starts, so that attempts made in parallel cannot outnumber the failures
+/// allowed.
+///
+/// # Fields
+///
+/// ~ `users`: Hash of the password of every user, by username
+/// ~ `failures`: Failed attempts to authenticate, by username
+/// ~ `decoy`: Hash passwords are verified against when the username is unknown, computed on first use
+/// ~ `max_failures`: Number of consecutive failed attempts locking a user out
+/// ~ `lockout`: How long a user stays locked out
+/// ~ `path`: File the store is persisted to, if any
+pub struct CredentialStore {
+     users: BTreeMap<String, String>,
+     failures: Mutex<HashMap<String, Failures>>,
+     decoy: OnceLock<String>,
+     max_failures: u32,
+     lockout: Duration,
+     path: Option<PathBuf>,
+}
+
+impl CredentialStore {
+     /// Creates a store without users that is not persisted
+     ///
+     /// # Arguments
+     /// * `max_failures`: Number of consecutive failed attempts locking a user out
+     /// * `lockout`: How long a user stays locked out
+     pub fn in_memory(max_failures: u32, lockout: Duration) -> Self {
+          Self {
+               users: BTreeMap::new(),
+               failures: Mutex::new(HashMap::new()),
+               decoy: OnceLock::new(),
+               max_failures,
+               lockout,
+               path: None,
+          }
+     }
+
+     /// Loads the store from the file at `path`, created on the first change if it does not exist
+     ///
+     /// # Errors
+     /// [`StorageError::Corrupted`] if a line of the file is not a username followed by a valid
+     /// password hash
+     pub fn load(path: &Path, max_failures: u32, lockout: Duration) -> Result<Self, StorageError> {
+          let mut store = Self::in_memory(max_failures, lockout);
+
+          if path.exists() {
+               for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+                    let line = line.trim();
+                    if line.is_empty() || line.starts_with('#') {
+                         continue;
+                    }
+                    let corrupted = || StorageError::Corrupted { message: format!("Invalid credential on line {} of {}", number + 1, path.display()) };
+                    let (username, hash) = line.split_once(':').ok_or_else(corrupted)?;
+                    PasswordHash::new(hash).map_err(|_| corrupted())?;
+                    store.users.insert(username.to_string(), hash.to_string());
+               }
+          }
+
+          store.path = Some(path.to_path_buf());
+          Ok(store)
+     }
+
+     /// Checks whether the user `username` exists
+     pub fn contains(&self, username: &str) -> bool {
+          self.users.contains_key(username)
+     }
+
+     /// Retrieves the usernames of the users, in alphabetical order
+     pub fn usernames(&self) -> Vec<String> {
+          self.users.keys().cloned().collect()
+     }
+
+     /// Starts an attempt to authenticate as `username` at `now`, counted as failed until its
+     /// outcome is recorded with [`CredentialStore::record`]
+     ///
+     /// # Returns
+     /// The hash the password must be verified against, along with whether the user exists.
+     /// The hash is a decoy for an unknown user.
+     ///
+     /// # Errors
+     /// [`ProtocolError::TooManyRequests114`] if the user is locked out, or as many attempts as
+     /// lock it out failed or are in progress
+     pub fn attempt(&self, username: &str, now: SystemTime) -> Result<(String, bool), ProtocolError> {
+          {
+               let mut failures = self.failures.lock().unwrap_or_else(PoisonError::into_inner);
+               let failures = failures.entry(username.to_string())
+                    .or_insert(Failures { count: 0, last: now, locked: None });
+               if let Some(until) = failures.locked.filter(|until| *until > now) {
+                    let seconds = until.duration_since(now).unwrap_or_default().as_secs();
+                    return Err(ProtocolError::TooManyRequests114(Error::new(format!("User {} is locked out for {}s", username, seconds))));
+               }
+               if failures.count >= self.max_failures {
+                    return Err(ProtocolError::TooManyRequests114(Error::new(format!("Too many attempts to authenticate as user {}", username))));
+               }
+               failures.count += 1;
+               failures.last = now;
+          }
+
+          match self.users.get(username) {
+               Some(hash) => Ok((hash.clone(), true)),
+               None => Ok((self.decoy.get_or_init(|| hash("").unwrap_or_default()).clone(), false)),
+          }
+     }
+
+     /// Records the outcome of an attempt to authenticate as `username` at `now`, started with
+     /// [`CredentialStore::attempt`], locking the user out once it failed too many times in a row
+     ///
+     /// # Errors
+     /// - [`ProtocolError::Unauthorized101`] if the attempt failed
+     /// - [`ProtocolError::TooManyRequests114`] if the failed attempt locked the user out
+     pub fn record(&self, username: &str, succeeded: bool, now: SystemTime) -> Result<(), ProtocolError> {
+          let mut failures = self.failures.lock().unwrap_or_else(PoisonError::into_inner);
+          if succeeded {
+               failures.remove(username);
+               return Ok(());
+          }
+
+          // the attempt was counted when it started
+          let failures = failures.entry(username.to_string())
+               .or_insert(Failures { count: 0, last: now, locked: None });
+          failures.last = now;
+          if failures.count < self.max_failures || failures.locked.is_some_and(|until| until > now) {
+               return Err(ProtocolError::Unauthorized101(Error::new("Invalid username or password".to_string())));
+          }
+
+          failures.count = 0;
+          failures.locked = Some(now + self.lockout);
+          Err(ProtocolError::TooManyRequests114(Error::new(format!("User {} is locked out for {}s", username, self.lockout.as_secs()))))
+     }
+
+     /// Lifts the lockout of the user `username` and forgets its failed attempts
+     ///
+     /// # Returns
+     /// `false` if the user had no failed attempt recorded
+     pub fn unlock(&mut self, username: &str) -> bool {
+          self.failures.get_mut().unwrap_or_else(PoisonError::into_inner).remove(username).is_some()
+     }
+
+     /// Forgets the failed attempts older than the lockout at `now` that did not lock their user
+     /// out, and the lockouts that ended
+     pub fn prune(&mut self, now: SystemTime) {
+          let lockout = self.lockout;
+          self.failures.get_mut().unwrap_or_else(PoisonError::into_inner).retain(|_, failures| match failures.locked {
+               Some(until) => until > now,
+               None => failures.last + lockout > now,
+          });
+     }
+
+     /// Adds the user `username` with the password hashed as `hash`, or changes its password
+     /// if it exists
+     ///
+     /// # Errors
+     /// [`ProtocolError::BadRequest100`] if the username is empty or holds a colon or whitespace
+     pub fn insert(&mut self, username: String, hash: String) -> Result<(), ProtocolError> {
+          if username.is_empty() || username.contains(|c: char| c == ':' || c.is_whitespace()) {
+               return Err(ProtocolError::BadRequest100(Error::new(format!("Invalid username {:?}", username))));
+          }
+          self.users.insert(username, hash);
+          Ok(self.persist()?)
+     }
+
+     /// Removes the user `username`
+     ///
+     /// # Returns
+     /// `false` if the user did not exist
+     pub fn remove(&mut self, username: &str) -> Result<bool, StorageError> {
+          if self.users.remove(username).is_none() {
+               return Ok(false);
+          }
+          self.failures.get_mut().unwrap_or_else(PoisonError::into_inner).remove(username);
+          self.persist()?;
+          Ok(true)
+     }
+
+     /// Rewrites the file with the current users
+     fn persist(&self) -> Result<(), StorageError> {
+          let path = match &self.path {
+               Some(path) => path,
+               None => return Ok(()),
+          };
+
+          let temp = path.with_extension("tmp");
+          {
+               let mut file = File::create(&temp)?;
+               for (username, hash) in &self.users {
+                    writeln!(file, "{}:{}", username, hash)?;
+               }
+               file.sync_all()?;
+          }
+          fs::rename(&temp, path)?;
+          storage::sync_parent(path)?;
+
+          Ok(())
+     }
+}
+
+/// Authenticates the users sending their username and password through the `Basic`
+/// authorization scheme, verifying the password outside of the lock of the failed attempts
+impl AuthProvider for CredentialStore {
+     fn authenticate(&self, units: &[MTPHeaderUnit], _peer: &Peer) -> Result<Identity, ProtocolError> {
+          let credentials = credentials(units, |method| matches!(method, MTPAuth::Authorization { scheme: AuthSchemes::Basic }))
+               .ok_or_else(|| unrecognised("No Basic credentials"))?;
+          let (username, password) = basic(credentials)?;
+
+          let now = SystemTime::now();
+          let (hash, known) = self.attempt(&username, now)?;
+          let verified = verify(&hash, &password) && known;
+          self.record(&username, verified, now)?;
+          Ok(Identity::new(username))
+     }
+}
+
+/// Hashes `password` with Argon2 under a random salt
+///
+/// # Returns
+/// The hash as a PHC string, recording the parameters and the salt it was computed with
+pub fn hash(password: &str) -> Result<String, StorageError> {
+     let salt = SaltString::generate(&mut OsRng);
+     Argon2::default().hash_password(password.as_bytes(), &salt)
+          .map(|hash| hash.to_string())
+          .map_err(|err| StorageError::Corrupted { message: format!("Failed to hash password: {}", err) })
+}
+
+/// Verifies `password` against the PHC string `hash`, comparing the hashes in constant time
+pub fn verify(hash: &str, password: &str) -> bool {
+     PasswordHash::new(hash)
+          .is_ok_and(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+}
+
+/// Decodes the credentials of the `Basic` authorization scheme, `<username>:<password>` in
+/// base64
+///
+/// # Returns
+/// The username and the password
+///
+/// # Errors
+/// [`ProtocolError::BadRequest100`] if the credentials are not valid base64 or hold no colon
+pub fn basic(credentials: &str) -> Result<(String, String), ProtocolError> {
+     let malformed = || ProtocolError::BadRequest100(Error::new("Malformed Basic credentials".to_string()));
+
+     let decoded = Base64::decode_vec(credentials.trim()).map_err(|_| malformed())?;
+     let decoded = String::from_utf8(decoded).map_err(|_| malformed())?;
+     let (username, password) = decoded.split_once(':').ok_or_else(malformed)?;
+     Ok((username.to_string(), password.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+     use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+     use net::protocol::error::ProtocolError;
+
+     use super::CredentialStore;
+
+     fn at(secs: u64) -> SystemTime {
+          UNIX_EPOCH + Duration::from_secs(secs)
+     }
+
+     fn store() -> CredentialStore {
+          let mut store = CredentialStore::in_memory(3, Duration::from_secs(60));
+          store.insert("alice".to_string(), "hash".to_string()).ok().unwrap();
+          store
+     }
+
+     #[test]
+     fn attempts_in_progress_count_as_failures() {
+          let store = store();
+          for _ in 0..3 {
+               assert_eq!(store.attempt("alice", at(0)).ok().unwrap(), ("hash".to_string(), true));
+          }
+          assert!(matches!(store.attempt("alice", at(0)), Err(ProtocolError::TooManyRequests114(_))));
+
+          // the first failure to be recorded locks the user out, the others are plain failures
+          assert!(matches!(store.record("alice", false, at(1)), Err(ProtocolError::TooManyRequests114(_))));
+          assert!(matches!(store.record("alice", false, at(1)), Err(ProtocolError::Unauthorized101(_))));
+          assert!(matches!(store.record("alice", false, at(1)), Err(ProtocolError::Unauthorized101(_))));
+          assert!(matches!(store.attempt("alice", at(60)), Err(ProtocolError::TooManyRequests114(_))));
+
+          // the lockout ends with a clean count
+          for _ in 0..3 {
+               store.attempt("alice", at(61)).ok().unwrap();
+          }
+          assert!(matches!(store.attempt("alice", at(61)), Err(ProtocolError::TooManyRequests114(_))));
+     }
+
+     #[test]
+     fn successes_reset_the_failures() {
+          let mut store = store();
+          for _ in 0..2 {
+               store.attempt("alice", at(0)).ok().unwrap();
+               assert!(matches!(store.record("alice", false, at(0)), Err(ProtocolError::Unauthorized101(_))));
+          }
+          store.attempt("alice", at(0)).ok().unwrap();
+          store.record("alice", true, at(0)).ok().unwrap();
+          for _ in 0..3 {
+               store.attempt("alice", at(0)).ok().unwrap();
+          }
+
+          assert!(store.unlock("alice"));
+          assert!(!store.unlock("alice"));
+          store.attempt("alice", at(0)).ok().unwrap();
+     }
+}
@@ -0,0 +1,181 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use crate::storage::codec::{Decoder, Encoder};
+use crate::storage::error::StorageError;
+use crate::storage::journal::Journal;
+
+/// Name of the journal the deduplication window of a durable queue is persisted to
+const JOURNAL: &str = "dedup.journal";
+
+/// Number of records the journal may hold beyond the live window before it is rewritten
+const SLACK: usize = 1024;
+
+/// Window of the message ids recently published to a queue, used to turn the retries of a
+/// publisher into duplicates rather than new messages.
+///
+/// Ids leave the window once it grows past the count configured for the queue or once they
+/// are older than its configured duration, whichever comes first. On a durable queue every id
+/// entering the window is recorded in a journal so that retries are still recognised after a
+/// restart.
+///
+/// # Fields
+///
+/// ~ `entries`: Ids in the window in publishing order, with the time they were published at
+///   and the offset they were published at
+/// ~ `ids`: Offsets the ids in the window were published at, by id
+/// ~ `journal`: Journal the window is persisted to, if any
+/// ~ `records`: Number of records held by the journal
+pub struct DedupWindow {
+     entries: VecDeque<(String, SystemTime, u64)>,
+     ids: HashMap<String, u64>,
+     journal: Option<Journal>,
+     records: usize,
+}
+
+impl DedupWindow {
+     /// Creates an empty window that is not persisted
+     pub fn in_memory() -> Self {
+          Self {
+               entries: VecDeque::new(),
+               ids: HashMap::new(),
+               journal: None,
+               records: 0,
+          }
+     }
+
+     /// Opens the window persisted in `dir`, restoring the ids it held
+     pub fn open(dir: &Path) -> Result<Self, StorageError> {
+          let (journal, records) = Journal::open(&dir.join(JOURNAL))?;
+          let mut window = Self::in_memory();
+
+          for record in records {
+               let mut decoder = Decoder::new(&record);
+               window.push(decoder.get_str()?, decoder.get_time()?, decoder.get_u64()?);
+          }
+
+          window.journal = Some(journal);
+          window.compact()?;
+
+          Ok(window)
+     }
+
+     /// Number of ids in the window
+     pub fn len(&self) -> usize {
+          self.entries.len()
+     }
+
+     /// Checks whether the window holds no id
+     pub fn is_empty(&self) -> bool {
+          self.entries.is_empty()
+     }
+
+     /// Retrieves the offset `id` was published at, if it is in the window
+     pub fn get(&self, id: &str) -> Option<u64> {
+          self.ids.get(id).copied()
+     }
+
+     /// Adds `id`, published at `offset` at `at`, to the window. The id is only guaranteed to
+     /// be on disk once the window is flushed with [`DedupWindow::sync`].
+     ///
+     /// # Errors
+     /// A [`StorageError`] if the id could not be recorded, in which case it is only part of
+     /// the window until the broker restarts
+     pub fn insert(&mut self, id: String, at: SystemTime, offset: u64) -> Result<(), StorageError> {
+          let recorded = match self.journal.as_mut() {
+               Some(journal) => journal.append(&record(&id, at, offset)),
+               None => Ok(()),
+          };
+          if recorded.is_ok() && self.journal.is_some() {
+               self.records += 1;
+          }
+          self.push(id, at, offset);
+
+          recorded
+     }
+
+     /// Removes the ids published before `now - duration` and the oldest ids past `count`.
+     /// The journal is rewritten once it holds too many records of ids that left the window.
+     pub fn prune(&mut self, now: SystemTime, duration: Option<Duration>, count: Option<usize>) -> Result<(), StorageError> {
+          let before = duration.and_then(|duration| now.checked_sub(duration));
+
+          while let Some((id, at, offset)) = self.entries.front() {
+               let aged = before.is_some_and(|before| *at < before);
+               let overflowing = count.is_some_and(|count| self.entries.len() > count);
+               if !(aged || overflowing) {
+                    break;
+               }
+
+               if self.ids.get(id) == Some(offset) {
+                    self.ids.remove(id);
+               }
+               self.entries.pop_front();
+          }
+
+          if self.records > self.entries.len() * 2 + SLACK {
+               self.compact()?;
+          }
+
+          Ok(())
+     }
+
+     /// Removes `id`, published at `offset`, from the window once its message is withdrawn. The
+     /// journal is rewritten without it.
+     pub fn remove(&mut self, id: &str, offset: u64) -> Result<(), StorageError> {
+          if self.ids.get(id) == Some(&offset) {
+               self.ids.remove(id);
+          }
+          self.entries.retain(|(_, _, published)| *published != offset);
+
+          self.compact()
+     }
+
+     /// Checks whether ids entered the window since it was last flushed to disk
+     pub fn is_dirty(&self) -> bool {
+          self.journal.as_ref().is_some_and(Journal::is_dirty)
+     }
+
+     /// Flushes the ids that entered the window to disk
+     pub fn sync(&mut self) -> Result<(), StorageError> {
+          match self.journal.as_mut() {
+               Some(journal) => journal.sync(),
+               None => Ok(()),
+          }
+     }
+
+     /// Hands out a handle to flush the ids that entered the window to disk without holding on
+     /// to the window, see [`Journal::detach_sync`]
+     pub fn detach_sync(&mut self) -> Result<Option<File>, StorageError> {
+          match self.journal.as_mut() {
+               Some(journal) => journal.detach_sync(),
+               None => Ok(None),
+          }
+     }
+
+     /// Adds an id to the window without recording it
+     fn push(&mut self, id: String, at: SystemTime, offset: u64) {
+          self.ids.insert(id.clone(), offset);
+          self.entries.push_back((id, at, offset));
+     }
+
+     /// Rewrites the journal with only the ids in the window
+     fn compact(&mut self) -> Result<(), StorageError> {
+          if let Some(journal) = self.journal.as_mut() {
+               let live: Vec<Vec<u8>> = self.entries.iter().map(|(id, at, offset)| record(id, *at, *offset)).collect();
+               journal.rewrite(&live)?;
+               self.records = live.len();
+          }
+          Ok(())
+     }
+}
+
+/// Journal record of an id entering the window
+fn record(id: &str, at: SystemTime, offset: u64) -> Vec<u8> {
+     let mut encoder = Encoder::new();
+     encoder.put_str(id);
+     encoder.put_time(at);
+     encoder.put_u64(offset);
+     encoder.into_bytes()
+}
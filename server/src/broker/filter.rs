@@ -0,0 +1,452 @@
+use std::cmp::Ordering;
+
+use net::protocol::{MTPHeaders, MTPMessage};
+use net::protocol::error::{Error, ProtocolError};
+use net::protocol::interface::{ContentType, MessageCategory, MessagePriority};
+
+/// Operator comparing a field of a message with a value
+enum Operator {
+     Equal,
+     NotEqual,
+     Less,
+     LessOrEqual,
+     Greater,
+     GreaterOrEqual,
+}
+
+impl Operator {
+     /// Checks whether `ordering`, the ordering of a field relative to the value, satisfies the
+     /// operator
+     fn holds(&self, ordering: Ordering) -> bool {
+          match self {
+               Operator::Equal => ordering == Ordering::Equal,
+               Operator::NotEqual => ordering != Ordering::Equal,
+               Operator::Less => ordering == Ordering::Less,
+               Operator::LessOrEqual => ordering != Ordering::Greater,
+               Operator::Greater => ordering == Ordering::Greater,
+               Operator::GreaterOrEqual => ordering != Ordering::Less,
+          }
+     }
+
+     /// Checks whether the operator only tests for equality
+     fn is_equality(&self) -> bool {
+          matches!(self, Operator::Equal | Operator::NotEqual)
+     }
+}
+
+/// Clone implementation for [Operator]
+impl Clone for Operator {
+     fn clone(&self) -> Self {
+          match self {
+               Operator::Equal => Operator::Equal,
+               Operator::NotEqual => Operator::NotEqual,
+               Operator::Less => Operator::Less,
+               Operator::LessOrEqual => Operator::LessOrEqual,
+               Operator::Greater => Operator::Greater,
+               Operator::GreaterOrEqual => Operator::GreaterOrEqual,
+          }
+     }
+}
+
+/// Node of a filter expression
+enum Expr {
+     /// Both expressions hold
+     And(Box<Expr>, Box<Expr>),
+     /// Either expression holds
+     Or(Box<Expr>, Box<Expr>),
+     /// The expression does not hold
+     Not(Box<Expr>),
+     /// The [`MessageCategory`] of the message compares to `category`, by name
+     Category { operator: Operator, category: &'static str },
+     /// The [`ContentType`] of the message compares to `content_type`, by name
+     ContentType { operator: Operator, content_type: &'static str },
+     /// The [`MessagePriority`] of the message compares to `rank`, by rank
+     Priority { operator: Operator, rank: u8 },
+     /// The storage cell `key` the message was published with compares to `value`
+     Cell { key: String, operator: Operator, value: String },
+}
+
+impl Expr {
+     /// Evaluates the expression over a message and the headers it was published with
+     fn eval(&self, headers: &MTPHeaders, message: &MTPMessage) -> bool {
+          match self {
+               Expr::And(left, right) => left.eval(headers, message) && right.eval(headers, message),
+               Expr::Or(left, right) => left.eval(headers, message) || right.eval(headers, message),
+               Expr::Not(expr) => !expr.eval(headers, message),
+               Expr::Category { operator, category } => operator.holds(category_name(message.category()).cmp(category)),
+               Expr::ContentType { operator, content_type } => operator.holds(content_type_name(message.content_type()).cmp(content_type)),
+               Expr::Priority { operator, rank } => operator.holds(priority_rank(message.priority()).cmp(rank)),
+               // a message without the cell satisfies no comparison on it
+               Expr::Cell { key, operator, value } => headers.local().get(key)
+                    .is_some_and(|cell| operator.holds(compare_cell(cell, value))),
+          }
+     }
+}
+
+/// Clone implementation for [Expr]
+impl Clone for Expr {
+     fn clone(&self) -> Self {
+          match self {
+               Expr::And(left, right) => Expr::And(left.clone(), right.clone()),
+               Expr::Or(left, right) => Expr::Or(left.clone(), right.clone()),
+               Expr::Not(expr) => Expr::Not(expr.clone()),
+               Expr::Category { operator, category } => Expr::Category { operator: operator.clone(), category },
+               Expr::ContentType { operator, content_type } => Expr::ContentType { operator: operator.clone(), content_type },
+               Expr::Priority { operator, rank } => Expr::Priority { operator: operator.clone(), rank: *rank },
+               Expr::Cell { key, operator, value } => Expr::Cell { key: key.clone(), operator: operator.clone(), value: value.clone() },
+          }
+     }
+}
+
+/// Token of a filter expression
+enum Token {
+     /// Field name, keyword or unquoted value
+     Word(String),
+     /// Quoted value
+     Quoted(String),
+     Operator(Operator),
+     Open,
+     Close,
+}
+
+/// Filter selecting the messages delivered to a consumer, evaluated by the broker over the
+/// metadata of each message before it is handed out.
+///
+/// An expression compares fields with values, combined with `AND`, `OR`, `NOT` and parentheses,
+/// `AND` binding tighter than `OR`:
+///
+/// ```text
+/// category = EVENT AND priority >= High AND region = 'eu'
+/// ```
+///
+/// The fields `category`, `priority` and `content_type` refer to the [`MessageCategory`],
+/// [`MessagePriority`] and [`ContentType`] of the message, and any other field to the storage
+/// cell of that key the message was published with. Priorities are ordered from `Low` to
+/// `Critical`, while categories and content types only support `=` and `!=`. Cells compare as
+/// numbers when both sides are numbers and as strings otherwise, and a message without the cell
+/// satisfies no comparison on it. Keywords, field names and enum values are case-insensitive,
+/// cell keys and quoted values are not.
+///
+/// # Fields
+///
+/// ~ `expression`: Expression the filter was parsed from
+/// ~ `root`: Root of the parsed expression
+pub struct Filter {
+     expression: String,
+     root: Expr,
+}
+
+impl Filter {
+     /// Parses a filter expression
+     ///
+     /// # Errors
+     /// [`ProtocolError::UnprocessableContent112`] if the expression is malformed, or compares
+     /// a field with a value it cannot take
+     pub fn parse(expression: &str) -> Result<Self, ProtocolError> {
+          let tokens = tokenize(expression)?;
+          let mut parser = Parser { tokens, position: 0 };
+          let root = parser.or()?;
+          if parser.position < parser.tokens.len() {
+               return Err(invalid("unexpected input after the end of the expression"));
+          }
+
+          Ok(Self { expression: expression.to_string(), root })
+     }
+
+     /// Retrieves the expression the filter was parsed from
+     pub fn expression(&self) -> &str {
+          &self.expression
+     }
+
+     /// Checks whether a message published with `headers` passes the filter
+     pub fn matches(&self, headers: &MTPHeaders, message: &MTPMessage) -> bool {
+          self.root.eval(headers, message)
+     }
+}
+
+/// Clone implementation for [Filter]
+impl Clone for Filter {
+     fn clone(&self) -> Self {
+          Self {
+               expression: self.expression.clone(),
+               root: self.root.clone(),
+          }
+     }
+}
+
+/// Recursive descent parser over the tokens of an expression
+struct Parser {
+     tokens: Vec<Token>,
+     position: usize,
+}
+
+impl Parser {
+     /// Parses a disjunction of conjunctions
+     fn or(&mut self) -> Result<Expr, ProtocolError> {
+          let mut expr = self.and()?;
+          while self.keyword("OR") {
+               expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+          }
+          Ok(expr)
+     }
+
+     /// Parses a conjunction of negations
+     fn and(&mut self) -> Result<Expr, ProtocolError> {
+          let mut expr = self.not()?;
+          while self.keyword("AND") {
+               expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+          }
+          Ok(expr)
+     }
+
+     /// Parses a possibly negated comparison or parenthesized expression
+     fn not(&mut self) -> Result<Expr, ProtocolError> {
+          if self.keyword("NOT") {
+               return Ok(Expr::Not(Box::new(self.not()?)));
+          }
+
+          match self.tokens.get(self.position) {
+               Some(Token::Open) => {
+                    self.position += 1;
+                    let expr = self.or()?;
+                    match self.tokens.get(self.position) {
+                         Some(Token::Close) => {
+                              self.position += 1;
+                              Ok(expr)
+                         },
+                         _ => Err(invalid("missing closing parenthesis")),
+                    }
+               },
+               _ => self.comparison(),
+          }
+     }
+
+     /// Parses the comparison of a field with a value
+     fn comparison(&mut self) -> Result<Expr, ProtocolError> {
+          let field = match self.tokens.get(self.position) {
+               Some(Token::Word(word)) if !is_keyword(word) => word.clone(),
+               Some(_) => return Err(invalid("expected a field name")),
+               None => return Err(invalid("unexpected end of the expression")),
+          };
+          let operator = match self.tokens.get(self.position + 1) {
+               Some(Token::Operator(operator)) => operator.clone(),
+               _ => return Err(invalid(&format!("expected an operator after {}", field))),
+          };
+          let value = match self.tokens.get(self.position + 2) {
+               Some(Token::Word(word)) if !is_keyword(word) => word.clone(),
+               Some(Token::Quoted(value)) => value.clone(),
+               _ => return Err(invalid(&format!("expected a value to compare {} with", field))),
+          };
+          self.position += 3;
+
+          match field.to_ascii_lowercase().as_str() {
+               "category" => {
+                    let category = parse_category(&value).ok_or_else(|| invalid(&format!("unknown category {}", value)))?;
+                    if !operator.is_equality() {
+                         return Err(invalid("categories are only compared with = and !="));
+                    }
+                    Ok(Expr::Category { operator, category })
+               },
+               "content_type" => {
+                    let content_type = parse_content_type(&value).ok_or_else(|| invalid(&format!("unknown content type {}", value)))?;
+                    if !operator.is_equality() {
+                         return Err(invalid("content types are only compared with = and !="));
+                    }
+                    Ok(Expr::ContentType { operator, content_type })
+               },
+               "priority" => {
+                    let rank = parse_priority(&value).ok_or_else(|| invalid(&format!("unknown priority {}", value)))?;
+                    Ok(Expr::Priority { operator, rank })
+               },
+               _ => Ok(Expr::Cell { key: field, operator, value }),
+          }
+     }
+
+     /// Consumes the next token if it is `keyword`
+     fn keyword(&mut self, keyword: &str) -> bool {
+          match self.tokens.get(self.position) {
+               Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword) => {
+                    self.position += 1;
+                    true
+               },
+               _ => false,
+          }
+     }
+}
+
+/// Splits an expression into tokens
+fn tokenize(expression: &str) -> Result<Vec<Token>, ProtocolError> {
+     let mut tokens = Vec::new();
+     let mut chars = expression.chars().peekable();
+
+     while let Some(c) = chars.next() {
+          let token = match c {
+               c if c.is_whitespace() => continue,
+               '(' => Token::Open,
+               ')' => Token::Close,
+               '=' => Token::Operator(Operator::Equal),
+               '!' if chars.next_if_eq(&'=').is_some() => Token::Operator(Operator::NotEqual),
+               '<' if chars.next_if_eq(&'=').is_some() => Token::Operator(Operator::LessOrEqual),
+               '<' if chars.next_if_eq(&'>').is_some() => Token::Operator(Operator::NotEqual),
+               '<' => Token::Operator(Operator::Less),
+               '>' if chars.next_if_eq(&'=').is_some() => Token::Operator(Operator::GreaterOrEqual),
+               '>' => Token::Operator(Operator::Greater),
+               '\'' | '"' => {
+                    let mut value = String::new();
+                    loop {
+                         match chars.next() {
+                              Some(end) if end == c => break,
+                              Some(next) => value.push(next),
+                              None => return Err(invalid("unterminated quoted value")),
+                         }
+                    }
+                    Token::Quoted(value)
+               },
+               c if is_word(c) => {
+                    let mut word = c.to_string();
+                    while let Some(next) = chars.next_if(|next| is_word(*next)) {
+                         word.push(next);
+                    }
+                    Token::Word(word)
+               },
+               c => return Err(invalid(&format!("unexpected character {}", c))),
+          };
+          tokens.push(token);
+     }
+
+     Ok(tokens)
+}
+
+/// Checks whether `c` may appear in a field name or an unquoted value
+fn is_word(c: char) -> bool {
+     c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ':')
+}
+
+/// Checks whether `word` is a keyword of the expression language
+fn is_keyword(word: &str) -> bool {
+     ["AND", "OR", "NOT"].iter().any(|keyword| word.eq_ignore_ascii_case(keyword))
+}
+
+/// Compares the value of a storage cell with `value`, as numbers when both are numbers
+fn compare_cell(cell: &str, value: &str) -> Ordering {
+     match (cell.parse::<f64>(), value.parse::<f64>()) {
+          (Ok(cell), Ok(value)) => cell.partial_cmp(&value).unwrap_or(Ordering::Less),
+          _ => cell.cmp(value),
+     }
+}
+
+/// Name a category is referred to by in expressions
+fn category_name(category: &MessageCategory) -> &'static str {
+     match category {
+          MessageCategory::EVENT => "EVENT",
+          MessageCategory::COMMAND => "COMMAND",
+          MessageCategory::REQUEST => "REQUEST",
+          MessageCategory::RESPONSE => "RESPONSE",
+          MessageCategory::ACKNOWLEDGEMENT => "ACKNOWLEDGEMENT",
+          MessageCategory::ERROR => "ERROR",
+          MessageCategory::NOTIFICATION => "NOTIFICATION",
+          MessageCategory::STATUS => "STATUS",
+     }
+}
+
+/// Name a content type is referred to by in expressions
+fn content_type_name(content_type: &ContentType) -> &'static str {
+     match content_type {
+          ContentType::JSON => "JSON",
+          ContentType::XML => "XML",
+     }
+}
+
+/// Rank of a priority, from the lowest to the highest
+fn priority_rank(priority: &MessagePriority) -> u8 {
+     match priority {
+          MessagePriority::Low => 0,
+          MessagePriority::Medium => 1,
+          MessagePriority::High => 2,
+          MessagePriority::Critical => 3,
+     }
+}
+
+/// Resolves the category named `value`
+fn parse_category(value: &str) -> Option<&'static str> {
+     ["EVENT", "COMMAND", "REQUEST", "RESPONSE", "ACKNOWLEDGEMENT", "ERROR", "NOTIFICATION", "STATUS"].into_iter()
+          .find(|name| name.eq_ignore_ascii_case(value))
+}
+
+/// Resolves the content type named `value`
+fn parse_content_type(value: &str) -> Option<&'static str> {
+     ["JSON", "XML"].into_iter().find(|name| name.eq_ignore_ascii_case(value))
+}
+
+/// Resolves the rank of the priority named `value`
+fn parse_priority(value: &str) -> Option<u8> {
+     ["Low", "Medium", "High", "Critical"].iter()
+          .position(|name| name.eq_ignore_ascii_case(value))
+          .map(|rank| rank as u8)
+}
+
+/// Builds the error reported for a malformed expression
+fn invalid(reason: &str) -> ProtocolError {
+     ProtocolError::UnprocessableContent112(Error::new(format!("Invalid filter: {}", reason)))
+}
+
+#[cfg(test)]
+mod tests {
+     use net::protocol::{MTPHeaders, MTPMessage, MTPStorage};
+     use net::protocol::interface::{ContentType, MessageCategory, MessagePriority, MessagePublish};
+
+     use super::Filter;
+
+     fn headers(cells: &[(&str, &str)]) -> MTPHeaders {
+          let mut local = MTPStorage::new(Vec::new());
+          for (key, value) in cells {
+               local.push(key.to_string(), value.to_string());
+          }
+          MTPHeaders::new(Vec::new(), local, None)
+     }
+
+     fn message(category: MessageCategory, priority: MessagePriority) -> MTPMessage {
+          MTPMessage::new(ContentType::JSON, priority, category, MessagePublish::ALL, String::new())
+     }
+
+     fn matches(expression: &str, headers: &MTPHeaders, message: &MTPMessage) -> bool {
+          Filter::parse(expression).ok().unwrap().matches(headers, message)
+     }
+
+     #[test]
+     fn comparisons_apply_to_fields_and_cells() {
+          let cells = headers(&[("region", "eu"), ("amount", "250")]);
+          let event = message(MessageCategory::EVENT, MessagePriority::High);
+
+          assert!(matches("category = event", &cells, &event));
+          assert!(matches("content_type != XML", &cells, &event));
+          assert!(matches("priority >= Medium", &cells, &event));
+          assert!(!matches("priority > High", &cells, &event));
+          assert!(matches("region = 'eu'", &cells, &event));
+          assert!(!matches("region = 'EU'", &cells, &event));
+          // cells compare as numbers when both sides are numbers
+          assert!(matches("amount > 99", &cells, &event));
+          assert!(matches("amount <> 250.5", &cells, &event));
+          // a message without the cell satisfies no comparison on it
+          assert!(!matches("missing = x", &cells, &event));
+          assert!(!matches("missing != x", &cells, &event));
+     }
+
+     #[test]
+     fn and_binds_tighter_than_or() {
+          let cells = headers(&[("region", "us")]);
+          let event = message(MessageCategory::EVENT, MessagePriority::Low);
+
+          assert!(matches("category = COMMAND AND region = eu OR priority = Low", &cells, &event));
+          assert!(!matches("category = COMMAND AND (region = eu OR priority = Low)", &cells, &event));
+          assert!(matches("NOT category = COMMAND and not (region = eu)", &cells, &event));
+     }
+
+     #[test]
+     fn malformed_expressions_are_rejected() {
+          for expression in ["", "category =", "= EVENT", "region = 'eu", "(priority = Low", "priority = Low)", "category > EVENT", "category = SOMETHING", "priority = Urgent", "region ~ eu", "region = eu AND"] {
+               assert!(Filter::parse(expression).is_err(), "{} should be rejected", expression);
+          }
+          assert_eq!(Filter::parse(" region = eu ").ok().unwrap().expression(), " region = eu ");
+     }
+}
@@ -0,0 +1,132 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::SystemTime;
+
+use net::protocol::interface::Distribution;
+
+use super::filter::Filter;
+use super::session::SessionId;
+
+/// A request of a client to subscribe to a private queue it is not admitted to, awaiting the
+/// decision of the moderator of the queue.
+///
+/// # Fields
+///
+/// ~ `session`: Session the client subscribed over
+/// ~ `group`: Consumer group the client subscribed as a member of, along with its distribution
+/// ~ `filter`: Filter the client subscribed with
+/// ~ `until`: Time the request expires at
+pub struct JoinRequest {
+     session: SessionId,
+     group: Option<(String, Distribution)>,
+     filter: Option<Filter>,
+     until: SystemTime,
+}
+
+impl JoinRequest {
+     /// Creates a request to subscribe over `session`, expiring at `until`
+     pub fn new(session: SessionId, group: Option<(String, Distribution)>, filter: Option<Filter>, until: SystemTime) -> Self {
+          Self { session, group, filter, until }
+     }
+
+     /// Retrieves the session the client subscribed over
+     pub fn session(&self) -> SessionId {
+          self.session
+     }
+
+     /// Retrieves the time the request expires at
+     pub fn until(&self) -> SystemTime {
+          self.until
+     }
+
+     /// Splits the request into the session, group and filter the client subscribed with
+     pub fn into_parts(self) -> (SessionId, Option<(String, Distribution)>, Option<Filter>) {
+          (self.session, self.group, self.filter)
+     }
+}
+
+/// Requests to join private queues awaiting the decision of their moderators.
+///
+/// Requests are only kept in memory: they are withdrawn when the session they were made over
+/// closes, and expire after the join timeout of the broker.
+///
+/// # Fields
+///
+/// ~ `entries`: Pending requests by queue, and by requesting client within a queue
+pub struct JoinRequests {
+     entries: HashMap<String, BTreeMap<String, JoinRequest>>,
+}
+
+impl JoinRequests {
+     /// Creates a set without pending requests
+     pub fn new() -> Self {
+          Self { entries: HashMap::new() }
+     }
+
+     /// Records the request of `client` to join `queue`, replacing the one it made before
+     ///
+     /// # Returns
+     /// `true` if the client had no request pending for the queue
+     pub fn insert(&mut self, queue: String, client: String, request: JoinRequest) -> bool {
+          self.entries.entry(queue).or_default().insert(client, request).is_none()
+     }
+
+     /// Removes the request of `client` to join `queue`
+     pub fn take(&mut self, queue: &str, client: &str) -> Option<JoinRequest> {
+          let requests = self.entries.get_mut(queue)?;
+          let request = requests.remove(client);
+          if requests.is_empty() {
+               self.entries.remove(queue);
+          }
+          request
+     }
+
+     /// Retrieves the clients whose request to join `queue` is pending, in alphabetical order
+     pub fn pending(&self, queue: &str) -> Vec<String> {
+          self.entries.get(queue)
+               .map(|requests| requests.keys().cloned().collect())
+               .unwrap_or_default()
+     }
+
+     /// Removes the requests that expired at `now`
+     ///
+     /// # Returns
+     /// The queue and client of every removed request, along with the request
+     pub fn expire(&mut self, now: SystemTime) -> Vec<(String, String, JoinRequest)> {
+          let mut expired = Vec::new();
+          for (queue, requests) in self.entries.iter_mut() {
+               let clients: Vec<String> = requests.iter()
+                    .filter(|(_, request)| request.until <= now)
+                    .map(|(client, _)| client.clone())
+                    .collect();
+               for client in clients {
+                    if let Some(request) = requests.remove(&client) {
+                         expired.push((queue.clone(), client, request));
+                    }
+               }
+          }
+          self.entries.retain(|_, requests| !requests.is_empty());
+          expired
+     }
+
+     /// Withdraws the requests made over `session`
+     pub fn release(&mut self, session: SessionId) {
+          for requests in self.entries.values_mut() {
+               requests.retain(|_, request| request.session != session);
+          }
+          self.entries.retain(|_, requests| !requests.is_empty());
+     }
+
+     /// Moves the requests to join the queue `from` to the queue `to`, once renamed
+     pub fn rename(&mut self, from: &str, to: &str) {
+          if let Some(requests) = self.entries.remove(from) {
+               self.entries.insert(to.to_string(), requests);
+          }
+     }
+}
+
+/// Default implementation for [JoinRequests]
+impl Default for JoinRequests {
+     fn default() -> Self {
+          Self::new()
+     }
+}
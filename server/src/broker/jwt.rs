@@ -0,0 +1,398 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Mutex, PoisonError, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use jsonwebtoken::{decode, decode_header, DecodingKey, Validation};
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::jwk::JwkSet;
+use serde_json::Value;
+
+use net::protocol::error::{Error, ProtocolError};
+use net::protocol::interface::{AuthSchemes, MTPAuth, MTPHeaderUnit, QueueRoles};
+use security_gateway::provider::{credentials, unrecognised, AuthProvider, Identity, Peer};
+
+use crate::storage::error::StorageError;
+
+use super::token::TokenClaims;
+
+/// A rule granting a role on a queue to the holders of the JWTs whose claim `claim` holds
+/// `value`, either as its value or as one of the values of an array.
+///
+/// # Fields
+///
+/// ~ `claim`: Path of the claim, its levels separated by dots such as `realm_access.roles`
+/// ~ `value`: Value the claim must hold
+/// ~ `queue`: Queue the role is granted on
+/// ~ `role`: Role granted on the queue
+pub struct RoleMapping {
+     claim: String,
+     value: String,
+     queue: String,
+     role: QueueRoles,
+}
+
+/// Clone implementation for [RoleMapping]
+impl Clone for RoleMapping {
+     fn clone(&self) -> Self {
+          Self {
+               claim: self.claim.clone(),
+               value: self.value.clone(),
+               queue: self.queue.clone(),
+               role: self.role.clone(),
+          }
+     }
+}
+
+/// Configuration of the verification of the JWTs clients authenticate with through
+/// `MTPAuth::ExternalToken` or the `Bearer` authorization scheme.
+///
+/// # Fields
+///
+/// ~ `jwks`: File holding the JSON Web Key Set the tokens are verified against
+/// ~ `issuers`: Issuers tokens are accepted from, any if empty
+/// ~ `audiences`: Audiences one of which tokens must be intended for, any if empty
+/// ~ `identity_claim`: Path of the claim holding the identity the holder authenticates as
+/// ~ `mappings`: Rules granting roles on queues from the claims of tokens
+/// ~ `leeway`: Clock skew tolerated when checking the `exp` and `nbf` claims
+pub struct JwtConfig {
+     jwks: PathBuf,
+     issuers: Vec<String>,
+     audiences: Vec<String>,
+     identity_claim: String,
+     mappings: Vec<RoleMapping>,
+     leeway: Duration,
+}
+
+impl JwtConfig {
+     /// Creates a configuration verifying tokens against the keys of the JWKS file at `jwks`,
+     /// authenticating their holder as their `sub` claim
+     pub fn new(jwks: PathBuf) -> Self {
+          Self {
+               jwks,
+               issuers: Vec::new(),
+               audiences: Vec::new(),
+               identity_claim: "sub".to_string(),
+               mappings: Vec::new(),
+               leeway: Duration::from_secs(60),
+          }
+     }
+
+     /// Accepts tokens issued by `issuer`, and only by the issuers accepted so far
+     pub fn with_issuer(mut self, issuer: String) -> Self {
+          self.issuers.push(issuer);
+          self
+     }
+
+     /// Accepts tokens intended for `audience`, and only for the audiences accepted so far
+     pub fn with_audience(mut self, audience: String) -> Self {
+          self.audiences.push(audience);
+          self
+     }
+
+     /// Sets the path of the claim holding the identity the holder of a token authenticates as
+     pub fn with_identity_claim(mut self, claim: String) -> Self {
+          self.identity_claim = claim;
+          self
+     }
+
+     /// Grants `role` on the queue `queue` to the holders of the tokens whose claim `claim`
+     /// holds `value`. The mapping added last prevails when several grant a role on a queue.
+     pub fn with_role_mapping(mut self, claim: String, value: String, queue: String, role: QueueRoles) -> Self {
+          self.mappings.push(RoleMapping { claim, value, queue, role });
+          self
+     }
+
+     /// Sets the clock skew tolerated when checking the `exp` and `nbf` claims
+     pub fn with_leeway(mut self, leeway: Duration) -> Self {
+          self.leeway = leeway;
+          self
+     }
+}
+
+/// Clone implementation for [JwtConfig]
+impl Clone for JwtConfig {
+     fn clone(&self) -> Self {
+          Self {
+               jwks: self.jwks.clone(),
+               issuers: self.issuers.clone(),
+               audiences: self.audiences.clone(),
+               identity_claim: self.identity_claim.clone(),
+               mappings: self.mappings.clone(),
+               leeway: self.leeway,
+          }
+     }
+}
+
+/// Verifier of the JWTs issued by an external identity provider.
+///
+/// A token must be signed by a key of the JWKS file, named by the `kid` of its header, with the
+/// algorithm of the key if the key sets one. Its `exp` claim is required, and its `nbf`, `iss`
+/// and `aud` claims are checked against the configuration. The verified claims are mapped to
+/// [`TokenClaims`]: the identity is read from the configured claim and roles are granted by
+/// the [`RoleMapping`]s the claims match.
+///
+/// The JWKS file is read again whenever it is modified, so that the keys of the provider are
+/// rotated without restarting the broker. The keys are locked so that tokens are verified
+/// concurrently with the file being read again.
+///
+/// # Fields
+///
+/// ~ `config`: Configuration of the verification
+/// ~ `keys`: Keys read from the JWKS file
+/// ~ `modified`: Time the JWKS file was modified at and its length when it was read
+pub struct JwtVerifier {
+     config: JwtConfig,
+     keys: RwLock<JwkSet>,
+     modified: Mutex<Option<(SystemTime, u64)>>,
+}
+
+impl JwtVerifier {
+     /// Creates a verifier reading the keys of the JWKS file of `config`
+     ///
+     /// # Errors
+     /// A [`StorageError`] if the JWKS file could not be read or is not a valid JWKS
+     pub fn open(config: JwtConfig) -> Result<Self, StorageError> {
+          let verifier = Self {
+               config,
+               keys: RwLock::new(JwkSet { keys: Vec::new() }),
+               modified: Mutex::new(None),
+          };
+          verifier.refresh()?;
+
+          Ok(verifier)
+     }
+
+     /// Reads the JWKS file again if it was modified since it was last read. The keys read last
+     /// are kept if it cannot be read.
+     ///
+     /// # Errors
+     /// A [`StorageError`] if the JWKS file could not be read or is not a valid JWKS
+     pub fn refresh(&self) -> Result<(), StorageError> {
+          // held while the file is read, so that it is read once when it changes
+          let mut read = self.modified.lock().unwrap_or_else(PoisonError::into_inner);
+          let metadata = fs::metadata(&self.config.jwks)?;
+          let modified = (metadata.modified()?, metadata.len());
+          if *read == Some(modified) {
+               return Ok(());
+          }
+
+          let text = fs::read_to_string(&self.config.jwks)?;
+          let keys = serde_json::from_str(&text)
+               .map_err(|err| StorageError::Corrupted { message: format!("Invalid JWKS in {}: {}", self.config.jwks.display(), err) })?;
+          *self.keys.write().unwrap_or_else(PoisonError::into_inner) = keys;
+          *read = Some(modified);
+
+          Ok(())
+     }
+
+     /// Verifies the JWT `token`, reading the JWKS file again first if it was modified
+     ///
+     /// # Returns
+     /// The identity and the roles the claims of the token map to, until the token expires
+     ///
+     /// # Errors
+     /// [`ProtocolError::Unauthorized101`] if the token is malformed, was not signed by a key of
+     /// the JWKS, expired, is not valid yet, or was issued by an issuer or for an audience that
+     /// is not accepted
+     pub fn verify(&self, token: &str) -> Result<TokenClaims, ProtocolError> {
+          // the keys read last keep verifying tokens while the file is being rewritten
+          let _ = self.refresh();
+
+          let header = decode_header(token).map_err(rejected)?;
+          let kid = header.kid.as_deref()
+               .ok_or_else(|| ProtocolError::Unauthorized101(Error::new("Token names no signing key".to_string())))?;
+          let key = {
+               let keys = self.keys.read().unwrap_or_else(PoisonError::into_inner);
+               let jwk = keys.find(kid)
+                    .ok_or_else(|| ProtocolError::Unauthorized101(Error::new(format!("Token was signed with unknown key {}", kid))))?;
+               if jwk.common.key_algorithm.is_some_and(|algorithm| format!("{:?}", algorithm) != format!("{:?}", header.alg)) {
+                    return Err(ProtocolError::Unauthorized101(Error::new(format!("Token was not signed with the algorithm of key {}", kid))));
+               }
+               DecodingKey::from_jwk(jwk).map_err(rejected)?
+          };
+
+          let mut validation = Validation::new(header.alg);
+          validation.leeway = self.config.leeway.as_secs();
+          validation.validate_nbf = true;
+          if self.config.audiences.is_empty() {
+               validation.validate_aud = false;
+          } else {
+               validation.set_audience(&self.config.audiences);
+               validation.required_spec_claims.insert("aud".to_string());
+          }
+          if !self.config.issuers.is_empty() {
+               validation.set_issuer(&self.config.issuers);
+               validation.required_spec_claims.insert("iss".to_string());
+          }
+
+          let claims = decode::<Value>(token, &key, &validation).map_err(rejected)?.claims;
+          self.map(&claims)
+     }
+
+     /// Maps the verified `claims` of a token to the identity and the roles of its holder
+     fn map(&self, claims: &Value) -> Result<TokenClaims, ProtocolError> {
+          let identity = lookup(claims, &self.config.identity_claim)
+               .and_then(Value::as_str)
+               .ok_or_else(|| ProtocolError::Unauthorized101(Error::new(format!("Token carries no {} claim", self.config.identity_claim))))?;
+          let expires = claims.get("exp")
+               .and_then(Value::as_f64)
+               .map(|exp| UNIX_EPOCH + Duration::from_secs_f64(exp.max(0.0)))
+               .unwrap_or(UNIX_EPOCH);
+
+          let mut mapped = TokenClaims::new(identity.to_string(), expires);
+          if let Some(id) = claims.get("jti").and_then(Value::as_str) {
+               mapped = mapped.with_id(id.to_string());
+          }
+          for mapping in &self.config.mappings {
+               if holds(lookup(claims, &mapping.claim), &mapping.value) {
+                    mapped = mapped.with_role(mapping.queue.clone(), mapping.role.clone());
+               }
+          }
+
+          Ok(mapped)
+     }
+}
+
+/// Authenticates the holders of the JWTs sent through `MTPAuth::ExternalToken` or the `Bearer`
+/// authorization scheme, as verified by [`JwtVerifier::verify`]
+impl AuthProvider for JwtVerifier {
+     fn authenticate(&self, units: &[MTPHeaderUnit], _peer: &Peer) -> Result<Identity, ProtocolError> {
+          let token = credentials(units, |method| matches!(method, MTPAuth::ExternalToken | MTPAuth::Authorization { scheme: AuthSchemes::Bearer }))
+               .ok_or_else(|| unrecognised("No bearer token"))?;
+          self.verify(token).map(Identity::from)
+     }
+}
+
+/// Retrieves the claim at `path` within `claims`, its levels separated by dots
+fn lookup<'a>(claims: &'a Value, path: &str) -> Option<&'a Value> {
+     path.split('.').try_fold(claims, |value, level| value.get(level))
+}
+
+/// Checks whether `claim` is `value`, or an array holding it
+fn holds(claim: Option<&Value>, value: &str) -> bool {
+     match claim {
+          Some(Value::String(claim)) => claim == value,
+          Some(Value::Array(claims)) => claims.iter().any(|claim| claim.as_str() == Some(value)),
+          _ => false,
+     }
+}
+
+/// Builds the error a token is rejected with from the error its verification failed with
+fn rejected(err: jsonwebtoken::errors::Error) -> ProtocolError {
+     let message = match err.kind() {
+          ErrorKind::ExpiredSignature => "Token expired".to_string(),
+          ErrorKind::ImmatureSignature => "Token is not valid yet".to_string(),
+          ErrorKind::InvalidIssuer => "Token was issued by an issuer that is not accepted".to_string(),
+          ErrorKind::InvalidAudience => "Token is not intended for an accepted audience".to_string(),
+          ErrorKind::InvalidSignature => "Token does not match its signature".to_string(),
+          ErrorKind::MissingRequiredClaim(claim) => format!("Token carries no {} claim", claim),
+          _ => format!("Invalid token: {}", err),
+     };
+     ProtocolError::Unauthorized101(Error::new(message))
+}
+
+#[cfg(test)]
+mod tests {
+     use std::fs;
+     use std::path::PathBuf;
+     use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+     use base64ct::Encoding;
+     use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+     use serde_json::{json, Value};
+
+     use net::protocol::interface::QueueRoles;
+
+     use super::{JwtConfig, JwtVerifier};
+
+     fn now() -> u64 {
+          SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+     }
+
+     /// Writes a JWKS holding an HS256 key for every `(kid, secret)` pair to `path`
+     fn jwks(path: &PathBuf, keys: &[(&str, &[u8])]) {
+          let keys: Vec<Value> = keys.iter()
+               .map(|(kid, secret)| json!({"kty": "oct", "kid": kid, "alg": "HS256", "k": base64ct::Base64UrlUnpadded::encode_string(secret)}))
+               .collect();
+          fs::write(path, json!({"keys": keys}).to_string()).unwrap();
+     }
+
+     fn token(kid: &str, secret: &[u8], claims: &Value) -> String {
+          let mut header = Header::new(Algorithm::HS256);
+          header.kid = Some(kid.to_string());
+          encode(&header, claims, &EncodingKey::from_secret(secret)).unwrap()
+     }
+
+     fn verifier(name: &str) -> (PathBuf, JwtVerifier) {
+          let dir = std::env::temp_dir().join(format!("jwt-{}-{}", name, std::process::id()));
+          let _ = fs::remove_dir_all(&dir);
+          fs::create_dir_all(&dir).unwrap();
+          let path = dir.join("jwks.json");
+          jwks(&path, &[("a", b"secret-a")]);
+
+          let config = JwtConfig::new(path)
+               .with_issuer("sso".to_string())
+               .with_audience("mq".to_string())
+               .with_identity_claim("profile.username".to_string())
+               .with_role_mapping("groups".to_string(), "admins".to_string(), "orders".to_string(), QueueRoles::Manager)
+               .with_leeway(Duration::from_secs(0));
+          (dir, JwtVerifier::open(config).ok().unwrap())
+     }
+
+     fn claims() -> Value {
+          json!({"sub": "u1", "jti": "t1", "iss": "sso", "aud": "mq", "exp": now() + 3600, "profile": {"username": "alice"}, "groups": ["users", "admins"]})
+     }
+
+     #[test]
+     fn claims_map_to_identity_and_roles() {
+          let (dir, verifier) = verifier("claims");
+
+          let mapped = verifier.verify(&token("a", b"secret-a", &claims())).ok().unwrap();
+          assert_eq!(mapped.identity(), "alice");
+          assert_eq!(mapped.id(), "t1");
+          assert!(matches!(mapped.role("orders"), Some(QueueRoles::Manager)));
+          assert_eq!(mapped.expires(), Some(UNIX_EPOCH + Duration::from_secs(claims()["exp"].as_u64().unwrap())));
+
+          let mut user = claims();
+          user["groups"] = json!("users");
+          assert!(verifier.verify(&token("a", b"secret-a", &user)).ok().unwrap().role("orders").is_none());
+
+          fs::remove_dir_all(&dir).unwrap();
+     }
+
+     #[test]
+     fn invalid_tokens_are_rejected() {
+          let (dir, verifier) = verifier("invalid");
+          let mut rejected = vec![token("a", b"wrong", &claims()), token("b", b"secret-a", &claims()), "not a token".to_string()];
+          for (claim, value) in [("exp", json!(now() - 10)), ("nbf", json!(now() + 100)), ("iss", json!("other")), ("aud", json!("other")), ("profile", json!({}))] {
+               let mut altered = claims();
+               altered[claim] = value;
+               rejected.push(token("a", b"secret-a", &altered));
+          }
+          for claim in ["exp", "aud", "iss"] {
+               let mut altered = claims();
+               altered.as_object_mut().unwrap().remove(claim);
+               rejected.push(token("a", b"secret-a", &altered));
+          }
+
+          for token in rejected {
+               assert!(verifier.verify(&token).is_err(), "{} should be rejected", token);
+          }
+
+          fs::remove_dir_all(&dir).unwrap();
+     }
+
+     #[test]
+     fn keys_are_read_again_when_the_jwks_changes() {
+          let (dir, verifier) = verifier("rotate");
+          let former = token("a", b"secret-a", &claims());
+          assert!(verifier.verify(&former).is_ok());
+
+          jwks(&dir.join("jwks.json"), &[("b", b"secret-b"), ("c", b"secret-c-longer")]);
+          assert!(verifier.verify(&token("b", b"secret-b", &claims())).is_ok());
+          assert!(verifier.verify(&former).is_err());
+
+          fs::remove_dir_all(&dir).unwrap();
+     }
+}
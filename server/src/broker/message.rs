@@ -0,0 +1,187 @@
+use std::time::{Duration, SystemTime};
+
+use net::protocol::{MTPEnvelope, MTPHeaders, MTPMessage};
+use net::protocol::interface::MTPHeaderUnit;
+
+use crate::storage::codec::{Decode, Decoder, Encode, Encoder};
+use crate::storage::error::StorageError;
+
+/// A message held by a queue of the broker.
+///
+/// Wraps the published [`MTPMessage`] together with the headers it was published with and the
+/// bookkeeping the broker needs to deliver it.
+///
+/// # Fields
+///
+/// ~ `offset`: Position of the message within its queue
+/// ~ `id`: Identifier of the message, taken from the `Message` header unit or assigned by the broker
+/// ~ `headers`: Headers the message was published with
+/// ~ `message`: The published message
+/// ~ `enqueued_at`: Time at which the broker accepted the message
+/// ~ `expires_at`: Time after which the message is no longer delivered, if any
+pub struct StoredMessage {
+     offset: u64,
+     id: String,
+     headers: MTPHeaders,
+     message: MTPMessage,
+     enqueued_at: SystemTime,
+     expires_at: Option<SystemTime>,
+}
+
+impl StoredMessage {
+     /// Creates a message accepted by the broker at `enqueued_at`.
+     ///
+     /// The expiry is measured from the `timestamp` of the `Message` header unit when the
+     /// publisher provided one, otherwise from the time the broker accepted the message.
+     ///
+     /// # Arguments
+     /// * `id`: Identifier of the message
+     /// * `headers`: Headers the message was published with
+     /// * `message`: The published message
+     /// * `enqueued_at`: Time at which the broker accepted the message
+     /// * `ttl`: Time-to-live of the message, if it expires
+     pub fn new(id: String, headers: MTPHeaders, message: MTPMessage, enqueued_at: SystemTime, ttl: Option<Duration>) -> Self {
+          let created = headers.units().iter().find_map(|unit| match unit {
+               MTPHeaderUnit::Message { timestamp, .. } => *timestamp,
+               _ => None,
+          }).unwrap_or(enqueued_at);
+
+          Self {
+               offset: 0,
+               id,
+               headers,
+               message,
+               enqueued_at,
+               expires_at: ttl.map(|ttl| created + ttl),
+          }
+     }
+
+     /// Retrieves the position of the message within its queue
+     pub fn offset(&self) -> u64 {
+          self.offset
+     }
+
+     /// Sets the position of the message within its queue
+     pub fn set_offset(&mut self, offset: u64) {
+          self.offset = offset;
+     }
+
+     /// Retrieves the identifier of the message
+     pub fn id(&self) -> &str {
+          &self.id
+     }
+
+     /// Retrieves the headers the message was published with
+     pub fn headers(&self) -> &MTPHeaders {
+          &self.headers
+     }
+
+     /// Retrieves the published message
+     pub fn message(&self) -> &MTPMessage {
+          &self.message
+     }
+
+     /// Retrieves the time at which the broker accepted the message
+     pub fn enqueued_at(&self) -> SystemTime {
+          self.enqueued_at
+     }
+
+     /// Retrieves the time after which the message is no longer delivered
+     pub fn expires_at(&self) -> Option<SystemTime> {
+          self.expires_at
+     }
+
+     /// Retrieves the key the message is compacted by: the `Key` header unit it was published
+     /// with, otherwise its id
+     pub fn key(&self) -> &str {
+          self.headers.units().iter().find_map(|unit| match unit {
+               MTPHeaderUnit::Key { key } => Some(key.as_str()),
+               _ => None,
+          }).unwrap_or(&self.id)
+     }
+
+     /// Retrieves the key the message is ordered by within a consumer group, if it was published
+     /// with a `PartitionKey` header unit
+     pub fn partition(&self) -> Option<&str> {
+          self.headers.units().iter().find_map(|unit| match unit {
+               MTPHeaderUnit::PartitionKey { key } => Some(key.as_str()),
+               _ => None,
+          })
+     }
+
+     /// Checks whether the message is a tombstone, deleting its key from a compacted queue
+     pub fn is_tombstone(&self) -> bool {
+          self.headers.units().iter().any(|unit| matches!(unit, MTPHeaderUnit::Tombstone))
+     }
+
+     /// Size of the content of the message in bytes
+     pub fn size(&self) -> usize {
+          self.message.message().len()
+     }
+
+     /// Checks whether the message has expired at `now`
+     pub fn is_expired(&self, now: SystemTime) -> bool {
+          matches!(self.expires_at, Some(expiry) if expiry <= now)
+     }
+
+     /// Prepares an expired message to be moved to a dead-letter queue at `now`.
+     /// Its expiry restarts from `now` with the time-to-live of the dead-letter queue.
+     pub fn dead_lettered(mut self, now: SystemTime, ttl: Option<Duration>) -> Self {
+          self.enqueued_at = now;
+          self.expires_at = ttl.map(|ttl| now + ttl);
+          self
+     }
+
+     /// Wraps the message for delivery from `queue`.
+     ///
+     /// # Returns
+     /// An [`MTPEnvelope`] whose headers carry a [`MTPHeaderUnit::Delivery`] unit the consumer
+     /// acknowledges the message with.
+     pub fn envelope(&self, queue: &str) -> MTPEnvelope {
+          let mut headers = self.headers.clone();
+          headers.push(MTPHeaderUnit::Delivery { queue: queue.to_string(), id: self.id.clone(), offset: self.offset });
+
+          MTPEnvelope::new(headers, self.message.clone())
+     }
+}
+
+/// Clone implementation for [StoredMessage]
+impl Clone for StoredMessage {
+     fn clone(&self) -> Self {
+          Self {
+               offset: self.offset,
+               id: self.id.clone(),
+               headers: self.headers.clone(),
+               message: self.message.clone(),
+               enqueued_at: self.enqueued_at,
+               expires_at: self.expires_at,
+          }
+     }
+}
+
+/// Encode implementation for [StoredMessage]
+/// The offset is not encoded as it is recorded by the log the message is stored in.
+impl Encode for StoredMessage {
+     fn encode(&self, encoder: &mut Encoder) {
+          encoder.put_str(&self.id);
+          encoder.put(&self.headers);
+          encoder.put(&self.message);
+          encoder.put_time(self.enqueued_at);
+          encoder.put_option(self.expires_at, Encoder::put_time);
+     }
+}
+
+/// Decode implementation for [StoredMessage]
+/// The offset of the decoded message is left to be set from the log it was read from.
+impl Decode for StoredMessage {
+     fn decode(decoder: &mut Decoder) -> Result<Self, StorageError> {
+          Ok(Self {
+               offset: 0,
+               id: decoder.get_str()?,
+               headers: decoder.get()?,
+               message: decoder.get()?,
+               enqueued_at: decoder.get_time()?,
+               expires_at: decoder.get_option(Decoder::get_time)?,
+          })
+     }
+}
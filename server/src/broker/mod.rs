@@ -0,0 +1,3204 @@
+/// Module containing the [`queue::Queue`] hosted by the broker and its configuration.
+///
+/// A queue keeps published messages in offset order and tracks, for each of its consumers,
+/// the position reached and the deliveries awaiting acknowledgement.
+pub mod queue;
+
+/// Module containing [`message::StoredMessage`], a published message as held by a queue.
+pub mod message;
+
+/// Module containing [`session::Session`], a client connected to the broker.
+pub mod session;
+
+/// Module containing helpers extracting the header units a request is interpreted with.
+pub mod request;
+
+/// Module containing the [`filter::Filter`] selecting the messages delivered to a consumer.
+pub mod filter;
+
+/// Module containing the [`dedup::DedupWindow`] of the message ids recently published to a queue.
+pub mod dedup;
+
+/// Module containing the [`scheduler::Scheduler`] holding messages published for delayed delivery.
+pub mod scheduler;
+
+/// Module containing the [`topic::TopicTrie`] resolving the queues a topic is delivered to.
+pub mod topic;
+
+/// Module containing the [`join::JoinRequest`] of a client awaiting admission to a private queue.
+pub mod join;
+
+/// Module containing the permission matrix deciding the [`role::Operation`]s a role permits.
+pub mod role;
+
+/// Module containing the [`alias::Aliases`] redirecting the former names of renamed queues.
+pub mod alias;
+
+/// Module containing the [`token::TokenService`] minting and verifying the tokens clients
+/// authenticate with through `MTPAuth::LocalToken`.
+pub mod token;
+
+/// Module containing the [`credential::CredentialStore`] of the users clients authenticate as
+/// through the `Basic` authorization scheme.
+pub mod credential;
+
+/// Module containing the [`jwt::JwtVerifier`] of the JWTs issued by an external identity
+/// provider that clients authenticate with.
+pub mod jwt;
+
+/// Module containing the [`cookie::CookieJar`] of the cookies clients resume their sessions
+/// with through `MTPAuth::Cookie`.
+pub mod cookie;
+
+/// Module containing the [`audit::AuditLog`] recording who authenticated and who changed
+/// queues and their permissions, chained by MAC under a key kept outside the log so that
+/// tampering is detected.
+pub mod audit;
+
+/// Module containing the [`transaction::Transaction`] staged by a session and the
+/// [`transaction::TransactionLog`] committed transactions are journaled to.
+pub mod transaction;
+
+/// Module containing the [`connection::Connection`] of a client embedded in the process hosting
+/// the broker, which sends requests to a session of the broker, receives the messages it pushes
+/// to the session and makes requests to other clients through temporary reply queues.
+pub mod connection;
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::File;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::sync::{watch, Notify};
+use tokio::sync::Mutex as AsyncMutex;
+use tokio::sync::futures::Notified;
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use net::protocol::{MTPEnvelope, MTPHeaders, MTPMessage, MTPPayload, MTPResponse, MTPStorage};
+use net::protocol::error::{Error, ProtocolError};
+use net::protocol::interface::{AuthSchemes, Distribution, MTPAuth, MTPHeaderUnit, MTPManagerAction, MTPRequestType, MTPStatusCode, MessageTransferProtocolPayload, QueueAccess, QueueRoles, TransactionStep};
+use security_gateway::chain::ProviderChain;
+use security_gateway::provider::{AuthProvider, Peer};
+
+use alias::Aliases;
+use audit::{AuditEntry, AuditLog};
+use cookie::CookieJar;
+use credential::CredentialStore;
+use jwt::{JwtConfig, JwtVerifier};
+use filter::Filter;
+use join::{JoinRequest, JoinRequests};
+use message::StoredMessage;
+use queue::{Queue, QueueConfig};
+use role::Operation;
+use scheduler::{ScheduledMessage, Scheduler};
+use session::{Session, SessionId, ADDRESS_PREFIX};
+use token::{TokenClaims, TokenService};
+use topic::TopicTrie;
+use transaction::{StagedAck, StagedPublish, Transaction, TransactionLog};
+
+use crate::storage;
+use crate::storage::error::StorageError;
+use crate::storage::log::LogConfig;
+use crate::storage::policy::SyncPolicy;
+
+/// Name of the directory holding the durable queues within the data directory
+const QUEUES: &str = "queues";
+
+/// Actor the audit log records the actions made through the API of the broker as, such as the
+/// queues provisioned by its operator
+const OPERATOR: &str = "operator";
+
+/// Configuration of the [`Broker`].
+///
+/// # Fields
+///
+/// ~ `prefetch`: Maximum number of unacknowledged messages pushed to a subscriber
+/// ~ `sweep_interval`: Period of the background sweeper expiring messages
+/// ~ `expired_retention`: How long the ids of expired messages are remembered, so that late
+///   acknowledgements are answered with [`ProtocolError::Gone109`]
+/// ~ `data_dir`: Directory the broker persists its state to, if persistence is enabled
+/// ~ `log`: Configuration of the logs durable queues are stored in
+/// ~ `sync`: Policy deciding when persisted changes are flushed to disk
+/// ~ `alias_retention`: How long the former name of a renamed queue keeps redirecting to it
+/// ~ `join_timeout`: How long a request to join a private queue awaits the decision of its moderator
+/// ~ `token_keys`: Keys installed on the token service on start, by id, the last one signing new tokens
+/// ~ `credentials`: File the users authenticating through the `Basic` scheme are stored in, if any
+/// ~ `max_auth_failures`: Number of consecutive failed attempts to authenticate locking a user out
+/// ~ `lockout`: How long a user stays locked out
+/// ~ `jwt`: Configuration of the verification of the JWTs of an external identity provider, if they are accepted
+/// ~ `resumption`: How long the session of a disconnected client awaits resumption, if sessions are resumable
+/// ~ `audit`: File authentication attempts and changes to queues and their permissions are recorded in,
+///   along with the key authenticating its entries, if any
+/// ~ `administrators`: Identities managing the users of the broker through manager actions
+pub struct BrokerConfig {
+     prefetch: usize,
+     sweep_interval: Duration,
+     expired_retention: Duration,
+     data_dir: Option<PathBuf>,
+     log: LogConfig,
+     sync: SyncPolicy,
+     alias_retention: Duration,
+     join_timeout: Duration,
+     token_keys: Vec<(String, Vec<u8>)>,
+     credentials: Option<PathBuf>,
+     max_auth_failures: u32,
+     lockout: Duration,
+     jwt: Option<JwtConfig>,
+     resumption: Option<Duration>,
+     audit: Option<(PathBuf, Vec<u8>)>,
+     administrators: BTreeSet<String>,
+}
+
+impl BrokerConfig {
+     /// Sets the maximum number of unacknowledged messages pushed to a subscriber
+     pub fn with_prefetch(mut self, prefetch: usize) -> Self {
+          self.prefetch = prefetch;
+          self
+     }
+
+     /// Sets the period of the background sweeper
+     pub fn with_sweep_interval(mut self, interval: Duration) -> Self {
+          self.sweep_interval = interval;
+          self
+     }
+
+     /// Sets how long the ids of expired messages are remembered
+     pub fn with_expired_retention(mut self, retention: Duration) -> Self {
+          self.expired_retention = retention;
+          self
+     }
+
+     /// Enables persistence, storing the state of the broker in `dir`
+     pub fn with_data_dir(mut self, dir: PathBuf) -> Self {
+          self.data_dir = Some(dir);
+          self
+     }
+
+     /// Sets the configuration of the logs durable queues are stored in
+     pub fn with_log(mut self, log: LogConfig) -> Self {
+          self.log = log;
+          self
+     }
+
+     /// Sets the policy deciding when persisted changes are flushed to disk.
+     /// With [`SyncPolicy::GroupCommit`] the flusher must be running, see [`Broker::spawn_flusher`].
+     pub fn with_sync(mut self, sync: SyncPolicy) -> Self {
+          self.sync = sync;
+          self
+     }
+
+     /// Sets how long the former name of a renamed queue keeps redirecting to it
+     pub fn with_alias_retention(mut self, retention: Duration) -> Self {
+          self.alias_retention = retention;
+          self
+     }
+
+     /// Sets how long a request to join a private queue awaits the decision of its moderator
+     pub fn with_join_timeout(mut self, timeout: Duration) -> Self {
+          self.join_timeout = timeout;
+          self
+     }
+
+     /// Installs the key `id` on the token service on start. The key installed last signs new
+     /// tokens, while the others keep verifying the tokens they signed.
+     pub fn with_token_key(mut self, id: String, secret: Vec<u8>) -> Self {
+          self.token_keys.push((id, secret));
+          self
+     }
+
+     /// Loads the users authenticating through the `Basic` scheme from the file at `path`,
+     /// rewritten as they are managed. Without it users are only kept in memory.
+     pub fn with_credentials(mut self, path: PathBuf) -> Self {
+          self.credentials = Some(path);
+          self
+     }
+
+     /// Locks a user out for `lockout` once it failed `max_failures` times in a row to authenticate
+     pub fn with_lockout(mut self, max_failures: u32, lockout: Duration) -> Self {
+          self.max_auth_failures = max_failures;
+          self.lockout = lockout;
+          self
+     }
+
+     /// Accepts the JWTs of an external identity provider, verified as configured by `jwt`
+     pub fn with_jwt(mut self, jwt: JwtConfig) -> Self {
+          self.jwt = Some(jwt);
+          self
+     }
+
+     /// Makes sessions resumable: every response to a request authenticating its client carries
+     /// a cookie in a `cookie` storage cell, resuming the session from another connection
+     /// through `MTPAuth::Cookie` for `grace` after the client disconnected, see
+     /// [`Broker::disconnect`]
+     pub fn with_resumption(mut self, grace: Duration) -> Self {
+          self.resumption = Some(grace);
+          self
+     }
+
+     /// Records every authentication attempt and every change clients make to queues and their
+     /// permissions in the audit log at `path`, see [`AuditLog`]. Its entries are authenticated
+     /// with `key`, which must be kept outside the log, away from whoever may write to it.
+     pub fn with_audit_log(mut self, path: PathBuf, key: Vec<u8>) -> Self {
+          self.audit = Some((path, key));
+          self
+     }
+
+     /// Lets the clients authenticated as `identity` add, remove and unlock the users of the
+     /// broker and change their passwords through manager actions
+     pub fn with_administrator(mut self, identity: String) -> Self {
+          self.administrators.insert(identity);
+          self
+     }
+}
+
+/// Default implementation for [BrokerConfig]
+impl Default for BrokerConfig {
+     fn default() -> Self {
+          Self {
+               prefetch: 16,
+               sweep_interval: Duration::from_secs(1),
+               expired_retention: Duration::from_secs(300),
+               data_dir: None,
+               log: LogConfig::default(),
+               sync: SyncPolicy::Always,
+               alias_retention: Duration::from_secs(7 * 24 * 3600),
+               join_timeout: Duration::from_secs(3600),
+               token_keys: Vec::new(),
+               credentials: None,
+               max_auth_failures: 5,
+               lockout: Duration::from_secs(900),
+               jwt: None,
+               resumption: None,
+               audit: None,
+               administrators: BTreeSet::new(),
+          }
+     }
+}
+
+/// State of the broker, guarded by a single lock so that every request observes and leaves
+/// the queues in a consistent state.
+///
+/// # Fields
+///
+/// ~ `queues`: Queues hosted by the broker, by name
+/// ~ `sessions`: Sessions of the connected clients, by id
+/// ~ `scheduler`: Messages published for delayed delivery
+/// ~ `topics`: Names of the queues by topic level
+/// ~ `transactions`: Open transactions, by the session that began them
+/// ~ `journal`: Journal of the committed transactions
+/// ~ `aliases`: Former names of renamed queues
+/// ~ `joins`: Requests to join private queues awaiting the decision of their moderators
+/// ~ `tokens`: Service minting and verifying the tokens clients authenticate with, shared with
+///   the authentication chain of the broker
+/// ~ `credentials`: Users clients authenticate as through the `Basic` scheme, shared with the
+///   authentication chain of the broker
+/// ~ `jwt`: Verifier of the JWTs of an external identity provider if they are accepted, shared
+///   with the authentication chain of the broker
+/// ~ `cookies`: Cookies clients resume their sessions with
+/// ~ `audit`: Log authentication attempts and changes to queues and their permissions are recorded in, if any
+struct BrokerState {
+     queues: HashMap<String, Queue>,
+     sessions: HashMap<SessionId, Session>,
+     scheduler: Scheduler,
+     topics: TopicTrie,
+     transactions: HashMap<SessionId, Transaction>,
+     journal: TransactionLog,
+     aliases: Aliases,
+     joins: JoinRequests,
+     tokens: Arc<RwLock<TokenService>>,
+     credentials: Arc<RwLock<CredentialStore>>,
+     jwt: Option<Arc<JwtVerifier>>,
+     cookies: CookieJar,
+     audit: Option<AuditLog>,
+}
+
+impl BrokerState {
+     /// Checks whether changes to the persisted state have not been flushed to disk yet
+     fn is_dirty(&self) -> bool {
+          self.scheduler.is_dirty() || self.journal.is_dirty() || self.queues.values().any(Queue::is_dirty)
+     }
+
+     /// Flushes the changes to the persisted state to disk. The journal of the transactions is
+     /// flushed last, so that a transaction is only marked as done once its changes are on disk.
+     fn sync(&mut self) -> Result<(), StorageError> {
+          for queue in self.queues.values_mut().filter(|queue| queue.is_dirty()) {
+               queue.sync()?;
+          }
+          self.scheduler.sync()?;
+          self.journal.sync()
+     }
+
+     /// Hands out handles to flush the changes to the persisted state to disk once the state is
+     /// unlocked, in the order [`BrokerState::sync`] flushes them. The changes count as flushed
+     /// from then on.
+     fn detach_sync(&mut self) -> Result<Vec<File>, StorageError> {
+          let mut files = Vec::new();
+          for queue in self.queues.values_mut().filter(|queue| queue.is_dirty()) {
+               files.extend(queue.detach_sync()?);
+          }
+          files.extend(self.scheduler.detach_sync()?);
+          files.extend(self.journal.detach_sync()?);
+          Ok(files)
+     }
+}
+
+/// A change made by delivering a published message, undone when the transaction it belongs to
+/// cannot be applied in full
+enum Applied {
+     /// The message was enqueued in `queue` at `offset`
+     Enqueued {
+          queue: String,
+          offset: u64,
+     },
+
+     /// The message `id` was scheduled for later delivery to `queue`
+     Scheduled {
+          queue: String,
+          id: String,
+     },
+}
+
+/// The message broker.
+///
+/// Clients are attached with [`Broker::connect`] and their requests are answered through
+/// [`Broker::handle`]. Messages are pushed to subscribers through the outbox of their session.
+///
+/// When persistence is enabled, a request is only answered once the changes it made reached
+/// the durability level of the configured [`SyncPolicy`].
+///
+/// # Example
+///
+/// ```no_run
+/// use std::net::SocketAddr;
+/// use std::sync::Arc;
+///
+/// use net::protocol::MTPPayload;
+/// use server::broker::{Broker, BrokerConfig};
+/// use server::storage::error::StorageError;
+///
+/// async fn serve(address: SocketAddr, payload: MTPPayload) -> Result<(), StorageError> {
+///     let broker = Arc::new(Broker::new(BrokerConfig::default())?);
+///     let sweeper = broker.spawn_sweeper();
+///     let scheduler = broker.spawn_scheduler();
+///     let flusher = broker.spawn_flusher();
+///
+///     let (session, mut outbox) = broker.connect(address);
+///     let response = broker.handle(session, payload).await;
+///     Ok(())
+/// }
+/// ```
+pub struct Broker {
+     config: BrokerConfig,
+     state: Mutex<BrokerState>,
+     provider: Arc<dyn AuthProvider>,
+     next_session: AtomicU64,
+     next_message: AtomicU64,
+     timer: Notify,
+     arrivals: Notify,
+     commits: watch::Sender<(u64, bool)>,
+     syncing: AsyncMutex<()>,
+}
+
+impl Broker {
+     /// Creates a broker without any sessions.
+     /// When persistence is enabled the state stored in the data directory is restored,
+     /// recovering from an unclean shutdown if needed. The transactions whose commit was
+     /// interrupted are applied again.
+     ///
+     /// # Errors
+     /// A [`StorageError`] if the persisted state could not be read, or the audit log does not verify
+     pub fn new(config: BrokerConfig) -> Result<Self, StorageError> {
+          let mut queues = HashMap::new();
+          let (scheduler, (journal, interrupted), aliases, mut tokens) = match &config.data_dir {
+               Some(dir) => {
+                    storage::create_dir(&dir.join(QUEUES))?;
+                    for entry in std::fs::read_dir(dir.join(QUEUES))? {
+                         let path = entry?.path();
+                         let mut queue = Queue::restore(&path, config.log.clone())?;
+                         // a rename interrupted before the directory of the queue was moved
+                         if path.file_name().and_then(|name| name.to_str()) != Some(queue::directory(queue.name()).as_str()) {
+                              queue.rename(queue.name().to_string(), config.log.clone())?;
+                         }
+                         queues.insert(queue.name().to_string(), queue);
+                    }
+                    (Scheduler::open(dir)?, TransactionLog::open(dir)?, Aliases::open(dir, SystemTime::now())?, TokenService::open(dir, SystemTime::now())?)
+               },
+               None => (Scheduler::in_memory(), (TransactionLog::in_memory(), Vec::new()), Aliases::in_memory(), TokenService::in_memory()),
+          };
+          for (id, secret) in &config.token_keys {
+               tokens.rotate(id.clone(), secret.clone());
+          }
+          let credentials = match &config.credentials {
+               Some(path) => CredentialStore::load(path, config.max_auth_failures, config.lockout)?,
+               None => CredentialStore::in_memory(config.max_auth_failures, config.lockout),
+          };
+          let tokens = Arc::new(RwLock::new(tokens));
+          let credentials = Arc::new(RwLock::new(credentials));
+          let jwt = config.jwt.clone().map(JwtVerifier::open).transpose()?.map(Arc::new);
+          let audit = config.audit.as_ref().map(|(path, key)| AuditLog::open(path, key)).transpose()?;
+
+          let mut topics = TopicTrie::new();
+          for name in queues.keys() {
+               topics.insert(name);
+          }
+
+          // the built-in mechanisms check credentials until another provider is installed
+          let mut providers = ProviderChain::new().with(Arc::clone(&tokens));
+          if let Some(jwt) = &jwt {
+               providers = providers.with(Arc::clone(jwt));
+          }
+          providers = providers.with(Arc::clone(&credentials));
+
+          let broker = Self {
+               config,
+               provider: Arc::new(providers),
+               state: Mutex::new(BrokerState {
+                    queues,
+                    sessions: HashMap::new(),
+                    scheduler,
+                    topics,
+                    transactions: HashMap::new(),
+                    journal,
+                    aliases,
+                    joins: JoinRequests::new(),
+                    tokens,
+                    credentials,
+                    jwt,
+                    cookies: CookieJar::new(),
+                    audit,
+               }),
+               next_session: AtomicU64::new(1),
+               next_message: AtomicU64::new(1),
+               timer: Notify::new(),
+               arrivals: Notify::new(),
+               commits: watch::Sender::new((0, true)),
+               syncing: AsyncMutex::new(()),
+          };
+          broker.recover(interrupted)?;
+
+          Ok(broker)
+     }
+
+     /// Checks the credentials of requests with `provider` instead of the built-in mechanisms,
+     /// such as a [`ProviderChain`] of other providers and the built-in ones, see
+     /// [`Broker::token_provider`], [`Broker::jwt_provider`] and [`Broker::credential_provider`]
+     pub fn with_auth_provider(mut self, provider: Arc<dyn AuthProvider>) -> Self {
+          self.provider = provider;
+          self
+     }
+
+     /// Retrieves the [`TokenService`] verifying the tokens minted by the broker, as a provider
+     /// to assemble an authentication chain with
+     pub fn token_provider(&self) -> Arc<dyn AuthProvider> {
+          self.lock().tokens.clone()
+     }
+
+     /// Retrieves the [`JwtVerifier`] of the JWTs of an external identity provider, as a
+     /// provider to assemble an authentication chain with
+     ///
+     /// # Returns
+     /// The verifier, or `None` if JWTs are not accepted
+     pub fn jwt_provider(&self) -> Option<Arc<dyn AuthProvider>> {
+          self.lock().jwt.clone().map(|jwt| jwt as Arc<dyn AuthProvider>)
+     }
+
+     /// Retrieves the [`CredentialStore`] of the users of the broker, as a provider to assemble
+     /// an authentication chain with
+     pub fn credential_provider(&self) -> Arc<dyn AuthProvider> {
+          self.lock().credentials.clone()
+     }
+
+     /// Opens a session for a client connected from `address`
+     ///
+     /// # Returns
+     /// The id of the session and the receiving half of its outbox, on which the broker pushes
+     /// deliveries and notifications
+     pub fn connect(&self, address: SocketAddr) -> (SessionId, UnboundedReceiver<MTPResponse>) {
+          let id = self.next_session.fetch_add(1, Ordering::Relaxed);
+          let (outbox, inbox) = mpsc::unbounded_channel();
+
+          self.lock().sessions.insert(id, Session::new(id, address, outbox));
+
+          (id, inbox)
+     }
+
+     /// Records the identity the client of `session` authenticated as, which then identifies the
+     /// client to the queues it consumes from and is admitted to
+     ///
+     /// # Errors
+     /// [`ProtocolError::Unauthorized101`] if the session is not open or the identity is
+     /// reserved, see [`identifiable`]
+     pub fn authenticate(&self, session: SessionId, identity: String) -> Result<(), ProtocolError> {
+          identifiable(&identity)?;
+          match self.lock().sessions.get_mut(&session) {
+               Some(session) => {
+                    session.authenticate(identity);
+                    Ok(())
+               },
+               None => Err(ProtocolError::Unauthorized101(Error::new("Session is not open".to_string()))),
+          }
+     }
+
+     /// Mints a token authenticating its holder as the identity of `claims` until they expire,
+     /// granting it the roles and restricting it to the queues they list
+     ///
+     /// # Errors
+     /// [`ProtocolError::PreconditionFailed110`] if no key is installed to sign it with
+     pub fn mint_token(&self, claims: TokenClaims) -> Result<String, ProtocolError> {
+          write(&self.lock().tokens).mint(claims, SystemTime::now())
+     }
+
+     /// Installs the key `id` on the token service, signing new tokens with it from now on.
+     /// The tokens signed with the keys installed before remain valid until those are retired.
+     pub fn rotate_token_key(&self, id: String, secret: Vec<u8>) {
+          write(&self.lock().tokens).rotate(id, secret);
+     }
+
+     /// Retires the key `id` of the token service, invalidating the tokens it signed. The
+     /// clients that authenticated with one of them are no longer authenticated.
+     ///
+     /// # Errors
+     /// [`ProtocolError::NotFound103`] if no key `id` is installed
+     pub fn retire_token_key(&self, id: &str) -> Result<(), ProtocolError> {
+          let mut state = self.lock();
+          if !write(&state.tokens).retire(id) {
+               return Err(ProtocolError::NotFound103(Error::new(format!("Token key {} not found", id))));
+          }
+          self.expel(&mut state, SystemTime::now());
+          self.persist(&mut state);
+          Ok(())
+     }
+
+     /// Revokes `token`, which is refused from now on. The clients that authenticated with it
+     /// are no longer authenticated.
+     ///
+     /// # Errors
+     /// - [`ProtocolError::Unauthorized101`] if the token was not signed by an installed key
+     /// - Any error the revocation could not be persisted with
+     pub fn revoke_token(&self, token: &str) -> Result<(), ProtocolError> {
+          let mut state = self.lock();
+          write(&state.tokens).revoke(token)?;
+          self.expel(&mut state, SystemTime::now());
+          self.persist(&mut state);
+          Ok(())
+     }
+
+     /// Adds the user `username`, authenticating through the `Basic` scheme with `password`
+     ///
+     /// # Errors
+     /// - [`ProtocolError::Conflict108`] if the user exists
+     /// - [`ProtocolError::BadRequest100`] if the username is empty or holds a colon or whitespace
+     /// - Any error the store could not be persisted with
+     pub fn add_user(&self, username: String, password: &str) -> Result<(), ProtocolError> {
+          let hash = credential::hash(password)?;
+          enrol(&self.lock(), username, hash)
+     }
+
+     /// Changes the password of the user `username` to `password`
+     ///
+     /// # Errors
+     /// - [`ProtocolError::NotFound103`] if the user does not exist
+     /// - Any error the store could not be persisted with
+     pub fn change_password(&self, username: String, password: &str) -> Result<(), ProtocolError> {
+          let hash = credential::hash(password)?;
+          rehash(&self.lock(), username, hash)
+     }
+
+     /// Removes the user `username`. The clients that authenticated as the user with a password
+     /// rather than with a token, which carries its own expiry, are no longer authenticated.
+     ///
+     /// # Errors
+     /// - [`ProtocolError::NotFound103`] if the user does not exist
+     /// - Any error the store could not be persisted with
+     pub fn remove_user(&self, username: &str) -> Result<(), ProtocolError> {
+          self.withdraw(&mut self.lock(), username)
+     }
+
+     /// Removes the user `username` as [`Broker::remove_user`] does
+     fn withdraw(&self, state: &mut BrokerState, username: &str) -> Result<(), ProtocolError> {
+          if !write(&state.credentials).remove(username)? {
+               return Err(ProtocolError::NotFound103(Error::new(format!("User {} not found", username))));
+          }
+
+          let sessions: Vec<SessionId> = state.sessions.values()
+               .filter(|session| session.identity() == Some(username))
+               .filter(|session| session.claims().is_none_or(|claims| claims.id().is_empty() && claims.expires().is_none()))
+               .map(Session::id)
+               .collect();
+          self.deauthenticate(state, sessions);
+          self.persist(state);
+          Ok(())
+     }
+
+     /// Lifts the lockout of the user `username`, forgetting its failed attempts to authenticate
+     ///
+     /// # Errors
+     /// [`ProtocolError::NotFound103`] if the user failed no attempt
+     pub fn unlock_user(&self, username: &str) -> Result<(), ProtocolError> {
+          unlock(&self.lock(), username)
+     }
+
+     /// Retrieves the users authenticating through the `Basic` scheme, in alphabetical order
+     pub fn users(&self) -> Vec<String> {
+          read(&self.lock().credentials).usernames()
+     }
+
+     /// Closes a session, removing the consumers it registered, deleting its temporary queues,
+     /// withdrawing its requests to join private queues and rolling back its open transaction.
+     ///
+     /// When sessions are resumable, a session holding a cookie is parked instead: its requests
+     /// to join queues are withdrawn and its transaction rolled back, while its consumers, along
+     /// with the messages they have in flight, and its temporary queues are kept for the grace
+     /// configured. The session is closed once the grace ends unless it is resumed.
+     pub fn disconnect(&self, session: SessionId) {
+          let mut state = self.lock();
+          match self.config.resumption.filter(|_| state.cookies.contains(session)) {
+               Some(grace) => park(&mut state, session, SystemTime::now() + grace),
+               None => close(&mut state, session),
+          }
+          self.persist(&mut state);
+     }
+
+     /// Provisions the queue `name`. Provisioning an existing queue with the configuration it
+     /// was created with does nothing. A durable queue is stored in the data directory of the
+     /// broker.
+     ///
+     /// A queue named after a pattern, such as `orders.*.created`, subscribes to the topics the
+     /// pattern matches: it receives a copy of every message published to them. A queue
+     /// provisioned under the former name of a renamed queue replaces the alias of that name.
+     ///
+     /// The provisioning is recorded in the audit log as made by the operator of the broker.
+     ///
+     /// # Errors
+     /// - [`ProtocolError::Conflict108`] if the queue exists with a different configuration, or
+     ///   another queue spells the same name with other separators
+     /// - [`ProtocolError::PreconditionFailed110`] if the queue is durable and persistence is disabled
+     /// - [`ProtocolError::InsufficientStorage126`] if the queue could not be stored, or its
+     ///   provisioning could not be recorded in the audit log
+     pub fn declare(&self, name: String, config: QueueConfig) -> Result<(), ProtocolError> {
+          let entry = AuditEntry::new(OPERATOR.to_string(), None, "declare", name.clone());
+          let intent = record(&mut self.lock(), entry.clone())?;
+          let declared = self.provision(name, config);
+          record(&mut self.lock(), entry.with_outcome(&declared).concluding(intent))?;
+          declared
+     }
+
+     /// Provisions the queue `name` as [`Broker::declare`] does, without recording it in the
+     /// audit log
+     fn provision(&self, name: String, config: QueueConfig) -> Result<(), ProtocolError> {
+          let mut state = self.lock();
+
+          if let Some(queue) = state.queues.get(&name) {
+               if *queue.config() != config {
+                    return Err(ProtocolError::Conflict108(Error::new(format!("Queue {} exists with a different configuration", name))));
+               }
+               return Ok(());
+          }
+          if let Some(existing) = named(&state, &name) {
+               return Err(ProtocolError::Conflict108(Error::new(format!("Queue {} names the same topic as queue {}", name, existing))));
+          }
+
+          let queue = if config.is_durable() {
+               let dir = self.config.data_dir.as_ref()
+                    .ok_or_else(|| ProtocolError::PreconditionFailed110(Error::new("Durable queues require persistence to be enabled".to_string())))?;
+               Queue::open(name.clone(), config, &dir.join(QUEUES).join(queue::directory(&name)), self.config.log.clone())?
+          } else {
+               Queue::new(name.clone(), config)
+          };
+          state.aliases.remove(&name)?;
+          state.topics.insert(&name);
+          state.queues.insert(name, queue);
+
+          Ok(())
+     }
+
+     /// Handles a request sent over `session`. A request naming a queue by a former name is
+     /// redirected to the queue, and its response carries the current name of the queue in a
+     /// `deprecated` storage cell.
+     ///
+     /// # Returns
+     /// The response to the request. Failures are reported through its status code.
+     pub async fn handle(&self, session: SessionId, payload: MTPPayload) -> MTPResponse {
+          let (headers, renamed) = self.redirect(payload.get_headers().unwrap_or_else(MTPHeaders::empty));
+
+          let (authenticated, result) = match self.credentials(session, &headers).await {
+               Ok(authenticated) => (authenticated, match payload.get_request() {
+                    MTPRequestType::Subscribe => self.subscribe(session, &headers),
+                    MTPRequestType::Unsubscribe => self.unsubscribe(session, &headers),
+                    MTPRequestType::Publish => self.publish(session, &headers, payload.get_message()),
+                    MTPRequestType::Pull => self.pull(session, &headers).await,
+                    MTPRequestType::Acknowledge => self.acknowledge(session, &headers),
+                    MTPRequestType::Ping => Ok(success(MTPStorage::new(Vec::new()))),
+                    MTPRequestType::Manage => self.manage(session, &headers).await,
+               }),
+               Err(err) => (false, Err(err)),
+          };
+
+          let result = match result {
+               Ok(response) => self.commit().await.map(|_| response),
+               Err(err) => Err(err),
+          };
+          let result = result.map(|response| {
+               renamed.into_iter().fold(response, |response, name| response.with_cell("deprecated".to_string(), name))
+          });
+          let result = match self.config.resumption {
+               Some(_) if authenticated => result.map(|response| {
+                    let cookie = self.lock().cookies.issue(session);
+                    response.with_cell("cookie".to_string(), cookie)
+               }),
+               _ => result,
+          };
+
+          result.unwrap_or_else(failure)
+     }
+
+     /// Closes the parked sessions whose grace ended, expires the messages of every queue whose
+     /// time-to-live has elapsed, applies the retention and compaction of durable queues, lifts
+     /// the bans that ended, expires the requests to join private queues that were not decided
+     /// in time and the authentication of the clients whose token expired, forgets the lockouts
+     /// that ended and reads the JWKS file again if it was modified
+     pub fn sweep(&self) {
+          let now = SystemTime::now();
+          let mut state = self.lock();
+
+          let ended: Vec<SessionId> = state.sessions.values()
+               .filter(|session| session.parked().is_some_and(|until| until <= now))
+               .map(Session::id)
+               .collect();
+          for session in ended {
+               close(&mut state, session);
+          }
+
+          let names: Vec<String> = state.queues.keys().cloned().collect();
+          for name in names {
+               if let Some(queue) = state.queues.get_mut(&name) {
+                    queue.expire(now);
+                    queue.prune_expired(now - self.config.expired_retention);
+                    // retried on the next sweep if the segments cannot be deleted or rewritten
+                    let _ = queue.retain(now);
+                    if queue.config().has_ended_bans(now) {
+                         let config = queue.config().clone().without_bans_ended(now);
+                         let _ = queue.set_config(config);
+                    }
+               }
+               self.settle(&mut state, &name, now);
+          }
+          // retried on the next sweep if the journal cannot be rewritten
+          let _ = state.aliases.prune(now);
+          let _ = write(&state.tokens).prune(now);
+          write(&state.credentials).prune(now);
+          if let Some(jwt) = &state.jwt {
+               let _ = jwt.refresh();
+          }
+          self.expel(&mut state, now);
+          for (queue, _, request) in state.joins.expire(now) {
+               if let Some(session) = state.sessions.get(&request.session()) {
+                    session.push(decision(queue, "expired"));
+               }
+          }
+          self.persist(&mut state);
+     }
+
+     /// Spawns the background sweeper, running [`Broker::sweep`] every `sweep_interval`
+     pub fn spawn_sweeper(self: &Arc<Self>) -> JoinHandle<()> {
+          let broker = Arc::clone(self);
+          tokio::spawn(async move {
+               let mut interval = tokio::time::interval(broker.config.sweep_interval);
+               loop {
+                    interval.tick().await;
+                    broker.sweep();
+               }
+          })
+     }
+
+     /// Spawns the task releasing scheduled messages into their queues as they become due
+     pub fn spawn_scheduler(self: &Arc<Self>) -> JoinHandle<()> {
+          let broker = Arc::clone(self);
+          tokio::spawn(async move {
+               loop {
+                    broker.release_due();
+
+                    let next = broker.lock().scheduler.next_due();
+                    match next {
+                         Some(at) => {
+                              let wait = at.duration_since(SystemTime::now()).unwrap_or_default();
+                              tokio::select! {
+                                   _ = tokio::time::sleep(wait) => {},
+                                   _ = broker.timer.notified() => {},
+                              }
+                         },
+                         None => broker.timer.notified().await,
+                    }
+               }
+          })
+     }
+
+     /// Spawns the flusher, flushing the changes of every request at once when the broker is
+     /// configured with [`SyncPolicy::GroupCommit`]. The task ends at once under other policies.
+     pub fn spawn_flusher(self: &Arc<Self>) -> JoinHandle<()> {
+          let broker = Arc::clone(self);
+          tokio::spawn(async move {
+               let interval = match broker.config.sync {
+                    SyncPolicy::GroupCommit { interval } => interval,
+                    _ => return,
+               };
+
+               let mut interval = tokio::time::interval(interval);
+               loop {
+                    interval.tick().await;
+                    broker.flush();
+               }
+          })
+     }
+
+     /// Flushes the pending changes to the persisted state to disk and answers the requests
+     /// waiting on them
+     pub fn flush(&self) {
+          let mut state = self.lock();
+          let synced = state.sync().is_ok();
+          self.commits.send_modify(|(epoch, ok)| {
+               *epoch += 1;
+               *ok = synced;
+          });
+     }
+
+     /// Enqueues the scheduled messages that are due
+     pub fn release_due(&self) {
+          let now = SystemTime::now();
+          let mut state = self.lock();
+
+          for scheduled in state.scheduler.take_due(now) {
+               let queue = scheduled.queue().to_string();
+               let topic = resolve(&state, &queue, now).unwrap_or_else(|| queue.clone());
+               let (id, headers, message) = scheduled.into_parts();
+               let targets = targets_of(&state, &topic);
+
+               // a message whose topic no queue matches anymore is dropped, while one that cannot
+               // be stored stays in the schedule journal and is released again after a restart,
+               // as is one whose release cannot be recorded
+               let published = StoredMessage::new(id.clone(), headers, message, now, None);
+               if let Ok(enqueued) = self.fan_out(&mut state, &topic, &targets, &published) {
+                    let _ = state.scheduler.released(&queue, &id);
+                    for (target, _) in enqueued {
+                         self.settle(&mut state, &target, now);
+                    }
+               }
+          }
+          self.persist(&mut state);
+     }
+
+     /// Authenticates the client of `session` as [`Broker::verify_credentials`] does, recording
+     /// the attempt in the audit log if the request carries credentials. The attempt is recorded
+     /// as pending before the credentials are checked, then concluded as made by the identity the
+     /// client authenticated as, along with the username claimed through the `Basic` scheme if
+     /// any. A client whose authentication cannot be concluded in the log is not left
+     /// authenticated.
+     ///
+     /// # Returns
+     /// Whether the request authenticated the client
+     ///
+     /// # Errors
+     /// - Any error of [`Broker::verify_credentials`]
+     /// - [`ProtocolError::InsufficientStorage126`] if the attempt could not be recorded
+     async fn credentials(&self, session: SessionId, headers: &MTPHeaders) -> Result<bool, ProtocolError> {
+          let Some((method, credentials)) = request::authentication(headers) else {
+               return self.verify_credentials(session, headers).await;
+          };
+          let attempt = |state: &BrokerState| {
+               let actor = actor_of(state, session);
+               let entry = AuditEntry::new(actor.0, actor.1, "authenticate", audit::method(&method).to_string());
+               match (&method, credential::basic(&credentials)) {
+                    (MTPAuth::Authorization { scheme: AuthSchemes::Basic }, Ok((username, _))) => entry.with_detail(format!("username={}", username)),
+                    _ => entry,
+               }
+          };
+
+          let intent = {
+               let mut state = self.lock();
+               let entry = attempt(&state);
+               record(&mut state, entry)?
+          };
+          let result = self.verify_credentials(session, headers).await;
+
+          let mut state = self.lock();
+          let entry = attempt(&state).with_outcome(&result).concluding(intent);
+          if let Err(err) = record(&mut state, entry) {
+               if result.is_ok_and(|authenticated| authenticated) {
+                    self.deauthenticate(&mut state, vec![session]);
+               }
+               return Err(err);
+          }
+          result
+     }
+
+     /// Authenticates the client of `session` with the credentials of the `Authentication` units
+     /// of a request, if it carries any. They are checked by the authentication provider of the
+     /// broker on a blocking thread, as providers may call remote services or hash passwords,
+     /// see [`Broker::with_auth_provider`]. Cookies resuming sessions are checked by
+     /// [`Broker::session_credentials`], as are requests carrying no credentials.
+     ///
+     /// # Returns
+     /// Whether the request authenticated the client
+     ///
+     /// # Errors
+     /// - [`ProtocolError::Unauthorized101`] if the session is not open, no provider of the
+     ///   chain recognises the credentials or the identity they vouch for is reserved, see
+     ///   [`identifiable`]
+     /// - The error of a provider refusing the credentials other than by not recognising them,
+     ///   such as [`ProtocolError::TooManyRequests114`] if a user is locked out
+     /// - [`ProtocolError::InternalServerError120`] if a provider panicked
+     /// - Any error of [`Broker::session_credentials`]
+     async fn verify_credentials(&self, session: SessionId, headers: &MTPHeaders) -> Result<bool, ProtocolError> {
+          let units: Vec<MTPHeaderUnit> = headers.units().iter()
+               .filter(|unit| matches!(unit, MTPHeaderUnit::Authentication { key, .. } if !matches!(key, MTPAuth::Cookie)))
+               .cloned()
+               .collect();
+          if units.is_empty() {
+               return self.session_credentials(session, headers);
+          }
+
+          let peer = match self.lock().sessions.get(&session) {
+               Some(session) => Peer::new(session.address()),
+               None => return Err(ProtocolError::Unauthorized101(Error::new("Session is not open".to_string()))),
+          };
+          let provider = Arc::clone(&self.provider);
+          let identity = tokio::task::spawn_blocking(move || provider.authenticate(&units, &peer)).await
+               .unwrap_or_else(|_| Err(ProtocolError::InternalServerError120(Error::new("Authentication provider failed".to_string()))))?;
+          identifiable(identity.name())?;
+          session_of(&mut self.lock(), session)?.authenticate_with(identity.into());
+          Ok(true)
+     }
+
+     /// Resumes the session the cookie of a request was issued to, see [`Broker::resume`], or
+     /// checks that the token the client of `session` authenticated with before is still valid
+     /// if the request carries no credentials, forgetting its authentication if it is not
+     ///
+     /// # Returns
+     /// Whether the request authenticated the client
+     ///
+     /// # Errors
+     /// - [`ProtocolError::Unauthorized101`] if the session is not open, sessions are not
+     ///   resumable, the cookie is refused, or the token the client authenticated with expired,
+     ///   was revoked or its key retired
+     fn session_credentials(&self, session: SessionId, headers: &MTPHeaders) -> Result<bool, ProtocolError> {
+          let now = SystemTime::now();
+          let mut state = self.lock();
+
+          match request::authentication(headers) {
+               Some((MTPAuth::Cookie, cookie)) if self.config.resumption.is_some() => {
+                    self.resume(&mut state, session, &cookie, now)?;
+                    return Ok(true);
+               },
+               Some(_) => return Err(ProtocolError::Unauthorized101(Error::new("Authentication method is not supported".to_string()))),
+               None => {},
+          }
+
+          let checked = state.sessions.get(&session)
+               .and_then(Session::claims)
+               .map(|claims| read(&state.tokens).check(claims, now));
+          match checked {
+               Some(Err(err)) => {
+                    self.expel(&mut state, now);
+                    Err(err)
+               },
+               _ => Ok(false),
+          }
+     }
+
+     /// Resumes in `session` the session the cookie `cookie` was issued to, whether parked or
+     /// still open on a connection it is then taken from. The session takes over its
+     /// authentication, its transaction, its consumers and its temporary queues, and is pushed
+     /// the messages they had in flight again. The cookie is used up.
+     ///
+     /// # Errors
+     /// [`ProtocolError::Unauthorized101`] if either session is not open, the cookie was not
+     /// issued, was used up or its session was closed, or the token the resumed session was
+     /// authenticated with is no longer valid
+     fn resume(&self, state: &mut BrokerState, session: SessionId, cookie: &str, now: SystemTime) -> Result<(), ProtocolError> {
+          let invalid = || ProtocolError::Unauthorized101(Error::new("Cookie is not valid".to_string()));
+
+          session_of(state, session)?;
+          let resumed = state.cookies.take(cookie)
+               .filter(|resumed| *resumed != session)
+               .and_then(|resumed| state.sessions.remove(&resumed))
+               .ok_or_else(invalid)?;
+          let from = resumed.id();
+          if let Some(Err(err)) = resumed.claims().map(|claims| read(&state.tokens).check(claims, now)) {
+               close(state, from);
+               return Err(err);
+          }
+          if resumed.parked().is_some_and(|until| until <= now) {
+               close(state, from);
+               return Err(invalid());
+          }
+
+          session_of(state, session)?.resume(resumed);
+          if let Some(transaction) = state.transactions.remove(&from) {
+               state.transactions.insert(session, transaction);
+          }
+          state.joins.release(from);
+          let names: Vec<String> = state.queues.keys().cloned().collect();
+          for name in names {
+               if let Some(queue) = state.queues.get_mut(&name) {
+                    queue.resume(from, session);
+               }
+               self.settle(state, &name, now);
+          }
+
+          Ok(())
+     }
+
+     /// Forgets the authentication of the clients whose token is no longer valid at `now`, see
+     /// [`Broker::deauthenticate`]
+     fn expel(&self, state: &mut BrokerState, now: SystemTime) {
+          let expelled: Vec<SessionId> = {
+               let tokens = read(&state.tokens);
+               state.sessions.values()
+                    .filter(|session| session.claims().is_some_and(|claims| tokens.check(claims, now).is_err()))
+                    .map(Session::id)
+                    .collect()
+          };
+          self.deauthenticate(state, expelled);
+     }
+
+     /// Forgets the authentication of the clients of `sessions`, then unsubscribes them from the
+     /// queues their access or role no longer permits them to consume
+     fn deauthenticate(&self, state: &mut BrokerState, sessions: Vec<SessionId>) {
+          if sessions.is_empty() {
+               return;
+          }
+
+          for id in sessions {
+               if let Some(session) = state.sessions.get_mut(&id) {
+                    session.deauthenticate();
+               }
+               state.cookies.forget(id);
+          }
+          let names: Vec<String> = state.queues.keys().cloned().collect();
+          for name in names {
+               let _ = self.evict(state, &name);
+          }
+     }
+
+     /// Subscribes the client of `session` to a queue, on its own or as a member of a consumer
+     /// group, pushing the messages it holds that pass the filter of the request. A client not
+     /// admitted to a private queue requests to join it instead.
+     fn subscribe(&self, session: SessionId, headers: &MTPHeaders) -> Result<MTPResponse, ProtocolError> {
+          let name = request::subscription(headers)?;
+          let group = request::group(headers);
+          let filter = request::filter(headers)?;
+          let mut state = self.lock();
+          let client = client_of(&state, session)?;
+
+          unbanned(&state, &name, &client)?;
+          allowed(&state, &name, session)?;
+          match accessible(&state, &name, session) {
+               Err(ProtocolError::Forbidden102(_)) if matches!(queue_of(&mut state, &name)?.config().access(), QueueAccess::Private) => {
+                    return self.request_join(&mut state, session, name, client, group, filter);
+               },
+               result => result?,
+          }
+          consumable(&mut state, &name, session, &Operation::Subscribe)?.subscribe(client, session, true, group, filter)?;
+          self.settle(&mut state, &name, SystemTime::now());
+
+          Ok(success(MTPStorage::new(Vec::new())))
+     }
+
+     /// Records the request of `client` to join the private queue `name`, notifying the sessions
+     /// of the clients whose role on the queue permits authorizing it with a frame carrying the
+     /// name of the queue in the `queue` storage cell and the client in the `join` cell
+     ///
+     /// # Returns
+     /// A response carrying `true` in the `pending` storage cell
+     fn request_join(&self, state: &mut BrokerState, session: SessionId, name: String, client: String, group: Option<(String, Distribution)>, filter: Option<Filter>) -> Result<MTPResponse, ProtocolError> {
+          let until = SystemTime::now() + self.config.join_timeout;
+          let fresh = state.joins.insert(name.clone(), client.clone(), JoinRequest::new(session, group, filter, until));
+
+          if let Some(queue) = state.queues.get(&name).filter(|_| fresh) {
+               let config = queue.config();
+               let authorize = MTPManagerAction::Authorize(client.clone());
+               let deciding = |session: &&Session| role::permits(config.role(&session.client()), &Operation::Manage(&authorize));
+
+               let mut notification = MTPStorage::new(Vec::new());
+               notification.push("queue".to_string(), name.clone());
+               notification.push("join".to_string(), client);
+               for session in state.sessions.values().filter(deciding) {
+                    session.push(success(notification.clone()));
+               }
+          }
+
+          let mut storage = MTPStorage::new(Vec::new());
+          storage.push("pending".to_string(), "true".to_string());
+          Ok(success(storage))
+     }
+
+     /// Unsubscribes the client of `session` from a queue
+     fn unsubscribe(&self, session: SessionId, headers: &MTPHeaders) -> Result<MTPResponse, ProtocolError> {
+          let name = request::subscription(headers)?;
+          let mut state = self.lock();
+          let client = client_of(&state, session)?;
+
+          let removed = state.queues.get_mut(&name).is_some_and(|queue| queue.unsubscribe(&client));
+          if !removed {
+               return Err(ProtocolError::NotFound103(Error::new(format!("Not subscribed to queue {}", name))));
+          }
+
+          Ok(success(MTPStorage::new(Vec::new())))
+     }
+
+     /// Publishes a message to a topic, as [`Broker::deliver`] does. Within a transaction the
+     /// message is staged until the transaction is committed. The access of the queue named after
+     /// the topic must permit the client to publish, while queues whose pattern matches the topic
+     /// receive the message whatever their access, which governs who consumes from them. A topic
+     /// spelling the name of a queue with other separators is published to that queue.
+     fn publish(&self, session: SessionId, headers: &MTPHeaders, message: Option<MTPMessage>) -> Result<MTPResponse, ProtocolError> {
+          let message = message.ok_or_else(|| ProtocolError::BadRequest100(Error::new("Publish request without a message".to_string())))?;
+          let (name, _) = request::publish_target(headers)?;
+          if topic::is_pattern(&name) {
+               return Err(ProtocolError::BadRequest100(Error::new(format!("Cannot publish to the pattern {}", name))));
+          }
+          let id = request::message_id(headers).unwrap_or_else(|| self.generate_id());
+          let now = SystemTime::now();
+          let mut state = self.lock();
+          let name = named(&state, &name).unwrap_or(name);
+          if state.queues.contains_key(&name) {
+               permitted(&state, &name, session, &Operation::Publish)?;
+          }
+
+          if !state.transactions.contains_key(&session) {
+               let client = client_of(&state, session)?;
+               let mut applied = Vec::new();
+               let storage = self.deliver(&mut state, StagedPublish::new(name, id, client, headers.clone(), message), now, &mut applied)?;
+               self.settle_applied(&mut state, &applied, now);
+               return Ok(success(storage));
+          }
+
+          matched(&state, &name)?;
+          let client = client_of(&state, session)?;
+          let mut storage = MTPStorage::new(Vec::new());
+          storage.push("id".to_string(), id.clone());
+          storage.push("staged".to_string(), "true".to_string());
+          if let Some(transaction) = state.transactions.get_mut(&session) {
+               transaction.publish(StagedPublish::new(name, id, client, headers.clone(), message));
+          }
+
+          Ok(success(storage))
+     }
+
+     /// Publishes `publish` to its topic and pushes it to the subscribers of the queues it is
+     /// delivered to: the queue named after the topic and every queue whose pattern matches it.
+     /// A message whose id is within the deduplication window of a queue is not enqueued in it
+     /// again, and is answered as a duplicate if it was enqueued in all of them before. A message
+     /// published not before a later time is scheduled instead, on behalf of its publisher.
+     ///
+     /// The message is not pushed to subscribers yet: the changes made are added to `applied`,
+     /// to be settled through [`Broker::settle_applied`] or undone through [`Broker::undo`].
+     ///
+     /// # Returns
+     /// The storage of the response: the id of the message, along with its offset when a queue is
+     /// named after the topic, the time it is scheduled at, or whether it is a duplicate
+     fn deliver(&self, state: &mut BrokerState, publish: StagedPublish, now: SystemTime, applied: &mut Vec<Applied>) -> Result<MTPStorage, ProtocolError> {
+          let (name, id, publisher, headers, message) = publish.into_parts();
+          let mut storage = MTPStorage::new(Vec::new());
+          storage.push("id".to_string(), id.clone());
+
+          // a retried delayed publish finds its message still scheduled
+          if let Some(scheduled) = state.scheduler.get(&name, &id) {
+               storage.push("scheduled".to_string(), scheduled.at().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis().to_string());
+               storage.push("duplicate".to_string(), "true".to_string());
+
+               return Ok(storage);
+          }
+
+          let mut fresh = Vec::new();
+          let mut original = None;
+          for target in matched(state, &name)? {
+               match queue_of(state, &target)?.duplicate_of(&id, now) {
+                    Some(offset) if target == name => original = Some(offset),
+                    Some(_) => {},
+                    None => fresh.push(target),
+               }
+          }
+
+          if fresh.is_empty() {
+               if let Some(offset) = original {
+                    storage.push("offset".to_string(), offset.to_string());
+               }
+               storage.push("duplicate".to_string(), "true".to_string());
+
+               return Ok(storage);
+          }
+
+          if let Some(at) = request::not_before(&headers, now).filter(|at| *at > now) {
+               state.scheduler.schedule(ScheduledMessage::new(name.clone(), id.clone(), publisher, headers, message, at))?;
+               applied.push(Applied::Scheduled { queue: name, id });
+               self.timer.notify_one();
+               storage.push("scheduled".to_string(), at.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis().to_string());
+
+               return Ok(storage);
+          }
+
+          let published = StoredMessage::new(id, headers, message, now, None);
+          for (target, offset) in self.fan_out(state, &name, &fresh, &published)? {
+               if target == name {
+                    storage.push("offset".to_string(), offset.to_string());
+               }
+               applied.push(Applied::Enqueued { queue: target, offset });
+          }
+
+          Ok(storage)
+     }
+
+     /// Hands out a batch of messages of a queue to the client of `session`, holding the
+     /// request open until messages arrive or the wait of the batch elapses
+     async fn pull(&self, session: SessionId, headers: &MTPHeaders) -> Result<MTPResponse, ProtocolError> {
+          let name = request::subscription(headers)?;
+          let group = request::group(headers);
+          let filter = request::filter(headers)?;
+          let (max_messages, max_bytes, wait) = request::batch(headers);
+          let deadline = Instant::now() + wait;
+
+          loop {
+               let (delivered, arrival) = self.fetch(session, &name, group.clone(), filter.clone(), max_messages, max_bytes)?;
+               if !delivered.is_empty() || Instant::now() >= deadline {
+                    return Ok(success(MTPStorage::new(Vec::new())).with_messages(delivered));
+               }
+
+               let _ = tokio::time::timeout_at(deadline, arrival).await;
+          }
+     }
+
+     /// Hands out the next messages of a queue to the client of `session`, registering it as a
+     /// consumer of the queue if it is not one yet, and setting the filter of its consumer
+     ///
+     /// # Returns
+     /// The messages handed out, along with a future completing on the next dispatch of any
+     /// queue. The future is created before the state is unlocked so that no dispatch is missed.
+     fn fetch(&self, session: SessionId, name: &str, group: Option<(String, Distribution)>, filter: Option<Filter>, max_messages: usize, max_bytes: usize) -> Result<(Vec<MTPEnvelope>, Notified<'_>), ProtocolError> {
+          let now = SystemTime::now();
+          let mut state = self.lock();
+          let client = client_of(&state, session)?;
+
+          let queue = consumable(&mut state, name, session, &Operation::Pull)?;
+          if group.is_some() || filter.is_some() || queue.consumer_of(&client).is_none() {
+               queue.subscribe(client.clone(), session, false, group, filter)?;
+          }
+          let delivered = queue.next_batch(&client, now, max_messages, max_bytes).iter()
+               .map(|message| message.envelope(name))
+               .collect();
+          self.settle(&mut state, name, now);
+
+          Ok((delivered, self.arrivals.notified()))
+     }
+
+     /// Acknowledges a message delivered to the client of `session`. Within a transaction the
+     /// acknowledgement is staged until the transaction is committed, the message remaining
+     /// unacknowledged meanwhile.
+     ///
+     /// # Errors
+     /// [`ProtocolError::Conflict108`] if the message was already acknowledged within the transaction
+     fn acknowledge(&self, session: SessionId, headers: &MTPHeaders) -> Result<MTPResponse, ProtocolError> {
+          let (name, offset) = request::delivery(headers)?;
+          let now = SystemTime::now();
+          let mut state = self.lock();
+          let client = client_of(&state, session)?;
+
+          if let Some(transaction) = state.transactions.get(&session) {
+               if transaction.acks().iter().any(|ack| ack.queue() == name && ack.offset() == offset) {
+                    return Err(ProtocolError::Conflict108(Error::new(format!("Message at offset {} is already acknowledged within the transaction", offset))));
+               }
+               consumable(&mut state, &name, session, &Operation::Pull)?.awaiting(&client, offset, now)?;
+
+               if let Some(transaction) = state.transactions.get_mut(&session) {
+                    transaction.acknowledge(StagedAck::new(name, client, offset));
+               }
+               let mut storage = MTPStorage::new(Vec::new());
+               storage.push("staged".to_string(), "true".to_string());
+
+               return Ok(success(storage));
+          }
+
+          let queue = consumable(&mut state, &name, session, &Operation::Pull)?;
+          let result = queue.acknowledge(&client, offset, now);
+          self.settle(&mut state, &name, now);
+
+          result.map(|_| success(MTPStorage::new(Vec::new())))
+     }
+
+     /// Provisions the queue described by the `QueueCreation` unit of a manage request, assigning
+     /// the `Moderator` role on it to the client of `session`, performs the step of its
+     /// `Transaction` unit and its manager actions, then provisions the temporary queue requested
+     /// by its `TemporaryQueue` unit once they all succeeded. The creation of the queue and the actions changing queues or their permissions are
+     /// recorded in the audit log, whether they succeed or fail.
+     ///
+     /// # Returns
+     /// A response carrying the name of the temporary queue in the `queue` storage cell, if one
+     /// was requested, and a `pending` cell for every client requesting to join the managed queue
+     /// if the pending requests were listed
+     ///
+     /// # Errors
+     /// - [`ProtocolError::Conflict108`] if a transaction is begun while one is open
+     /// - [`ProtocolError::PreconditionFailed110`] if a transaction is committed or rolled back
+     ///   while none is open
+     /// - [`ProtocolError::Forbidden102`] if the role of the client on the managed queue does not
+     ///   permit one of the manager actions, or the client manages users without being an
+     ///   administrator of the broker
+     /// - Any error the commit of the transaction failed with, see [`Broker::commit_transaction`]
+     /// - [`ProtocolError::InsufficientStorage126`] if an action could not be recorded
+     /// - [`ProtocolError::InternalServerError120`] if a password could not be hashed
+     async fn manage(&self, session: SessionId, headers: &MTPHeaders) -> Result<MTPResponse, ProtocolError> {
+          let creation = request::creation(headers)?;
+          let temporary = request::temporary(headers);
+          let step = request::transaction(headers);
+          let actions = request::actions(headers);
+          if creation.is_none() && !temporary && step.is_none() && actions.is_empty() {
+               return Err(ProtocolError::BadRequest100(Error::new("Missing Administration, QueueCreation, TemporaryQueue or Transaction header".to_string())));
+          }
+
+          let actor = actor_of(&self.lock(), session);
+          if let Some((name, config)) = creation {
+               let entry = AuditEntry::new(actor.0.clone(), actor.1, "create", name.clone());
+               let intent = record(&mut self.lock(), entry.clone())?;
+               let created = self.create(session, name, config);
+               record(&mut self.lock(), entry.with_outcome(&created).concluding(intent))?;
+               created?;
+          }
+
+          // passwords are slow to hash by design, so they are hashed on a blocking thread before
+          // the broker is locked, and only for its administrators
+          let administering = administrator(&self.config, &self.lock(), session).is_ok();
+          let mut hashes = Vec::new();
+          for action in &actions {
+               hashes.push(match action {
+                    MTPManagerAction::AddUser { password, .. } | MTPManagerAction::ChangePassword { password, .. } if administering => {
+                         let password = password.clone();
+                         match tokio::task::spawn_blocking(move || credential::hash(&password)).await {
+                              Ok(hash) => Some(hash?),
+                              Err(_) => return Err(ProtocolError::InternalServerError120(Error::new("Password could not be hashed".to_string()))),
+                         }
+                    },
+                    _ => None,
+               });
+          }
+
+          let mut state = self.lock();
+          let mut storage = MTPStorage::new(Vec::new());
+
+          if let Some(step) = step {
+               client_of(&state, session)?;
+               let open = state.transactions.remove(&session);
+               match (step, open) {
+                    (TransactionStep::Begin, Some(transaction)) => {
+                         state.transactions.insert(session, transaction);
+                         return Err(ProtocolError::Conflict108(Error::new("A transaction is already open".to_string())));
+                    },
+                    (TransactionStep::Begin, None) => {
+                         state.transactions.insert(session, Transaction::new());
+                    },
+                    (TransactionStep::Commit, Some(transaction)) => {
+                         self.commit_transaction(&mut state, session, transaction)?;
+                    },
+                    (TransactionStep::Rollback, Some(_)) => {},
+                    (_, None) => return Err(ProtocolError::PreconditionFailed110(Error::new("No transaction is open".to_string()))),
+               }
+          }
+
+          for (action, hash) in actions.into_iter().zip(hashes) {
+               let Some((name, detail)) = audit::action(&action) else {
+                    self.administer(&mut state, session, headers, action, hash, &mut storage)?;
+                    continue;
+               };
+               let target = match &action {
+                    MTPManagerAction::Cancel(id) => scheduled(&state, headers, id).ok(),
+                    MTPManagerAction::AddUser { username, .. } | MTPManagerAction::ChangePassword { username, .. } => Some(username.clone()),
+                    MTPManagerAction::RemoveUser(username) | MTPManagerAction::UnlockUser(username) => Some(username.clone()),
+                    _ => request::managed(headers).ok(),
+               };
+               let mut entry = AuditEntry::new(actor.0.clone(), actor.1, name, target.unwrap_or_default());
+               if let Some(detail) = detail {
+                    entry = entry.with_detail(detail);
+               }
+
+               // the action is only applied once recorded as pending
+               let intent = record(&mut state, entry.clone())?;
+               let result = self.administer(&mut state, session, headers, action, hash, &mut storage);
+               record(&mut state, entry.with_outcome(&result).concluding(intent))?;
+               result?;
+          }
+
+          // the temporary queue is provisioned last, so that it is not left behind by a failing
+          // action of the same request
+          if temporary {
+               client_of(&state, session)?;
+               let name = format!("reply-{}", self.generate_id());
+               let entry = AuditEntry::new(actor.0.clone(), actor.1, "create", name.clone()).with_detail("temporary".to_string());
+               record(&mut state, entry.with_success())?;
+               state.queues.insert(name.clone(), Queue::temporary(name.clone(), session));
+               storage.push("queue".to_string(), name);
+          }
+          self.timer.notify_one();
+
+          Ok(success(storage))
+     }
+
+     /// Creates the queue `name` requested by the client of `session` through a `QueueCreation`
+     /// unit, the client becoming its moderator
+     ///
+     /// # Errors
+     /// - [`ProtocolError::Unauthorized101`] if the session is not open
+     /// - [`ProtocolError::Forbidden102`] if the token of the client does not allow the queue
+     /// - Any error of [`Broker::provision`]
+     fn create(&self, session: SessionId, name: String, config: QueueConfig) -> Result<(), ProtocolError> {
+          let client = {
+               let state = self.lock();
+               allowed(&state, &name, session)?;
+               client_of(&state, session)?
+          };
+          self.provision(name, config.with_role(client, QueueRoles::Moderator))
+     }
+
+     /// Performs the manager action `action` requested by the client of `session`, once
+     /// permitted by its role on the queue the action operates on. A scheduled message may be
+     /// cancelled by the client that published it, as [`cancellable`] checks. The users of the
+     /// broker are managed by its administrators, see [`BrokerConfig::with_administrator`]. An
+     /// action setting a password takes its `hash` computed beforehand, which it is refused
+     /// without.
+     fn administer(&self, state: &mut BrokerState, session: SessionId, headers: &MTPHeaders, action: MTPManagerAction, hash: Option<String>, storage: &mut MTPStorage) -> Result<(), ProtocolError> {
+          if role::is_user_action(&action) {
+               administrator(&self.config, state, session)?;
+          } else if !matches!(action, MTPManagerAction::Cancel(_)) {
+               permitted(state, &request::managed(headers)?, session, &Operation::Manage(&action))?;
+          }
+          match action {
+               MTPManagerAction::Cancel(id) => {
+                    let queue = scheduled(state, headers, &id)?;
+                    cancellable(state, session, &queue, &id)?;
+                    state.scheduler.cancel(&queue, &id)?;
+               },
+               MTPManagerAction::Rename(to) => {
+                    self.rename(state, request::managed(headers)?, to)?;
+               },
+               MTPManagerAction::AccessorModify(access) => {
+                    self.modify_access(state, request::managed(headers)?, access)?;
+               },
+               MTPManagerAction::Authorize(client) => {
+                    self.authorize(state, request::managed(headers)?, client)?;
+               },
+               MTPManagerAction::Reject(client) => {
+                    let name = request::managed(headers)?;
+                    let request = state.joins.take(&name, &client)
+                         .ok_or_else(|| ProtocolError::NotFound103(Error::new(format!("Client {} has not requested to join queue {}", client, name))))?;
+                    if let Some(requester) = state.sessions.get(&request.session()) {
+                         requester.push(decision(name, "rejected"));
+                    }
+               },
+               MTPManagerAction::Pending => {
+                    let name = request::managed(headers)?;
+                    for client in state.joins.pending(&name) {
+                         storage.push("pending".to_string(), client);
+                    }
+               },
+               MTPManagerAction::Assign(client, role) => {
+                    self.assign(state, request::managed(headers)?, client, Some(role))?;
+               },
+               MTPManagerAction::Revoke(client) => {
+                    self.assign(state, request::managed(headers)?, client, None)?;
+               },
+               MTPManagerAction::Dispose { client, ban, disconnect } => {
+                    self.dispose(state, request::managed(headers)?, client, ban, disconnect)?;
+               },
+               MTPManagerAction::Delete => {
+                    self.delete(state, request::managed(headers)?)?;
+               },
+               MTPManagerAction::AddUser { username, .. } => {
+                    enrol(state, username, hashed(hash)?)?;
+               },
+               MTPManagerAction::ChangePassword { username, .. } => {
+                    rehash(state, username, hashed(hash)?)?;
+               },
+               MTPManagerAction::RemoveUser(username) => {
+                    self.withdraw(state, &username)?;
+               },
+               MTPManagerAction::UnlockUser(username) => {
+                    unlock(state, &username)?;
+               },
+          }
+
+          Ok(())
+     }
+
+     /// Switches the queue `name` to `access`. The clients subscribed to the queue that the new
+     /// access does not permit are unsubscribed from it.
+     ///
+     /// # Errors
+     /// [`ProtocolError::NotFound103`] if the queue was not provisioned
+     fn modify_access(&self, state: &mut BrokerState, name: String, access: QueueAccess) -> Result<(), ProtocolError> {
+          let queue = queue_of(state, &name)?;
+          let config = queue.config().clone().with_access(access);
+          queue.set_config(config)?;
+          self.evict(state, &name)
+     }
+
+     /// Assigns `role` on the queue `name` to `client`, or withdraws the role of the client when
+     /// `role` is `None`. The clients subscribed to the queue that their new role does not permit
+     /// to consume from it are unsubscribed from it.
+     ///
+     /// # Errors
+     /// - [`ProtocolError::NotFound103`] if the queue was not provisioned
+     /// - [`ProtocolError::Conflict108`] if the queue would be left without a moderator
+     fn assign(&self, state: &mut BrokerState, name: String, client: String, role: Option<QueueRoles>) -> Result<(), ProtocolError> {
+          let queue = queue_of(state, &name)?;
+          let config = match role {
+               Some(role) => queue.config().clone().with_role(client, role),
+               None => queue.config().clone().without_role(&client),
+          };
+          let moderated = |config: &QueueConfig| config.roles().values().any(|role| matches!(role, QueueRoles::Moderator));
+          if moderated(queue.config()) && !moderated(&config) {
+               return Err(ProtocolError::Conflict108(Error::new(format!("Queue {} would be left without a moderator", name))));
+          }
+          queue.set_config(config)?;
+          self.evict(state, &name)
+     }
+
+     /// Unsubscribes from the queue `name` the clients whose access or role no longer permits
+     /// them to consume from it
+     fn evict(&self, state: &mut BrokerState, name: &str) -> Result<(), ProtocolError> {
+          let subscribed: HashSet<SessionId> = queue_of(state, name)?.consumers().values()
+               .flat_map(|consumer| consumer.members().values().map(|member| member.session()))
+               .collect();
+          let denied: Vec<SessionId> = subscribed.into_iter()
+               .filter(|subscriber| permitted(state, name, *subscriber, &Operation::Subscribe).is_err())
+               .collect();
+          let queue = queue_of(state, name)?;
+          for subscriber in denied {
+               queue.release(subscriber);
+          }
+          self.settle(state, name, SystemTime::now());
+
+          Ok(())
+     }
+
+     /// Disposes of `client` on the queue `name`: the client is unsubscribed from the queue, the
+     /// messages it left unacknowledged going back to the other members of its group, and its
+     /// request to join the queue is withdrawn. The sessions of the client are pushed a frame
+     /// carrying the name of the queue in the `queue` storage cell and `disposed` in the
+     /// `decision` cell, along with the end of its ban in the `banned` cell if it is banned.
+     ///
+     /// # Arguments
+     /// * `ban`: How long the client is refused the queue, if it is
+     /// * `disconnect`: Whether the sessions of the client are closed once notified
+     ///
+     /// # Errors
+     /// [`ProtocolError::NotFound103`] if the queue was not provisioned, or the client is neither
+     /// subscribed to it, requesting to join it nor banned from it
+     fn dispose(&self, state: &mut BrokerState, name: String, client: String, ban: Option<Duration>, disconnect: bool) -> Result<(), ProtocolError> {
+          let now = SystemTime::now();
+          let queue = queue_of(state, &name)?;
+          let until = ban.map(|ban| now + ban);
+          if let Some(until) = until {
+               let config = queue.config().clone().with_ban(client.clone(), until);
+               queue.set_config(config)?;
+          }
+          let removed = queue.unsubscribe(&client);
+          let withdrawn = state.joins.take(&name, &client).is_some();
+          if !removed && !withdrawn && until.is_none() {
+               return Err(ProtocolError::NotFound103(Error::new(format!("Client {} is not subscribed to queue {}", client, name))));
+          }
+
+          let mut notice = MTPStorage::new(Vec::new());
+          notice.push("queue".to_string(), name.clone());
+          notice.push("decision".to_string(), "disposed".to_string());
+          if let Some(until) = until {
+               notice.push("banned".to_string(), until.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis().to_string());
+          }
+          let sessions: Vec<SessionId> = state.sessions.values()
+               .filter(|session| session.client() == client)
+               .map(Session::id)
+               .collect();
+          for session in sessions.iter().filter_map(|session| state.sessions.get(session)) {
+               session.push(success(notice.clone()));
+          }
+          if disconnect {
+               for session in sessions {
+                    close(state, session);
+               }
+          }
+          self.settle(state, &name, now);
+
+          Ok(())
+     }
+
+     /// Deletes the queue `name` along with its messages, pushing its subscribers a frame carrying
+     /// the name of the queue in the `deleted` storage cell. The requests to join the queue are
+     /// withdrawn.
+     ///
+     /// # Errors
+     /// - [`ProtocolError::NotFound103`] if the queue was not provisioned
+     /// - [`ProtocolError::Forbidden102`] if the queue is temporary
+     /// - [`ProtocolError::InsufficientStorage126`] if a durable queue could not be deleted from disk
+     fn delete(&self, state: &mut BrokerState, name: String) -> Result<(), ProtocolError> {
+          if queue_of(state, &name)?.owner().is_some() {
+               return Err(ProtocolError::Forbidden102(Error::new(format!("Temporary queue {} cannot be deleted", name))));
+          }
+          let Some(queue) = state.queues.remove(&name) else {
+               return Err(ProtocolError::NotFound103(Error::new(format!("Queue {} not found", name))));
+          };
+          state.topics.remove(&name);
+          for client in state.joins.pending(&name) {
+               state.joins.take(&name, &client);
+          }
+
+          let mut notification = MTPStorage::new(Vec::new());
+          notification.push("deleted".to_string(), name);
+          let sessions: HashSet<SessionId> = queue.consumers().values()
+               .flat_map(|consumer| consumer.members().values().map(|member| member.session()))
+               .collect();
+          for session in sessions.iter().filter_map(|session| state.sessions.get(session)) {
+               session.push(success(notification.clone()));
+          }
+
+          Ok(queue.delete()?)
+     }
+
+     /// Admits `client` to the queue `name`. A client whose request to join the queue was pending
+     /// is subscribed to it as it requested, and pushed a frame carrying the name of the queue in
+     /// the `queue` storage cell and `authorized` in the `decision` cell.
+     ///
+     /// # Errors
+     /// [`ProtocolError::NotFound103`] if the queue was not provisioned
+     fn authorize(&self, state: &mut BrokerState, name: String, client: String) -> Result<(), ProtocolError> {
+          let queue = queue_of(state, &name)?;
+          let config = queue.config().clone().with_admitted(client.clone());
+          queue.set_config(config)?;
+
+          let Some(request) = state.joins.take(&name, &client) else {
+               return Ok(());
+          };
+          let (requester, group, filter) = request.into_parts();
+          if !state.sessions.contains_key(&requester) {
+               return Ok(());
+          }
+          queue_of(state, &name)?.subscribe(client, requester, true, group, filter)?;
+          if let Some(requester) = state.sessions.get(&requester) {
+               requester.push(decision(name.clone(), "authorized"));
+          }
+          self.settle(state, &name, SystemTime::now());
+
+          Ok(())
+     }
+
+     /// Renames the queue `name` to `to` at once. The former name is kept as an alias redirecting
+     /// to the queue for the alias retention of the broker, the dead-letter queues configured
+     /// after it are renamed, and the clients consuming the queue are pushed a frame carrying
+     /// the former name in the `renamed` storage cell and the new one in the `queue` cell. The
+     /// operations staged on the queue by open transactions follow it.
+     ///
+     /// # Errors
+     /// - [`ProtocolError::BadRequest100`] if the new name is empty
+     /// - [`ProtocolError::NotFound103`] if the queue was not provisioned
+     /// - [`ProtocolError::Forbidden102`] if the queue is temporary
+     /// - [`ProtocolError::Conflict108`] if a queue or the alias of another queue has the new name,
+     ///   or another queue spells it with other separators
+     /// - [`ProtocolError::InsufficientStorage126`] if a durable queue could not be moved
+     fn rename(&self, state: &mut BrokerState, name: String, to: String) -> Result<(), ProtocolError> {
+          let now = SystemTime::now();
+          if to.is_empty() {
+               return Err(ProtocolError::BadRequest100(Error::new("Rename action without a queue name".to_string())));
+          }
+          if queue_of(state, &name)?.owner().is_some() {
+               return Err(ProtocolError::Forbidden102(Error::new(format!("Temporary queue {} cannot be renamed", name))));
+          }
+          if to == name {
+               return Ok(());
+          }
+          if state.queues.contains_key(&to) {
+               return Err(ProtocolError::Conflict108(Error::new(format!("Queue {} already exists", to))));
+          }
+          if let Some(existing) = named(state, &to).filter(|existing| *existing != name) {
+               return Err(ProtocolError::Conflict108(Error::new(format!("Queue {} names the same topic as queue {}", to, existing))));
+          }
+          if let Some(target) = state.aliases.resolve(&to, now).filter(|target| *target != name) {
+               return Err(ProtocolError::Conflict108(Error::new(format!("{} is the former name of queue {}", to, target))));
+          }
+
+          let mut queue = match state.queues.remove(&name) {
+               Some(queue) => queue,
+               None => return Err(ProtocolError::NotFound103(Error::new(format!("Queue {} not found", name)))),
+          };
+          if let Err(err) = queue.rename(to.clone(), self.config.log.clone()) {
+               state.queues.insert(name, queue);
+               return Err(err.into());
+          }
+          let sessions: HashSet<SessionId> = queue.consumers().values()
+               .flat_map(|consumer| consumer.members().values().map(|member| member.session()))
+               .collect();
+          state.topics.remove(&name);
+          state.topics.insert(&to);
+          state.queues.insert(to.clone(), queue);
+          for transaction in state.transactions.values_mut() {
+               transaction.rename(&name, &to);
+          }
+          state.joins.rename(&name, &to);
+
+          let mut notification = MTPStorage::new(Vec::new());
+          notification.push("renamed".to_string(), name.clone());
+          notification.push("queue".to_string(), to.clone());
+          for session in sessions.iter().filter_map(|session| state.sessions.get(session)) {
+               session.push(success(notification.clone()));
+          }
+
+          for dependent in state.queues.values_mut().filter(|queue| queue.config().dead_letter() == Some(name.as_str())) {
+               let config = dependent.config().clone().with_dead_letter(to.clone());
+               dependent.set_config(config)?;
+          }
+          state.aliases.insert(name, to, now + self.config.alias_retention)?;
+
+          Ok(())
+     }
+
+     /// Rewrites the queue names of `headers` that are former names of renamed queues to the
+     /// current names of the queues
+     ///
+     /// # Returns
+     /// The rewritten headers, along with the current names of the queues that were redirected
+     fn redirect(&self, headers: MTPHeaders) -> (MTPHeaders, Vec<String>) {
+          let now = SystemTime::now();
+          let state = self.lock();
+          let mut renamed = Vec::new();
+
+          let mut current = |queue: &String| -> String {
+               match resolve(&state, queue, now) {
+                    Some(target) => {
+                         renamed.push(target.clone());
+                         target
+                    },
+                    None => queue.clone(),
+               }
+          };
+          let units = headers.units().iter().map(|unit| match unit {
+               MTPHeaderUnit::Subscription { queue } => MTPHeaderUnit::Subscription { queue: current(queue) },
+               MTPHeaderUnit::MessagePublish { queue, to } => MTPHeaderUnit::MessagePublish { queue: current(queue), to: to.clone() },
+               MTPHeaderUnit::Delivery { queue, id, offset } => MTPHeaderUnit::Delivery { queue: current(queue), id: id.clone(), offset: *offset },
+               unit => unit.clone(),
+          }).collect();
+
+          (MTPHeaders::new(units, headers.local().clone(), headers.timestamp()), renamed)
+     }
+
+     /// Commits the transaction of `session`, publishing its messages and applying its
+     /// acknowledgements at once. Every check is made before the transaction is journaled and
+     /// flushed to disk, so that a commit interrupted by a crash is applied again on restart. The
+     /// messages are pushed to subscribers once the whole transaction is applied.
+     ///
+     /// # Errors
+     /// - [`ProtocolError::NotFound103`] or [`ProtocolError::Gone109`] if a message acknowledged
+     ///   within the transaction no longer awaits acknowledgement, or no queue matches the topic
+     ///   of a published message anymore
+     /// - [`ProtocolError::InsufficientStorage126`] if the published messages do not fit in a
+     ///   queue rejecting overflowing messages, or the transaction could not be journaled or
+     ///   its messages stored
+     ///
+     /// Nothing is applied when the commit fails: the messages already published are withdrawn.
+     /// The transaction is closed either way.
+     fn commit_transaction(&self, state: &mut BrokerState, session: SessionId, mut transaction: Transaction) -> Result<(), ProtocolError> {
+          let now = SystemTime::now();
+          let client = client_of(state, session)?;
+
+          for ack in transaction.acks_mut() {
+               let consumer = consumable(state, ack.queue(), session, &Operation::Pull)?.awaiting(&client, ack.offset(), now)?;
+               ack.set_consumer(consumer);
+          }
+
+          let mut demand: HashMap<&str, (usize, u64)> = HashMap::new();
+          for publish in transaction.publishes() {
+               matched(state, publish.topic())?;
+               if request::not_before(publish.headers(), now).is_some_and(|at| at > now) || !state.queues.contains_key(publish.topic()) {
+                    continue;
+               }
+               let (count, bytes) = demand.entry(publish.topic()).or_default();
+               *count += 1;
+               *bytes += publish.message().message().len() as u64;
+          }
+          for (name, (count, bytes)) in demand {
+               if !queue_of(state, name)?.has_room(count, bytes) {
+                    return Err(ProtocolError::InsufficientStorage126(Error::new(format!("Transaction does not fit in queue {}", name))));
+               }
+          }
+
+          let number = state.journal.commit(&transaction)?;
+          let (publishes, acks) = transaction.into_parts();
+
+          let mut applied = Vec::new();
+          for (index, publish) in publishes.into_iter().enumerate() {
+               let delivered = match self.deliver(state, publish, now, &mut applied) {
+                    Ok(_) => state.journal.step(number, index).map_err(ProtocolError::from),
+                    Err(err) => Err(err),
+               };
+               if let Err(err) = delivered {
+                    self.undo(state, applied);
+                    state.journal.done(number)?;
+                    state.sync()?;
+                    return Err(err);
+               }
+          }
+          for ack in acks.iter() {
+               if let Some(queue) = state.queues.get_mut(ack.queue()) {
+                    queue.acknowledge_for(ack.consumer(), ack.offset());
+               }
+          }
+          state.journal.done(number)?;
+
+          self.settle_applied(state, &applied, now);
+          for ack in acks.iter() {
+               self.settle(state, ack.queue(), now);
+          }
+          Ok(())
+     }
+
+     /// Undoes the changes made while applying a transaction that could not be applied in full
+     fn undo(&self, state: &mut BrokerState, applied: Vec<Applied>) {
+          for change in applied.into_iter().rev() {
+               match change {
+                    Applied::Enqueued { queue, offset } => {
+                         if let Some(queue) = state.queues.get_mut(&queue) {
+                              queue.withdraw(offset);
+                         }
+                    },
+                    Applied::Scheduled { queue, id } => {
+                         let _ = state.scheduler.cancel(&queue, &id);
+                    },
+               }
+          }
+     }
+
+     /// Pushes the messages enqueued by `applied` to the subscribers of their queues
+     fn settle_applied(&self, state: &mut BrokerState, applied: &[Applied], now: SystemTime) {
+          for change in applied {
+               if let Applied::Enqueued { queue, .. } = change {
+                    self.settle(state, queue, now);
+               }
+          }
+     }
+
+     /// Applies again the transactions whose commit was interrupted. Only the messages the
+     /// journal does not mark as applied are published again, as a retry would be.
+     fn recover(&self, interrupted: Vec<Transaction>) -> Result<(), StorageError> {
+          if interrupted.is_empty() {
+               return Ok(());
+          }
+
+          let now = SystemTime::now();
+          let mut state = self.lock();
+          for transaction in interrupted {
+               let (publishes, acks) = transaction.into_parts();
+               let mut applied = Vec::new();
+               for publish in publishes {
+                    // a message that cannot be stored, or whose topic no queue matches anymore, is lost
+                    let _ = self.deliver(&mut state, publish, now, &mut applied);
+               }
+               for ack in acks {
+                    if let Some(queue) = state.queues.get_mut(ack.queue()) {
+                         queue.acknowledge_for(ack.consumer(), ack.offset());
+                    }
+               }
+               self.settle_applied(&mut state, &applied, now);
+          }
+
+          state.sync()?;
+          state.journal.clear()?;
+          Ok(())
+     }
+
+     /// Enqueues a message published to `topic` in the `targets` it matches, with the
+     /// time-to-live each of them defaults to. The queue named after the topic is served first.
+     /// The message is pushed to the subscribers of the queues once they are settled.
+     ///
+     /// # Returns
+     /// The queues the message was enqueued in, along with its offset in each of them
+     ///
+     /// # Errors
+     /// Any error of the queue named after the topic. A queue subscribed to the topic through a
+     /// pattern that cannot take the message misses it instead, leaving the other queues unaffected.
+     fn fan_out(&self, state: &mut BrokerState, topic: &str, targets: &[String], published: &StoredMessage) -> Result<Vec<(String, u64)>, ProtocolError> {
+          let (named, subscribed): (Vec<&String>, Vec<&String>) = targets.iter().partition(|target| *target == topic);
+          let now = published.enqueued_at();
+
+          let mut enqueued = Vec::new();
+          for target in named.into_iter().chain(subscribed) {
+               let queue = queue_of(state, target)?;
+               let ttl = request::ttl(published.headers()).or(queue.config().ttl());
+               let copy = StoredMessage::new(published.id().to_string(), published.headers().clone(), published.message().clone(), now, ttl);
+               match queue.enqueue(copy) {
+                    Ok(offset) => enqueued.push((target.clone(), offset)),
+                    Err(err) if target == topic => return Err(err),
+                    Err(_) => continue,
+               }
+          }
+
+          Ok(enqueued)
+     }
+
+     /// Waits until the changes made by a request reach the durability level of the sync policy
+     ///
+     /// # Errors
+     /// [`ProtocolError::InsufficientStorage126`] if the changes could not be flushed to disk
+     async fn commit(&self) -> Result<(), ProtocolError> {
+          match self.config.sync {
+               SyncPolicy::Always => {
+                    // the files are flushed once the state is unlocked, one request at a time so
+                    // that a request does not return while the flush covering its changes runs
+                    let _syncing = self.syncing.lock().await;
+                    let files = self.lock().detach_sync()?;
+                    if files.is_empty() {
+                         return Ok(());
+                    }
+
+                    match tokio::task::spawn_blocking(move || files.iter().try_for_each(File::sync_data)).await {
+                         Ok(synced) => Ok(synced.map_err(StorageError::from)?),
+                         Err(_) => Err(ProtocolError::InsufficientStorage126(Error::new("Changes could not be flushed to disk".to_string()))),
+                    }
+               },
+               SyncPolicy::GroupCommit { .. } => {
+                    let mut commits = self.commits.subscribe();
+                    let epoch = {
+                         let state = self.lock();
+                         if !state.is_dirty() {
+                              return Ok(());
+                         }
+                         commits.borrow().0
+                    };
+
+                    // the state lock is held while a flush is recorded, so the next one covers the request
+                    let synced = commits.wait_for(|(flushed, _)| *flushed > epoch).await
+                         .map(|commit| commit.1)
+                         .unwrap_or(false);
+                    if !synced {
+                         return Err(ProtocolError::InsufficientStorage126(Error::new("Changes could not be flushed to disk".to_string())));
+                    }
+                    Ok(())
+               },
+               SyncPolicy::Os => Ok(()),
+          }
+     }
+
+     /// Flushes the changes made outside of a request under [`SyncPolicy::Always`].
+     /// Under other policies they are left to the flusher or the operating system.
+     fn persist(&self, state: &mut BrokerState) {
+          if let SyncPolicy::Always = self.config.sync {
+               let _ = state.sync();
+          }
+     }
+
+     /// Moves the expired messages of a queue to its dead-letter queue and pushes the messages
+     /// its subscribers are due
+     fn settle(&self, state: &mut BrokerState, name: &str, now: SystemTime) {
+          let (dead_letters, target) = match state.queues.get_mut(name) {
+               Some(queue) => (queue.take_dead_letters(), queue.config().dead_letter().map(str::to_string)),
+               None => return,
+          };
+
+          if let Some(target) = target.filter(|_| !dead_letters.is_empty()) {
+               // dead letters are dropped while the dead-letter queue does not exist
+               if let Some(queue) = state.queues.get_mut(&target) {
+                    let ttl = queue.config().ttl();
+                    for message in dead_letters {
+                         // a dead letter that cannot be stored is dropped
+                         let _ = queue.enqueue(message.dead_lettered(now, ttl));
+                    }
+                    self.dispatch(state, &target, now);
+               }
+          }
+
+          self.dispatch(state, name, now);
+     }
+
+     /// Pushes messages to the subscribers of a queue until each has `prefetch` messages in flight.
+     /// Within a consumer group each message is pushed to the member picked by its distribution,
+     /// or to the member its partition key belongs to.
+     fn dispatch(&self, state: &mut BrokerState, name: &str, now: SystemTime) {
+          let BrokerState { queues, sessions, .. } = state;
+          let queue = match queues.get_mut(name) {
+               Some(queue) => queue,
+               None => return,
+          };
+          self.arrivals.notify_waiters();
+
+          let consumers: Vec<String> = queue.consumers().keys().cloned().collect();
+          for consumer in consumers {
+               // members whose session cannot take more messages
+               let mut skipped = HashSet::new();
+
+               while let Some((client, session)) = queue.consumers().get(&consumer)
+                    .and_then(|consumer| consumer.assign(self.config.prefetch, &skipped))
+                    .map(|(client, member)| (client.to_string(), member.session()))
+               {
+
+                    let session = match sessions.get(&session).filter(|session| session.parked().is_none()) {
+                         Some(session) => session,
+                         None => {
+                              skipped.insert(client);
+                              continue;
+                         },
+                    };
+
+                    // a member may have nothing to take while the others do, as the messages of a
+                    // partition key are held back for the member it belongs to
+                    let message = match queue.next(&client, now) {
+                         Some(message) => message,
+                         None => {
+                              skipped.insert(client);
+                              continue;
+                         },
+                    };
+
+                    let frame = success(MTPStorage::new(Vec::new())).with_messages(vec![message.envelope(name)]);
+                    if !session.push(frame) {
+                         skipped.insert(client);
+                    }
+               }
+          }
+     }
+
+     /// Generates an id for a message published without one
+     fn generate_id(&self) -> String {
+          let epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+          format!("{:x}-{:x}", epoch, self.next_message.fetch_add(1, Ordering::Relaxed))
+     }
+
+     /// Locks the state of the broker
+     fn lock(&self) -> MutexGuard<'_, BrokerState> {
+          self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+     }
+}
+
+/// Locks `lock` for reading, recovering it if a thread panicked while holding it
+fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+     lock.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Locks `lock` for writing, recovering it if a thread panicked while holding it
+fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+     lock.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Closes `session`, as [`Broker::disconnect`] does. Dropping the outbox of the session ends
+/// the stream of frames pushed to it once the frames already pushed are received. The deletion
+/// of its temporary queues is recorded in the audit log.
+fn close(state: &mut BrokerState, session: SessionId) {
+     let actor = actor_of(state, session);
+     let temporary: Vec<String> = state.queues.iter()
+          .filter(|(_, queue)| queue.owner() == Some(session))
+          .map(|(name, _)| name.clone())
+          .collect();
+     for name in temporary {
+          // a closed session cannot keep its temporary queues: they are dropped even if their
+          // deletion cannot be recorded
+          let entry = AuditEntry::new(actor.0.clone(), actor.1, "delete", name.clone()).with_detail("temporary".to_string());
+          let _ = record(state, entry.with_success());
+          state.queues.remove(&name);
+     }
+
+     state.sessions.remove(&session);
+     state.cookies.forget(session);
+     state.transactions.remove(&session);
+     state.joins.release(session);
+     for queue in state.queues.values_mut() {
+          queue.release(session);
+     }
+}
+
+/// Parks `session` until `until`, as [`Broker::disconnect`] does when sessions are resumable
+fn park(state: &mut BrokerState, session: SessionId, until: SystemTime) {
+     if let Some(parked) = state.sessions.get_mut(&session) {
+          parked.park(until);
+     }
+     state.transactions.remove(&session);
+     state.joins.release(session);
+}
+
+/// Retrieves the client of `session` as recorded in the audit log, along with the address it
+/// is connected from
+fn actor_of(state: &BrokerState, session: SessionId) -> (String, Option<SocketAddr>) {
+     match state.sessions.get(&session) {
+          Some(session) => (session.client(), Some(session.address())),
+          None => ("-".to_string(), None),
+     }
+}
+
+/// Records `entry` in the audit log, if the broker keeps one
+///
+/// # Returns
+/// The sequence number of the entry, or `None` if the broker keeps no audit log
+///
+/// # Errors
+/// [`ProtocolError::InsufficientStorage126`] if the entry could not be written
+fn record(state: &mut BrokerState, entry: AuditEntry) -> Result<Option<u64>, ProtocolError> {
+     match state.audit.as_mut() {
+          Some(audit) => Ok(Some(audit.append(entry, SystemTime::now())?)),
+          None => Ok(None),
+     }
+}
+
+/// Retrieves the identifier the client of `session` consumes queues as
+///
+/// # Errors
+/// [`ProtocolError::Unauthorized101`] if the session is not open
+fn client_of(state: &BrokerState, session: SessionId) -> Result<String, ProtocolError> {
+     state.sessions.get(&session)
+          .map(Session::client)
+          .ok_or_else(|| ProtocolError::Unauthorized101(Error::new("Session is not open".to_string())))
+}
+
+/// Checks that a client may authenticate as `identity`, which must not pass for the address of
+/// a client that did not authenticate, see [`Session::client`]
+///
+/// # Errors
+/// [`ProtocolError::Unauthorized101`] if `identity` starts with [`ADDRESS_PREFIX`]
+fn identifiable(identity: &str) -> Result<(), ProtocolError> {
+     if session::is_address(identity) {
+          return Err(ProtocolError::Unauthorized101(Error::new(format!("Identities starting with {} are reserved", ADDRESS_PREFIX))));
+     }
+     Ok(())
+}
+
+/// Retrieves the session `session`
+///
+/// # Errors
+/// [`ProtocolError::Unauthorized101`] if the session is not open
+fn session_of(state: &mut BrokerState, session: SessionId) -> Result<&mut Session, ProtocolError> {
+     state.sessions.get_mut(&session)
+          .ok_or_else(|| ProtocolError::Unauthorized101(Error::new("Session is not open".to_string())))
+}
+
+/// Retrieves the queue `name`
+///
+/// # Errors
+/// [`ProtocolError::NotFound103`] if the queue was not provisioned
+fn queue_of<'a>(state: &'a mut BrokerState, name: &str) -> Result<&'a mut Queue, ProtocolError> {
+     state.queues.get_mut(name)
+          .ok_or_else(|| ProtocolError::NotFound103(Error::new(format!("Queue {} not found", name))))
+}
+
+/// Retrieves the queue `name` for the client of `session` to consume from through `operation`
+///
+/// # Errors
+/// - Any error [`permitted`] fails with
+/// - [`ProtocolError::Forbidden102`] if the queue is exclusive to another session
+fn consumable<'a>(state: &'a mut BrokerState, name: &str, session: SessionId, operation: &Operation) -> Result<&'a mut Queue, ProtocolError> {
+     permitted(state, name, session, operation)?;
+     let queue = queue_of(state, name)?;
+     if queue.owner().is_some_and(|owner| owner != session) {
+          return Err(ProtocolError::Forbidden102(Error::new(format!("Queue {} is exclusive to another session", name))));
+     }
+     Ok(queue)
+}
+
+/// Retrieves the topic the message `id` a cancel request names is scheduled on: the queue named
+/// by the `Subscription` unit of the request if it carries one, otherwise the only topic a
+/// message with this id is scheduled on
+///
+/// # Errors
+/// - [`ProtocolError::NotFound103`] if no message with this id is scheduled on the topic
+/// - [`ProtocolError::Conflict108`] if the request names no queue while messages with this id
+///   are scheduled on several topics
+fn scheduled(state: &BrokerState, headers: &MTPHeaders, id: &str) -> Result<String, ProtocolError> {
+     if let Ok(queue) = request::managed(headers) {
+          let queue = named(state, &queue).unwrap_or(queue);
+          return match state.scheduler.contains(&queue, id) {
+               true => Ok(queue),
+               false => Err(ProtocolError::NotFound103(Error::new(format!("No message {} is scheduled on {}", id, queue)))),
+          };
+     }
+
+     match state.scheduler.queues_of(id).as_slice() {
+          [] => Err(ProtocolError::NotFound103(Error::new(format!("No message {} is scheduled", id)))),
+          [queue] => Ok(queue.to_string()),
+          _ => Err(ProtocolError::Conflict108(Error::new(format!("Messages {} are scheduled on several queues, one must be named through a Subscription header", id)))),
+     }
+}
+
+/// Checks whether the client of `session` may cancel the message `id` scheduled on `topic`:
+/// the client that published it may, as may the clients whose role permits cancelling on the
+/// queue named after the topic
+///
+/// # Errors
+/// - [`ProtocolError::Unauthorized101`] if the session is not open
+/// - [`ProtocolError::NotFound103`] if no message with this id is scheduled on the topic
+/// - [`ProtocolError::Forbidden102`] if the client did not publish the message and no queue is
+///   named after its topic
+/// - Any error [`permitted`] fails with otherwise
+fn cancellable(state: &BrokerState, session: SessionId, topic: &str, id: &str) -> Result<(), ProtocolError> {
+     let client = client_of(state, session)?;
+     let scheduled = state.scheduler.get(topic, id)
+          .ok_or_else(|| ProtocolError::NotFound103(Error::new(format!("No message {} is scheduled on {}", id, topic))))?;
+     if scheduled.publisher() == client {
+          return Ok(());
+     }
+
+     let queue = resolve(state, topic, SystemTime::now()).unwrap_or_else(|| topic.to_string());
+     if !state.queues.contains_key(&queue) {
+          return Err(ProtocolError::Forbidden102(Error::new(format!("Only the publisher of message {} may cancel it", id))));
+     }
+     permitted(state, &queue, session, &Operation::Manage(&MTPManagerAction::Cancel(id.to_string())))
+}
+
+/// Checks whether the client of `session` may perform `operation` on the queue `name`: its
+/// access must permit the client to use the queue, as [`accessible`] checks, and the role of the
+/// client on the queue must permit the operation. The role the queue assigns the client prevails
+/// over the one granted by its token. Manager actions only require the token to allow the queue.
+///
+/// # Errors
+/// - Any error [`accessible`] fails with
+/// - [`ProtocolError::Forbidden102`] if the role of the client does not permit the operation
+fn permitted(state: &BrokerState, name: &str, session: SessionId, operation: &Operation) -> Result<(), ProtocolError> {
+     let client = client_of(state, session)?;
+     match operation {
+          Operation::Manage(_) => allowed(state, name, session)?,
+          _ => accessible(state, name, session)?,
+     }
+     let queue = state.queues.get(name)
+          .ok_or_else(|| ProtocolError::NotFound103(Error::new(format!("Queue {} not found", name))))?;
+
+     let granted = state.sessions.get(&session)
+          .and_then(Session::claims)
+          .and_then(|claims| claims.role(name));
+     if !role::permits(queue.config().role(&client).or(granted), operation) {
+          return Err(ProtocolError::Forbidden102(Error::new(format!("Client {} may not {} on queue {}", client, operation.name(), name))));
+     }
+     Ok(())
+}
+
+/// Checks whether the client of `session` authenticated as one of the administrators of the
+/// broker, who manage its users
+///
+/// # Errors
+/// - [`ProtocolError::Unauthorized101`] if the session is not open
+/// - [`ProtocolError::Forbidden102`] if the client is not an administrator
+fn administrator(config: &BrokerConfig, state: &BrokerState, session: SessionId) -> Result<(), ProtocolError> {
+     let client = client_of(state, session)?;
+     let identity = state.sessions.get(&session).and_then(Session::identity);
+     if !identity.is_some_and(|identity| config.administrators.contains(identity)) {
+          return Err(ProtocolError::Forbidden102(Error::new(format!("Client {} may not manage users", client))));
+     }
+     Ok(())
+}
+
+/// Takes the password hash computed for a manager action before the broker was locked, which
+/// is missing if the client was not an administrator of the broker then
+///
+/// # Errors
+/// - [`ProtocolError::Forbidden102`] if the hash is missing
+fn hashed(hash: Option<String>) -> Result<String, ProtocolError> {
+     hash.ok_or_else(|| ProtocolError::Forbidden102(Error::new("Client may not manage users".to_string())))
+}
+
+/// Adds the user `username` with the password hashed as `hash`
+///
+/// # Errors
+/// - [`ProtocolError::Conflict108`] if the user exists
+/// - [`ProtocolError::BadRequest100`] if the username is empty or holds a colon or whitespace
+/// - Any error the store could not be persisted with
+fn enrol(state: &BrokerState, username: String, hash: String) -> Result<(), ProtocolError> {
+     let mut credentials = write(&state.credentials);
+     if credentials.contains(&username) {
+          return Err(ProtocolError::Conflict108(Error::new(format!("User {} already exists", username))));
+     }
+     credentials.insert(username, hash)
+}
+
+/// Changes the password of the user `username` to the one hashed as `hash`
+///
+/// # Errors
+/// - [`ProtocolError::NotFound103`] if the user does not exist
+/// - Any error the store could not be persisted with
+fn rehash(state: &BrokerState, username: String, hash: String) -> Result<(), ProtocolError> {
+     let mut credentials = write(&state.credentials);
+     if !credentials.contains(&username) {
+          return Err(ProtocolError::NotFound103(Error::new(format!("User {} not found", username))));
+     }
+     credentials.insert(username, hash)
+}
+
+/// Lifts the lockout of the user `username`, forgetting its failed attempts to authenticate
+///
+/// # Errors
+/// [`ProtocolError::NotFound103`] if the user failed no attempt
+fn unlock(state: &BrokerState, username: &str) -> Result<(), ProtocolError> {
+     if !write(&state.credentials).unlock(username) {
+          return Err(ProtocolError::NotFound103(Error::new(format!("User {} is not locked out", username))));
+     }
+     Ok(())
+}
+
+/// Checks whether the access of the queue `name` permits the client of `session` to publish to,
+/// subscribe to and pull from it. Anyone may use a public queue, only authenticated clients a
+/// protected one, and only the clients holding a role on or admitted to a private one, the roles
+/// granted by the token of the client counting. The token must allow the queue, if it restricts
+/// the client to some.
+///
+/// # Errors
+/// - [`ProtocolError::NotFound103`] if the queue was not provisioned
+/// - [`ProtocolError::Unauthorized101`] if the session is not open, or the queue is protected and
+///   the client did not authenticate
+/// - [`ProtocolError::Forbidden102`] if the client is banned from the queue, its token does not
+///   allow the queue, or the queue is private and the client is not admitted to it
+fn accessible(state: &BrokerState, name: &str, session: SessionId) -> Result<(), ProtocolError> {
+     let queue = state.queues.get(name)
+          .ok_or_else(|| ProtocolError::NotFound103(Error::new(format!("Queue {} not found", name))))?;
+     let session = state.sessions.get(&session)
+          .ok_or_else(|| ProtocolError::Unauthorized101(Error::new("Session is not open".to_string())))?;
+     unbanned(state, name, &session.client())?;
+     allowed(state, name, session.id())?;
+
+     let granted = session.claims().is_some_and(|claims| claims.role(name).is_some());
+     match queue.config().access() {
+          QueueAccess::Public => Ok(()),
+          QueueAccess::Protected if session.identity().is_some() => Ok(()),
+          QueueAccess::Protected => Err(ProtocolError::Unauthorized101(Error::new(format!("Queue {} requires an authenticated client", name)))),
+          QueueAccess::Private if granted || queue.config().is_admitted(&session.client()) => Ok(()),
+          QueueAccess::Private => Err(ProtocolError::Forbidden102(Error::new(format!("Client {} is not admitted to queue {}", session.client(), name)))),
+     }
+}
+
+/// Checks that the token the client of `session` authenticated with, if it did with one, allows
+/// the client to use the queue `name`
+///
+/// # Errors
+/// [`ProtocolError::Forbidden102`] if the token restricts the client to other queues
+fn allowed(state: &BrokerState, name: &str, session: SessionId) -> Result<(), ProtocolError> {
+     match state.sessions.get(&session).and_then(Session::claims) {
+          Some(claims) if !claims.allows(name) => Err(ProtocolError::Forbidden102(Error::new(format!("Token of client {} does not allow queue {}", claims.identity(), name)))),
+          _ => Ok(()),
+     }
+}
+
+/// Checks that `client` is not banned from the queue `name`
+///
+/// # Errors
+/// [`ProtocolError::Forbidden102`] if the client is banned from the queue
+fn unbanned(state: &BrokerState, name: &str, client: &str) -> Result<(), ProtocolError> {
+     let now = SystemTime::now();
+     match state.queues.get(name).and_then(|queue| queue.config().banned_until(client, now)) {
+          Some(until) => {
+               let seconds = until.duration_since(now).unwrap_or_default().as_secs();
+               Err(ProtocolError::Forbidden102(Error::new(format!("Client {} is banned from queue {} for {}s", client, name, seconds))))
+          },
+          None => Ok(()),
+     }
+}
+
+/// Retrieves the current name of the queue formerly named `name`, unless a queue is named `name`
+fn resolve(state: &BrokerState, name: &str, now: SystemTime) -> Option<String> {
+     if state.queues.contains_key(name) {
+          return None;
+     }
+     state.aliases.resolve(name, now).map(str::to_string)
+}
+
+/// Retrieves the queues a message published to `topic` is delivered to, as [`targets_of`] does
+///
+/// # Errors
+/// [`ProtocolError::NotFound103`] if no queue matches the topic
+fn matched(state: &BrokerState, topic: &str) -> Result<Vec<String>, ProtocolError> {
+     let targets = targets_of(state, topic);
+     if targets.is_empty() {
+          return Err(ProtocolError::NotFound103(Error::new(format!("No queue matches topic {}", topic))));
+     }
+     Ok(targets)
+}
+
+/// Retrieves the queues a message published to `topic` is delivered to. A temporary queue only
+/// receives the messages published to it by name, and is never matched by a pattern.
+fn targets_of(state: &BrokerState, topic: &str) -> Vec<String> {
+     match state.queues.get(topic) {
+          Some(queue) if queue.owner().is_some() => vec![topic.to_string()],
+          _ => state.topics.matches(topic),
+     }
+}
+
+/// Retrieves the queue named after `topic`, which may spell its name with other separators
+fn named(state: &BrokerState, topic: &str) -> Option<String> {
+     if topic::is_pattern(topic) {
+          return None;
+     }
+     if state.queues.contains_key(topic) {
+          return Some(topic.to_string());
+     }
+     state.topics.matches(topic).into_iter().find(|target| !topic::is_pattern(target))
+}
+
+/// Builds a successful response carrying `storage`
+fn success(storage: MTPStorage) -> MTPResponse {
+     MTPResponse::construct(MTPStatusCode::Success0, MTPHeaders::empty(), storage)
+}
+
+/// Builds the frame pushed to a client when its request to join `queue` is decided
+fn decision(queue: String, verdict: &str) -> MTPResponse {
+     let mut storage = MTPStorage::new(Vec::new());
+     storage.push("queue".to_string(), queue);
+     storage.push("decision".to_string(), verdict.to_string());
+     success(storage)
+}
+
+/// Builds a response reporting `error`
+fn failure(error: ProtocolError) -> MTPResponse {
+     MTPResponse::construct(MTPStatusCode::Error1(error), MTPHeaders::empty(), MTPStorage::new(Vec::new()))
+}
+
+#[cfg(test)]
+mod tests {
+     use std::sync::Arc;
+     use std::time::{Duration, SystemTime};
+
+     use base64ct::{Base64, Encoding};
+     use tokio::sync::mpsc::UnboundedReceiver;
+
+     use net::protocol::{MTPHeaders, MTPMessage, MTPPayload, MTPResponse, MTPStorage};
+     use net::protocol::error::ProtocolError;
+     use net::protocol::interface::{AuthSchemes, ContentType, Distribution, MTPAuth, MTPHeaderUnit, MTPManagerAction, MTPStatusCode, MessageCategory, MessagePriority, MessagePublish, MessageTransferProtocolResponse, Overflow, QueueAccess, TransactionStep};
+     use security_gateway::chain::ProviderChain;
+     use security_gateway::provider::Identity;
+     use security_gateway::tokens::StaticTokens;
+
+     use super::{Broker, BrokerConfig, SessionId};
+     use super::queue::QueueConfig;
+     use super::token::TokenClaims;
+
+     const KEY: &[u8] = b"audit-key";
+
+     fn headers(units: Vec<MTPHeaderUnit>) -> MTPHeaders {
+          MTPHeaders::new(units, MTPStorage::new(Vec::new()), Some(SystemTime::now()))
+     }
+
+     fn create(name: &str, access: QueueAccess) -> MTPPayload {
+          MTPPayload::manage(headers(vec![MTPHeaderUnit::QueueCreation { name: name.to_string(), access, durable: false, max_length: None, max_bytes: None, overflow: Overflow::Reject, ttl: None, max_deliveries: None, dead_letter: None }]), None)
+     }
+
+     fn publish(topic: &str) -> MTPPayload {
+          let message = MTPMessage::new(ContentType::JSON, MessagePriority::Low, MessageCategory::EVENT, MessagePublish::ALL, "{}".to_string());
+          MTPPayload::publish(headers(vec![MTPHeaderUnit::MessagePublish { queue: topic.to_string(), to: MessagePublish::ALL }]), Some(message))
+     }
+
+     fn publish_with(topic: &str, units: Vec<MTPHeaderUnit>) -> MTPPayload {
+          let message = MTPMessage::new(ContentType::JSON, MessagePriority::Low, MessageCategory::EVENT, MessagePublish::ALL, "{}".to_string());
+          let units = [vec![MTPHeaderUnit::MessagePublish { queue: topic.to_string(), to: MessagePublish::ALL }], units].concat();
+          MTPPayload::publish(headers(units), Some(message))
+     }
+
+     fn subscribe(queue: &str, units: Vec<MTPHeaderUnit>) -> MTPPayload {
+          let units = [vec![MTPHeaderUnit::Subscription { queue: queue.to_string() }], units].concat();
+          MTPPayload::subscribe(headers(units), None)
+     }
+
+     fn schedule(topic: &str, id: &str) -> MTPPayload {
+          let message = MTPMessage::new(ContentType::JSON, MessagePriority::Low, MessageCategory::EVENT, MessagePublish::ALL, "{}".to_string());
+          MTPPayload::publish(headers(vec![
+               MTPHeaderUnit::MessagePublish { queue: topic.to_string(), to: MessagePublish::ALL },
+               MTPHeaderUnit::Message { id: id.to_string(), timestamp: None, priority: MessagePriority::Low, category: MessageCategory::EVENT, content_type: ContentType::JSON },
+               MTPHeaderUnit::Delay { delay: Duration::from_secs(60) },
+          ]), Some(message))
+     }
+
+     fn cancel(id: &str) -> MTPPayload {
+          MTPPayload::manage(headers(vec![MTPHeaderUnit::Administration { action: MTPManagerAction::Cancel(id.to_string()) }]), None)
+     }
+
+     fn pull(queue: &str, max_messages: u32, max_bytes: u64, wait: Duration) -> MTPPayload {
+          MTPPayload::pull(headers(vec![MTPHeaderUnit::Subscription { queue: queue.to_string() }, MTPHeaderUnit::Batch { max_messages, max_bytes, wait }]), None)
+     }
+
+     fn step(step: TransactionStep) -> MTPPayload {
+          MTPPayload::manage(headers(vec![MTPHeaderUnit::Transaction { step }]), None)
+     }
+
+     fn acknowledge(queue: &str, offset: u64) -> MTPPayload {
+          MTPPayload::acknowledge(headers(vec![MTPHeaderUnit::Delivery { queue: queue.to_string(), id: String::new(), offset }]), None)
+     }
+
+     fn offsets(response: &MTPResponse) -> Vec<u64> {
+          response.get_messages().iter()
+               .filter_map(|envelope| envelope.headers().units().iter().find_map(|unit| match unit {
+                    MTPHeaderUnit::Delivery { offset, .. } => Some(*offset),
+                    _ => None,
+               }))
+               .collect()
+     }
+
+     fn delivered(rx: &mut UnboundedReceiver<MTPResponse>) -> usize {
+          let mut count = 0;
+          while let Ok(frame) = rx.try_recv() {
+               count += frame.get_messages().len();
+          }
+          count
+     }
+
+     fn cell(response: &MTPResponse, key: &str) -> Option<String> {
+          response.get_storage().and_then(|storage| storage.get(key).map(str::to_string))
+     }
+
+     fn manage(queue: &str, action: MTPManagerAction) -> MTPPayload {
+          MTPPayload::manage(headers(vec![MTPHeaderUnit::Subscription { queue: queue.to_string() }, MTPHeaderUnit::Administration { action }]), None)
+     }
+
+     fn administer(action: MTPManagerAction) -> MTPPayload {
+          MTPPayload::manage(headers(vec![MTPHeaderUnit::Administration { action }]), None)
+     }
+
+     fn authenticate(key: MTPAuth, value: &str) -> MTPPayload {
+          MTPPayload::ping(headers(vec![MTPHeaderUnit::Authentication { key, value: value.to_string() }]), None)
+     }
+
+     fn basic(username: &str, password: &str) -> MTPPayload {
+          authenticate(MTPAuth::Authorization { scheme: AuthSchemes::Basic }, &Base64::encode_string(format!("{}:{}", username, password).as_bytes()))
+     }
+
+     fn identity(broker: &Broker, session: SessionId) -> Option<String> {
+          broker.lock().sessions[&session].identity().map(str::to_string)
+     }
+
+     fn succeeded(response: &MTPResponse) -> bool {
+          matches!(response.get_status_code(), MTPStatusCode::Success0)
+     }
+
+     fn forbidden(response: &MTPResponse) -> bool {
+          matches!(response.get_status_code(), MTPStatusCode::Error1(ProtocolError::Forbidden102(_)))
+     }
+
+     fn conflicting(response: &MTPResponse) -> bool {
+          matches!(response.get_status_code(), MTPStatusCode::Error1(ProtocolError::Conflict108(_)))
+     }
+
+     #[tokio::test]
+     async fn expired_messages_are_dead_lettered() {
+          let broker = Broker::new(BrokerConfig::default()).ok().unwrap();
+          broker.declare("orders".to_string(), QueueConfig::default().with_dead_letter("expired".to_string())).ok().unwrap();
+          broker.declare("expired".to_string(), QueueConfig::default()).ok().unwrap();
+          let (session, mut rx) = broker.connect("127.0.0.1:1".parse().unwrap());
+
+          assert!(succeeded(&broker.handle(session, publish_with("orders", vec![MTPHeaderUnit::TimeToLive { ttl: Duration::from_millis(20) }])).await));
+          assert!(succeeded(&broker.handle(session, publish("orders")).await));
+          tokio::time::sleep(Duration::from_millis(50)).await;
+          broker.sweep();
+          assert_eq!(broker.lock().queues["orders"].len(), 1);
+
+          assert!(succeeded(&broker.handle(session, subscribe("expired", Vec::new())).await));
+          assert_eq!(rx.try_recv().unwrap().get_messages().len(), 1);
+     }
+
+     #[tokio::test]
+     async fn publish_checks_queue_spelled_with_other_separators() {
+          let broker = Broker::new(BrokerConfig::default()).ok().unwrap();
+          let (owner, _owner_rx) = broker.connect("127.0.0.1:1".parse().unwrap());
+          let (other, _other_rx) = broker.connect("127.0.0.1:2".parse().unwrap());
+
+          assert!(succeeded(&broker.handle(owner, create("orders.eu.created", QueueAccess::Private)).await));
+          assert!(succeeded(&broker.handle(owner, create("orders.#", QueueAccess::Public)).await));
+          assert!(forbidden(&broker.handle(other, publish("orders.eu.created")).await));
+          assert!(forbidden(&broker.handle(other, publish("orders/eu/created")).await));
+
+          let response = broker.handle(owner, publish("orders/eu/created")).await;
+          assert!(succeeded(&response));
+          assert!(response.get_storage().unwrap().get("offset").is_some());
+     }
+
+     #[tokio::test]
+     async fn delayed_messages_are_delivered_once_due() {
+          let broker = Broker::new(BrokerConfig::default()).ok().unwrap();
+          broker.declare("orders".to_string(), QueueConfig::default()).ok().unwrap();
+          let (session, mut rx) = broker.connect("127.0.0.1:1".parse().unwrap());
+          assert!(succeeded(&broker.handle(session, subscribe("orders", Vec::new())).await));
+
+          assert!(succeeded(&broker.handle(session, publish_with("orders", vec![MTPHeaderUnit::Delay { delay: Duration::from_millis(20) }])).await));
+          broker.release_due();
+          assert!(rx.try_recv().is_err());
+
+          tokio::time::sleep(Duration::from_millis(50)).await;
+          broker.release_due();
+          assert_eq!(rx.try_recv().unwrap().get_messages().len(), 1);
+     }
+
+     #[tokio::test]
+     async fn consumer_groups_share_messages_and_rebalance() {
+          let broker = Broker::new(BrokerConfig::default().with_prefetch(100)).ok().unwrap();
+          broker.declare("orders".to_string(), QueueConfig::default()).ok().unwrap();
+          let (first, mut first_rx) = broker.connect("127.0.0.1:1".parse().unwrap());
+          let (second, mut second_rx) = broker.connect("127.0.0.1:2".parse().unwrap());
+          let (single, mut single_rx) = broker.connect("127.0.0.1:3".parse().unwrap());
+          let group = |distribution| vec![MTPHeaderUnit::ConsumerGroup { group: "billing".to_string(), distribution }];
+
+          assert!(succeeded(&broker.handle(first, subscribe("orders", group(Distribution::RoundRobin))).await));
+          assert!(succeeded(&broker.handle(second, subscribe("orders", group(Distribution::RoundRobin))).await));
+          assert!(conflicting(&broker.handle(single, subscribe("orders", group(Distribution::LeastLoaded))).await));
+          assert!(succeeded(&broker.handle(single, subscribe("orders", Vec::new())).await));
+          for _ in 0..4 {
+               assert!(succeeded(&broker.handle(single, publish("orders")).await));
+          }
+          assert_eq!(delivered(&mut first_rx), 2);
+          assert_eq!(delivered(&mut second_rx), 2);
+          assert_eq!(delivered(&mut single_rx), 4);
+
+          // the unacknowledged messages of a leaving member go to the remaining ones
+          assert!(succeeded(&broker.handle(first, MTPPayload::unsubscribe(headers(vec![MTPHeaderUnit::Subscription { queue: "orders".to_string() }]), None)).await));
+          assert!(succeeded(&broker.handle(single, publish("orders")).await));
+          assert_eq!(delivered(&mut second_rx), 3);
+          assert_eq!(delivered(&mut first_rx), 0);
+     }
+
+     #[tokio::test]
+     async fn pulls_return_bounded_batches_and_wait_for_messages() {
+          let broker = Arc::new(Broker::new(BrokerConfig::default()).ok().unwrap());
+          broker.declare("orders".to_string(), QueueConfig::default()).ok().unwrap();
+          let (consumer, _consumer_rx) = broker.connect("127.0.0.1:1".parse().unwrap());
+          let (producer, _producer_rx) = broker.connect("127.0.0.1:2".parse().unwrap());
+          for _ in 0..5 {
+               assert!(succeeded(&broker.handle(producer, publish("orders")).await));
+          }
+
+          assert_eq!(broker.handle(consumer, pull("orders", 3, 1000, Duration::ZERO)).await.get_messages().len(), 3);
+          assert_eq!(broker.handle(consumer, pull("orders", 10, 3, Duration::ZERO)).await.get_messages().len(), 1);
+          assert_eq!(broker.handle(consumer, pull("orders", 10, 1000, Duration::ZERO)).await.get_messages().len(), 1);
+          assert!(broker.handle(consumer, pull("orders", 10, 1000, Duration::from_millis(20))).await.get_messages().is_empty());
+
+          let waiting = {
+               let broker = Arc::clone(&broker);
+               tokio::spawn(async move { broker.handle(consumer, pull("orders", 10, 1000, Duration::from_secs(5))).await })
+          };
+          tokio::time::sleep(Duration::from_millis(20)).await;
+          assert!(succeeded(&broker.handle(producer, publish("orders")).await));
+          let response = tokio::time::timeout(Duration::from_secs(1), waiting).await.ok().unwrap().unwrap();
+          assert_eq!(response.get_messages().len(), 1);
+     }
+
+     #[tokio::test]
+     async fn created_queues_apply_their_bounds() {
+          let broker = Broker::new(BrokerConfig::default()).ok().unwrap();
+          let (session, _rx) = broker.connect("127.0.0.1:1".parse().unwrap());
+          let bounded = |name: &str, max_length, overflow, dead_letter: Option<&str>| MTPPayload::manage(headers(vec![MTPHeaderUnit::QueueCreation { name: name.to_string(), access: QueueAccess::Public, durable: false, max_length, max_bytes: None, overflow, ttl: None, max_deliveries: None, dead_letter: dead_letter.map(str::to_string) }]), None);
+
+          assert!(matches!(broker.handle(session, publish("orders")).await.get_status_code(), MTPStatusCode::Error1(ProtocolError::NotFound103(_))));
+          assert!(succeeded(&broker.handle(session, bounded("orders", Some(2), Overflow::Reject, None)).await));
+          assert!(succeeded(&broker.handle(session, bounded("orders", Some(2), Overflow::Reject, None)).await));
+          assert!(conflicting(&broker.handle(session, bounded("orders", Some(3), Overflow::Reject, None)).await));
+          assert!(succeeded(&broker.handle(session, publish("orders")).await));
+          assert!(succeeded(&broker.handle(session, publish("orders")).await));
+          assert!(matches!(broker.handle(session, publish("orders")).await.get_status_code(), MTPStatusCode::Error1(ProtocolError::InsufficientStorage126(_))));
+
+          // the oldest messages of a full queue make room by moving to its dead-letter queue
+          assert!(succeeded(&broker.handle(session, create("overflowed", QueueAccess::Public)).await));
+          assert!(succeeded(&broker.handle(session, bounded("invoices", Some(2), Overflow::DeadLetter, Some("overflowed"))).await));
+          for _ in 0..4 {
+               assert!(succeeded(&broker.handle(session, publish("invoices")).await));
+          }
+          assert_eq!(broker.lock().queues["invoices"].len(), 2);
+          assert_eq!(broker.lock().queues["overflowed"].len(), 2);
+     }
+
+     #[tokio::test]
+     async fn transactions_apply_all_or_nothing() {
+          let broker = Broker::new(BrokerConfig::default()).ok().unwrap();
+          broker.declare("orders".to_string(), QueueConfig::default()).ok().unwrap();
+          broker.declare("invoices".to_string(), QueueConfig::default()).ok().unwrap();
+          broker.declare("shipments".to_string(), QueueConfig::default().with_max_length(1)).ok().unwrap();
+          let (session, _rx) = broker.connect("127.0.0.1:1".parse().unwrap());
+          let (other, _other_rx) = broker.connect("127.0.0.1:2".parse().unwrap());
+          let pending = |broker: &Broker, queue: &str| broker.lock().queues[queue].len();
+
+          assert!(succeeded(&broker.handle(session, publish("orders")).await));
+          let offset = offsets(&broker.handle(session, pull("orders", 10, 1000, Duration::ZERO)).await)[0];
+          assert!(matches!(broker.handle(session, step(TransactionStep::Commit)).await.get_status_code(), MTPStatusCode::Error1(ProtocolError::PreconditionFailed110(_))));
+
+          // staged changes are invisible until committed and discarded on rollback
+          assert!(succeeded(&broker.handle(session, step(TransactionStep::Begin)).await));
+          assert!(conflicting(&broker.handle(session, step(TransactionStep::Begin)).await));
+          assert!(succeeded(&broker.handle(session, acknowledge("orders", offset)).await));
+          assert!(succeeded(&broker.handle(session, publish("invoices")).await));
+          assert_eq!(pending(&broker, "invoices"), 0);
+          assert!(succeeded(&broker.handle(session, step(TransactionStep::Rollback)).await));
+          assert_eq!(pending(&broker, "invoices"), 0);
+
+          // a commit failing on one message applies none of the others
+          assert!(succeeded(&broker.handle(session, step(TransactionStep::Begin)).await));
+          assert!(succeeded(&broker.handle(session, acknowledge("orders", offset)).await));
+          assert!(succeeded(&broker.handle(session, publish("invoices")).await));
+          assert!(succeeded(&broker.handle(session, publish("shipments")).await));
+          assert!(succeeded(&broker.handle(other, publish("shipments")).await));
+          assert!(!succeeded(&broker.handle(session, step(TransactionStep::Commit)).await));
+          assert_eq!(pending(&broker, "invoices"), 0);
+          assert_eq!(pending(&broker, "shipments"), 1);
+
+          assert!(succeeded(&broker.handle(session, step(TransactionStep::Begin)).await));
+          assert!(succeeded(&broker.handle(session, acknowledge("orders", offset)).await));
+          assert!(succeeded(&broker.handle(session, publish("invoices")).await));
+          assert!(succeeded(&broker.handle(session, step(TransactionStep::Commit)).await));
+          assert_eq!(pending(&broker, "invoices"), 1);
+          assert!(!succeeded(&broker.handle(session, acknowledge("orders", offset)).await));
+
+          // closing a session rolls its transaction back
+          assert!(succeeded(&broker.handle(other, step(TransactionStep::Begin)).await));
+          assert!(succeeded(&broker.handle(other, publish("invoices")).await));
+          broker.disconnect(other);
+          assert_eq!(pending(&broker, "invoices"), 1);
+     }
+
+     #[tokio::test]
+     async fn partition_keys_stay_with_one_group_member_in_order() {
+          let broker = Broker::new(BrokerConfig::default().with_prefetch(100)).ok().unwrap();
+          broker.declare("orders".to_string(), QueueConfig::default()).ok().unwrap();
+          let (first, mut first_rx) = broker.connect("127.0.0.1:1".parse().unwrap());
+          let (second, mut second_rx) = broker.connect("127.0.0.1:2".parse().unwrap());
+          let group = || vec![MTPHeaderUnit::ConsumerGroup { group: "billing".to_string(), distribution: Distribution::RoundRobin }];
+          let keyed = |key: &str, content: String| {
+               let message = MTPMessage::new(ContentType::JSON, MessagePriority::Low, MessageCategory::EVENT, MessagePublish::ALL, content);
+               MTPPayload::publish(headers(vec![MTPHeaderUnit::MessagePublish { queue: "orders".to_string(), to: MessagePublish::ALL }, MTPHeaderUnit::PartitionKey { key: key.to_string() }]), Some(message))
+          };
+          let contents = |rx: &mut UnboundedReceiver<MTPResponse>| {
+               let mut contents = Vec::new();
+               while let Ok(frame) = rx.try_recv() {
+                    contents.extend(frame.get_messages().iter().map(|envelope| envelope.message().message().to_string()));
+               }
+               contents
+          };
+
+          assert!(succeeded(&broker.handle(first, subscribe("orders", group())).await));
+          for index in 0..3 {
+               for key in ["eu", "us"] {
+                    assert!(succeeded(&broker.handle(first, keyed(key, format!("{}-{}", key, index))).await));
+               }
+          }
+          assert_eq!(contents(&mut first_rx).len(), 6);
+
+          // the keys awaiting acknowledgement by the first member stay with it
+          assert!(succeeded(&broker.handle(second, subscribe("orders", group())).await));
+          for key in ["eu", "us"] {
+               assert!(succeeded(&broker.handle(first, keyed(key, format!("{}-3", key))).await));
+          }
+          assert_eq!(contents(&mut first_rx), vec!["eu-3", "us-3"]);
+          assert!(contents(&mut second_rx).is_empty());
+
+          // new keys are spread, each to a single member in publishing order
+          for index in 0..2 {
+               for key in ["a", "b", "c", "d"] {
+                    assert!(succeeded(&broker.handle(first, keyed(key, format!("{}-{}", key, index))).await));
+               }
+          }
+          let (first, second) = (contents(&mut first_rx), contents(&mut second_rx));
+          assert!(!first.is_empty() && !second.is_empty());
+          for key in ["a", "b", "c", "d"] {
+               let held: Vec<&Vec<String>> = [&first, &second].into_iter().filter(|contents| contents.iter().any(|content| content.starts_with(key))).collect();
+               assert_eq!(held.len(), 1);
+               let ordered: Vec<&String> = held[0].iter().filter(|content| content.starts_with(key)).collect();
+               assert_eq!(ordered, vec![&format!("{}-0", key), &format!("{}-1", key)]);
+          }
+     }
+
+     #[tokio::test]
+     async fn scheduled_messages_are_cancelled_by_publisher_or_manager() {
+          let broker = Broker::new(BrokerConfig::default()).ok().unwrap();
+          let (owner, _owner_rx) = broker.connect("127.0.0.1:1".parse().unwrap());
+          let (publisher, _publisher_rx) = broker.connect("127.0.0.1:2".parse().unwrap());
+          let (other, _other_rx) = broker.connect("127.0.0.1:3".parse().unwrap());
+
+          assert!(succeeded(&broker.handle(owner, create("orders", QueueAccess::Public)).await));
+          assert!(succeeded(&broker.handle(owner, create("metrics.#", QueueAccess::Public)).await));
+          for (topic, id) in [("orders", "a"), ("orders", "b"), ("metrics.cpu", "c")] {
+               assert!(succeeded(&broker.handle(publisher, schedule(topic, id)).await));
+          }
+
+          assert!(forbidden(&broker.handle(other, cancel("a")).await));
+          assert!(succeeded(&broker.handle(publisher, cancel("a")).await));
+          assert!(succeeded(&broker.handle(owner, cancel("b")).await));
+          assert!(forbidden(&broker.handle(owner, cancel("c")).await));
+          assert!(succeeded(&broker.handle(publisher, cancel("c")).await));
+     }
+
+     #[tokio::test]
+     async fn scheduled_messages_are_deduplicated_per_queue() {
+          let broker = Broker::new(BrokerConfig::default()).ok().unwrap();
+          let (session, _rx) = broker.connect("127.0.0.1:1".parse().unwrap());
+          assert!(succeeded(&broker.handle(session, create("orders", QueueAccess::Public)).await));
+          assert!(succeeded(&broker.handle(session, create("billing", QueueAccess::Public)).await));
+
+          let first = broker.handle(session, schedule("orders", "a")).await;
+          let retried = broker.handle(session, schedule("orders", "a")).await;
+          assert!(succeeded(&retried));
+          assert_eq!(cell(&retried, "duplicate").as_deref(), Some("true"));
+          assert_eq!(cell(&retried, "scheduled"), cell(&first, "scheduled"));
+
+          let other = broker.handle(session, schedule("billing", "a")).await;
+          assert!(succeeded(&other));
+          assert!(cell(&other, "duplicate").is_none());
+
+          assert!(conflicting(&broker.handle(session, cancel("a")).await));
+          assert!(succeeded(&broker.handle(session, manage("billing", MTPManagerAction::Cancel("a".to_string()))).await));
+          assert!(succeeded(&broker.handle(session, cancel("a")).await));
+          assert!(!succeeded(&broker.handle(session, cancel("a")).await));
+     }
+
+     #[tokio::test]
+     async fn builtin_mechanisms_follow_the_configured_provider() {
+          let provider = StaticTokens::new().with_token("ci-token", Identity::new("ci".to_string()));
+          let broker = Broker::new(BrokerConfig::default().with_token_key("k1".to_string(), b"secret".to_vec())).ok().unwrap();
+          let chain = ProviderChain::new()
+               .with(provider)
+               .with(broker.token_provider())
+               .with(broker.credential_provider());
+          let broker = broker.with_auth_provider(Arc::new(chain));
+          broker.add_user("alice".to_string(), "password").ok().unwrap();
+          let token = broker.mint_token(TokenClaims::new("alice".to_string(), SystemTime::now() + Duration::from_secs(60))).ok().unwrap();
+
+          let (configured, _configured_rx) = broker.connect("127.0.0.1:1".parse().unwrap());
+          let (password, _password_rx) = broker.connect("127.0.0.1:2".parse().unwrap());
+          let (local, _local_rx) = broker.connect("127.0.0.1:3".parse().unwrap());
+          assert!(succeeded(&broker.handle(configured, authenticate(MTPAuth::ExternalToken, "ci-token")).await));
+          assert!(!succeeded(&broker.handle(password, basic("alice", "wrong")).await));
+          assert!(succeeded(&broker.handle(password, basic("alice", "password")).await));
+          assert!(succeeded(&broker.handle(local, authenticate(MTPAuth::LocalToken, &token)).await));
+          assert!(!succeeded(&broker.handle(local, authenticate(MTPAuth::ExternalToken, &token)).await));
+          assert_eq!(identity(&broker, configured).as_deref(), Some("ci"));
+
+          // removing the user only reaches the clients that authenticated with its password
+          broker.remove_user("alice").ok().unwrap();
+          assert_eq!(identity(&broker, password), None);
+          assert_eq!(identity(&broker, local).as_deref(), Some("alice"));
+
+          broker.revoke_token(&token).ok().unwrap();
+          assert_eq!(identity(&broker, local), None);
+          assert!(!succeeded(&broker.handle(local, authenticate(MTPAuth::LocalToken, &token)).await));
+     }
+
+     #[tokio::test]
+     async fn actions_are_audited_before_they_take_effect() {
+          let dir = std::env::temp_dir().join(format!("broker-audit-{}", std::process::id()));
+          std::fs::create_dir_all(&dir).unwrap();
+          let path = dir.join("audit.log");
+          let _ = std::fs::remove_file(&path);
+
+          let broker = Broker::new(BrokerConfig::default().with_audit_log(path.clone(), KEY.to_vec())).ok().unwrap();
+          let (session, _rx) = broker.connect("127.0.0.1:1".parse().unwrap());
+          assert!(succeeded(&broker.handle(session, create("orders", QueueAccess::Public)).await));
+          assert!(succeeded(&broker.handle(session, manage("orders", MTPManagerAction::Delete)).await));
+
+          let content = std::fs::read_to_string(&path).unwrap();
+          let entries: Vec<serde_json::Value> = content.lines()
+               .map(|line| serde_json::from_str(line.split_once(' ').unwrap().1).unwrap())
+               .collect();
+          let outcomes: Vec<(&str, &str, Option<u64>)> = entries.iter()
+               .map(|entry| (entry["action"].as_str().unwrap(), entry["outcome"].as_str().unwrap(), entry["concludes"].as_u64()))
+               .collect();
+          assert_eq!(outcomes, vec![("create", "pending", None), ("create", "success", Some(1)), ("delete", "pending", None), ("delete", "success", Some(3))]);
+
+          std::fs::remove_dir_all(&dir).unwrap();
+     }
+
+     #[tokio::test]
+     async fn operator_and_temporary_queues_are_audited() {
+          let dir = std::env::temp_dir().join(format!("broker-audit-paths-{}", std::process::id()));
+          std::fs::create_dir_all(&dir).unwrap();
+          let path = dir.join("audit.log");
+          let _ = std::fs::remove_file(&path);
+
+          let broker = Broker::new(BrokerConfig::default().with_audit_log(path.clone(), KEY.to_vec())).ok().unwrap();
+          broker.declare("orders".to_string(), QueueConfig::default()).ok().unwrap();
+          let (session, _rx) = broker.connect("127.0.0.1:1".parse().unwrap());
+          assert!(succeeded(&broker.handle(session, schedule("orders", "a")).await));
+          assert!(succeeded(&broker.handle(session, cancel("a")).await));
+          assert!(succeeded(&broker.handle(session, MTPPayload::manage(headers(vec![MTPHeaderUnit::TemporaryQueue]), None)).await));
+          broker.disconnect(session);
+
+          let content = std::fs::read_to_string(&path).unwrap();
+          let entries: Vec<serde_json::Value> = content.lines()
+               .map(|line| serde_json::from_str(line.split_once(' ').unwrap().1).unwrap())
+               .collect();
+          let actions: Vec<(&str, &str, &str)> = entries.iter()
+               .map(|entry| (entry["actor"].as_str().unwrap(), entry["action"].as_str().unwrap(), entry["outcome"].as_str().unwrap()))
+               .collect();
+          assert_eq!(actions, vec![
+               ("operator", "declare", "pending"),
+               ("operator", "declare", "success"),
+               ("address:127.0.0.1:1", "cancel", "pending"),
+               ("address:127.0.0.1:1", "cancel", "success"),
+               ("address:127.0.0.1:1", "create", "success"),
+               ("address:127.0.0.1:1", "delete", "success"),
+          ]);
+          assert_eq!(entries[2]["target"], "orders");
+          assert_eq!(entries[2]["detail"], "id=a");
+          assert_eq!(entries[4]["target"], entries[5]["target"]);
+
+          std::fs::remove_dir_all(&dir).unwrap();
+     }
+
+     #[tokio::test]
+     async fn roles_of_unauthenticated_clients_are_not_persisted() {
+          let dir = std::env::temp_dir().join(format!("broker-address-roles-{}", std::process::id()));
+          let _ = std::fs::remove_dir_all(&dir);
+          let durable = |name: &str| MTPPayload::manage(headers(vec![MTPHeaderUnit::QueueCreation { name: name.to_string(), access: QueueAccess::Public, durable: true, max_length: None, max_bytes: None, overflow: Overflow::Reject, ttl: None, max_deliveries: None, dead_letter: None }]), None);
+
+          let broker = Broker::new(BrokerConfig::default().with_data_dir(dir.clone())).ok().unwrap();
+          let (anonymous, _anonymous_rx) = broker.connect("127.0.0.1:1".parse().unwrap());
+          let (alice, _alice_rx) = broker.connect("127.0.0.1:2".parse().unwrap());
+          assert!(broker.authenticate(alice, "address:127.0.0.1:1".to_string()).is_err());
+          broker.authenticate(alice, "alice".to_string()).ok().unwrap();
+          assert!(succeeded(&broker.handle(anonymous, durable("orders")).await));
+          assert!(succeeded(&broker.handle(alice, durable("invoices")).await));
+          assert!(broker.lock().queues["orders"].config().role("address:127.0.0.1:1").is_some());
+          drop(broker);
+
+          let broker = Broker::new(BrokerConfig::default().with_data_dir(dir.clone())).ok().unwrap();
+          assert!(broker.lock().queues["orders"].config().roles().is_empty());
+          assert!(broker.lock().queues["invoices"].config().role("alice").is_some());
+
+          std::fs::remove_dir_all(&dir).unwrap();
+     }
+
+     #[tokio::test]
+     async fn temporary_queues_are_exclusive_to_their_session() {
+          let broker = Broker::new(BrokerConfig::default()).ok().unwrap();
+          let (requester, mut requester_rx) = broker.connect("127.0.0.1:1".parse().unwrap());
+          let (responder, _responder_rx) = broker.connect("127.0.0.1:2".parse().unwrap());
+
+          let response = broker.handle(requester, MTPPayload::manage(headers(vec![MTPHeaderUnit::TemporaryQueue]), None)).await;
+          let name = cell(&response, "queue").unwrap();
+          assert!(succeeded(&broker.handle(requester, subscribe(&name, Vec::new())).await));
+          assert!(forbidden(&broker.handle(responder, subscribe(&name, Vec::new())).await));
+
+          // other clients reply to the queue by name
+          assert!(succeeded(&broker.handle(responder, publish_with(&name, vec![MTPHeaderUnit::Correlation { id: "1".to_string() }])).await));
+          assert_eq!(delivered(&mut requester_rx), 1);
+
+          broker.disconnect(requester);
+          assert!(!broker.lock().queues.contains_key(&name));
+     }
+
+     #[tokio::test]
+     async fn temporary_queues_are_not_left_behind_by_failing_actions() {
+          let broker = Broker::new(BrokerConfig::default()).ok().unwrap();
+          let (session, _rx) = broker.connect("127.0.0.1:1".parse().unwrap());
+
+          let payload = MTPPayload::manage(headers(vec![
+               MTPHeaderUnit::TemporaryQueue,
+               MTPHeaderUnit::Subscription { queue: "missing".to_string() },
+               MTPHeaderUnit::Administration { action: MTPManagerAction::Delete },
+          ]), None);
+          assert!(!succeeded(&broker.handle(session, payload).await));
+          assert!(broker.lock().queues.is_empty());
+
+          let response = broker.handle(session, MTPPayload::manage(headers(vec![MTPHeaderUnit::TemporaryQueue]), None)).await;
+          let name = cell(&response, "queue").unwrap();
+          assert!(broker.lock().queues.contains_key(&name));
+     }
+
+     #[tokio::test]
+     async fn renamed_queues_redirect_their_former_name() {
+          let broker = Broker::new(BrokerConfig::default()).ok().unwrap();
+          let (session, mut rx) = broker.connect("127.0.0.1:1".parse().unwrap());
+          assert!(succeeded(&broker.handle(session, create("orders", QueueAccess::Public)).await));
+          assert!(succeeded(&broker.handle(session, create("invoices", QueueAccess::Public)).await));
+          assert!(succeeded(&broker.handle(session, subscribe("orders", Vec::new())).await));
+
+          assert!(conflicting(&broker.handle(session, manage("orders", MTPManagerAction::Rename("invoices".to_string()))).await));
+          assert!(succeeded(&broker.handle(session, manage("orders", MTPManagerAction::Rename("purchases".to_string()))).await));
+          let notification = rx.try_recv().unwrap();
+          assert_eq!(cell(&notification, "renamed").as_deref(), Some("orders"));
+          assert_eq!(cell(&notification, "queue").as_deref(), Some("purchases"));
+
+          let response = broker.handle(session, publish("orders")).await;
+          assert!(succeeded(&response));
+          assert_eq!(cell(&response, "deprecated").as_deref(), Some("purchases"));
+          assert_eq!(delivered(&mut rx), 1);
+          assert!(!succeeded(&broker.handle(session, manage("invoices", MTPManagerAction::Rename("orders".to_string()))).await));
+
+          // declaring a queue under the former name retires the alias
+          broker.declare("orders".to_string(), QueueConfig::default()).ok().unwrap();
+          let response = broker.handle(session, publish("orders")).await;
+          assert!(succeeded(&response));
+          assert_eq!(cell(&response, "deprecated"), None);
+     }
+
+     #[tokio::test]
+     async fn access_modes_restrict_who_uses_a_queue() {
+          let broker = Broker::new(BrokerConfig::default()).ok().unwrap();
+          let (moderator, _moderator_rx) = broker.connect("127.0.0.1:1".parse().unwrap());
+          let (other, mut other_rx) = broker.connect("127.0.0.1:2".parse().unwrap());
+          let unauthorized = |response: &MTPResponse| matches!(response.get_status_code(), MTPStatusCode::Error1(ProtocolError::Unauthorized101(_)));
+
+          assert!(succeeded(&broker.handle(moderator, create("orders", QueueAccess::Private)).await));
+          assert!(succeeded(&broker.handle(moderator, publish("orders")).await));
+          assert!(forbidden(&broker.handle(other, publish("orders")).await));
+          assert!(forbidden(&broker.handle(other, pull("orders", 10, 1000, Duration::ZERO)).await));
+          assert!(forbidden(&broker.handle(other, manage("orders", MTPManagerAction::AccessorModify(QueueAccess::Public))).await));
+
+          broker.declare("invoices".to_string(), QueueConfig::default().with_access(QueueAccess::Private).with_admitted("address:127.0.0.1:2".to_string())).ok().unwrap();
+          assert!(succeeded(&broker.handle(other, publish("invoices")).await));
+
+          // protected queues admit any authenticated client
+          assert!(succeeded(&broker.handle(moderator, manage("orders", MTPManagerAction::AccessorModify(QueueAccess::Protected))).await));
+          assert!(unauthorized(&broker.handle(other, subscribe("orders", Vec::new())).await));
+          broker.authenticate(other, "bob".to_string()).ok().unwrap();
+          assert!(succeeded(&broker.handle(other, subscribe("orders", Vec::new())).await));
+          delivered(&mut other_rx);
+
+          // making the queue private again evicts the clients it no longer admits
+          assert!(succeeded(&broker.handle(moderator, manage("orders", MTPManagerAction::AccessorModify(QueueAccess::Private))).await));
+          assert!(succeeded(&broker.handle(moderator, publish("orders")).await));
+          assert_eq!(delivered(&mut other_rx), 0);
+          assert!(succeeded(&broker.handle(moderator, manage("orders", MTPManagerAction::AccessorModify(QueueAccess::Public))).await));
+          assert!(succeeded(&broker.handle(other, publish("orders")).await));
+     }
+
+     #[tokio::test]
+     async fn join_requests_await_the_decision_of_the_moderator() {
+          let broker = Broker::new(BrokerConfig::default().with_join_timeout(Duration::from_millis(20))).ok().unwrap();
+          let (moderator, mut moderator_rx) = broker.connect("127.0.0.1:1".parse().unwrap());
+          let (admitted, mut admitted_rx) = broker.connect("127.0.0.1:2".parse().unwrap());
+          let (rejected, mut rejected_rx) = broker.connect("127.0.0.1:3".parse().unwrap());
+          assert!(succeeded(&broker.handle(moderator, create("orders", QueueAccess::Private)).await));
+
+          let response = broker.handle(admitted, subscribe("orders", Vec::new())).await;
+          assert!(succeeded(&response));
+          assert_eq!(cell(&response, "pending").as_deref(), Some("true"));
+          assert_eq!(cell(&moderator_rx.try_recv().unwrap(), "join").as_deref(), Some("address:127.0.0.1:2"));
+          assert!(succeeded(&broker.handle(rejected, subscribe("orders", Vec::new())).await));
+          moderator_rx.try_recv().unwrap();
+
+          assert!(forbidden(&broker.handle(admitted, manage("orders", MTPManagerAction::Pending)).await));
+          let response = broker.handle(moderator, manage("orders", MTPManagerAction::Pending)).await;
+          assert_eq!(response.get_storage().unwrap().items().len(), 2);
+
+          assert!(succeeded(&broker.handle(moderator, manage("orders", MTPManagerAction::Authorize("address:127.0.0.1:2".to_string()))).await));
+          assert_eq!(cell(&admitted_rx.try_recv().unwrap(), "decision").as_deref(), Some("authorized"));
+          assert!(succeeded(&broker.handle(moderator, publish("orders")).await));
+          assert_eq!(delivered(&mut admitted_rx), 1);
+
+          assert!(succeeded(&broker.handle(moderator, manage("orders", MTPManagerAction::Reject("address:127.0.0.1:3".to_string()))).await));
+          assert_eq!(cell(&rejected_rx.try_recv().unwrap(), "decision").as_deref(), Some("rejected"));
+          assert!(!succeeded(&broker.handle(moderator, manage("orders", MTPManagerAction::Reject("address:127.0.0.1:3".to_string()))).await));
+
+          // requests left undecided expire
+          assert!(succeeded(&broker.handle(rejected, subscribe("orders", Vec::new())).await));
+          tokio::time::sleep(Duration::from_millis(50)).await;
+          broker.sweep();
+          assert_eq!(cell(&rejected_rx.try_recv().unwrap(), "decision").as_deref(), Some("expired"));
+     }
+
+     #[tokio::test]
+     async fn disposed_clients_are_evicted_and_banned() {
+          let broker = Broker::new(BrokerConfig::default()).ok().unwrap();
+          let (moderator, _moderator_rx) = broker.connect("127.0.0.1:1".parse().unwrap());
+          let (disposed, mut disposed_rx) = broker.connect("127.0.0.1:2".parse().unwrap());
+          let (member, mut member_rx) = broker.connect("127.0.0.1:3".parse().unwrap());
+          let group = || vec![MTPHeaderUnit::ConsumerGroup { group: "billing".to_string(), distribution: Distribution::RoundRobin }];
+          let dispose = |client: &str, ban, disconnect| manage("orders", MTPManagerAction::Dispose { client: client.to_string(), ban, disconnect });
+
+          assert!(succeeded(&broker.handle(moderator, create("orders", QueueAccess::Public)).await));
+          assert!(succeeded(&broker.handle(disposed, subscribe("orders", group())).await));
+          assert!(succeeded(&broker.handle(moderator, publish("orders")).await));
+          assert_eq!(delivered(&mut disposed_rx), 1);
+          assert!(succeeded(&broker.handle(member, subscribe("orders", group())).await));
+          delivered(&mut member_rx);
+
+          assert!(forbidden(&broker.handle(disposed, dispose("address:127.0.0.1:3", None, false)).await));
+          assert!(succeeded(&broker.handle(moderator, dispose("address:127.0.0.1:2", Some(Duration::from_millis(20)), true)).await));
+          let notification = disposed_rx.try_recv().unwrap();
+          assert_eq!(cell(&notification, "decision").as_deref(), Some("disposed"));
+          assert!(cell(&notification, "banned").is_some());
+
+          // the unacknowledged messages of the disposed client go to the rest of its group
+          assert_eq!(delivered(&mut member_rx), 1);
+          assert!(!succeeded(&broker.handle(disposed, subscribe("orders", group())).await));
+
+          let (returning, _returning_rx) = broker.connect("127.0.0.1:2".parse().unwrap());
+          assert!(forbidden(&broker.handle(returning, subscribe("orders", group())).await));
+          assert!(forbidden(&broker.handle(returning, publish("orders")).await));
+          tokio::time::sleep(Duration::from_millis(50)).await;
+          broker.sweep();
+          assert!(succeeded(&broker.handle(returning, subscribe("orders", group())).await));
+          assert!(!succeeded(&broker.handle(moderator, dispose("nobody", None, false)).await));
+     }
+
+     #[tokio::test]
+     async fn cookies_resume_sessions_within_their_grace() {
+          let broker = Broker::new(BrokerConfig::default().with_prefetch(100).with_resumption(Duration::from_millis(50))).ok().unwrap();
+          broker.declare("orders".to_string(), QueueConfig::default()).ok().unwrap();
+          broker.add_user("alice".to_string(), "password").ok().unwrap();
+          let (producer, _producer_rx) = broker.connect("127.0.0.1:1".parse().unwrap());
+          let unauthorized = |response: &MTPResponse| matches!(response.get_status_code(), MTPStatusCode::Error1(ProtocolError::Unauthorized101(_)));
+
+          let (first, mut first_rx) = broker.connect("127.0.0.1:2".parse().unwrap());
+          let cookie = cell(&broker.handle(first, basic("alice", "password")).await, "cookie").unwrap();
+          assert!(cell(&broker.handle(producer, MTPPayload::ping(headers(Vec::new()), None)).await, "cookie").is_none());
+          assert!(succeeded(&broker.handle(first, subscribe("orders", Vec::new())).await));
+          assert!(succeeded(&broker.handle(producer, publish("orders")).await));
+          assert_eq!(delivered(&mut first_rx), 1);
+
+          // the resumed session keeps its subscription and the messages it had in flight
+          broker.disconnect(first);
+          assert!(succeeded(&broker.handle(producer, publish("orders")).await));
+          let (second, mut second_rx) = broker.connect("127.0.0.1:3".parse().unwrap());
+          let response = broker.handle(second, authenticate(MTPAuth::Cookie, &cookie)).await;
+          assert!(succeeded(&response));
+          let renewed = cell(&response, "cookie").unwrap();
+          assert_ne!(renewed, cookie);
+          assert_eq!(delivered(&mut second_rx), 2);
+          assert_eq!(identity(&broker, second).as_deref(), Some("alice"));
+
+          let (third, _third_rx) = broker.connect("127.0.0.1:4".parse().unwrap());
+          assert!(unauthorized(&broker.handle(third, authenticate(MTPAuth::Cookie, &cookie)).await));
+          assert!(unauthorized(&broker.handle(third, authenticate(MTPAuth::Cookie, "garbage")).await));
+
+          // a session parked past its grace is closed
+          broker.disconnect(second);
+          tokio::time::sleep(Duration::from_millis(80)).await;
+          broker.sweep();
+          assert!(unauthorized(&broker.handle(third, authenticate(MTPAuth::Cookie, &renewed)).await));
+     }
+
+     #[tokio::test]
+     async fn queues_cannot_share_a_topic() {
+          let broker = Broker::new(BrokerConfig::default()).ok().unwrap();
+          let (session, _rx) = broker.connect("127.0.0.1:1".parse().unwrap());
+
+          assert!(succeeded(&broker.handle(session, create("orders.eu", QueueAccess::Public)).await));
+          assert!(succeeded(&broker.handle(session, create("orders.us", QueueAccess::Public)).await));
+          assert!(conflicting(&broker.handle(session, create("orders/eu", QueueAccess::Public)).await));
+          assert!(conflicting(&broker.handle(session, manage("orders.us", MTPManagerAction::Rename("orders/eu".to_string()))).await));
+     }
+
+     #[tokio::test]
+     async fn users_are_managed_by_administrators() {
+          let config = BrokerConfig::default()
+               .with_administrator("root".to_string())
+               .with_lockout(1, Duration::from_secs(60));
+          let broker = Broker::new(config).ok().unwrap();
+          broker.add_user("root".to_string(), "toor").ok().unwrap();
+          let (admin, _admin_rx) = broker.connect("127.0.0.1:1".parse().unwrap());
+          let (client, _client_rx) = broker.connect("127.0.0.1:2".parse().unwrap());
+          assert!(succeeded(&broker.handle(admin, basic("root", "toor")).await));
+
+          let add = |password: &str| administer(MTPManagerAction::AddUser { username: "bob".to_string(), password: password.to_string() });
+          assert!(forbidden(&broker.handle(client, add("secret")).await));
+          assert!(succeeded(&broker.handle(admin, add("secret")).await));
+          assert!(conflicting(&broker.handle(admin, add("other")).await));
+          assert_eq!(broker.users(), vec!["bob", "root"]);
+
+          // a single failure locks bob out until unlocked
+          assert!(!succeeded(&broker.handle(client, basic("bob", "wrong")).await));
+          assert!(!succeeded(&broker.handle(client, basic("bob", "secret")).await));
+          assert!(forbidden(&broker.handle(client, administer(MTPManagerAction::UnlockUser("bob".to_string()))).await));
+          assert!(succeeded(&broker.handle(admin, administer(MTPManagerAction::UnlockUser("bob".to_string()))).await));
+          assert!(succeeded(&broker.handle(client, basic("bob", "secret")).await));
+
+          let change = administer(MTPManagerAction::ChangePassword { username: "bob".to_string(), password: "renewed".to_string() });
+          assert!(succeeded(&broker.handle(admin, change).await));
+          assert!(succeeded(&broker.handle(client, basic("bob", "renewed")).await));
+          assert_eq!(identity(&broker, client).as_deref(), Some("bob"));
+
+          assert!(succeeded(&broker.handle(admin, administer(MTPManagerAction::RemoveUser("bob".to_string()))).await));
+          assert!(identity(&broker, client).is_none());
+          assert!(!succeeded(&broker.handle(admin, administer(MTPManagerAction::RemoveUser("bob".to_string()))).await));
+     }
+}
@@ -0,0 +1,1941 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::File;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use net::protocol::error::{Error, ProtocolError};
+use net::protocol::interface::{Distribution, Overflow, QueueAccess, QueueRoles};
+
+use super::dedup::DedupWindow;
+use super::filter::Filter;
+use super::message::StoredMessage;
+use super::session::{self, SessionId};
+
+use crate::storage;
+use crate::storage::codec::{Decode, Decoder, Encode, Encoder};
+use crate::storage::error::StorageError;
+use crate::storage::journal::Journal;
+use crate::storage::log::{Log, LogConfig};
+
+/// Name of the journal holding the name and configuration of a durable queue
+const META: &str = "queue.meta";
+
+/// Name of the journal holding the positions and deliveries of the consumers of a durable queue
+const CONSUMERS: &str = "consumers.journal";
+
+/// Configuration of a queue hosted by the broker.
+///
+/// # Fields
+///
+/// ~ `access`: Who may join the queue
+/// ~ `roles`: Roles assigned to clients on the queue, by client. A client holding a role is
+///   admitted to the queue while it is private.
+/// ~ `admitted`: Clients admitted to the queue while it is private
+/// ~ `bans`: Time until which clients are refused the queue, by client
+/// ~ `ttl`: Default time-to-live of the messages published to the queue
+/// ~ `dead_letter`: Queue to which expired, overflowing and given up messages are moved instead
+///   of being dropped
+/// ~ `max_length`: Number of messages past which the queue overflows
+/// ~ `max_bytes`: Total size of the content of the messages past which the queue overflows
+/// ~ `overflow`: What happens to a message published to a full queue
+/// ~ `max_deliveries`: Number of deliveries of a message to a consumer after which it is given up on
+/// ~ `durable`: Whether the messages of the queue are stored on disk
+/// ~ `retention`: How long the segments of a durable queue are kept after their last message
+/// ~ `retention_bytes`: Size past which the oldest segments of a durable queue are deleted
+/// ~ `compacted`: Whether only the latest message of each key is kept
+/// ~ `dedup_window`: How long the id of a published message is remembered to recognise retries
+/// ~ `dedup_count`: Number of the latest published message ids remembered to recognise retries
+pub struct QueueConfig {
+     access: QueueAccess,
+     roles: BTreeMap<String, QueueRoles>,
+     admitted: BTreeSet<String>,
+     bans: BTreeMap<String, SystemTime>,
+     ttl: Option<Duration>,
+     dead_letter: Option<String>,
+     max_length: Option<usize>,
+     max_bytes: Option<u64>,
+     overflow: Overflow,
+     max_deliveries: Option<u32>,
+     durable: bool,
+     retention: Option<Duration>,
+     retention_bytes: Option<u64>,
+     compacted: bool,
+     dedup_window: Option<Duration>,
+     dedup_count: Option<usize>,
+}
+
+impl QueueConfig {
+     /// Sets who may join the queue
+     pub fn with_access(mut self, access: QueueAccess) -> Self {
+          self.access = access;
+          self
+     }
+
+     /// Assigns `role` on the queue to `client`, replacing the role it held
+     pub fn with_role(mut self, client: String, role: QueueRoles) -> Self {
+          self.roles.insert(client, role);
+          self
+     }
+
+     /// Withdraws the role of `client` on the queue
+     pub fn without_role(mut self, client: &str) -> Self {
+          self.roles.remove(client);
+          self
+     }
+
+     /// Admits `client` to the queue while it is private
+     pub fn with_admitted(mut self, client: String) -> Self {
+          self.admitted.insert(client);
+          self
+     }
+
+     /// Refuses the queue to `client` until `until`
+     pub fn with_ban(mut self, client: String, until: SystemTime) -> Self {
+          self.bans.insert(client, until);
+          self
+     }
+
+     /// Lifts the bans that ended at `now`
+     pub fn without_bans_ended(mut self, now: SystemTime) -> Self {
+          self.bans.retain(|_, until| *until > now);
+          self
+     }
+
+     /// Sets the default time-to-live of the messages of the queue.
+     /// A `TimeToLive` header unit on a published message takes precedence.
+     pub fn with_ttl(mut self, ttl: Duration) -> Self {
+          self.ttl = Some(ttl);
+          self
+     }
+
+     /// Sets the queue to which expired, overflowing and given up messages are dead-lettered.
+     /// Dead letters are dropped while the queue does not exist.
+     pub fn with_dead_letter(mut self, queue: String) -> Self {
+          self.dead_letter = Some(queue);
+          self
+     }
+
+     /// Sets the number of messages the queue holds before it overflows
+     pub fn with_max_length(mut self, length: usize) -> Self {
+          self.max_length = Some(length);
+          self
+     }
+
+     /// Sets the total size of the content of the messages the queue holds before it overflows
+     pub fn with_max_bytes(mut self, bytes: u64) -> Self {
+          self.max_bytes = Some(bytes);
+          self
+     }
+
+     /// Sets what happens to a message published to a full queue
+     pub fn with_overflow(mut self, overflow: Overflow) -> Self {
+          self.overflow = overflow;
+          self
+     }
+
+     /// Sets the number of times a message is delivered to a consumer without being
+     /// acknowledged before it is given up on, dead-lettering it if the queue has a dead-letter
+     /// queue configured
+     pub fn with_max_deliveries(mut self, deliveries: u32) -> Self {
+          self.max_deliveries = Some(deliveries);
+          self
+     }
+
+     /// Sets whether the messages of the queue are stored on disk.
+     /// The durability of a queue is fixed when it is created.
+     pub fn with_durable(mut self, durable: bool) -> Self {
+          self.durable = durable;
+          self
+     }
+
+     /// Sets how long the segments of a durable queue are kept once their last message was
+     /// published. Whole segments are deleted, along with the messages they hold whether or not
+     /// they were consumed.
+     pub fn with_retention(mut self, retention: Duration) -> Self {
+          self.retention = Some(retention);
+          self
+     }
+
+     /// Sets the size past which the oldest segments of a durable queue are deleted, along with
+     /// the messages they hold whether or not they were consumed
+     pub fn with_retention_bytes(mut self, bytes: u64) -> Self {
+          self.retention_bytes = Some(bytes);
+          self
+     }
+
+     /// Sets whether only the latest message of each key is kept.
+     /// Messages are keyed by their `Key` header unit, or by their id when they carry none.
+     pub fn with_compacted(mut self, compacted: bool) -> Self {
+          self.compacted = compacted;
+          self
+     }
+
+     /// Sets how long the id of a published message is remembered. A message published again
+     /// with the same id within the window is answered as a duplicate and not enqueued.
+     pub fn with_dedup_window(mut self, window: Duration) -> Self {
+          self.dedup_window = Some(window);
+          self
+     }
+
+     /// Sets how many of the latest published message ids are remembered. A message published
+     /// again with one of those ids is answered as a duplicate and not enqueued.
+     pub fn with_dedup_count(mut self, count: usize) -> Self {
+          self.dedup_count = Some(count);
+          self
+     }
+
+     /// Retrieves who may join the queue
+     pub fn access(&self) -> &QueueAccess {
+          &self.access
+     }
+
+     /// Retrieves the role of `client` on the queue
+     pub fn role(&self, client: &str) -> Option<&QueueRoles> {
+          self.roles.get(client)
+     }
+
+     /// Retrieves the roles assigned to clients on the queue, by client
+     pub fn roles(&self) -> &BTreeMap<String, QueueRoles> {
+          &self.roles
+     }
+
+     /// Retrieves the clients admitted to the queue while it is private
+     pub fn admitted(&self) -> &BTreeSet<String> {
+          &self.admitted
+     }
+
+     /// Checks whether `client` holds a role on or is admitted to the queue
+     pub fn is_admitted(&self, client: &str) -> bool {
+          self.roles.contains_key(client) || self.admitted.contains(client)
+     }
+
+     /// Retrieves the time until which `client` is refused the queue, if it is banned at `now`
+     pub fn banned_until(&self, client: &str, now: SystemTime) -> Option<SystemTime> {
+          self.bans.get(client).copied().filter(|until| *until > now)
+     }
+
+     /// Checks whether a ban on the queue ended at `now`
+     pub fn has_ended_bans(&self, now: SystemTime) -> bool {
+          self.bans.values().any(|until| *until <= now)
+     }
+
+     /// Retrieves the default time-to-live of the messages of the queue
+     pub fn ttl(&self) -> Option<Duration> {
+          self.ttl
+     }
+
+     /// Retrieves the queue to which expired, overflowing and given up messages are dead-lettered
+     pub fn dead_letter(&self) -> Option<&str> {
+          self.dead_letter.as_deref()
+     }
+
+     /// Retrieves the number of messages the queue holds before it overflows
+     pub fn max_length(&self) -> Option<usize> {
+          self.max_length
+     }
+
+     /// Retrieves the total size of the content of the messages the queue holds before it overflows
+     pub fn max_bytes(&self) -> Option<u64> {
+          self.max_bytes
+     }
+
+     /// Retrieves what happens to a message published to a full queue
+     pub fn overflow(&self) -> &Overflow {
+          &self.overflow
+     }
+
+     /// Retrieves the number of times a message is delivered to a consumer before it is given up on
+     pub fn max_deliveries(&self) -> Option<u32> {
+          self.max_deliveries
+     }
+
+     /// Checks whether the messages of the queue are stored on disk
+     pub fn is_durable(&self) -> bool {
+          self.durable
+     }
+
+     /// Retrieves how long the segments of a durable queue are kept
+     pub fn retention(&self) -> Option<Duration> {
+          self.retention
+     }
+
+     /// Retrieves the size past which the oldest segments of a durable queue are deleted
+     pub fn retention_bytes(&self) -> Option<u64> {
+          self.retention_bytes
+     }
+
+     /// Checks whether only the latest message of each key is kept
+     pub fn is_compacted(&self) -> bool {
+          self.compacted
+     }
+
+     /// Retrieves how long the id of a published message is remembered
+     pub fn dedup_window(&self) -> Option<Duration> {
+          self.dedup_window
+     }
+
+     /// Retrieves how many of the latest published message ids are remembered
+     pub fn dedup_count(&self) -> Option<usize> {
+          self.dedup_count
+     }
+
+     /// Checks whether published messages are deduplicated by id
+     pub fn is_deduplicated(&self) -> bool {
+          self.dedup_window.is_some() || self.dedup_count.is_some()
+     }
+}
+
+/// Default implementation for [QueueConfig]
+/// The queue is public, without roles or bans and unbounded, rejecting messages if it is later bounded. Messages never
+/// expire, are delivered until acknowledged, are dropped rather than dead-lettered, are only kept
+/// in memory, are never compacted and are not deduplicated
+impl Default for QueueConfig {
+     fn default() -> Self {
+          Self {
+               access: QueueAccess::Public,
+               roles: BTreeMap::new(),
+               admitted: BTreeSet::new(),
+               bans: BTreeMap::new(),
+               ttl: None,
+               dead_letter: None,
+               max_length: None,
+               max_bytes: None,
+               overflow: Overflow::Reject,
+               max_deliveries: None,
+               durable: false,
+               retention: None,
+               retention_bytes: None,
+               compacted: false,
+               dedup_window: None,
+               dedup_count: None,
+          }
+     }
+}
+
+/// Clone implementation for [QueueConfig]
+impl Clone for QueueConfig {
+     fn clone(&self) -> Self {
+          Self {
+               access: self.access.clone(),
+               roles: self.roles.clone(),
+               admitted: self.admitted.clone(),
+               bans: self.bans.clone(),
+               ttl: self.ttl,
+               dead_letter: self.dead_letter.clone(),
+               max_length: self.max_length,
+               max_bytes: self.max_bytes,
+               overflow: self.overflow.clone(),
+               max_deliveries: self.max_deliveries,
+               durable: self.durable,
+               retention: self.retention,
+               retention_bytes: self.retention_bytes,
+               compacted: self.compacted,
+               dedup_window: self.dedup_window,
+               dedup_count: self.dedup_count,
+          }
+     }
+}
+
+/// PartialEq implementation for [QueueConfig]
+/// Two configurations are equal when every setting matches, telling whether provisioning an
+/// existing queue again conflicts with it. The roles, the admitted clients and the bans are not
+/// compared, since they change as the queue is used.
+impl PartialEq for QueueConfig {
+     fn eq(&self, other: &Self) -> bool {
+          let settings = |config: &Self| {
+               let mut encoder = Encoder::new();
+               encoder.put(&Self { roles: BTreeMap::new(), admitted: BTreeSet::new(), bans: BTreeMap::new(), ..config.clone() });
+               encoder.into_bytes()
+          };
+          settings(self) == settings(other)
+     }
+}
+
+/// Encode implementation for [QueueConfig]
+impl Encode for QueueConfig {
+     fn encode(&self, encoder: &mut Encoder) {
+          encoder.put(&self.access);
+          encoder.put_u32(self.roles.len() as u32);
+          for (client, role) in self.roles.iter() {
+               encoder.put_str(client);
+               encoder.put(role);
+          }
+          encoder.put_u32(self.admitted.len() as u32);
+          for client in self.admitted.iter() {
+               encoder.put_str(client);
+          }
+          encoder.put_u32(self.bans.len() as u32);
+          for (client, until) in self.bans.iter() {
+               encoder.put_str(client);
+               encoder.put_time(*until);
+          }
+          encoder.put_option(self.ttl, Encoder::put_duration);
+          encoder.put_option(self.dead_letter.as_deref(), Encoder::put_str);
+          encoder.put_option(self.max_length.map(|length| length as u64), Encoder::put_u64);
+          encoder.put_option(self.max_bytes, Encoder::put_u64);
+          encoder.put(&self.overflow);
+          encoder.put_option(self.max_deliveries, Encoder::put_u32);
+          encoder.put_u8(self.durable as u8);
+          encoder.put_option(self.retention, Encoder::put_duration);
+          encoder.put_option(self.retention_bytes, Encoder::put_u64);
+          encoder.put_u8(self.compacted as u8);
+          encoder.put_option(self.dedup_window, Encoder::put_duration);
+          encoder.put_option(self.dedup_count.map(|count| count as u64), Encoder::put_u64);
+     }
+}
+
+/// Decode implementation for [QueueConfig]
+impl Decode for QueueConfig {
+     fn decode(decoder: &mut Decoder) -> Result<Self, StorageError> {
+          let access = decoder.get()?;
+          let mut roles = BTreeMap::new();
+          for _ in 0..decoder.get_u32()? {
+               roles.insert(decoder.get_str()?, decoder.get()?);
+          }
+          let mut admitted = BTreeSet::new();
+          for _ in 0..decoder.get_u32()? {
+               admitted.insert(decoder.get_str()?);
+          }
+          let mut bans = BTreeMap::new();
+          for _ in 0..decoder.get_u32()? {
+               bans.insert(decoder.get_str()?, decoder.get_time()?);
+          }
+
+          Ok(Self {
+               access,
+               roles,
+               admitted,
+               bans,
+               ttl: decoder.get_option(Decoder::get_duration)?,
+               dead_letter: decoder.get_option(Decoder::get_str)?,
+               max_length: decoder.get_option(Decoder::get_u64)?.map(|length| length as usize),
+               max_bytes: decoder.get_option(Decoder::get_u64)?,
+               overflow: decoder.get()?,
+               max_deliveries: decoder.get_option(Decoder::get_u32)?,
+               durable: decoder.get_u8()? != 0,
+               retention: decoder.get_option(Decoder::get_duration)?,
+               retention_bytes: decoder.get_option(Decoder::get_u64)?,
+               compacted: decoder.get_u8()? != 0,
+               dedup_window: decoder.get_option(Decoder::get_duration)?,
+               dedup_count: decoder.get_option(Decoder::get_u64)?.map(|count| count as usize),
+          })
+     }
+}
+
+/// Storage of a durable queue.
+///
+/// # Fields
+///
+/// ~ `log`: Log the messages of the queue are appended to
+/// ~ `meta`: Journal holding the name and configuration of the queue
+/// ~ `consumers`: Journal recording the changes to the consumers of the queue
+struct Durable {
+     log: Log,
+     meta: Journal,
+     consumers: Journal,
+}
+
+/// Change to the consumers of a durable queue, recorded in its consumer journal so that
+/// positions and unacknowledged deliveries are restored after a restart.
+enum ConsumerEvent {
+     /// A consumer was registered, starting at `position`, or the filter of a registered one
+     /// was replaced
+     Subscribe { consumer: String, group: Option<Distribution>, position: u64, filter: Option<String> },
+     /// The message at `offset` was delivered to a consumer
+     Deliver { consumer: String, offset: u64 },
+     /// A consumer acknowledged the message at `offset`
+     Acknowledge { consumer: String, offset: u64 },
+     /// A consumer was removed
+     Unsubscribe { consumer: String },
+     /// Every message before `offset` was dropped
+     Trim { offset: u64 },
+     /// The message at `offset` expired
+     Expire { offset: u64 },
+     /// A consumer moved past the message at `offset` without delivering it, holding it back
+     /// until the member its partition key belongs to takes it
+     Defer { consumer: String, offset: u64 },
+     /// A consumer moved past the message at `offset` without delivering it, as it does not
+     /// pass the filter of the consumer
+     Skip { consumer: String, offset: u64 },
+}
+
+/// Encode implementation for [ConsumerEvent]
+impl Encode for ConsumerEvent {
+     fn encode(&self, encoder: &mut Encoder) {
+          match self {
+               ConsumerEvent::Subscribe { consumer, group, position, filter } => {
+                    encoder.put_u8(0);
+                    encoder.put_str(consumer);
+                    encoder.put_option(group.as_ref(), Encoder::put);
+                    encoder.put_u64(*position);
+                    encoder.put_option(filter.as_deref(), Encoder::put_str);
+               },
+               ConsumerEvent::Deliver { consumer, offset } => {
+                    encoder.put_u8(1);
+                    encoder.put_str(consumer);
+                    encoder.put_u64(*offset);
+               },
+               ConsumerEvent::Acknowledge { consumer, offset } => {
+                    encoder.put_u8(2);
+                    encoder.put_str(consumer);
+                    encoder.put_u64(*offset);
+               },
+               ConsumerEvent::Unsubscribe { consumer } => {
+                    encoder.put_u8(3);
+                    encoder.put_str(consumer);
+               },
+               ConsumerEvent::Trim { offset } => {
+                    encoder.put_u8(4);
+                    encoder.put_u64(*offset);
+               },
+               ConsumerEvent::Expire { offset } => {
+                    encoder.put_u8(5);
+                    encoder.put_u64(*offset);
+               },
+               ConsumerEvent::Defer { consumer, offset } => {
+                    encoder.put_u8(6);
+                    encoder.put_str(consumer);
+                    encoder.put_u64(*offset);
+               },
+               ConsumerEvent::Skip { consumer, offset } => {
+                    encoder.put_u8(7);
+                    encoder.put_str(consumer);
+                    encoder.put_u64(*offset);
+               },
+          }
+     }
+}
+
+/// Decode implementation for [ConsumerEvent]
+impl Decode for ConsumerEvent {
+     fn decode(decoder: &mut Decoder) -> Result<Self, StorageError> {
+          Ok(match decoder.get_u8()? {
+               0 => ConsumerEvent::Subscribe { consumer: decoder.get_str()?, group: decoder.get_option(Decoder::get)?, position: decoder.get_u64()?, filter: decoder.get_option(Decoder::get_str)? },
+               1 => ConsumerEvent::Deliver { consumer: decoder.get_str()?, offset: decoder.get_u64()? },
+               2 => ConsumerEvent::Acknowledge { consumer: decoder.get_str()?, offset: decoder.get_u64()? },
+               3 => ConsumerEvent::Unsubscribe { consumer: decoder.get_str()? },
+               4 => ConsumerEvent::Trim { offset: decoder.get_u64()? },
+               5 => ConsumerEvent::Expire { offset: decoder.get_u64()? },
+               6 => ConsumerEvent::Defer { consumer: decoder.get_str()?, offset: decoder.get_u64()? },
+               7 => ConsumerEvent::Skip { consumer: decoder.get_str()?, offset: decoder.get_u64()? },
+               tag => return Err(StorageError::Corrupted { message: format!("Unknown consumer event {}", tag) }),
+          })
+     }
+}
+
+/// A client consuming a queue through a [`Consumer`].
+///
+/// # Fields
+///
+/// ~ `session`: The session of the client
+/// ~ `push`: Whether messages are pushed to the session or only handed out when pulled
+pub struct Member {
+     session: SessionId,
+     push: bool,
+}
+
+impl Member {
+     /// Retrieves the session of the client
+     pub fn session(&self) -> SessionId {
+          self.session
+     }
+
+     /// Checks whether messages are pushed to the client
+     pub fn is_push(&self) -> bool {
+          self.push
+     }
+}
+
+/// Position of a consumer within a queue.
+///
+/// Every consumer reads the queue independently of the others, so a message published to the
+/// queue is delivered to each of its consumers. A consumer is either a single client, or a
+/// consumer group whose members share its position: each message reaching a group is delivered
+/// to exactly one of its members, picked according to the [`Distribution`] of the group.
+///
+/// Messages are rebalanced as members come and go. The messages a leaving member had not
+/// acknowledged are handed to the remaining members, and a joining member takes its share of
+/// the messages that follow.
+///
+/// Within a group, the messages published with a partition key are delivered in order to the
+/// member the key belongs to: the member holding an unacknowledged message of the key, otherwise
+/// the member the key hashes to. A message whose key belongs to another member is held back and
+/// handed out to that member ahead of the messages past the position of the consumer.
+///
+/// # Fields
+///
+/// ~ `group`: Distribution of messages among the members of a group, `None` for a single client
+/// ~ `members`: Clients consuming through the consumer, by client. A consumer restored from disk
+///   has no members until its clients subscribe again.
+/// ~ `turn`: Index of the member the next message goes to under round-robin distribution
+/// ~ `position`: Offset of the next message to deliver
+/// ~ `unacked`: Offsets delivered but not yet acknowledged, with the member they were delivered to
+///   and the time of delivery
+/// ~ `redeliver`: Offsets handed out before the messages past `position`: those delivered but
+///   never acknowledged, and those held back for the member their partition key belongs to
+/// ~ `deliveries`: Number of times the offsets not yet acknowledged were delivered
+/// ~ `filter`: Filter the messages must pass to be delivered through the consumer, if any. It is
+///   shared by the members of a group and restored along with the consumer.
+pub struct Consumer {
+     group: Option<Distribution>,
+     members: BTreeMap<String, Member>,
+     turn: usize,
+     position: u64,
+     unacked: BTreeMap<u64, (String, SystemTime)>,
+     redeliver: BTreeSet<u64>,
+     deliveries: HashMap<u64, u32>,
+     filter: Option<Filter>,
+}
+
+impl Consumer {
+     /// Creates a consumer without members starting at `position`
+     fn new(group: Option<Distribution>, position: u64) -> Self {
+          Self {
+               group,
+               members: BTreeMap::new(),
+               turn: 0,
+               position,
+               unacked: BTreeMap::new(),
+               redeliver: BTreeSet::new(),
+               deliveries: HashMap::new(),
+               filter: None,
+          }
+     }
+
+     /// Retrieves the distribution of messages among the members of a group, `None` for a
+     /// single client
+     pub fn group(&self) -> Option<&Distribution> {
+          self.group.as_ref()
+     }
+
+     /// Retrieves the filter the messages must pass to be delivered through the consumer
+     pub fn filter(&self) -> Option<&Filter> {
+          self.filter.as_ref()
+     }
+
+     /// Retrieves the clients consuming through the consumer, by client
+     pub fn members(&self) -> &BTreeMap<String, Member> {
+          &self.members
+     }
+
+     /// Number of messages delivered through the consumer and not yet acknowledged
+     pub fn in_flight(&self) -> usize {
+          self.unacked.len()
+     }
+
+     /// Number of messages delivered to `client` and not yet acknowledged
+     pub fn in_flight_of(&self, client: &str) -> usize {
+          self.unacked.values().filter(|(member, _)| member == client).count()
+     }
+
+     /// Picks the member the next message is pushed to, among the members messages are pushed
+     /// to that have fewer than `prefetch` messages in flight, leaving out the clients of `skip`
+     pub fn assign(&self, prefetch: usize, skip: &HashSet<String>) -> Option<(&str, &Member)> {
+          let eligible = |(client, member): &(&String, &Member)| {
+               member.push && !skip.contains(client.as_str()) && self.in_flight_of(client) < prefetch
+          };
+
+          let picked = match self.group {
+               Some(Distribution::LeastLoaded) => self.members.iter()
+                    .filter(eligible)
+                    .min_by_key(|(client, _)| self.in_flight_of(client)),
+               _ => {
+                    let count = self.members.len().max(1);
+                    self.members.iter()
+                         .cycle()
+                         .skip(self.turn % count)
+                         .take(self.members.len())
+                         .find(eligible)
+               },
+          };
+
+          picked.map(|(client, member)| (client.as_str(), member))
+     }
+
+     /// Removes `client` from the members, handing the messages it had not acknowledged to the
+     /// remaining members
+     fn leave(&mut self, client: &str) {
+          self.members.remove(client);
+          self.requeue(client);
+     }
+
+     /// Hands the messages `client` had not acknowledged out again, before any other
+     fn requeue(&mut self, client: &str) {
+          let released: Vec<u64> = self.unacked.iter()
+               .filter(|(_, (member, _))| member == client)
+               .map(|(offset, _)| *offset)
+               .collect();
+          for offset in released {
+               self.unacked.remove(&offset);
+               self.redeliver.insert(offset);
+          }
+     }
+
+     /// Lowest offset the consumer still needs
+     fn low_watermark(&self) -> u64 {
+          let unacked = self.unacked.keys().next().copied().unwrap_or(self.position);
+          let redeliver = self.redeliver.iter().next().copied().unwrap_or(self.position);
+          self.position.min(unacked).min(redeliver)
+     }
+}
+
+/// A queue of the broker.
+///
+/// Messages are kept in offset order until every consumer of the queue has acknowledged them
+/// or they expire. Expired messages are either dropped or, when the queue has a dead-letter
+/// queue configured, set aside until the broker moves them there. So are the oldest messages
+/// of a full queue when it makes room for new ones, and messages delivered to a consumer more
+/// times than the queue allows.
+///
+/// On a compacted queue a message is dropped as soon as a later message of the same key is
+/// published, unless it awaits acknowledgement.
+///
+/// # Fields
+///
+/// ~ `name`: Identifier of the queue
+/// ~ `config`: Configuration of the queue
+/// ~ `messages`: Messages held by the queue, by offset
+/// ~ `keys`: Offsets of the latest message held for each key, on a compacted queue
+/// ~ `next_offset`: Offset assigned to the next published message
+/// ~ `bytes`: Total size of the content of the messages held
+/// ~ `consumers`: Consumers of the queue, by client for a single client and by group name for a
+///   consumer group
+/// ~ `memberships`: Name of the consumer each client consumes through, by client
+/// ~ `expired`: Offsets of the messages that expired recently with the time they expired at
+/// ~ `dead_letters`: Expired messages waiting to be moved to the dead-letter queue
+/// ~ `published`: Ids recently published to the queue, when it deduplicates messages
+/// ~ `owner`: Session the queue is exclusive to, for a temporary queue
+/// ~ `durable`: Storage of the queue, if it is durable
+pub struct Queue {
+     name: String,
+     config: QueueConfig,
+     messages: BTreeMap<u64, StoredMessage>,
+     keys: HashMap<String, u64>,
+     next_offset: u64,
+     bytes: u64,
+     consumers: HashMap<String, Consumer>,
+     memberships: HashMap<String, String>,
+     expired: HashMap<u64, SystemTime>,
+     dead_letters: Vec<StoredMessage>,
+     published: DedupWindow,
+     owner: Option<SessionId>,
+     durable: Option<Durable>,
+}
+
+impl Queue {
+     /// Creates an empty queue
+     ///
+     /// # Arguments
+     /// * `name`: Identifier of the queue
+     /// * `config`: Configuration of the queue
+     pub fn new(name: String, config: QueueConfig) -> Self {
+          Self {
+               name,
+               config,
+               messages: BTreeMap::new(),
+               keys: HashMap::new(),
+               next_offset: 0,
+               bytes: 0,
+               consumers: HashMap::new(),
+               memberships: HashMap::new(),
+               expired: HashMap::new(),
+               dead_letters: Vec::new(),
+               published: DedupWindow::in_memory(),
+               owner: None,
+               durable: None,
+          }
+     }
+
+     /// Creates an empty temporary queue exclusive to `owner`, with the default configuration
+     pub fn temporary(name: String, owner: SessionId) -> Self {
+          let mut queue = Self::new(name, QueueConfig::default());
+          queue.owner = Some(owner);
+          queue
+     }
+
+     /// Opens the durable queue stored in `dir`, creating it if it does not exist.
+     /// The messages held by its log and the consumers recorded in its consumer journal are
+     /// restored, see [`Queue::restore`].
+     ///
+     /// # Arguments
+     /// * `name`: Identifier of the queue
+     /// * `config`: Configuration of the queue
+     /// * `dir`: Directory the queue is stored in
+     /// * `log`: Configuration of the log the messages are appended to
+     pub fn open(name: String, config: QueueConfig, dir: &Path, log: LogConfig) -> Result<Self, StorageError> {
+          storage::create_dir(dir)?;
+          let (meta, _) = Journal::open(&dir.join(META))?;
+          Self::load(name, config, dir, log, meta)
+     }
+
+     /// Restores the durable queue stored in `dir` with the name and configuration it was
+     /// last stored with.
+     ///
+     /// Records torn by an unclean shutdown are discarded from the log and the journals of the
+     /// queue. Every consumer recorded is restored with its position, detached from any session
+     /// until its client subscribes again. The messages it had not acknowledged are delivered
+     /// to it again before any other.
+     pub fn restore(dir: &Path, log: LogConfig) -> Result<Self, StorageError> {
+          let (meta, records) = Journal::open(&dir.join(META))?;
+          let record = records.last()
+               .ok_or_else(|| StorageError::Corrupted { message: format!("Queue {} has no configuration", dir.display()) })?;
+
+          let mut decoder = Decoder::new(record);
+          let name = decoder.get_str()?;
+          let config = decoder.get()?;
+
+          Self::load(name, config, dir, log, meta)
+     }
+
+     /// Retrieves the identifier of the queue
+     pub fn name(&self) -> &str {
+          &self.name
+     }
+
+     /// Retrieves the session a temporary queue is exclusive to
+     pub fn owner(&self) -> Option<SessionId> {
+          self.owner
+     }
+
+     /// Retrieves the configuration of the queue
+     pub fn config(&self) -> &QueueConfig {
+          &self.config
+     }
+
+     /// Renames the queue to `name`, moving a durable queue to the directory of that name next
+     /// to its current one. The new name is stored before the directory is moved, so that a queue
+     /// restored from a directory named after another queue is known to have been interrupted
+     /// while moving, and is renamed again to complete the move.
+     pub fn rename(&mut self, name: String, log: LogConfig) -> Result<(), StorageError> {
+          self.sync()?;
+
+          if let Some(durable) = self.durable.as_mut() {
+               durable.meta.rewrite(&[meta_record(&name, &self.config)])?;
+
+               let from = durable.log.dir().to_path_buf();
+               let dir = from.with_file_name(directory(&name));
+               if from != dir {
+                    std::fs::rename(&from, &dir)?;
+                    storage::sync_parent(&dir)?;
+                    let (meta, _) = Journal::open(&dir.join(META))?;
+                    let (consumers, _) = Journal::open(&dir.join(CONSUMERS))?;
+                    self.durable = Some(Durable { log: Log::open(&dir, log)?, meta, consumers });
+                    self.published = DedupWindow::open(&dir)?;
+               }
+          }
+          self.name = name;
+
+          Ok(())
+     }
+
+     /// Deletes the queue along with the messages and consumers stored on disk if it is durable
+     pub fn delete(self) -> Result<(), StorageError> {
+          let dir = self.durable.as_ref().map(|durable| durable.log.dir().to_path_buf());
+          drop(self);
+          match dir {
+               Some(dir) => Ok(std::fs::remove_dir_all(dir)?),
+               None => Ok(()),
+          }
+     }
+
+     /// Replaces the configuration of the queue, storing it if the queue is durable
+     pub fn set_config(&mut self, config: QueueConfig) -> Result<(), StorageError> {
+          if let Some(durable) = self.durable.as_mut() {
+               durable.meta.rewrite(&[meta_record(&self.name, &config)])?;
+          }
+          self.config = config;
+          Ok(())
+     }
+
+     /// Number of messages held by the queue
+     pub fn len(&self) -> usize {
+          self.messages.len()
+     }
+
+     /// Checks whether the queue holds no messages
+     pub fn is_empty(&self) -> bool {
+          self.messages.is_empty()
+     }
+
+     /// Retrieves the consumers of the queue, by client for a single client and by group name for
+     /// a consumer group
+     pub fn consumers(&self) -> &HashMap<String, Consumer> {
+          &self.consumers
+     }
+
+     /// Retrieves the name of the consumer `client` consumes the queue through
+     pub fn consumer_of(&self, client: &str) -> Option<&str> {
+          self.memberships.get(client).map(String::as_str)
+     }
+
+     /// Retrieves the offset the message `id` was published at, if it was published within the
+     /// deduplication window of the queue at `now`
+     pub fn duplicate_of(&mut self, id: &str, now: SystemTime) -> Option<u64> {
+          if !self.config.is_deduplicated() {
+               return None;
+          }
+
+          // retried on the next publish if the journal cannot be rewritten
+          let _ = self.published.prune(now, self.config.dedup_window, self.config.dedup_count);
+          self.published.get(id)
+     }
+
+     /// Appends a message to the queue. The message is written to the log of a durable queue
+     /// before it is made available to consumers. Its id enters the deduplication window of
+     /// the queue, if it has one.
+     ///
+     /// # Returns
+     /// The offset assigned to the message
+     ///
+     /// # Errors
+     /// [`ProtocolError::InsufficientStorage126`] if the queue is full and rejects overflowing
+     /// messages, or the message could not be stored
+     pub fn enqueue(&mut self, mut message: StoredMessage) -> Result<u64, ProtocolError> {
+          self.make_room(message.size() as u64)?;
+          let offset = self.next_offset;
+
+          if let Some(durable) = self.durable.as_mut() {
+               let mut encoder = Encoder::new();
+               encoder.put(&message);
+               durable.log.append(offset, message.enqueued_at(), &encoder.into_bytes())?;
+          }
+          self.next_offset += 1;
+
+          if self.config.is_deduplicated() {
+               // an id that cannot be recorded is forgotten on restart, letting a retry through
+               let _ = self.published.insert(message.id().to_string(), message.enqueued_at(), offset);
+               let _ = self.published.prune(message.enqueued_at(), self.config.dedup_window, self.config.dedup_count);
+          }
+
+          message.set_offset(offset);
+          self.hold(message);
+
+          Ok(offset)
+     }
+
+     /// Registers `client` as a consumer of the queue, on its own or as a member of a consumer
+     /// group. A new consumer starts from the oldest message held, while an existing one,
+     /// including one restored from disk, keeps its position. A client consumes a queue through a
+     /// single consumer: subscribing through another one leaves the previous one.
+     ///
+     /// # Arguments
+     /// * `client`: Identifier of the consuming client
+     /// * `session`: Session of the consuming client
+     /// * `push`: Whether messages are pushed to the session as they arrive
+     /// * `group`: Name and distribution of the consumer group to join, if any
+     /// * `filter`: Filter replacing the one of the consumer, if any. The members of a group share
+     ///   its filter, so a member only replaces it while no other member consumes through the group.
+     ///
+     /// # Errors
+     /// - [`ProtocolError::Conflict108`] if the consumer exists as a single client while joining a
+     ///   group, or the other way around, or the group exists with another distribution, or other
+     ///   members consume through the group with another filter
+     pub fn subscribe(&mut self, client: String, session: SessionId, push: bool, group: Option<(String, Distribution)>, filter: Option<Filter>) -> Result<(), ProtocolError> {
+          let (name, distribution) = match group {
+               Some((name, distribution)) => (name, Some(distribution)),
+               None => (client.clone(), None),
+          };
+
+          if let Some(consumer) = self.consumers.get(&name) {
+               let matching = match (&consumer.group, &distribution) {
+                    (Some(current), Some(requested)) => std::mem::discriminant(current) == std::mem::discriminant(requested),
+                    (None, None) => true,
+                    _ => false,
+               };
+               if !matching {
+                    return Err(ProtocolError::Conflict108(Error::new(format!("Consumer {} of queue {} exists with another distribution", name, self.name))));
+               }
+
+               let shared = consumer.group.is_some() && consumer.members.keys().any(|member| *member != client);
+               let conflicting = filter.as_ref()
+                    .is_some_and(|filter| consumer.filter.as_ref().map(Filter::expression) != Some(filter.expression()));
+               if shared && conflicting {
+                    return Err(ProtocolError::Conflict108(Error::new(format!("Consumer group {} of queue {} consumes with another filter", name, self.name))));
+               }
+          }
+
+          if self.memberships.get(&client).is_some_and(|current| *current != name) {
+               self.leave(&client);
+          }
+
+          let replaced = match (self.consumers.get(&name), &filter) {
+               (None, _) => true,
+               (Some(consumer), Some(filter)) => consumer.filter.as_ref().map(Filter::expression) != Some(filter.expression()),
+               (Some(_), None) => false,
+          };
+          if replaced {
+               let consumer = self.consumers.entry(name.clone()).or_insert_with(|| {
+                    let start = self.messages.keys().next().copied().unwrap_or(self.next_offset);
+                    Consumer::new(distribution, start)
+               });
+               if filter.is_some() {
+                    consumer.filter = filter;
+               }
+               let event = ConsumerEvent::Subscribe {
+                    consumer: name.clone(),
+                    group: consumer.group.clone(),
+                    position: consumer.position,
+                    filter: consumer.filter.as_ref().map(|filter| filter.expression().to_string()),
+               };
+               self.record(event);
+          }
+
+          if let Some(consumer) = self.consumers.get_mut(&name) {
+               let member = consumer.members.entry(client.clone()).or_insert(Member { session, push });
+               member.session = session;
+               member.push |= push;
+          }
+          self.memberships.insert(client, name);
+          self.trim();
+
+          Ok(())
+     }
+
+     /// Removes `client` from the consumer it consumes through. The consumer of a single client
+     /// is removed along with it, releasing its unacknowledged messages, while a consumer group
+     /// hands the messages of the client to its remaining members.
+     ///
+     /// # Returns
+     /// `false` if `client` was not consuming from the queue
+     pub fn unsubscribe(&mut self, client: &str) -> bool {
+          let removed = self.leave(client);
+          self.trim();
+          removed
+     }
+
+     /// Removes every client belonging to `session` from the consumers of the queue
+     pub fn release(&mut self, session: SessionId) {
+          let clients: Vec<String> = self.memberships.iter()
+               .filter(|(client, name)| {
+                    self.consumers.get(*name)
+                         .and_then(|consumer| consumer.members.get(*client))
+                         .is_some_and(|member| member.session == session)
+               })
+               .map(|(client, _)| client.clone())
+               .collect();
+
+          for client in clients {
+               self.leave(&client);
+          }
+          self.trim();
+     }
+
+     /// Hands the clients belonging to the session `from` over to the session `to`, which
+     /// resumed it, along with the queue if it is exclusive to `from`. The messages the clients
+     /// had not acknowledged are delivered to them again, as those pushed to the session after
+     /// its connection was lost never reached them.
+     pub fn resume(&mut self, from: SessionId, to: SessionId) {
+          if self.owner == Some(from) {
+               self.owner = Some(to);
+          }
+
+          for consumer in self.consumers.values_mut() {
+               let clients: Vec<String> = consumer.members.iter_mut()
+                    .filter(|(_, member)| member.session == from)
+                    .map(|(client, member)| {
+                         member.session = to;
+                         client.clone()
+                    })
+                    .collect();
+               for client in clients {
+                    consumer.requeue(&client);
+               }
+          }
+     }
+
+     /// Hands out the next message for `client` and records it as unacknowledged.
+     /// Messages awaiting redelivery are handed out first. Messages that expired before they
+     /// could be delivered, and messages not passing the filter of the consumer, are skipped.
+     ///
+     /// # Returns
+     /// The delivered message, or `None` when the consumer of `client` has caught up or
+     /// `client` does not consume from the queue
+     pub fn next(&mut self, client: &str, now: SystemTime) -> Option<StoredMessage> {
+          self.next_batch(client, now, 1, usize::MAX).pop()
+     }
+
+     /// Hands out the next messages for `client` in delivery order, as [`Queue::next`] does,
+     /// until `max_messages` are handed out or the next message would take the total size of
+     /// their content past `max_bytes`. The first message is handed out whatever its size.
+     ///
+     /// # Returns
+     /// The delivered messages, empty when the consumer of `client` has caught up or `client`
+     /// does not consume from the queue
+     pub fn next_batch(&mut self, client: &str, now: SystemTime, max_messages: usize, max_bytes: usize) -> Vec<StoredMessage> {
+          let mut batch: Vec<StoredMessage> = Vec::new();
+          let mut bytes = 0;
+          let name = match self.memberships.get(client) {
+               Some(name) => name.clone(),
+               None => return batch,
+          };
+
+          while batch.len() < max_messages {
+               let offset = match self.upcoming(&name, client, now) {
+                    Some(offset) => offset,
+                    None => break,
+               };
+
+               let message = match self.messages.get(&offset) {
+                    Some(message) => message.clone(),
+                    None => break,
+               };
+               if !batch.is_empty() && bytes + message.size() > max_bytes {
+                    break;
+               }
+
+               if let Some(consumer) = self.consumers.get_mut(&name) {
+                    consumer.redeliver.remove(&offset);
+                    consumer.position = consumer.position.max(offset + 1);
+                    consumer.unacked.insert(offset, (client.to_string(), now));
+                    *consumer.deliveries.entry(offset).or_insert(0) += 1;
+                    if consumer.group.is_some() {
+                         consumer.turn = consumer.members.range(..=client.to_string()).count();
+                    }
+               }
+               self.record(ConsumerEvent::Deliver { consumer: name.clone(), offset });
+
+               bytes += message.size();
+               batch.push(message);
+          }
+          // drops the messages every consumer skipped
+          self.trim();
+
+          batch
+     }
+
+     /// Checks that the message at `offset` was delivered to `client` and awaits its
+     /// acknowledgement, without acknowledging it
+     ///
+     /// # Returns
+     /// The name of the consumer `client` consumes the queue through
+     ///
+     /// # Errors
+     /// - [`ProtocolError::Gone109`] if the message expired before it was acknowledged
+     /// - [`ProtocolError::NotFound103`] if the message was not delivered to `client`
+     pub fn awaiting(&self, client: &str, offset: u64, now: SystemTime) -> Result<String, ProtocolError> {
+          let id = match self.messages.get(&offset) {
+               Some(message) => message.id(),
+               None if self.expired.contains_key(&offset) => {
+                    return Err(ProtocolError::Gone109(Error::new(format!("Message at offset {} has expired", offset))));
+               },
+               None => {
+                    return Err(ProtocolError::NotFound103(Error::new(format!("Message at offset {} not found in queue {}", offset, self.name))));
+               }
+          };
+
+          let name = self.memberships.get(client).cloned().unwrap_or_default();
+          let delivered = match self.consumers.get(&name) {
+               Some(consumer) => consumer.unacked.get(&offset).is_some_and(|(member, _)| member == client),
+               None => false,
+          };
+          if !delivered {
+               return Err(ProtocolError::NotFound103(Error::new(format!("Message {} is not awaiting acknowledgement", id))));
+          }
+
+          if self.messages.get(&offset).is_some_and(|message| message.is_expired(now)) {
+               return Err(ProtocolError::Gone109(Error::new(format!("Message {} has expired", id))));
+          }
+
+          Ok(name)
+     }
+
+     /// Acknowledges the message at `offset` delivered to `client`
+     ///
+     /// # Errors
+     /// - [`ProtocolError::Gone109`] if the message expired before it was acknowledged
+     /// - [`ProtocolError::NotFound103`] if the message was not delivered to `client`
+     pub fn acknowledge(&mut self, client: &str, offset: u64, now: SystemTime) -> Result<(), ProtocolError> {
+          let name = match self.awaiting(client, offset, now) {
+               Ok(name) => name,
+               Err(ProtocolError::Gone109(err)) => {
+                    self.expire_offset(offset, now);
+                    return Err(ProtocolError::Gone109(err));
+               },
+               Err(err) => return Err(err),
+          };
+
+          if let Some(consumer) = self.consumers.get_mut(&name) {
+               consumer.unacked.remove(&offset);
+               consumer.deliveries.remove(&offset);
+          }
+          self.record(ConsumerEvent::Acknowledge { consumer: name, offset });
+          self.trim();
+
+          Ok(())
+     }
+
+     /// Acknowledges the message at `offset` on behalf of the consumer `consumer`, whichever member
+     /// it was delivered to, including a message restored from disk that awaits redelivery
+     ///
+     /// # Returns
+     /// `false` if the message is no longer held or the consumer does not await its acknowledgement
+     pub fn acknowledge_for(&mut self, consumer: &str, offset: u64) -> bool {
+          if !self.messages.contains_key(&offset) {
+               return false;
+          }
+
+          let awaited = match self.consumers.get_mut(consumer) {
+               Some(consumer) => {
+                    let unacked = consumer.unacked.remove(&offset).is_some();
+                    let redeliver = consumer.redeliver.remove(&offset);
+                    consumer.deliveries.remove(&offset);
+                    unacked || redeliver
+               },
+               None => false,
+          };
+          if awaited {
+               self.record(ConsumerEvent::Acknowledge { consumer: consumer.to_string(), offset });
+               self.trim();
+          }
+
+          awaited
+     }
+
+     /// Removes the message at `offset`, enqueued by a transaction that could not be applied in
+     /// full, along with its id from the deduplication window of the queue
+     pub fn withdraw(&mut self, offset: u64) {
+          if let Some(message) = self.discard(offset) {
+               // an id that cannot be removed on disk is answered as a duplicate after a restart
+               let _ = self.published.remove(message.id(), offset);
+          }
+     }
+
+     /// Checks whether `count` more messages taking `bytes` in total can be enqueued. A queue that
+     /// makes room by dropping its oldest messages always can.
+     pub fn has_room(&self, count: usize, bytes: u64) -> bool {
+          if !matches!(self.config.overflow, Overflow::Reject) {
+               return true;
+          }
+
+          let long = self.config.max_length.is_some_and(|max| self.messages.len() + count > max);
+          let large = self.config.max_bytes.is_some_and(|max| self.bytes + bytes > max);
+          !(long || large)
+     }
+
+     /// Expires every message whose time-to-live has elapsed at `now`, including messages
+     /// delivered but not yet acknowledged
+     pub fn expire(&mut self, now: SystemTime) {
+          let offsets: Vec<u64> = self.messages.iter()
+               .filter(|(_, message)| message.is_expired(now))
+               .map(|(offset, _)| *offset)
+               .collect();
+
+          for offset in offsets {
+               self.expire_offset(offset, now);
+          }
+     }
+
+     /// Forgets messages that expired before `before`.
+     /// Acknowledging them afterwards is answered as if they never existed.
+     pub fn prune_expired(&mut self, before: SystemTime) {
+          self.expired.retain(|_, at| *at >= before);
+     }
+
+     /// Takes the expired messages waiting to be moved to the dead-letter queue
+     pub fn take_dead_letters(&mut self) -> Vec<StoredMessage> {
+          std::mem::take(&mut self.dead_letters)
+     }
+
+     /// Checks whether changes to the queue have not been flushed to disk yet
+     pub fn is_dirty(&self) -> bool {
+          self.published.is_dirty()
+               || self.durable.as_ref().is_some_and(|durable| durable.log.is_dirty() || durable.consumers.is_dirty())
+     }
+
+     /// Flushes the changes to a durable queue to disk
+     pub fn sync(&mut self) -> Result<(), StorageError> {
+          if let Some(durable) = self.durable.as_mut() {
+               durable.log.sync()?;
+               durable.consumers.sync()?;
+          }
+          self.published.sync()
+     }
+
+     /// Hands out handles to flush the changes to a durable queue to disk without holding on to
+     /// the queue, in the order [`Queue::sync`] flushes them
+     pub fn detach_sync(&mut self) -> Result<Vec<File>, StorageError> {
+          let mut files = Vec::new();
+          if let Some(durable) = self.durable.as_mut() {
+               files.extend(durable.log.detach_sync()?);
+               files.extend(durable.consumers.detach_sync()?);
+          }
+          files.extend(self.published.detach_sync()?);
+          Ok(files)
+     }
+
+     /// Applies the retention and compaction configured for a durable queue at `now`.
+     ///
+     /// Segments past the retention are deleted along with the messages they hold, moving every
+     /// consumer past them. On a compacted queue, the segments rolled since the last compaction
+     /// are rewritten with only the latest message of each key, tombstones included until every
+     /// consumer has moved past them.
+     pub fn retain(&mut self, now: SystemTime) -> Result<(), StorageError> {
+          let durable = match self.durable.as_mut() {
+               Some(durable) => durable,
+               None => return Ok(()),
+          };
+
+          let before = self.config.retention.and_then(|retention| now.checked_sub(retention));
+          if let Some(start) = durable.log.retain(before, self.config.retention_bytes)? {
+               self.drop_before(start);
+          }
+
+          let durable = match self.durable.as_mut() {
+               Some(durable) if self.config.compacted && durable.log.compaction_due() => durable,
+               _ => return Ok(()),
+          };
+
+          let mut latest: HashMap<String, u64> = HashMap::new();
+          let mut tombstones = HashSet::new();
+          for record in durable.log.records(durable.log.start_offset())? {
+               let message: StoredMessage = Decoder::new(record.body()).get()?;
+               if message.is_tombstone() {
+                    tombstones.insert(record.offset());
+               }
+               latest.insert(message.key().to_string(), record.offset());
+          }
+
+          // tombstones are kept until every consumer has moved past them
+          let consumed = self.messages.keys().next().copied().unwrap_or(self.next_offset);
+          let kept: HashSet<u64> = latest.into_values()
+               .filter(|offset| !tombstones.contains(offset) || *offset >= consumed)
+               .collect();
+
+          durable.log.compact(|record| kept.contains(&record.offset()))
+     }
+
+     /// Builds a durable queue out of the log and consumer journal stored in `dir`, replaying
+     /// the consumer journal over the messages of the log
+     fn load(name: String, config: QueueConfig, dir: &Path, log: LogConfig, mut meta: Journal) -> Result<Self, StorageError> {
+          meta.rewrite(&[meta_record(&name, &config)])?;
+          let log = Log::open(dir, log)?;
+          let (mut consumers, records) = Journal::open(&dir.join(CONSUMERS))?;
+
+          let mut queue = Self::new(name, config);
+          let mut trimmed = 0;
+          let mut expired = HashSet::new();
+          for record in records {
+               match Decoder::new(&record).get()? {
+                    ConsumerEvent::Subscribe { consumer, group, position, filter } => {
+                         let filter = match filter {
+                              Some(expression) => Some(Filter::parse(&expression).map_err(|_| StorageError::Corrupted { message: format!("Malformed filter {} of consumer {}", expression, consumer) })?),
+                              None => None,
+                         };
+                         // a consumer registered again only had its filter replaced
+                         queue.consumers.entry(consumer).or_insert_with(|| Consumer::new(group, position)).filter = filter;
+                    },
+                    ConsumerEvent::Deliver { consumer, offset } => {
+                         if let Some(consumer) = queue.consumers.get_mut(&consumer) {
+                              consumer.position = consumer.position.max(offset + 1);
+                              consumer.redeliver.insert(offset);
+                              *consumer.deliveries.entry(offset).or_insert(0) += 1;
+                         }
+                    },
+                    ConsumerEvent::Acknowledge { consumer, offset } => {
+                         if let Some(consumer) = queue.consumers.get_mut(&consumer) {
+                              consumer.redeliver.remove(&offset);
+                              consumer.deliveries.remove(&offset);
+                         }
+                    },
+                    ConsumerEvent::Unsubscribe { consumer } => {
+                         queue.consumers.remove(&consumer);
+                    },
+                    ConsumerEvent::Trim { offset } => trimmed = trimmed.max(offset),
+                    ConsumerEvent::Expire { offset } => {
+                         expired.insert(offset);
+                    },
+                    ConsumerEvent::Defer { consumer, offset } => {
+                         if let Some(consumer) = queue.consumers.get_mut(&consumer) {
+                              consumer.position = consumer.position.max(offset + 1);
+                              consumer.redeliver.insert(offset);
+                         }
+                    },
+                    ConsumerEvent::Skip { consumer, offset } => {
+                         if let Some(consumer) = queue.consumers.get_mut(&consumer) {
+                              consumer.position = consumer.position.max(offset + 1);
+                              consumer.redeliver.remove(&offset);
+                              consumer.deliveries.remove(&offset);
+                         }
+                    },
+               }
+          }
+
+          for record in log.records(trimmed)? {
+               if expired.contains(&record.offset()) {
+                    continue;
+               }
+
+               let mut message: StoredMessage = Decoder::new(record.body()).get()?;
+               message.set_offset(record.offset());
+               queue.hold(message);
+          }
+          queue.next_offset = log.next_offset();
+
+          // compact the journal down to the restored state
+          let mut snapshot = vec![ConsumerEvent::Trim { offset: trimmed }];
+          snapshot.extend(expired.into_iter().filter(|offset| *offset >= trimmed).map(|offset| ConsumerEvent::Expire { offset }));
+          for (name, consumer) in queue.consumers.iter_mut() {
+               consumer.redeliver.retain(|offset| queue.messages.contains_key(offset));
+               consumer.deliveries.retain(|offset, _| consumer.redeliver.contains(offset));
+               snapshot.push(ConsumerEvent::Subscribe {
+                    consumer: name.clone(),
+                    group: consumer.group.clone(),
+                    position: consumer.position,
+                    filter: consumer.filter.as_ref().map(|filter| filter.expression().to_string()),
+               });
+               // one delivery per attempt, keeping count of the deliveries of each message, while
+               // messages held back for their partition key were never delivered
+               for offset in consumer.redeliver.iter() {
+                    match consumer.deliveries.get(offset).copied().unwrap_or(0) {
+                         0 => snapshot.push(ConsumerEvent::Defer { consumer: name.clone(), offset: *offset }),
+                         count => snapshot.extend((0..count).map(|_| ConsumerEvent::Deliver { consumer: name.clone(), offset: *offset })),
+                    }
+               }
+          }
+          let snapshot: Vec<Vec<u8>> = snapshot.iter().map(|event| {
+               let mut encoder = Encoder::new();
+               encoder.put(event);
+               encoder.into_bytes()
+          }).collect();
+          consumers.rewrite(&snapshot)?;
+
+          queue.published = DedupWindow::open(dir)?;
+          queue.durable = Some(Durable { log, meta, consumers });
+
+          Ok(queue)
+     }
+
+     /// Finds the offset of the next message to deliver to `client` through the consumer `name`,
+     /// expiring the messages whose time-to-live elapsed on the way. The messages whose partition
+     /// key belongs to another member of the group are held back for that member.
+     fn upcoming(&mut self, name: &str, client: &str, now: SystemTime) -> Option<u64> {
+          loop {
+               let consumer = self.consumers.get(name)?;
+               let redelivered = consumer.redeliver.iter()
+                    .find(|offset| !self.held_back(name, client, **offset))
+                    .copied();
+               let offset = match redelivered {
+                    Some(offset) => offset,
+                    None => {
+                         let offset = *self.messages.range(consumer.position..).next()?.0;
+                         if self.held_back(name, client, offset) {
+                              self.defer(name, offset);
+                              continue;
+                         }
+                         offset
+                    },
+               };
+
+               let exhausted = self.config.max_deliveries
+                    .is_some_and(|max| consumer.deliveries.get(&offset).is_some_and(|count| *count >= max));
+               if exhausted {
+                    self.give_up(name, offset);
+                    continue;
+               }
+
+               let filtered = consumer.filter.as_ref().is_some_and(|filter| {
+                    self.messages.get(&offset)
+                         .is_some_and(|message| !message.is_expired(now) && !filter.matches(message.headers(), message.message()))
+               });
+               if filtered {
+                    self.pass_over(name, offset);
+                    continue;
+               }
+
+               match self.messages.get(&offset) {
+                    Some(message) if message.is_expired(now) => self.expire_offset(offset, now),
+                    Some(_) => return Some(offset),
+                    None => {
+                         // no longer held, nothing to deliver again
+                         self.consumers.get_mut(name)?.redeliver.remove(&offset);
+                    },
+               }
+          }
+     }
+
+     /// Checks whether the message at `offset` is held back from `client`, its partition key
+     /// belonging to another member of the group consuming through `name`: the member holding an
+     /// unacknowledged message of the key, otherwise the member the key hashes to
+     fn held_back(&self, name: &str, client: &str, offset: u64) -> bool {
+          let consumer = match self.consumers.get(name) {
+               Some(consumer) if consumer.group.is_some() => consumer,
+               _ => return false,
+          };
+          let key = match self.messages.get(&offset).and_then(StoredMessage::partition) {
+               Some(key) => key,
+               None => return false,
+          };
+
+          let holder = consumer.unacked.iter()
+               .find(|(offset, _)| self.messages.get(offset).and_then(StoredMessage::partition) == Some(key))
+               .map(|(_, (member, _))| member.as_str());
+          let owner = holder.or_else(|| {
+               let mut hasher = DefaultHasher::new();
+               key.hash(&mut hasher);
+               let index = hasher.finish().checked_rem(consumer.members.len() as u64)?;
+               consumer.members.keys().nth(index as usize).map(String::as_str)
+          });
+
+          owner.is_some_and(|owner| owner != client)
+     }
+
+     /// Moves the consumer `name` past the message at `offset` without delivering it, holding it
+     /// back to be handed out to the member its partition key belongs to
+     fn defer(&mut self, name: &str, offset: u64) {
+          if let Some(consumer) = self.consumers.get_mut(name) {
+               consumer.redeliver.insert(offset);
+               consumer.position = consumer.position.max(offset + 1);
+          }
+          self.record(ConsumerEvent::Defer { consumer: name.to_string(), offset });
+     }
+
+     /// Moves the consumer `name` past the message at `offset` without delivering it, as it does
+     /// not pass the filter of the consumer
+     fn pass_over(&mut self, name: &str, offset: u64) {
+          if let Some(consumer) = self.consumers.get_mut(name) {
+               consumer.redeliver.remove(&offset);
+               consumer.deliveries.remove(&offset);
+               consumer.position = consumer.position.max(offset + 1);
+          }
+          self.record(ConsumerEvent::Skip { consumer: name.to_string(), offset });
+     }
+
+     /// Stops delivering the message at `offset` to the consumer `name`, once it was delivered
+     /// as many times as the queue allows without being acknowledged. The message is
+     /// dead-lettered if the queue has a dead-letter queue configured.
+     fn give_up(&mut self, name: &str, offset: u64) {
+          if let Some(consumer) = self.consumers.get_mut(name) {
+               consumer.redeliver.remove(&offset);
+               consumer.deliveries.remove(&offset);
+          }
+          self.record(ConsumerEvent::Acknowledge { consumer: name.to_string(), offset });
+
+          if let Some(message) = self.messages.get(&offset).filter(|_| self.config.dead_letter.is_some()) {
+               self.dead_letters.push(message.clone());
+          }
+          self.trim();
+     }
+
+     /// Makes room for a message of `size` bytes when the queue is full, according to its
+     /// overflow policy
+     ///
+     /// # Errors
+     /// [`ProtocolError::InsufficientStorage126`] if the queue is full and rejects overflowing
+     /// messages
+     fn make_room(&mut self, size: u64) -> Result<(), ProtocolError> {
+          loop {
+               let long = self.config.max_length.is_some_and(|max| self.messages.len() >= max);
+               let large = self.config.max_bytes.is_some_and(|max| self.bytes + size > max);
+               if !(long || large) {
+                    return Ok(());
+               }
+
+               let head = match self.messages.keys().next() {
+                    Some(offset) => *offset,
+                    None => return match self.config.overflow {
+                         Overflow::Reject => Err(ProtocolError::InsufficientStorage126(Error::new(format!("Message does not fit in queue {}", self.name)))),
+                         // the message is larger than the queue, let it in alone
+                         _ => Ok(()),
+                    },
+               };
+
+               match self.config.overflow {
+                    Overflow::Reject => {
+                         return Err(ProtocolError::InsufficientStorage126(Error::new(format!("Queue {} is full", self.name))));
+                    },
+                    Overflow::DropHead => {
+                         self.discard(head);
+                    },
+                    Overflow::DeadLetter => {
+                         if let Some(message) = self.discard(head).filter(|_| self.config.dead_letter.is_some()) {
+                              self.dead_letters.push(message);
+                         }
+                    },
+               }
+          }
+     }
+
+     /// Removes `client` from the consumer it consumes through, removing the consumer of a
+     /// single client altogether, including one restored from disk that `client` did not
+     /// subscribe to again
+     ///
+     /// # Returns
+     /// `false` if `client` was not consuming from the queue
+     fn leave(&mut self, client: &str) -> bool {
+          let name = self.memberships.remove(client).unwrap_or_else(|| client.to_string());
+          let single = match self.consumers.get_mut(&name) {
+               Some(consumer) if consumer.group.is_some() => {
+                    if !consumer.members.contains_key(client) {
+                         return false;
+                    }
+                    consumer.leave(client);
+                    false
+               },
+               Some(_) => true,
+               None => return false,
+          };
+
+          if single {
+               self.consumers.remove(&name);
+               self.record(ConsumerEvent::Unsubscribe { consumer: name });
+          }
+          true
+     }
+
+     /// Records a change to the consumers of a durable queue.
+     /// A change that cannot be recorded is lost on restart, which at worst causes messages to
+     /// be delivered again.
+     fn record(&mut self, event: ConsumerEvent) {
+          if let Some(durable) = self.durable.as_mut() {
+               let mut encoder = Encoder::new();
+               encoder.put(&event);
+               let _ = durable.consumers.append(&encoder.into_bytes());
+          }
+     }
+
+     /// Makes a message available to consumers, dropping the earlier message of its key on a
+     /// compacted queue
+     fn hold(&mut self, message: StoredMessage) {
+          let offset = message.offset();
+
+          if self.config.compacted {
+               if let Some(previous) = self.keys.insert(message.key().to_string(), offset) {
+                    self.supersede(previous);
+               }
+          }
+
+          self.bytes += message.size() as u64;
+          self.messages.insert(offset, message);
+     }
+
+     /// Drops the message at `offset`, superseded by a later message of the same key, unless
+     /// it awaits acknowledgement
+     fn supersede(&mut self, offset: u64) {
+          if self.consumers.values().any(|consumer| consumer.unacked.contains_key(&offset)) {
+               return;
+          }
+
+          if let Some(message) = self.messages.remove(&offset) {
+               self.forget(&message);
+               for consumer in self.consumers.values_mut() {
+                    consumer.redeliver.remove(&offset);
+                    consumer.deliveries.remove(&offset);
+               }
+          }
+     }
+
+     /// Removes the key of a dropped message, unless a later message took it over
+     fn forget(&mut self, message: &StoredMessage) {
+          self.bytes -= message.size() as u64;
+          if self.keys.get(message.key()) == Some(&message.offset()) {
+               self.keys.remove(message.key());
+          }
+     }
+
+     /// Drops every message before `start`, deleted by the retention of the queue, moving every
+     /// consumer past them
+     fn drop_before(&mut self, start: u64) {
+          let retained = self.messages.split_off(&start);
+          for message in std::mem::replace(&mut self.messages, retained).into_values() {
+               self.forget(&message);
+          }
+
+          for consumer in self.consumers.values_mut() {
+               consumer.position = consumer.position.max(start);
+               consumer.unacked.retain(|offset, _| *offset >= start);
+               consumer.redeliver.retain(|offset| *offset >= start);
+               consumer.deliveries.retain(|offset, _| *offset >= start);
+          }
+          self.record(ConsumerEvent::Trim { offset: start });
+     }
+
+     /// Removes the message at `offset` as expired
+     fn expire_offset(&mut self, offset: u64, now: SystemTime) {
+          let message = match self.discard(offset) {
+               Some(message) => message,
+               None => return,
+          };
+
+          self.expired.insert(offset, now);
+          if self.config.dead_letter.is_some() {
+               self.dead_letters.push(message);
+          }
+     }
+
+     /// Removes the message at `offset` before every consumer is done with it, recording it so
+     /// that it is not restored on restart
+     fn discard(&mut self, offset: u64) -> Option<StoredMessage> {
+          let message = self.messages.remove(&offset)?;
+
+          self.forget(&message);
+          for consumer in self.consumers.values_mut() {
+               consumer.unacked.remove(&offset);
+               consumer.redeliver.remove(&offset);
+               consumer.deliveries.remove(&offset);
+          }
+          self.record(ConsumerEvent::Expire { offset });
+
+          Some(message)
+     }
+
+     /// Drops the messages every consumer is done with.
+     /// Messages are kept while the queue has no consumers.
+     fn trim(&mut self) {
+          let watermark = match self.consumers.values().map(Consumer::low_watermark).min() {
+               Some(watermark) => watermark,
+               None => return,
+          };
+
+          if self.messages.keys().next().is_none_or(|first| *first >= watermark) {
+               return;
+          }
+
+          let retained = self.messages.split_off(&watermark);
+          for message in std::mem::replace(&mut self.messages, retained).into_values() {
+               self.forget(&message);
+          }
+          self.record(ConsumerEvent::Trim { offset: watermark });
+     }
+}
+
+/// Name of the directory a durable queue is stored in.
+/// Characters other than ASCII letters, digits, `-` and `_` are escaped as `%XX`.
+pub fn directory(name: &str) -> String {
+     name.bytes().map(|byte| match byte {
+          b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' => (byte as char).to_string(),
+          _ => format!("%{:02X}", byte),
+     }).collect()
+}
+
+/// Journal record holding the name and configuration of a queue. The roles of the clients that
+/// did not authenticate and their admissions are left out, since their address may belong to
+/// another client by the time the queue is restored.
+fn meta_record(name: &str, config: &QueueConfig) -> Vec<u8> {
+     let mut encoder = Encoder::new();
+     encoder.put_str(name);
+     encoder.put(&QueueConfig {
+          roles: config.roles.iter().filter(|(client, _)| !session::is_address(client)).map(|(client, role)| (client.clone(), role.clone())).collect(),
+          admitted: config.admitted.iter().filter(|client| !session::is_address(client)).cloned().collect(),
+          ..config.clone()
+     });
+     encoder.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+     use std::fs;
+     use std::path::{Path, PathBuf};
+     use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+     use net::protocol::{MTPHeaders, MTPMessage, MTPStorage};
+     use net::protocol::error::ProtocolError;
+     use net::protocol::interface::{ContentType, Distribution, MTPHeaderUnit, MessageCategory, MessagePriority, MessagePublish};
+
+     use super::{Queue, QueueConfig};
+     use crate::broker::filter::Filter;
+     use crate::broker::message::StoredMessage;
+     use crate::storage::codec::Decoder;
+     use crate::storage::log::LogConfig;
+
+     fn scratch(name: &str) -> PathBuf {
+          let dir = std::env::temp_dir().join(format!("queue-{}-{}", name, std::process::id()));
+          let _ = fs::remove_dir_all(&dir);
+          dir
+     }
+
+     fn at(secs: u64) -> SystemTime {
+          UNIX_EPOCH + Duration::from_secs(secs)
+     }
+
+     fn message(id: &str, key: &str, tombstone: bool, enqueued_at: SystemTime) -> StoredMessage {
+          let mut units = vec![MTPHeaderUnit::Key { key: key.to_string() }];
+          if tombstone {
+               units.push(MTPHeaderUnit::Tombstone);
+          }
+          let body = MTPMessage::new(ContentType::JSON, MessagePriority::Low, MessageCategory::EVENT, MessagePublish::ALL, id.to_string());
+          StoredMessage::new(id.to_string(), MTPHeaders::new(units, MTPStorage::new(Vec::new()), None), body, enqueued_at, None)
+     }
+
+     fn regional(id: &str, region: &str) -> StoredMessage {
+          let mut local = MTPStorage::new(Vec::new());
+          local.push("region".to_string(), region.to_string());
+          let body = MTPMessage::new(ContentType::JSON, MessagePriority::Low, MessageCategory::EVENT, MessagePublish::ALL, id.to_string());
+          StoredMessage::new(id.to_string(), MTPHeaders::new(Vec::new(), local, None), body, at(0), None)
+     }
+
+     fn filter(expression: &str) -> Option<Filter> {
+          Some(Filter::parse(expression).ok().unwrap())
+     }
+
+     fn expression(queue: &Queue, consumer: &str) -> Option<String> {
+          queue.consumers()[consumer].filter().map(|filter| filter.expression().to_string())
+     }
+
+     /// Opens a durable queue rolling a segment for every message
+     fn durable(dir: &Path, config: QueueConfig) -> Queue {
+          Queue::open("orders".to_string(), config.with_durable(true), dir, LogConfig::default().with_segment_bytes(1)).ok().unwrap()
+     }
+
+     /// Ids of the messages held by the log of a durable queue
+     fn logged(queue: &Queue) -> Vec<String> {
+          let log = &queue.durable.as_ref().unwrap().log;
+          log.records(log.start_offset()).ok().unwrap().iter()
+               .map(|record| Decoder::new(record.body()).get::<StoredMessage>().ok().unwrap().id().to_string())
+               .collect()
+     }
+
+     #[test]
+     fn retention_drops_aged_segments_and_moves_consumers() {
+          let dir = scratch("retention");
+          let mut queue = durable(&dir, QueueConfig::default().with_retention(Duration::from_secs(15)));
+          queue.subscribe("consumer".to_string(), 1, false, None, None).ok().unwrap();
+          for (offset, id) in ["a", "b", "c", "d"].into_iter().enumerate() {
+               queue.enqueue(message(id, id, false, at(offset as u64 * 10))).ok().unwrap();
+          }
+
+          queue.retain(at(30)).ok().unwrap();
+          assert_eq!(logged(&queue), vec!["c", "d"]);
+          assert_eq!(queue.len(), 2);
+          assert_eq!(queue.consumers()["consumer"].position, 2);
+          assert_eq!(queue.next("consumer", at(30)).map(|message| message.id().to_string()), Some("c".to_string()));
+
+          fs::remove_dir_all(&dir).unwrap();
+     }
+
+     #[test]
+     fn retention_bytes_keep_the_active_segment() {
+          let dir = scratch("retention-bytes");
+          let mut queue = durable(&dir, QueueConfig::default().with_retention_bytes(1));
+          for id in ["a", "b", "c"] {
+               queue.enqueue(message(id, id, false, at(0))).ok().unwrap();
+          }
+
+          queue.retain(at(0)).ok().unwrap();
+          assert_eq!(logged(&queue), vec!["c"]);
+          assert_eq!(queue.len(), 1);
+
+          fs::remove_dir_all(&dir).unwrap();
+     }
+
+     #[test]
+     fn compaction_keeps_the_latest_message_of_each_key() {
+          let dir = scratch("compaction");
+          let mut queue = durable(&dir, QueueConfig::default().with_compacted(true));
+          queue.subscribe("consumer".to_string(), 1, false, None, None).ok().unwrap();
+          for (id, key, tombstone) in [("a1", "a", false), ("a2", "a", false), ("b1", "b", false), ("x1", "x", false), ("x2", "x", true), ("p1", "p", false)] {
+               queue.enqueue(message(id, key, tombstone, at(0))).ok().unwrap();
+          }
+
+          // the tombstone is kept while a consumer has yet to move past it
+          queue.retain(at(0)).ok().unwrap();
+          assert_eq!(logged(&queue), vec!["a2", "b1", "x2", "p1"]);
+
+          assert_eq!(queue.len(), 4);
+
+          for _ in 0..3 {
+               let offset = queue.next("consumer", at(0)).unwrap().offset();
+               queue.acknowledge("consumer", offset, at(0)).ok().unwrap();
+          }
+          queue.enqueue(message("p2", "p", false, at(0))).ok().unwrap();
+          queue.retain(at(0)).ok().unwrap();
+          assert_eq!(logged(&queue), vec!["a2", "b1", "p2"]);
+
+          fs::remove_dir_all(&dir).unwrap();
+     }
+
+     #[test]
+     fn messages_sharing_an_id_are_acknowledged_by_offset() {
+          let mut queue = Queue::new("orders".to_string(), QueueConfig::default());
+          queue.subscribe("consumer".to_string(), 1, false, None, None).ok().unwrap();
+          queue.enqueue(message("a", "first", false, at(0))).ok().unwrap();
+          queue.enqueue(message("a", "second", false, at(0))).ok().unwrap();
+
+          let first = queue.next("consumer", at(0)).unwrap().offset();
+          let second = queue.next("consumer", at(0)).unwrap().offset();
+          assert_ne!(first, second);
+
+          // the first message still awaits acknowledgement, holding back the trimming of the queue
+          queue.acknowledge("consumer", second, at(0)).ok().unwrap();
+          assert_eq!(queue.len(), 2);
+          queue.acknowledge("consumer", first, at(0)).ok().unwrap();
+          assert_eq!(queue.len(), 0);
+
+          let again = queue.acknowledge("consumer", first, at(0));
+          assert!(matches!(again, Err(ProtocolError::NotFound103(_))));
+     }
+
+     #[test]
+     fn withdrawn_messages_leave_the_dedup_window() {
+          let mut queue = Queue::new("orders".to_string(), QueueConfig::default().with_dedup_count(16));
+          queue.subscribe("consumer".to_string(), 1, false, None, None).ok().unwrap();
+          let kept = queue.enqueue(message("a", "a", false, at(0))).ok().unwrap();
+          let withdrawn = queue.enqueue(message("b", "b", false, at(0))).ok().unwrap();
+
+          queue.withdraw(withdrawn);
+          assert_eq!(queue.len(), 1);
+          assert_eq!(queue.duplicate_of("a", at(0)), Some(kept));
+          assert_eq!(queue.duplicate_of("b", at(0)), None);
+
+          // the withdrawn message is never handed out
+          assert_eq!(queue.next("consumer", at(0)).map(|message| message.offset()), Some(kept));
+          assert!(queue.next("consumer", at(0)).is_none());
+     }
+
+     #[test]
+     fn group_members_share_their_filter() {
+          let mut queue = Queue::new("orders".to_string(), QueueConfig::default());
+          let group = || Some(("workers".to_string(), Distribution::RoundRobin));
+          queue.subscribe("a".to_string(), 1, false, group(), filter("region = eu")).ok().unwrap();
+
+          // another member cannot impose its own filter on the group
+          let conflict = queue.subscribe("b".to_string(), 2, false, group(), filter("region = us"));
+          assert!(matches!(conflict, Err(ProtocolError::Conflict108(_))));
+          assert!(queue.consumer_of("b").is_none());
+          queue.subscribe("b".to_string(), 2, false, group(), None).ok().unwrap();
+          queue.subscribe("b".to_string(), 2, false, group(), filter("region = eu")).ok().unwrap();
+          assert_eq!(expression(&queue, "workers").as_deref(), Some("region = eu"));
+
+          // the last member consuming through the group may replace it
+          queue.unsubscribe("b");
+          queue.subscribe("a".to_string(), 1, false, group(), filter("region = us")).ok().unwrap();
+          assert_eq!(expression(&queue, "workers").as_deref(), Some("region = us"));
+
+          // a client on its own replaces its filter
+          queue.subscribe("c".to_string(), 3, false, None, filter("region = eu")).ok().unwrap();
+          queue.subscribe("c".to_string(), 3, false, None, filter("region = us")).ok().unwrap();
+          assert_eq!(expression(&queue, "c").as_deref(), Some("region = us"));
+     }
+
+     #[test]
+     fn filters_and_skipped_messages_survive_a_restart() {
+          let dir = scratch("filter");
+          let mut queue = durable(&dir, QueueConfig::default());
+          queue.subscribe("consumer".to_string(), 1, false, None, filter("region = eu")).ok().unwrap();
+          for (id, region) in [("a", "us"), ("b", "eu"), ("c", "us")] {
+               queue.enqueue(regional(id, region)).ok().unwrap();
+          }
+
+          let delivered = queue.next("consumer", at(0)).map(|message| (message.id().to_string(), message.offset())).unwrap();
+          assert_eq!(delivered.0, "b");
+          queue.acknowledge("consumer", delivered.1, at(0)).ok().unwrap();
+          assert!(queue.next("consumer", at(0)).is_none());
+          drop(queue);
+
+          let mut queue = durable(&dir, QueueConfig::default());
+          assert_eq!(expression(&queue, "consumer").as_deref(), Some("region = eu"));
+          assert_eq!(queue.consumers()["consumer"].position, 3);
+          for (id, region) in [("d", "us"), ("e", "eu")] {
+               queue.enqueue(regional(id, region)).ok().unwrap();
+          }
+          queue.subscribe("consumer".to_string(), 1, false, None, None).ok().unwrap();
+          assert_eq!(queue.next("consumer", at(0)).map(|message| message.id().to_string()), Some("e".to_string()));
+          drop(queue);
+
+          // the journal compacted on restart keeps the filter as well
+          let queue = durable(&dir, QueueConfig::default());
+          assert_eq!(expression(&queue, "consumer").as_deref(), Some("region = eu"));
+
+          fs::remove_dir_all(&dir).unwrap();
+     }
+}
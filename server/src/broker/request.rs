@@ -0,0 +1,185 @@
+use std::time::{Duration, SystemTime};
+
+use net::protocol::MTPHeaders;
+use net::protocol::error::{Error, ProtocolError};
+use net::protocol::interface::{Distribution, MTPAuth, MTPHeaderUnit, MTPManagerAction, MessagePublish, TransactionStep};
+
+use super::filter::Filter;
+use super::queue::QueueConfig;
+
+/// Retrieves the method and the credentials of the `Authentication` unit of a request, if it
+/// carries one
+pub fn authentication(headers: &MTPHeaders) -> Option<(MTPAuth, String)> {
+     headers.units().iter().find_map(|unit| match unit {
+          MTPHeaderUnit::Authentication { key, value } => Some((key.clone(), value.clone())),
+          _ => None,
+     })
+}
+
+/// Retrieves the queue a subscribe, unsubscribe or pull request operates on
+///
+/// # Errors
+/// [`ProtocolError::BadRequest100`] if the headers carry no `Subscription` unit
+pub fn subscription(headers: &MTPHeaders) -> Result<String, ProtocolError> {
+     headers.units().iter().find_map(|unit| match unit {
+          MTPHeaderUnit::Subscription { queue } => Some(queue.clone()),
+          _ => None,
+     }).ok_or_else(|| ProtocolError::BadRequest100(Error::new("Missing Subscription header".to_string())))
+}
+
+/// Retrieves the queue the manager actions of a manage request operate on
+///
+/// # Errors
+/// [`ProtocolError::BadRequest100`] if the headers carry no `Subscription` unit
+pub fn managed(headers: &MTPHeaders) -> Result<String, ProtocolError> {
+     subscription(headers)
+          .map_err(|_| ProtocolError::BadRequest100(Error::new("Missing Subscription header naming the managed queue".to_string())))
+}
+
+/// Retrieves the consumer group a subscribe or pull request joins, along with the distribution
+/// of messages among its members
+pub fn group(headers: &MTPHeaders) -> Option<(String, Distribution)> {
+     headers.units().iter().find_map(|unit| match unit {
+          MTPHeaderUnit::ConsumerGroup { group, distribution } => Some((group.clone(), distribution.clone())),
+          _ => None,
+     })
+}
+
+/// Retrieves the filter selecting the messages a subscribe or pull request is delivered
+///
+/// # Errors
+/// [`ProtocolError::UnprocessableContent112`] if the `Filter` unit carries an invalid expression
+pub fn filter(headers: &MTPHeaders) -> Result<Option<Filter>, ProtocolError> {
+     headers.units().iter().find_map(|unit| match unit {
+          MTPHeaderUnit::Filter { expression } => Some(Filter::parse(expression)),
+          _ => None,
+     }).transpose()
+}
+
+/// Retrieves the queue a message is published to and how it is published
+///
+/// # Errors
+/// [`ProtocolError::BadRequest100`] if the headers carry no `MessagePublish` unit
+pub fn publish_target(headers: &MTPHeaders) -> Result<(String, MessagePublish), ProtocolError> {
+     headers.units().iter().find_map(|unit| match unit {
+          MTPHeaderUnit::MessagePublish { queue, to } => Some((queue.clone(), to.clone())),
+          _ => None,
+     }).ok_or_else(|| ProtocolError::BadRequest100(Error::new("Missing MessagePublish header".to_string())))
+}
+
+/// Retrieves the message acknowledged by an acknowledge request
+///
+/// # Returns
+/// The queue the message was delivered from and the offset of the message in it
+///
+/// # Errors
+/// [`ProtocolError::BadRequest100`] if the headers carry no `Delivery` unit
+pub fn delivery(headers: &MTPHeaders) -> Result<(String, u64), ProtocolError> {
+     headers.units().iter().find_map(|unit| match unit {
+          MTPHeaderUnit::Delivery { queue, offset, .. } => Some((queue.clone(), *offset)),
+          _ => None,
+     }).ok_or_else(|| ProtocolError::BadRequest100(Error::new("Missing Delivery header".to_string())))
+}
+
+/// Retrieves the id of a published message from its `Message` unit
+pub fn message_id(headers: &MTPHeaders) -> Option<String> {
+     headers.units().iter().find_map(|unit| match unit {
+          MTPHeaderUnit::Message { id, .. } if !id.is_empty() => Some(id.clone()),
+          _ => None,
+     })
+}
+
+/// Retrieves the time-to-live of a published message
+pub fn ttl(headers: &MTPHeaders) -> Option<Duration> {
+     headers.units().iter().find_map(|unit| match unit {
+          MTPHeaderUnit::TimeToLive { ttl } => Some(*ttl),
+          _ => None,
+     })
+}
+
+/// Retrieves the batch a pull request returns
+///
+/// # Returns
+/// The maximum number of messages, at least one, the maximum total size of their content and
+/// the time for which the request is held open when no message is available. A pull request
+/// without a `Batch` unit returns a single message without waiting.
+pub fn batch(headers: &MTPHeaders) -> (usize, usize, Duration) {
+     headers.units().iter().find_map(|unit| match unit {
+          MTPHeaderUnit::Batch { max_messages, max_bytes, wait } => {
+               Some(((*max_messages as usize).max(1), usize::try_from(*max_bytes).unwrap_or(usize::MAX), *wait))
+          },
+          _ => None,
+     }).unwrap_or((1, usize::MAX, Duration::ZERO))
+}
+
+/// Retrieves the time before which a published message is not delivered, given that the
+/// broker received it at `now`. A `NotBefore` unit takes precedence over a `Delay` unit.
+pub fn not_before(headers: &MTPHeaders, now: SystemTime) -> Option<SystemTime> {
+     let at = headers.units().iter().find_map(|unit| match unit {
+          MTPHeaderUnit::NotBefore { at } => Some(*at),
+          _ => None,
+     });
+
+     at.or_else(|| headers.units().iter().find_map(|unit| match unit {
+          MTPHeaderUnit::Delay { delay } => Some(now + *delay),
+          _ => None,
+     }))
+}
+
+/// Retrieves the manager actions carried by the `Administration` units of a manage request
+pub fn actions(headers: &MTPHeaders) -> Vec<MTPManagerAction> {
+     headers.units().iter().filter_map(|unit| match unit {
+          MTPHeaderUnit::Administration { action } => Some(action.clone()),
+          _ => None,
+     }).collect()
+}
+
+/// Checks whether a manage request asks for a temporary queue
+pub fn temporary(headers: &MTPHeaders) -> bool {
+     headers.units().iter().any(|unit| matches!(unit, MTPHeaderUnit::TemporaryQueue))
+}
+
+/// Retrieves the transaction step a manage request performs
+pub fn transaction(headers: &MTPHeaders) -> Option<TransactionStep> {
+     headers.units().iter().find_map(|unit| match unit {
+          MTPHeaderUnit::Transaction { step } => Some(step.clone()),
+          _ => None,
+     })
+}
+
+/// Retrieves the queue provisioned by the `QueueCreation` unit of a manage request along with
+/// its configuration
+///
+/// # Errors
+/// [`ProtocolError::BadRequest100`] if the `QueueCreation` unit names no queue
+pub fn creation(headers: &MTPHeaders) -> Result<Option<(String, QueueConfig)>, ProtocolError> {
+     let unit = headers.units().iter().find(|unit| matches!(unit, MTPHeaderUnit::QueueCreation { .. }));
+     let Some(MTPHeaderUnit::QueueCreation { name, access, durable, max_length, max_bytes, overflow, ttl, max_deliveries, dead_letter }) = unit else {
+          return Ok(None);
+     };
+     if name.is_empty() {
+          return Err(ProtocolError::BadRequest100(Error::new("QueueCreation header without a queue name".to_string())));
+     }
+
+     let mut config = QueueConfig::default()
+          .with_access(access.clone())
+          .with_durable(*durable)
+          .with_overflow(overflow.clone());
+     if let Some(length) = max_length {
+          config = config.with_max_length(usize::try_from(*length).unwrap_or(usize::MAX));
+     }
+     if let Some(bytes) = max_bytes {
+          config = config.with_max_bytes(*bytes);
+     }
+     if let Some(ttl) = ttl {
+          config = config.with_ttl(*ttl);
+     }
+     if let Some(deliveries) = max_deliveries {
+          config = config.with_max_deliveries(*deliveries);
+     }
+     if let Some(queue) = dead_letter {
+          config = config.with_dead_letter(queue.clone());
+     }
+
+     Ok(Some((name.clone(), config)))
+}
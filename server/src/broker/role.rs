@@ -0,0 +1,69 @@
+use net::protocol::interface::{MTPManagerAction, QueueRoles};
+
+/// An operation a client performs on a queue, checked against the role of the client on it.
+///
+/// ## Variants
+///
+/// ~ `Publish`: Publishing a message to the queue by name
+/// ~ `Subscribe`: Subscribing to the queue to be pushed its messages
+/// ~ `Pull`: Pulling messages from the queue and acknowledging them
+/// ~ `Manage`: Performing a manager action on the queue, including deleting it
+pub enum Operation<'a> {
+     Publish,
+     Subscribe,
+     Pull,
+     Manage(&'a MTPManagerAction),
+}
+
+impl Operation<'_> {
+     /// Retrieves the name of the operation, as reported to clients it is refused to
+     pub fn name(&self) -> &'static str {
+          match self {
+               Self::Publish => "publish",
+               Self::Subscribe => "subscribe",
+               Self::Pull => "pull",
+               Self::Manage(MTPManagerAction::Rename(_)) => "rename",
+               Self::Manage(MTPManagerAction::Authorize(_)) => "authorize",
+               Self::Manage(MTPManagerAction::Reject(_)) => "reject",
+               Self::Manage(MTPManagerAction::Dispose { .. }) => "dispose",
+               Self::Manage(MTPManagerAction::AccessorModify(_)) => "modify the access",
+               Self::Manage(MTPManagerAction::Cancel(_)) => "cancel",
+               Self::Manage(MTPManagerAction::Pending) => "list the pending requests",
+               Self::Manage(MTPManagerAction::Assign(..)) => "assign roles",
+               Self::Manage(MTPManagerAction::Revoke(_)) => "revoke roles",
+               Self::Manage(MTPManagerAction::Delete) => "delete",
+               Self::Manage(MTPManagerAction::AddUser { .. }) => "add users",
+               Self::Manage(MTPManagerAction::ChangePassword { .. }) => "change passwords",
+               Self::Manage(MTPManagerAction::RemoveUser(_)) => "remove users",
+               Self::Manage(MTPManagerAction::UnlockUser(_)) => "unlock users",
+          }
+     }
+}
+
+/// Checks whether a client holding `role` on a queue may perform `operation` on it, `None`
+/// standing for a client without a role. See [`QueueRoles`] for the permission matrix.
+pub fn permits(role: Option<&QueueRoles>, operation: &Operation) -> bool {
+     // users are managed by the administrators of the broker, whatever their roles on queues
+     if matches!(operation, Operation::Manage(action) if is_user_action(action)) {
+          return false;
+     }
+
+     let role = match role {
+          Some(QueueRoles::Moderator) => return true,
+          Some(role) => role,
+          None => return matches!(operation, Operation::Publish | Operation::Subscribe | Operation::Pull),
+     };
+
+     match operation {
+          Operation::Publish => matches!(role, QueueRoles::Manager | QueueRoles::Producer | QueueRoles::Couple),
+          Operation::Subscribe | Operation::Pull => matches!(role, QueueRoles::Manager | QueueRoles::Consumer | QueueRoles::Couple),
+          Operation::Manage(MTPManagerAction::Cancel(_) | MTPManagerAction::Authorize(_) | MTPManagerAction::Reject(_) | MTPManagerAction::Pending | MTPManagerAction::Dispose { .. } | MTPManagerAction::Rename(_)) => matches!(role, QueueRoles::Manager),
+          Operation::Manage(MTPManagerAction::AccessorModify(_) | MTPManagerAction::Assign(..) | MTPManagerAction::Revoke(_) | MTPManagerAction::Delete) => false,
+          Operation::Manage(MTPManagerAction::AddUser { .. } | MTPManagerAction::ChangePassword { .. } | MTPManagerAction::RemoveUser(_) | MTPManagerAction::UnlockUser(_)) => false,
+     }
+}
+
+/// Checks whether `action` manages the users of the broker rather than a queue
+pub fn is_user_action(action: &MTPManagerAction) -> bool {
+     matches!(action, MTPManagerAction::AddUser { .. } | MTPManagerAction::ChangePassword { .. } | MTPManagerAction::RemoveUser(_) | MTPManagerAction::UnlockUser(_))
+}
@@ -0,0 +1,289 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::path::Path;
+use std::time::SystemTime;
+
+use net::protocol::{MTPHeaders, MTPMessage};
+use net::protocol::error::{Error, ProtocolError};
+
+use crate::storage::codec::{Decode, Decoder, Encode, Encoder};
+use crate::storage::error::StorageError;
+use crate::storage::journal::Journal;
+
+/// Name of the journal scheduled messages are persisted to within the data directory
+const JOURNAL: &str = "schedule.journal";
+
+/// A message published for delayed delivery, held by the [`Scheduler`] until it is due.
+///
+/// # Fields
+///
+/// ~ `queue`: Queue the message is published to
+/// ~ `id`: Identifier of the message
+/// ~ `publisher`: Client that published the message, which may cancel it
+/// ~ `headers`: Headers the message was published with
+/// ~ `message`: The published message
+/// ~ `at`: Time at which the message becomes visible in its queue
+pub struct ScheduledMessage {
+     queue: String,
+     id: String,
+     publisher: String,
+     headers: MTPHeaders,
+     message: MTPMessage,
+     at: SystemTime,
+}
+
+impl ScheduledMessage {
+     /// Creates a message published by `publisher`, due at `at`
+     pub fn new(queue: String, id: String, publisher: String, headers: MTPHeaders, message: MTPMessage, at: SystemTime) -> Self {
+          Self { queue, id, publisher, headers, message, at }
+     }
+
+     /// Retrieves the queue the message is published to
+     pub fn queue(&self) -> &str {
+          &self.queue
+     }
+
+     /// Retrieves the identifier of the message
+     pub fn id(&self) -> &str {
+          &self.id
+     }
+
+     /// Retrieves the client that published the message
+     pub fn publisher(&self) -> &str {
+          &self.publisher
+     }
+
+     /// Retrieves the time at which the message becomes visible
+     pub fn at(&self) -> SystemTime {
+          self.at
+     }
+
+     /// Consumes the scheduled message, returning its id, headers and message
+     pub fn into_parts(self) -> (String, MTPHeaders, MTPMessage) {
+          (self.id, self.headers, self.message)
+     }
+}
+
+impl Encode for ScheduledMessage {
+     fn encode(&self, encoder: &mut Encoder) {
+          encoder.put_str(&self.queue);
+          encoder.put_str(&self.id);
+          encoder.put_str(&self.publisher);
+          encoder.put(&self.headers);
+          encoder.put(&self.message);
+          encoder.put_time(self.at);
+     }
+}
+
+impl Decode for ScheduledMessage {
+     fn decode(decoder: &mut Decoder) -> Result<Self, StorageError> {
+          Ok(Self {
+               queue: decoder.get_str()?,
+               id: decoder.get_str()?,
+               publisher: decoder.get_str()?,
+               headers: decoder.get()?,
+               message: decoder.get()?,
+               at: decoder.get_time()?,
+          })
+     }
+}
+
+/// Timer structure holding messages published for delayed delivery.
+///
+/// Messages are ordered by the time they are due, and identified by the queue they are published
+/// to along with their id, so that queues do not share a space of ids. When the broker persists its state, every
+/// scheduled and released message is recorded in a journal so that pending messages survive a
+/// restart. A message released right before a crash may be released once more on restart.
+///
+/// # Fields
+///
+/// ~ `timers`: Scheduled messages by due time, ties broken in scheduling order
+/// ~ `ids`: Keys of `timers` by queue and message id
+/// ~ `sequence`: Counter breaking ties between messages due at the same time
+/// ~ `journal`: Journal the schedule is persisted to, if any
+pub struct Scheduler {
+     timers: BTreeMap<(SystemTime, u64), ScheduledMessage>,
+     ids: HashMap<(String, String), (SystemTime, u64)>,
+     sequence: u64,
+     journal: Option<Journal>,
+}
+
+impl Scheduler {
+     /// Creates a scheduler that does not persist its messages
+     pub fn in_memory() -> Self {
+          Self {
+               timers: BTreeMap::new(),
+               ids: HashMap::new(),
+               sequence: 0,
+               journal: None,
+          }
+     }
+
+     /// Opens the scheduler persisted in `dir`, restoring the messages still pending
+     pub fn open(dir: &Path) -> Result<Self, StorageError> {
+          let (journal, records) = Journal::open(&dir.join(JOURNAL))?;
+          let mut scheduler = Self::in_memory();
+
+          for record in records {
+               let mut decoder = Decoder::new(&record);
+               match decoder.get_u8()? {
+                    0 => scheduler.insert(decoder.get()?),
+                    1 => {
+                         let queue = decoder.get_str()?;
+                         scheduler.remove(&queue, &decoder.get_str()?);
+                    },
+                    tag => return Err(StorageError::Corrupted { message: format!("Unknown schedule record {}", tag) }),
+               }
+          }
+
+          // drop the records of released and cancelled messages
+          let mut journal = journal;
+          let live: Vec<Vec<u8>> = scheduler.timers.values().map(schedule_record).collect();
+          journal.rewrite(&live)?;
+          scheduler.journal = Some(journal);
+
+          Ok(scheduler)
+     }
+
+     /// Number of messages pending
+     pub fn len(&self) -> usize {
+          self.timers.len()
+     }
+
+     /// Checks whether no message is pending
+     pub fn is_empty(&self) -> bool {
+          self.timers.is_empty()
+     }
+
+     /// Holds `message` until it is due
+     ///
+     /// # Errors
+     /// - [`ProtocolError::Conflict108`] if a message with the same id is already scheduled on
+     ///   the same queue
+     /// - [`ProtocolError::InsufficientStorage126`] if the message could not be persisted
+     pub fn schedule(&mut self, message: ScheduledMessage) -> Result<(), ProtocolError> {
+          if self.contains(&message.queue, &message.id) {
+               return Err(ProtocolError::Conflict108(Error::new(format!("Message {} is already scheduled on {}", message.id, message.queue))));
+          }
+
+          if let Some(journal) = self.journal.as_mut() {
+               journal.append(&schedule_record(&message))?;
+          }
+          self.insert(message);
+
+          Ok(())
+     }
+
+     /// Cancels the message `id` scheduled on the queue `queue`
+     ///
+     /// # Errors
+     /// - [`ProtocolError::NotFound103`] if no message with this id is pending on the queue
+     /// - [`ProtocolError::InsufficientStorage126`] if the cancellation could not be persisted
+     pub fn cancel(&mut self, queue: &str, id: &str) -> Result<ScheduledMessage, ProtocolError> {
+          if !self.contains(queue, id) {
+               return Err(ProtocolError::NotFound103(Error::new(format!("No message {} is scheduled on {}", id, queue))));
+          }
+
+          if let Some(journal) = self.journal.as_mut() {
+               journal.append(&remove_record(queue, id))?;
+          }
+
+          self.remove(queue, id).ok_or_else(|| ProtocolError::NotFound103(Error::new(format!("No message {} is scheduled on {}", id, queue))))
+     }
+
+     /// Retrieves the message `id` scheduled on the queue `queue`
+     pub fn get(&self, queue: &str, id: &str) -> Option<&ScheduledMessage> {
+          self.ids.get(&(queue.to_string(), id.to_string())).and_then(|key| self.timers.get(key))
+     }
+
+     /// Checks whether the message `id` is scheduled on the queue `queue`
+     pub fn contains(&self, queue: &str, id: &str) -> bool {
+          self.ids.contains_key(&(queue.to_string(), id.to_string()))
+     }
+
+     /// Retrieves the queues a message `id` is scheduled on, in lexicographic order
+     pub fn queues_of(&self, id: &str) -> Vec<&str> {
+          let mut queues: Vec<&str> = self.ids.keys()
+               .filter(|(_, scheduled)| scheduled == id)
+               .map(|(queue, _)| queue.as_str())
+               .collect();
+          queues.sort_unstable();
+          queues
+     }
+
+     /// Retrieves the time at which the next message is due
+     pub fn next_due(&self) -> Option<SystemTime> {
+          self.timers.keys().next().map(|(at, _)| *at)
+     }
+
+     /// Takes every message due at `now`, in the order they are due
+     pub fn take_due(&mut self, now: SystemTime) -> Vec<ScheduledMessage> {
+          let pending = self.timers.split_off(&(now, u64::MAX));
+          let due = std::mem::replace(&mut self.timers, pending);
+
+          due.into_values().inspect(|message| {
+               self.ids.remove(&(message.queue.clone(), message.id.clone()));
+          }).collect()
+     }
+
+     /// Records that a message taken with [`Scheduler::take_due`] has been enqueued
+     pub fn released(&mut self, queue: &str, id: &str) -> Result<(), StorageError> {
+          match self.journal.as_mut() {
+               Some(journal) => journal.append(&remove_record(queue, id)),
+               None => Ok(()),
+          }
+     }
+
+     /// Checks whether changes to the schedule have not been flushed to disk yet
+     pub fn is_dirty(&self) -> bool {
+          self.journal.as_ref().is_some_and(Journal::is_dirty)
+     }
+
+     /// Flushes the changes to the schedule to disk
+     pub fn sync(&mut self) -> Result<(), StorageError> {
+          match self.journal.as_mut() {
+               Some(journal) => journal.sync(),
+               None => Ok(()),
+          }
+     }
+
+     /// Hands out a handle to flush the changes to the schedule to disk without holding on to
+     /// the scheduler, see [`Journal::detach_sync`]
+     pub fn detach_sync(&mut self) -> Result<Option<File>, StorageError> {
+          match self.journal.as_mut() {
+               Some(journal) => journal.detach_sync(),
+               None => Ok(None),
+          }
+     }
+
+     fn insert(&mut self, message: ScheduledMessage) {
+          self.remove(&message.queue, &message.id);
+
+          let key = (message.at, self.sequence);
+          self.sequence += 1;
+          self.ids.insert((message.queue.clone(), message.id.clone()), key);
+          self.timers.insert(key, message);
+     }
+
+     fn remove(&mut self, queue: &str, id: &str) -> Option<ScheduledMessage> {
+          let key = self.ids.remove(&(queue.to_string(), id.to_string()))?;
+          self.timers.remove(&key)
+     }
+}
+
+/// Journal record scheduling `message`
+fn schedule_record(message: &ScheduledMessage) -> Vec<u8> {
+     let mut encoder = Encoder::new();
+     encoder.put_u8(0);
+     encoder.put(message);
+     encoder.into_bytes()
+}
+
+/// Journal record removing the message `id` scheduled on the queue `queue` from the schedule
+fn remove_record(queue: &str, id: &str) -> Vec<u8> {
+     let mut encoder = Encoder::new();
+     encoder.put_u8(1);
+     encoder.put_str(queue);
+     encoder.put_str(id);
+     encoder.into_bytes()
+}
@@ -0,0 +1,131 @@
+use std::net::SocketAddr;
+use std::time::SystemTime;
+
+use tokio::sync::mpsc::UnboundedSender;
+
+use net::protocol::MTPResponse;
+
+use super::token::TokenClaims;
+
+/// Identifier of a session within the broker
+pub type SessionId = u64;
+
+/// Prefix of the identifier of a client that did not authenticate, followed by its address.
+/// No identity a client authenticates as may start with it.
+pub const ADDRESS_PREFIX: &str = "address:";
+
+/// Checks whether `client` identifies a client that did not authenticate by its address, see
+/// [`Session::client`]
+pub fn is_address(client: &str) -> bool {
+     client.starts_with(ADDRESS_PREFIX)
+}
+
+/// A client connected to the broker.
+///
+/// Every session owns an outbox to which the broker pushes frames (deliveries and notifications)
+/// outside of the request/response cycle. The receiving half is handed to the transport serving
+/// the connection when the session is opened.
+///
+/// # Fields
+///
+/// ~ `id`: The identifier of the session within the broker
+/// ~ `address`: The address of the connected client
+/// ~ `outbox`: The sending half of the channel frames are pushed through
+/// ~ `identity`: The identity the client authenticated as, if it did
+/// ~ `claims`: The claims of the token the client authenticated with, if it did with one
+/// ~ `parked`: Time the session is closed at unless resumed, once its client disconnected
+pub struct Session {
+     id: SessionId,
+     address: SocketAddr,
+     outbox: UnboundedSender<MTPResponse>,
+     identity: Option<String>,
+     claims: Option<TokenClaims>,
+     parked: Option<SystemTime>,
+}
+
+impl Session {
+     /// Creates a new session for a client connected from `address`
+     ///
+     /// # Arguments
+     /// * `id`: The identifier assigned to the session
+     /// * `address`: The address of the connected client
+     /// * `outbox`: The channel on which pushed frames are sent
+     pub fn new(id: SessionId, address: SocketAddr, outbox: UnboundedSender<MTPResponse>) -> Self {
+          Self { id, address, outbox, identity: None, claims: None, parked: None }
+     }
+
+     /// Retrieves the identifier of the session
+     pub fn id(&self) -> SessionId {
+          self.id
+     }
+
+     /// Retrieves the address of the connected client
+     pub fn address(&self) -> SocketAddr {
+          self.address
+     }
+
+     /// Retrieves the identity the client authenticated as, if it did
+     pub fn identity(&self) -> Option<&str> {
+          self.identity.as_deref()
+     }
+
+     /// Retrieves the claims of the token the client authenticated with, if it did with one
+     pub fn claims(&self) -> Option<&TokenClaims> {
+          self.claims.as_ref()
+     }
+
+     /// Records the identity the client authenticated as
+     pub fn authenticate(&mut self, identity: String) {
+          self.identity = Some(identity);
+          self.claims = None;
+     }
+
+     /// Records the token the client authenticated with, along with the identity it vouches for
+     pub fn authenticate_with(&mut self, claims: TokenClaims) {
+          self.identity = Some(claims.identity().to_string());
+          self.claims = Some(claims);
+     }
+
+     /// Forgets how the client authenticated, once its credentials are no longer valid
+     pub fn deauthenticate(&mut self) {
+          self.identity = None;
+          self.claims = None;
+     }
+
+     /// Retrieves the time the session is closed at unless resumed, if its client disconnected
+     pub fn parked(&self) -> Option<SystemTime> {
+          self.parked
+     }
+
+     /// Keeps the session until `until` once its client disconnected, for the client to resume it
+     /// from another connection
+     pub fn park(&mut self, until: SystemTime) {
+          self.parked = Some(until);
+     }
+
+     /// Takes over the authentication of the session `parked`, resumed by the client of this one
+     pub fn resume(&mut self, parked: Session) {
+          self.identity = parked.identity;
+          self.claims = parked.claims;
+     }
+
+     /// Identifier of the client as seen by the queues it consumes from and is admitted to.
+     ///
+     /// # Returns
+     /// The identity the client authenticated as, or its address behind [`ADDRESS_PREFIX`] if it
+     /// did not
+     pub fn client(&self) -> String {
+          match &self.identity {
+               Some(identity) => identity.clone(),
+               None => format!("{}{}", ADDRESS_PREFIX, self.address),
+          }
+     }
+
+     /// Pushes a frame to the client
+     ///
+     /// # Returns
+     /// `false` if the transport serving the session has gone away
+     pub fn push(&self, frame: MTPResponse) -> bool {
+          self.outbox.send(frame).is_ok()
+     }
+}
@@ -0,0 +1,508 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use net::protocol::error::{Error, ProtocolError};
+use net::protocol::interface::{MTPAuth, MTPHeaderUnit, QueueRoles};
+use security_gateway::provider::{credentials, unrecognised, AuthProvider, Identity, Peer};
+
+use crate::storage::codec::{Decoder, Encoder};
+use crate::storage::error::StorageError;
+use crate::storage::journal::Journal;
+
+/// Name of the journal the revoked tokens are persisted to within the data directory
+const JOURNAL: &str = "revocations.journal";
+
+/// What a token vouches for, whether minted by the broker or by an external issuer whose
+/// claims are mapped to these, see [`super::jwt::JwtVerifier`].
+///
+/// A token restricted to some queues only permits its holder to use those, along with the
+/// queues it grants a role on. A token restricted to no queue permits every queue the access of
+/// which admits the client.
+///
+/// # Fields
+///
+/// ~ `id`: Identifier of the token, assigned when it is minted and used to revoke it
+/// ~ `key`: Id of the key the token was signed with, if it was minted by the broker
+/// ~ `identity`: Identity the holder of the token authenticates as
+/// ~ `roles`: Roles the token grants its holder, by queue
+/// ~ `queues`: Queues the token restricts its holder to, if any
+/// ~ `expires`: Time the token expires at, if it does
+pub struct TokenClaims {
+     id: String,
+     key: Option<String>,
+     identity: String,
+     roles: BTreeMap<String, QueueRoles>,
+     queues: BTreeSet<String>,
+     expires: Option<SystemTime>,
+}
+
+impl TokenClaims {
+     /// Creates the claims of a token authenticating its holder as `identity` until `expires`,
+     /// granting no role and restricting it to no queue
+     pub fn new(identity: String, expires: SystemTime) -> Self {
+          Self {
+               id: String::new(),
+               key: None,
+               identity,
+               roles: BTreeMap::new(),
+               queues: BTreeSet::new(),
+               expires: Some(expires),
+          }
+     }
+
+     /// Grants the holder of the token `role` on the queue `queue`
+     pub fn with_role(mut self, queue: String, role: QueueRoles) -> Self {
+          self.roles.insert(queue, role);
+          self
+     }
+
+     /// Sets the identifier of a token not minted by the broker, as assigned by its issuer
+     pub fn with_id(mut self, id: String) -> Self {
+          self.id = id;
+          self
+     }
+
+     /// Restricts the holder of the token to the queue `queue`, along with the other queues it
+     /// is restricted to
+     pub fn with_queue(mut self, queue: String) -> Self {
+          self.queues.insert(queue);
+          self
+     }
+
+     /// Retrieves the identifier of the token, empty until it is minted
+     pub fn id(&self) -> &str {
+          &self.id
+     }
+
+     /// Retrieves the identity the holder of the token authenticates as
+     pub fn identity(&self) -> &str {
+          &self.identity
+     }
+
+     /// Retrieves the role the token grants its holder on the queue `queue`, if any
+     pub fn role(&self, queue: &str) -> Option<&QueueRoles> {
+          self.roles.get(queue)
+     }
+
+     /// Retrieves the time the token expires at, if it does
+     pub fn expires(&self) -> Option<SystemTime> {
+          self.expires
+     }
+
+     /// Checks whether the token permits its holder to use the queue `queue`
+     pub fn allows(&self, queue: &str) -> bool {
+          self.queues.is_empty() || self.queues.contains(queue) || self.roles.contains_key(queue)
+     }
+
+     /// Encodes the claims as signed within a token
+     fn encode(&self) -> Vec<u8> {
+          let mut encoder = Encoder::new();
+          encoder.put_str(&self.id);
+          encoder.put_str(&self.identity);
+          encoder.put_u32(self.roles.len() as u32);
+          for (queue, role) in &self.roles {
+               encoder.put_str(queue);
+               encoder.put(role);
+          }
+          encoder.put_u32(self.queues.len() as u32);
+          for queue in &self.queues {
+               encoder.put_str(queue);
+          }
+          encoder.put_option(self.expires, Encoder::put_time);
+          encoder.into_bytes()
+     }
+
+     /// Decodes the claims signed within a token
+     fn decode(bytes: &[u8]) -> Result<Self, StorageError> {
+          let mut decoder = Decoder::new(bytes);
+          let id = decoder.get_str()?;
+          let identity = decoder.get_str()?;
+          let mut roles = BTreeMap::new();
+          for _ in 0..decoder.get_u32()? {
+               roles.insert(decoder.get_str()?, decoder.get()?);
+          }
+          let mut queues = BTreeSet::new();
+          for _ in 0..decoder.get_u32()? {
+               queues.insert(decoder.get_str()?);
+          }
+          let expires = decoder.get_option(Decoder::get_time)?;
+
+          Ok(Self { id, key: None, identity, roles, queues, expires })
+     }
+}
+
+/// Conversion of an [`Identity`] vouched for by an authentication provider into the claims of
+/// a token granting the same roles and restricted to the same queues until the identity stops
+/// being vouched for, revoked along with the token that vouched for it if any
+impl From<Identity> for TokenClaims {
+     fn from(identity: Identity) -> Self {
+          Self {
+               id: identity.token().unwrap_or_default().to_string(),
+               key: identity.key().map(str::to_string),
+               identity: identity.name().to_string(),
+               roles: identity.roles().clone(),
+               queues: identity.queues().clone(),
+               expires: identity.expires(),
+          }
+     }
+}
+
+/// Conversion of the claims of a verified token into the [`Identity`] they vouch for
+impl From<TokenClaims> for Identity {
+     fn from(claims: TokenClaims) -> Self {
+          let mut identity = Identity::new(claims.identity);
+          for (queue, role) in claims.roles {
+               identity = identity.with_role(queue, role);
+          }
+          for queue in claims.queues {
+               identity = identity.with_queue(queue);
+          }
+          if let Some(expires) = claims.expires {
+               identity = identity.with_expiry(expires);
+          }
+          if !claims.id.is_empty() {
+               identity = identity.with_token(claims.id, claims.key);
+          }
+          identity
+     }
+}
+
+/// Clone implementation for [TokenClaims]
+impl Clone for TokenClaims {
+     fn clone(&self) -> Self {
+          Self {
+               id: self.id.clone(),
+               key: self.key.clone(),
+               identity: self.identity.clone(),
+               roles: self.roles.clone(),
+               queues: self.queues.clone(),
+               expires: self.expires,
+          }
+     }
+}
+
+/// Service minting the tokens clients authenticate with through `MTPAuth::LocalToken`, and
+/// verifying them.
+///
+/// A token reads `<key>.<claims>.<signature>`: the id of the key it was signed with, the
+/// [`TokenClaims`] it vouches for in hexadecimal, and the HMAC-SHA256 of both by that key in
+/// hexadecimal. New tokens are signed with the key installed last, while tokens signed with any
+/// installed key are verified, so that keys are rotated without invalidating the tokens in use
+/// until the former key is retired.
+///
+/// Revoked tokens are remembered until they expire. When persistence is enabled they are
+/// recorded in a journal, rewritten as a whole on every change since tokens are seldom revoked.
+/// Keys are never persisted: they are installed anew by the operator on every start.
+///
+/// # Fields
+///
+/// ~ `keys`: Secrets tokens are verified with, by key id
+/// ~ `signing`: Id of the key new tokens are signed with, if any is installed
+/// ~ `revoked`: Time every revoked token expires at if it does, by token id
+/// ~ `minted`: Number of tokens minted since the service started
+/// ~ `journal`: Journal the revoked tokens are persisted to, if any
+pub struct TokenService {
+     keys: HashMap<String, Vec<u8>>,
+     signing: Option<String>,
+     revoked: HashMap<String, Option<SystemTime>>,
+     minted: u64,
+     journal: Option<Journal>,
+}
+
+impl TokenService {
+     /// Creates a service without keys whose revoked tokens are not persisted
+     pub fn in_memory() -> Self {
+          Self {
+               keys: HashMap::new(),
+               signing: None,
+               revoked: HashMap::new(),
+               minted: 0,
+               journal: None,
+          }
+     }
+
+     /// Opens the revoked tokens persisted in `dir`, restoring those that had not expired at `now`
+     pub fn open(dir: &Path, now: SystemTime) -> Result<Self, StorageError> {
+          let (journal, records) = Journal::open(&dir.join(JOURNAL))?;
+          let mut service = Self::in_memory();
+
+          for record in records {
+               let mut decoder = Decoder::new(&record);
+               service.revoked.insert(decoder.get_str()?, decoder.get_option(Decoder::get_time)?);
+          }
+
+          service.journal = Some(journal);
+          service.prune(now)?;
+
+          Ok(service)
+     }
+
+     /// Installs the key `id`, signing new tokens with it from now on. Tokens signed with the
+     /// keys installed before remain valid until those are retired.
+     pub fn rotate(&mut self, id: String, secret: Vec<u8>) {
+          self.keys.insert(id.clone(), secret);
+          self.signing = Some(id);
+     }
+
+     /// Retires the key `id`, invalidating the tokens signed with it. No token is minted until
+     /// another key is installed if it signed new tokens.
+     ///
+     /// # Returns
+     /// `false` if no key `id` was installed
+     pub fn retire(&mut self, id: &str) -> bool {
+          if self.signing.as_deref() == Some(id) {
+               self.signing = None;
+          }
+          self.keys.remove(id).is_some()
+     }
+
+     /// Mints a token vouching for `claims`, assigning it a fresh id
+     ///
+     /// # Errors
+     /// [`ProtocolError::PreconditionFailed110`] if no key is installed to sign it with
+     pub fn mint(&mut self, mut claims: TokenClaims, now: SystemTime) -> Result<String, ProtocolError> {
+          let key = self.signing.clone()
+               .ok_or_else(|| ProtocolError::PreconditionFailed110(Error::new("No key is installed to sign tokens with".to_string())))?;
+
+          self.minted += 1;
+          let issued = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+          claims.id = format!("{}-{:x}-{:x}", key, issued, self.minted);
+          claims.key = Some(key.clone());
+
+          let body = format!("{}.{}", key, hex(&claims.encode()));
+          let signature = hex(&self.sign(&key, body.as_bytes()).finalize().into_bytes());
+          Ok(format!("{}.{}", body, signature))
+     }
+
+     /// Verifies `token`, checking its signature, that it did not expire at `now` and that it
+     /// was not revoked
+     ///
+     /// # Returns
+     /// The claims the token vouches for
+     ///
+     /// # Errors
+     /// [`ProtocolError::Unauthorized101`] if the token is malformed, was signed with a key that
+     /// is not installed, does not match its signature, expired or was revoked
+     pub fn verify(&self, token: &str, now: SystemTime) -> Result<TokenClaims, ProtocolError> {
+          let claims = self.decode(token)?;
+          self.check(&claims, now)?;
+          Ok(claims)
+     }
+
+     /// Checks that the token vouching for `claims` neither expired at `now` nor was revoked,
+     /// and that the key it was signed with was not retired since if it was minted by the broker
+     ///
+     /// # Errors
+     /// [`ProtocolError::Unauthorized101`] if the token expired, was revoked or its key retired
+     pub fn check(&self, claims: &TokenClaims, now: SystemTime) -> Result<(), ProtocolError> {
+          if let Some(key) = claims.key.as_ref().filter(|key| !self.keys.contains_key(*key)) {
+               return Err(ProtocolError::Unauthorized101(Error::new(format!("Token {} was signed with retired key {}", claims.id, key))));
+          }
+          if claims.expires.is_some_and(|expires| expires <= now) {
+               return Err(ProtocolError::Unauthorized101(Error::new(format!("Token {} expired", claims.id))));
+          }
+          if self.revoked.contains_key(&claims.id) {
+               return Err(ProtocolError::Unauthorized101(Error::new(format!("Token {} was revoked", claims.id))));
+          }
+          Ok(())
+     }
+
+     /// Revokes `token`, which is rejected from now on until it expires
+     ///
+     /// # Returns
+     /// The claims the token vouched for
+     ///
+     /// # Errors
+     /// - [`ProtocolError::Unauthorized101`] if the token is malformed, was signed with a key
+     ///   that is not installed or does not match its signature
+     /// - The [`StorageError`] the revocation could not be persisted with, as a [`ProtocolError`]
+     pub fn revoke(&mut self, token: &str) -> Result<TokenClaims, ProtocolError> {
+          let claims = self.decode(token)?;
+          self.revoked.insert(claims.id.clone(), claims.expires);
+          self.persist()?;
+          Ok(claims)
+     }
+
+     /// Forgets the revoked tokens that expired at `now`, which are rejected as expired anyway
+     pub fn prune(&mut self, now: SystemTime) -> Result<(), StorageError> {
+          let count = self.revoked.len();
+          self.revoked.retain(|_, expires| expires.is_none_or(|expires| expires > now));
+          if self.revoked.len() == count {
+               return Ok(());
+          }
+          self.persist()
+     }
+
+     /// Decodes the claims `token` vouches for, checking its signature
+     fn decode(&self, token: &str) -> Result<TokenClaims, ProtocolError> {
+          let malformed = || ProtocolError::Unauthorized101(Error::new("Malformed token".to_string()));
+
+          let (body, signature) = token.rsplit_once('.').ok_or_else(malformed)?;
+          let (key, claims) = body.split_once('.').ok_or_else(malformed)?;
+          if !self.keys.contains_key(key) {
+               return Err(ProtocolError::Unauthorized101(Error::new(format!("Token was signed with unknown key {}", key))));
+          }
+
+          let signature = unhex(signature).ok_or_else(malformed)?;
+          self.sign(key, body.as_bytes()).verify_slice(&signature)
+               .map_err(|_| ProtocolError::Unauthorized101(Error::new("Token does not match its signature".to_string())))?;
+
+          let mut claims = unhex(claims)
+               .and_then(|bytes| TokenClaims::decode(&bytes).ok())
+               .ok_or_else(malformed)?;
+          claims.key = Some(key.to_string());
+          Ok(claims)
+     }
+
+     /// Computes the HMAC of `body` by the installed key `key`
+     fn sign(&self, key: &str, body: &[u8]) -> Hmac<Sha256> {
+          let secret = self.keys.get(key).map(Vec::as_slice).unwrap_or_default();
+          let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+          mac.update(body);
+          mac
+     }
+
+     /// Rewrites the journal with the current revoked tokens
+     fn persist(&mut self) -> Result<(), StorageError> {
+          let journal = match self.journal.as_mut() {
+               Some(journal) => journal,
+               None => return Ok(()),
+          };
+
+          let records: Vec<Vec<u8>> = self.revoked.iter().map(|(id, expires)| {
+               let mut encoder = Encoder::new();
+               encoder.put_str(id);
+               encoder.put_option(*expires, Encoder::put_time);
+               encoder.into_bytes()
+          }).collect();
+          journal.rewrite(&records)
+     }
+}
+
+/// Authenticates the holders of the tokens sent through `MTPAuth::LocalToken`, as verified by
+/// [`TokenService::verify`]
+impl AuthProvider for TokenService {
+     fn authenticate(&self, units: &[MTPHeaderUnit], _peer: &Peer) -> Result<Identity, ProtocolError> {
+          let token = credentials(units, |method| matches!(method, MTPAuth::LocalToken))
+               .ok_or_else(|| unrecognised("No local token"))?;
+          self.verify(token, SystemTime::now()).map(Identity::from)
+     }
+}
+
+/// Default implementation for [TokenService]
+impl Default for TokenService {
+     fn default() -> Self {
+          Self::in_memory()
+     }
+}
+
+/// Encodes `bytes` in lowercase hexadecimal
+fn hex(bytes: &[u8]) -> String {
+     bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Decodes the hexadecimal `text`, if it is valid
+fn unhex(text: &str) -> Option<Vec<u8>> {
+     if !text.len().is_multiple_of(2) || !text.is_ascii() {
+          return None;
+     }
+     (0..text.len()).step_by(2)
+          .map(|at| u8::from_str_radix(&text[at..at + 2], 16).ok())
+          .collect()
+}
+
+#[cfg(test)]
+mod tests {
+     use std::fs;
+     use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+     use net::protocol::interface::QueueRoles;
+
+     use super::{TokenClaims, TokenService};
+
+     fn at(secs: u64) -> SystemTime {
+          UNIX_EPOCH + Duration::from_secs(secs)
+     }
+
+     fn service() -> TokenService {
+          let mut service = TokenService::in_memory();
+          service.rotate("k1".to_string(), b"first secret".to_vec());
+          service
+     }
+
+     #[test]
+     fn minted_tokens_verify_until_they_expire() {
+          let mut service = service();
+          let claims = TokenClaims::new("alice".to_string(), at(100))
+               .with_role("orders".to_string(), QueueRoles::Manager)
+               .with_queue("billing".to_string());
+          let token = service.mint(claims, at(0)).ok().unwrap();
+
+          let verified = service.verify(&token, at(50)).ok().unwrap();
+          assert_eq!(verified.identity(), "alice");
+          assert!(verified.id().starts_with("k1-"));
+          assert!(matches!(verified.role("orders"), Some(QueueRoles::Manager)));
+          assert!(verified.allows("orders") && verified.allows("billing") && !verified.allows("audit"));
+          assert!(service.verify(&token, at(100)).is_err());
+     }
+
+     #[test]
+     fn altered_tokens_are_rejected() {
+          let mut service = service();
+          let token = service.mint(TokenClaims::new("alice".to_string(), at(100)), at(0)).ok().unwrap();
+          let forged = service.mint(TokenClaims::new("mallory".to_string(), at(100)), at(0)).ok().unwrap();
+
+          // claims of one token under the signature of another
+          let claims = token.split('.').nth(1).unwrap();
+          let signature = forged.rsplit('.').next().unwrap();
+          assert!(service.verify(&format!("k1.{}.{}", claims, signature), at(0)).is_err());
+          assert!(service.verify(&format!("k2.{}", token.split_once('.').unwrap().1), at(0)).is_err());
+          assert!(service.verify(&token[..token.len() - 1], at(0)).is_err());
+          assert!(service.verify("k1.zz.zz", at(0)).is_err());
+          assert!(service.verify("", at(0)).is_err());
+     }
+
+     #[test]
+     fn rotated_keys_verify_until_retired() {
+          let mut service = service();
+          let former = service.mint(TokenClaims::new("alice".to_string(), at(100)), at(0)).ok().unwrap();
+          service.rotate("k2".to_string(), b"second secret".to_vec());
+          let current = service.mint(TokenClaims::new("alice".to_string(), at(100)), at(0)).ok().unwrap();
+
+          assert!(current.starts_with("k2."));
+          assert!(service.verify(&former, at(0)).is_ok());
+          assert!(service.retire("k1"));
+          assert!(service.verify(&former, at(0)).is_err());
+          assert!(service.verify(&current, at(0)).is_ok());
+          assert!(service.retire("k2"));
+          assert!(service.mint(TokenClaims::new("alice".to_string(), at(100)), at(0)).is_err());
+     }
+
+     #[test]
+     fn revocations_survive_reopen_until_tokens_expire() {
+          let dir = std::env::temp_dir().join(format!("token-revocations-{}", std::process::id()));
+          let _ = fs::remove_dir_all(&dir);
+          fs::create_dir_all(&dir).unwrap();
+
+          let mut service = TokenService::open(&dir, at(0)).ok().unwrap();
+          service.rotate("k1".to_string(), b"first secret".to_vec());
+          let token = service.mint(TokenClaims::new("alice".to_string(), at(100)), at(0)).ok().unwrap();
+          let other = service.mint(TokenClaims::new("bob".to_string(), at(100)), at(0)).ok().unwrap();
+          service.revoke(&token).ok().unwrap();
+          assert!(service.verify(&token, at(0)).is_err());
+
+          let mut reopened = TokenService::open(&dir, at(0)).ok().unwrap();
+          reopened.rotate("k1".to_string(), b"first secret".to_vec());
+          assert!(reopened.verify(&token, at(0)).is_err());
+          assert!(reopened.verify(&other, at(0)).is_ok());
+
+          let expired = TokenService::open(&dir, at(100)).ok().unwrap();
+          assert!(expired.revoked.is_empty());
+
+          fs::remove_dir_all(&dir).unwrap();
+     }
+}
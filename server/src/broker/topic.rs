@@ -0,0 +1,175 @@
+use std::collections::{BTreeSet, HashMap};
+
+/// Wildcard matching exactly one level of a topic
+pub const SINGLE_LEVEL: &str = "*";
+
+/// Wildcard matching any number of levels of a topic, including none
+pub const MULTI_LEVEL: &str = "#";
+
+/// Splits a topic or a pattern into its levels. Levels are separated by `.` or `/`, so that
+/// `orders.eu.created` and `orders/eu/created` name the same topic.
+pub fn levels(topic: &str) -> Vec<&str> {
+     topic.split(['.', '/']).collect()
+}
+
+/// Checks whether `name` is a pattern, that is whether one of its levels is a wildcard
+pub fn is_pattern(name: &str) -> bool {
+     levels(name).iter().any(|level| *level == SINGLE_LEVEL || *level == MULTI_LEVEL)
+}
+
+/// Node of a [`TopicTrie`]
+///
+/// # Fields
+///
+/// ~ `children`: Nodes of the next level, by level. Wildcards are stored as levels of their own.
+/// ~ `names`: Names ending at this node
+struct Node {
+     children: HashMap<String, Node>,
+     names: BTreeSet<String>,
+}
+
+impl Node {
+     /// Creates a node without children
+     fn new() -> Self {
+          Self {
+               children: HashMap::new(),
+               names: BTreeSet::new(),
+          }
+     }
+
+     /// Collects the names of this node and its descendants matching `levels` into `matched`
+     fn collect(&self, levels: &[&str], matched: &mut BTreeSet<String>) {
+          if let Some(multi) = self.children.get(MULTI_LEVEL) {
+               // the wildcard takes none, some or all of the remaining levels
+               for skipped in 0..=levels.len() {
+                    multi.collect(&levels[skipped..], matched);
+               }
+          }
+
+          let Some((level, rest)) = levels.split_first() else {
+               matched.extend(self.names.iter().cloned());
+               return;
+          };
+
+          if let Some(child) = self.children.get(*level) {
+               child.collect(rest, matched);
+          }
+          if let Some(single) = self.children.get(SINGLE_LEVEL) {
+               single.collect(rest, matched);
+          }
+     }
+
+     /// Removes `name`, spelled by `levels` from this node, pruning the descendants left empty
+     ///
+     /// # Returns
+     /// Whether this node is left without names and children
+     fn remove(&mut self, levels: &[&str], name: &str) -> bool {
+          match levels.split_first() {
+               Some((level, rest)) => {
+                    if self.children.get_mut(*level).is_some_and(|child| child.remove(rest, name)) {
+                         self.children.remove(*level);
+                    }
+               },
+               None => {
+                    self.names.remove(name);
+               },
+          }
+          self.names.is_empty() && self.children.is_empty()
+     }
+}
+
+/// Index of the queue names of the broker by topic level, resolving the queues a message
+/// published to a topic is delivered to.
+///
+/// A name without wildcards only matches the topic it spells. In a pattern, [`SINGLE_LEVEL`]
+/// matches exactly one level and [`MULTI_LEVEL`] any number of levels, so that
+/// `orders.*.created` matches `orders.eu.created` and `metrics.#` matches `metrics` as well as
+/// `metrics.cpu.user`. Matching walks the levels of the topic once per wildcard branch, without
+/// visiting the names that cannot match.
+///
+/// # Fields
+///
+/// ~ `root`: Node of the first level
+pub struct TopicTrie {
+     root: Node,
+}
+
+impl TopicTrie {
+     /// Creates an empty trie
+     pub fn new() -> Self {
+          Self { root: Node::new() }
+     }
+
+     /// Adds the queue `name` to the trie
+     pub fn insert(&mut self, name: &str) {
+          let node = levels(name).into_iter().fold(&mut self.root, |node, level| {
+               node.children.entry(level.to_string()).or_insert_with(Node::new)
+          });
+          node.names.insert(name.to_string());
+     }
+
+     /// Removes the queue `name` from the trie
+     pub fn remove(&mut self, name: &str) {
+          self.root.remove(&levels(name), name);
+     }
+
+     /// Retrieves the names matching `topic`, in lexicographic order
+     pub fn matches(&self, topic: &str) -> Vec<String> {
+          let mut matched = BTreeSet::new();
+          self.root.collect(&levels(topic), &mut matched);
+          matched.into_iter().collect()
+     }
+}
+
+/// Default implementation for [TopicTrie]
+impl Default for TopicTrie {
+     fn default() -> Self {
+          Self::new()
+     }
+}
+
+#[cfg(test)]
+mod tests {
+     use super::{is_pattern, TopicTrie};
+
+     fn trie(names: &[&str]) -> TopicTrie {
+          let mut trie = TopicTrie::new();
+          for name in names {
+               trie.insert(name);
+          }
+          trie
+     }
+
+     #[test]
+     fn wildcards_match_one_or_many_levels() {
+          let trie = trie(&["orders.eu.created", "orders.*.created", "orders.#", "metrics.#", "metrics.*", "*"]);
+
+          assert_eq!(trie.matches("orders.eu.created"), vec!["orders.#", "orders.*.created", "orders.eu.created"]);
+          assert_eq!(trie.matches("orders.us.created"), vec!["orders.#", "orders.*.created"]);
+          assert_eq!(trie.matches("orders"), vec!["*", "orders.#"]);
+          assert_eq!(trie.matches("metrics.cpu.user"), vec!["metrics.#"]);
+          assert_eq!(trie.matches("metrics.cpu"), vec!["metrics.#", "metrics.*"]);
+          assert!(trie.matches("billing.eu").is_empty());
+     }
+
+     #[test]
+     fn separators_name_the_same_levels() {
+          let trie = trie(&["orders/eu/created", "orders.*.updated"]);
+
+          assert_eq!(trie.matches("orders.eu.created"), vec!["orders/eu/created"]);
+          assert_eq!(trie.matches("orders/eu.updated"), vec!["orders.*.updated"]);
+          assert!(is_pattern("orders/#"));
+          assert!(!is_pattern("orders/eu#"));
+     }
+
+     #[test]
+     fn removed_names_no_longer_match() {
+          let mut trie = trie(&["orders.eu.created", "orders.#"]);
+
+          trie.remove("orders.eu.created");
+          assert_eq!(trie.matches("orders.eu.created"), vec!["orders.#"]);
+          trie.remove("orders.#");
+          assert!(trie.matches("orders.eu.created").is_empty());
+          assert!(trie.root.children.is_empty());
+     }
+}
@@ -0,0 +1,469 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::path::Path;
+
+use net::protocol::{MTPHeaders, MTPMessage};
+
+use crate::storage::codec::{Decode, Decoder, Encode, Encoder};
+use crate::storage::error::StorageError;
+use crate::storage::journal::Journal;
+
+/// Name of the journal committed transactions are persisted to within the data directory
+const JOURNAL: &str = "transactions.journal";
+
+/// Number of records the journal may hold before it is cleared once no transaction is pending
+const SLACK: usize = 1024;
+
+/// A message published within a transaction, staged until the transaction is committed.
+///
+/// # Fields
+///
+/// ~ `topic`: Topic the message is published to
+/// ~ `id`: Identifier of the message
+/// ~ `publisher`: Client publishing the message
+/// ~ `headers`: Headers the message was published with
+/// ~ `message`: The published message
+pub struct StagedPublish {
+     topic: String,
+     id: String,
+     publisher: String,
+     headers: MTPHeaders,
+     message: MTPMessage,
+}
+
+impl StagedPublish {
+     /// Creates a message staged by `publisher` for publishing to `topic`
+     pub fn new(topic: String, id: String, publisher: String, headers: MTPHeaders, message: MTPMessage) -> Self {
+          Self { topic, id, publisher, headers, message }
+     }
+
+     /// Retrieves the topic the message is published to
+     pub fn topic(&self) -> &str {
+          &self.topic
+     }
+
+     /// Retrieves the identifier of the message
+     pub fn id(&self) -> &str {
+          &self.id
+     }
+
+     /// Retrieves the headers the message was published with
+     pub fn headers(&self) -> &MTPHeaders {
+          &self.headers
+     }
+
+     /// Retrieves the published message
+     pub fn message(&self) -> &MTPMessage {
+          &self.message
+     }
+
+     /// Splits the staged message into its topic, id, publisher, headers and message
+     pub fn into_parts(self) -> (String, String, String, MTPHeaders, MTPMessage) {
+          (self.topic, self.id, self.publisher, self.headers, self.message)
+     }
+}
+
+/// An acknowledgement made within a transaction, staged until the transaction is committed.
+///
+/// # Fields
+///
+/// ~ `queue`: Queue the message was delivered from
+/// ~ `client`: Client the message was delivered to
+/// ~ `consumer`: Consumer the client consumes the queue through, resolved on commit
+/// ~ `offset`: Offset of the message in the queue
+pub struct StagedAck {
+     queue: String,
+     client: String,
+     consumer: String,
+     offset: u64,
+}
+
+impl StagedAck {
+     /// Creates an acknowledgement of the message at `offset` delivered from `queue` to `client`
+     pub fn new(queue: String, client: String, offset: u64) -> Self {
+          Self { queue, client, consumer: String::new(), offset }
+     }
+
+     /// Retrieves the queue the message was delivered from
+     pub fn queue(&self) -> &str {
+          &self.queue
+     }
+
+     /// Retrieves the client the message was delivered to
+     pub fn client(&self) -> &str {
+          &self.client
+     }
+
+     /// Retrieves the consumer the client consumes the queue through
+     pub fn consumer(&self) -> &str {
+          &self.consumer
+     }
+
+     /// Sets the consumer the client consumes the queue through
+     pub fn set_consumer(&mut self, consumer: String) {
+          self.consumer = consumer;
+     }
+
+     /// Retrieves the offset of the message in the queue
+     pub fn offset(&self) -> u64 {
+          self.offset
+     }
+}
+
+/// Operations staged by the open transaction of a session.
+///
+/// # Fields
+///
+/// ~ `publishes`: Messages published within the transaction, in publishing order
+/// ~ `acks`: Acknowledgements made within the transaction, in acknowledging order
+pub struct Transaction {
+     publishes: Vec<StagedPublish>,
+     acks: Vec<StagedAck>,
+}
+
+impl Transaction {
+     /// Creates a transaction without staged operations
+     pub fn new() -> Self {
+          Self {
+               publishes: Vec::new(),
+               acks: Vec::new(),
+          }
+     }
+
+     /// Stages the publishing of a message
+     pub fn publish(&mut self, publish: StagedPublish) {
+          self.publishes.push(publish);
+     }
+
+     /// Stages an acknowledgement
+     pub fn acknowledge(&mut self, ack: StagedAck) {
+          self.acks.push(ack);
+     }
+
+     /// Retrieves the messages published within the transaction
+     pub fn publishes(&self) -> &[StagedPublish] {
+          &self.publishes
+     }
+
+     /// Retrieves the acknowledgements made within the transaction
+     pub fn acks(&self) -> &[StagedAck] {
+          &self.acks
+     }
+
+     /// Retrieves the acknowledgements made within the transaction, to resolve their consumers
+     pub fn acks_mut(&mut self) -> &mut [StagedAck] {
+          &mut self.acks
+     }
+
+     /// Redirects the staged operations on the queue `from` to the queue `to`, once renamed
+     pub fn rename(&mut self, from: &str, to: &str) {
+          for publish in self.publishes.iter_mut().filter(|publish| publish.topic == from) {
+               publish.topic = to.to_string();
+          }
+          for ack in self.acks.iter_mut().filter(|ack| ack.queue == from) {
+               ack.queue = to.to_string();
+          }
+     }
+
+     /// Splits the transaction into its staged messages and acknowledgements
+     pub fn into_parts(self) -> (Vec<StagedPublish>, Vec<StagedAck>) {
+          (self.publishes, self.acks)
+     }
+}
+
+/// Default implementation for [Transaction]
+impl Default for Transaction {
+     fn default() -> Self {
+          Self::new()
+     }
+}
+
+/// Encode implementation for [Transaction]
+impl Encode for Transaction {
+     fn encode(&self, encoder: &mut Encoder) {
+          encoder.put_u32(self.publishes.len() as u32);
+          for publish in self.publishes.iter() {
+               encoder.put_str(&publish.topic);
+               encoder.put_str(&publish.id);
+               encoder.put_str(&publish.publisher);
+               encoder.put(&publish.headers);
+               encoder.put(&publish.message);
+          }
+
+          encoder.put_u32(self.acks.len() as u32);
+          for ack in self.acks.iter() {
+               encoder.put_str(&ack.queue);
+               encoder.put_str(&ack.client);
+               encoder.put_str(&ack.consumer);
+               encoder.put_u64(ack.offset);
+          }
+     }
+}
+
+/// Decode implementation for [Transaction]
+impl Decode for Transaction {
+     fn decode(decoder: &mut Decoder) -> Result<Self, StorageError> {
+          let mut transaction = Self::new();
+
+          for _ in 0..decoder.get_u32()? {
+               transaction.publishes.push(StagedPublish {
+                    topic: decoder.get_str()?,
+                    id: decoder.get_str()?,
+                    publisher: decoder.get_str()?,
+                    headers: decoder.get()?,
+                    message: decoder.get()?,
+               });
+          }
+
+          for _ in 0..decoder.get_u32()? {
+               transaction.acks.push(StagedAck {
+                    queue: decoder.get_str()?,
+                    client: decoder.get_str()?,
+                    consumer: decoder.get_str()?,
+                    offset: decoder.get_u64()?,
+               });
+          }
+
+          Ok(transaction)
+     }
+}
+
+/// Write-ahead journal of committed transactions.
+///
+/// A transaction is recorded and flushed to disk before it is applied to the queues, and marked
+/// as done once applied. Each message it publishes is marked as applied once enqueued. The
+/// transactions recorded but not marked as done when the broker stopped are applied again on
+/// restart, so that a commit interrupted by a crash takes effect as a whole, without publishing
+/// again the messages marked as applied.
+///
+/// # Fields
+///
+/// ~ `journal`: Journal the transactions are persisted to, if any
+/// ~ `next`: Number of the next transaction recorded
+/// ~ `pending`: Number of transactions recorded and not yet done
+/// ~ `records`: Number of records held by the journal
+pub struct TransactionLog {
+     journal: Option<Journal>,
+     next: u64,
+     pending: usize,
+     records: usize,
+}
+
+impl TransactionLog {
+     /// Creates a log that does not persist transactions
+     pub fn in_memory() -> Self {
+          Self {
+               journal: None,
+               next: 0,
+               pending: 0,
+               records: 0,
+          }
+     }
+
+     /// Opens the log persisted in `dir`
+     ///
+     /// # Returns
+     /// The log, along with the transactions recorded but not done, in commit order, without the
+     /// messages marked as applied. They must be applied again before the log is cleared with
+     /// [`TransactionLog::clear`].
+     pub fn open(dir: &Path) -> Result<(Self, Vec<Transaction>), StorageError> {
+          let (journal, records) = Journal::open(&dir.join(JOURNAL))?;
+          let mut committed: Vec<(u64, Transaction)> = Vec::new();
+          let mut steps: HashMap<u64, HashSet<usize>> = HashMap::new();
+          let mut next = 0;
+
+          for record in records.iter() {
+               let mut decoder = Decoder::new(record);
+               match decoder.get_u8()? {
+                    0 => {
+                         let number = decoder.get_u64()?;
+                         next = next.max(number + 1);
+                         committed.push((number, decoder.get()?));
+                    },
+                    1 => {
+                         let number = decoder.get_u64()?;
+                         committed.retain(|(committed, _)| *committed != number);
+                         steps.remove(&number);
+                    },
+                    2 => {
+                         let number = decoder.get_u64()?;
+                         steps.entry(number).or_default().insert(decoder.get_u32()? as usize);
+                    },
+                    tag => return Err(StorageError::Corrupted { message: format!("Unknown transaction record {}", tag) }),
+               }
+          }
+
+          let log = Self {
+               journal: Some(journal),
+               next,
+               pending: committed.len(),
+               records: records.len(),
+          };
+
+          let interrupted = committed.into_iter().map(|(number, mut transaction)| {
+               if let Some(applied) = steps.get(&number) {
+                    let mut index = 0;
+                    transaction.publishes.retain(|_| {
+                         index += 1;
+                         !applied.contains(&(index - 1))
+                    });
+               }
+               transaction
+          }).collect();
+
+          Ok((log, interrupted))
+     }
+
+     /// Records a committed transaction and flushes it to disk, before it is applied
+     ///
+     /// # Returns
+     /// The number of the transaction, with which it is marked as done
+     pub fn commit(&mut self, transaction: &Transaction) -> Result<u64, StorageError> {
+          let number = self.next;
+
+          if let Some(journal) = self.journal.as_mut() {
+               let mut encoder = Encoder::new();
+               encoder.put_u8(0);
+               encoder.put_u64(number);
+               encoder.put(transaction);
+               journal.append(&encoder.into_bytes())?;
+               journal.sync()?;
+               self.records += 1;
+          }
+          self.next += 1;
+          self.pending += 1;
+
+          Ok(number)
+     }
+
+     /// Marks the message published at `index` within the transaction `number` as applied, so
+     /// that it is not published again if the transaction is applied again on restart
+     pub fn step(&mut self, number: u64, index: usize) -> Result<(), StorageError> {
+          if let Some(journal) = self.journal.as_mut() {
+               let mut encoder = Encoder::new();
+               encoder.put_u8(2);
+               encoder.put_u64(number);
+               encoder.put_u32(index as u32);
+               journal.append(&encoder.into_bytes())?;
+               self.records += 1;
+          }
+          Ok(())
+     }
+
+     /// Marks the transaction `number` as applied
+     pub fn done(&mut self, number: u64) -> Result<(), StorageError> {
+          self.pending = self.pending.saturating_sub(1);
+
+          if let Some(journal) = self.journal.as_mut() {
+               let mut encoder = Encoder::new();
+               encoder.put_u8(1);
+               encoder.put_u64(number);
+               journal.append(&encoder.into_bytes())?;
+               self.records += 1;
+          }
+          Ok(())
+     }
+
+     /// Removes every record from the journal, once the transactions it holds are applied
+     pub fn clear(&mut self) -> Result<(), StorageError> {
+          if let Some(journal) = self.journal.as_mut() {
+               journal.rewrite(&[])?;
+          }
+          self.pending = 0;
+          self.records = 0;
+          Ok(())
+     }
+
+     /// Checks whether records were appended since the journal was last flushed to disk
+     pub fn is_dirty(&self) -> bool {
+          self.journal.as_ref().is_some_and(Journal::is_dirty)
+     }
+
+     /// Flushes the records appended to the journal to disk. The journal is cleared instead once
+     /// it holds too many records and no transaction is pending, so it must be flushed after the
+     /// changes made by the transactions it holds.
+     pub fn sync(&mut self) -> Result<(), StorageError> {
+          if self.pending == 0 && self.records > SLACK {
+               return self.clear();
+          }
+
+          match self.journal.as_mut() {
+               Some(journal) => journal.sync(),
+               None => Ok(()),
+          }
+     }
+
+     /// Hands out a handle to flush the records appended to the journal to disk without
+     /// holding on to the log, see [`Journal::detach_sync`]. The journal is cleared instead as
+     /// [`TransactionLog::sync`] clears it.
+     pub fn detach_sync(&mut self) -> Result<Option<File>, StorageError> {
+          if self.pending == 0 && self.records > SLACK {
+               self.clear()?;
+               return Ok(None);
+          }
+
+          match self.journal.as_mut() {
+               Some(journal) => journal.detach_sync(),
+               None => Ok(None),
+          }
+     }
+}
+
+#[cfg(test)]
+mod tests {
+     use std::fs;
+     use std::path::PathBuf;
+
+     use net::protocol::{MTPHeaders, MTPMessage, MTPStorage};
+     use net::protocol::interface::{ContentType, MessageCategory, MessagePriority, MessagePublish};
+
+     use super::{StagedAck, StagedPublish, Transaction, TransactionLog};
+
+     fn scratch(name: &str) -> PathBuf {
+          let dir = std::env::temp_dir().join(format!("transaction-{}-{}", name, std::process::id()));
+          let _ = fs::remove_dir_all(&dir);
+          fs::create_dir_all(&dir).unwrap();
+          dir
+     }
+
+     fn publish(id: &str) -> StagedPublish {
+          let message = MTPMessage::new(ContentType::JSON, MessagePriority::Low, MessageCategory::EVENT, MessagePublish::ALL, id.to_string());
+          StagedPublish::new("orders".to_string(), id.to_string(), "publisher".to_string(), MTPHeaders::new(Vec::new(), MTPStorage::new(Vec::new()), None), message)
+     }
+
+     fn ids(transaction: &Transaction) -> Vec<&str> {
+          transaction.publishes().iter().map(StagedPublish::id).collect()
+     }
+
+     #[test]
+     fn interrupted_transactions_skip_applied_messages() {
+          let dir = scratch("steps");
+          let (mut log, interrupted) = TransactionLog::open(&dir).ok().unwrap();
+          assert!(interrupted.is_empty());
+
+          let mut first = Transaction::new();
+          for id in ["a", "b", "c"] {
+               first.publish(publish(id));
+          }
+          first.acknowledge(StagedAck::new("orders".to_string(), "consumer".to_string(), 4));
+          let mut second = Transaction::new();
+          second.publish(publish("d"));
+
+          let number = log.commit(&first).ok().unwrap();
+          log.step(number, 0).ok().unwrap();
+          log.step(number, 2).ok().unwrap();
+          let done = log.commit(&second).ok().unwrap();
+          log.step(done, 0).ok().unwrap();
+          log.done(done).ok().unwrap();
+          log.sync().ok().unwrap();
+          drop(log);
+
+          // only the message not marked as applied is published again, along with the acknowledgement
+          let (_, interrupted) = TransactionLog::open(&dir).ok().unwrap();
+          assert_eq!(interrupted.len(), 1);
+          assert_eq!(ids(&interrupted[0]), vec!["b"]);
+          assert_eq!(interrupted[0].acks()[0].offset(), 4);
+
+          fs::remove_dir_all(&dir).unwrap();
+     }
+}
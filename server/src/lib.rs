@@ -0,0 +1,89 @@
+/// Module containing the message broker of the `excal-mq` system.
+///
+/// The broker owns every queue hosted by the server along with the sessions of the clients
+/// connected to it. It interprets the payloads of the message transfer protocol defined in
+/// [`net::protocol`] and answers each of them with an [`net::protocol::MTPResponse`].
+///
+/// # Features
+///
+/// - **Queues**: Stores published messages and tracks the position and the unacknowledged
+///   deliveries of every consumer of a queue.
+/// - **Provisioning**: Creates queues explicitly from `QueueCreation` requests, bounding their
+///   length and size, applying their overflow policy and dead-lettering messages delivered
+///   too many times.
+/// - **Topics**: Delivers a message published to a hierarchical topic to the queue of that name
+///   and to every queue named after a wildcard pattern matching it, resolved through a trie.
+/// - **Consumer groups**: Spreads the messages of a queue among the members of a group, round-robin
+///   or to the least loaded member, rebalancing unacknowledged messages as members leave.
+/// - **Partition keys**: Delivers the messages of a partition key to a single member of a group
+///   in publishing order, holding them back from other members while one awaits acknowledgement.
+/// - **Filters**: Delivers to a consumer only the messages whose metadata and storage cells
+///   match the filter expression it subscribed with.
+/// - **Local tokens**: Mints HMAC-signed tokens vouching for an identity, its roles, the queues
+///   it may use and an expiry, verified on the `LocalToken` authentication of a session, with
+///   key rotation and revocation.
+/// - **Basic authentication**: Authenticates users by username and password against a store of
+///   salted Argon2 hashes loaded from a file, locking users out after repeated failures. The
+///   administrators of the broker manage its users through manager actions.
+/// - **JWT authentication**: Accepts the JWTs of an external identity provider, verified against
+///   the keys of a local JWKS file read again on change, mapping their claims to identities and
+///   roles on queues.
+/// - **Authentication providers**: Checks credentials with a pluggable provider of the
+///   `security-gateway` crate, such as static tokens, a file of users or an OAuth 2.0
+///   introspection endpoint chained together with the built-in mechanisms, which are
+///   providers of the same kind and check credentials on their own by default.
+/// - **Session resumption**: Issues authenticated clients an opaque cookie resuming their
+///   session from another connection within a grace window, along with its subscriptions,
+///   consumer group memberships and the messages it had in flight.
+/// - **Audit log**: Records every authentication attempt, creation and deletion of a queue,
+///   temporary queues included, change to the access or the roles of a queue and cancellation
+///   of a scheduled message in an append-only file chained by HMAC under a key kept outside
+///   of it, verified by the `verify-audit` command of the server. Actions are recorded before
+///   they take effect.
+/// - **Access control**: Restricts private queues to their moderator and admitted clients and
+///   protected queues to authenticated clients, on subscribe, pull and publish.
+/// - **Roles**: Checks every request against the role of its client on the queue, such as
+///   producers that may not consume and consumers that may not publish.
+/// - **Join requests**: Holds the subscriptions of clients not admitted to a private queue until
+///   its moderator authorizes or rejects them, pushing the decision to the requesting client.
+/// - **Disposal**: Evicts a client from a queue on demand of its moderators, optionally banning
+///   it from the queue for a while and closing its sessions.
+/// - **Renaming**: Renames queues at once, keeping their former names as temporary aliases that
+///   redirect requests, and notifies their subscribers of the new names.
+/// - **Temporary queues**: Provisions queues exclusive to a session, deleted when it closes, to
+///   which the responses to its requests are published.
+/// - **Embedded clients**: Connects clients running in the process hosting the broker to a
+///   session of it, making requests to other clients and waiting for their responses.
+/// - **Sessions**: Keeps an outbox per connected client through which messages and
+///   notifications are pushed by the broker.
+/// - **Long polling**: Answers pull requests with batches of messages, holding them open until
+///   messages arrive when the queue has none to deliver.
+/// - **Deduplication**: Recognises messages published again with the same id within the
+///   deduplication window of a queue and answers them without enqueueing them twice.
+/// - **Expiry**: Drops or dead-letters messages whose time-to-live has elapsed, both lazily
+///   on delivery and periodically through a background sweeper.
+/// - **Scheduling**: Holds messages published for delayed delivery until they are due.
+/// - **Transactions**: Stages the messages a session publishes and acknowledges within a
+///   transaction and applies them at once on commit, journaling the commit so that it survives
+///   a crash. A transaction is rolled back when its session closes.
+/// - **Durability**: Stores the messages of durable queues in segmented logs on disk along
+///   with the positions of their consumers, and recovers them when the broker starts. Changes
+///   are flushed to disk according to a [`storage::policy::SyncPolicy`].
+/// - **Retention**: Bounds the disk use of durable queues by deleting their oldest segments,
+///   and compacts changelog queues down to the latest message of each key.
+///
+/// # See Also
+///
+/// - [`net::protocol::interface`] for the request types and header units handled by the broker.
+pub mod broker;
+
+/// Module containing the on-disk persistence of the broker.
+///
+/// This module provides the building blocks the broker persists its state with when it is
+/// configured with a data directory, such as the binary encoding of protocol types,
+/// append-only journals and the segmented logs durable queues are stored in.
+///
+/// # See Also
+///
+/// - [`broker`] for the state persisted through this module.
+pub mod storage;
@@ -1,14 +1,56 @@
+use std::path::Path;
+use std::process::ExitCode;
+
 use tokio::runtime::Runtime;
 
-fn main() {
+use server::broker::audit;
+use server::storage::error::StorageError;
+
+fn main() -> ExitCode {
+
+    let args: Vec<String> = std::env::args().collect();
+    if let [_, command, path, key] = args.as_slice() {
+        if command == "verify-audit" {
+            return verify_audit(Path::new(path), Path::new(key));
+        }
+    }
 
     let rt = Runtime::new().expect("Runtime error { tokio failed to create async runtime... }");
 
     rt.block_on( async {
         run().await;
     });
+
+    ExitCode::SUCCESS
 }
 
 async fn  run(){
 
 }
+
+/// Verifies the audit log at `path` against the key stored in the file at `key`, printing the
+/// number of its entries and the MAC of the last one to keep as a checkpoint
+fn verify_audit(path: &Path, key: &Path) -> ExitCode {
+    let key = match std::fs::read(key) {
+        Ok(key) => key,
+        Err(err) => {
+            eprintln!("Audit key {} could not be read: {}", key.display(), err);
+            return ExitCode::FAILURE;
+        },
+    };
+
+    match audit::verify(path, &key) {
+        Ok((count, head)) => {
+            println!("Audit log {} verified: {} entries, last MAC {}", path.display(), count, head);
+            ExitCode::SUCCESS
+        },
+        Err(StorageError::Corrupted { message }) => {
+            eprintln!("{}", message);
+            ExitCode::FAILURE
+        },
+        Err(StorageError::IoError { source }) => {
+            eprintln!("Audit log {} could not be read: {}", path.display(), source);
+            ExitCode::FAILURE
+        },
+    }
+}
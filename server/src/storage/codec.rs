@@ -0,0 +1,511 @@
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use net::protocol::{MTPHeaders, MTPMessage, MTPStorage, StorageCell};
+use net::protocol::interface::{ContentType, Distribution, MTPHeaderUnit, MessageCategory, MessagePriority, MessagePublish, Overflow, QueueAccess, QueueRoles};
+
+use super::error::StorageError;
+
+/// Buffer persisted values are written to.
+/// Integers are written in big endian, strings and byte strings are prefixed with their length.
+pub struct Encoder {
+     buf: Vec<u8>,
+}
+
+impl Encoder {
+     /// Creates an empty encoder
+     pub fn new() -> Self {
+          Self { buf: Vec::new() }
+     }
+
+     /// Consumes the encoder, returning the encoded bytes
+     pub fn into_bytes(self) -> Vec<u8> {
+          self.buf
+     }
+
+     pub fn put_u8(&mut self, value: u8) {
+          self.buf.push(value);
+     }
+
+     pub fn put_u32(&mut self, value: u32) {
+          self.buf.extend_from_slice(&value.to_be_bytes());
+     }
+
+     pub fn put_u64(&mut self, value: u64) {
+          self.buf.extend_from_slice(&value.to_be_bytes());
+     }
+
+     pub fn put_bytes(&mut self, value: &[u8]) {
+          self.put_u32(value.len() as u32);
+          self.buf.extend_from_slice(value);
+     }
+
+     pub fn put_str(&mut self, value: &str) {
+          self.put_bytes(value.as_bytes());
+     }
+
+     pub fn put_duration(&mut self, value: Duration) {
+          self.put_u64(value.as_nanos() as u64);
+     }
+
+     pub fn put_time(&mut self, value: SystemTime) {
+          self.put_duration(value.duration_since(UNIX_EPOCH).unwrap_or_default());
+     }
+
+     pub fn put_option<T>(&mut self, value: Option<T>, put: impl FnOnce(&mut Self, T)) {
+          match value {
+               Some(value) => {
+                    self.put_u8(1);
+                    put(self, value);
+               },
+               None => self.put_u8(0),
+          }
+     }
+
+     pub fn put<T: Encode + ?Sized>(&mut self, value: &T) {
+          value.encode(self);
+     }
+}
+
+/// Default implementation for [Encoder]
+impl Default for Encoder {
+     fn default() -> Self {
+          Self::new()
+     }
+}
+
+/// Reader over bytes written by an [`Encoder`]
+pub struct Decoder<'a> {
+     buf: &'a [u8],
+     pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+     /// Creates a decoder reading `buf` from the start
+     pub fn new(buf: &'a [u8]) -> Self {
+          Self { buf, pos: 0 }
+     }
+
+     /// Checks whether every byte has been read
+     pub fn is_empty(&self) -> bool {
+          self.pos >= self.buf.len()
+     }
+
+     fn take(&mut self, len: usize) -> Result<&'a [u8], StorageError> {
+          if self.buf.len() - self.pos < len {
+               return Err(StorageError::Corrupted { message: "Unexpected end of record".to_string() });
+          }
+          let bytes = &self.buf[self.pos..self.pos + len];
+          self.pos += len;
+          Ok(bytes)
+     }
+
+     pub fn get_u8(&mut self) -> Result<u8, StorageError> {
+          Ok(self.take(1)?[0])
+     }
+
+     pub fn get_u32(&mut self) -> Result<u32, StorageError> {
+          let mut bytes = [0u8; 4];
+          bytes.copy_from_slice(self.take(4)?);
+          Ok(u32::from_be_bytes(bytes))
+     }
+
+     pub fn get_u64(&mut self) -> Result<u64, StorageError> {
+          let mut bytes = [0u8; 8];
+          bytes.copy_from_slice(self.take(8)?);
+          Ok(u64::from_be_bytes(bytes))
+     }
+
+     pub fn get_bytes(&mut self) -> Result<Vec<u8>, StorageError> {
+          let len = self.get_u32()? as usize;
+          Ok(self.take(len)?.to_vec())
+     }
+
+     pub fn get_str(&mut self) -> Result<String, StorageError> {
+          String::from_utf8(self.get_bytes()?)
+               .map_err(|_| StorageError::Corrupted { message: "Invalid UTF-8 in record".to_string() })
+     }
+
+     pub fn get_duration(&mut self) -> Result<Duration, StorageError> {
+          Ok(Duration::from_nanos(self.get_u64()?))
+     }
+
+     pub fn get_time(&mut self) -> Result<SystemTime, StorageError> {
+          Ok(UNIX_EPOCH + self.get_duration()?)
+     }
+
+     pub fn get_option<T>(&mut self, get: impl FnOnce(&mut Self) -> Result<T, StorageError>) -> Result<Option<T>, StorageError> {
+          match self.get_u8()? {
+               0 => Ok(None),
+               1 => Ok(Some(get(self)?)),
+               tag => Err(corrupted("option", tag)),
+          }
+     }
+
+     pub fn get<T: Decode>(&mut self) -> Result<T, StorageError> {
+          T::decode(self)
+     }
+}
+
+/// Values that can be written by an [`Encoder`]
+pub trait Encode {
+     fn encode(&self, encoder: &mut Encoder);
+}
+
+/// Values that can be read by a [`Decoder`]
+pub trait Decode: Sized {
+     fn decode(decoder: &mut Decoder) -> Result<Self, StorageError>;
+}
+
+/// Builds the error reported for an unknown enum tag
+fn corrupted(kind: &str, tag: u8) -> StorageError {
+     StorageError::Corrupted { message: format!("Unknown {} tag {}", kind, tag) }
+}
+
+impl Encode for MTPHeaders {
+     /// Units only meaningful to the request they arrived with (authentication, administration,
+     /// queue creation, subscription and delivery) are not persisted.
+     fn encode(&self, encoder: &mut Encoder) {
+          let units: Vec<&MTPHeaderUnit> = self.units().iter().filter(|unit| matches!(unit,
+               MTPHeaderUnit::Source { .. }
+               | MTPHeaderUnit::Message { .. }
+               | MTPHeaderUnit::MessagePublish { .. }
+               | MTPHeaderUnit::TimeToLive { .. }
+               | MTPHeaderUnit::NotBefore { .. }
+               | MTPHeaderUnit::Delay { .. }
+               | MTPHeaderUnit::Key { .. }
+               | MTPHeaderUnit::Tombstone
+               | MTPHeaderUnit::ReplyTo { .. }
+               | MTPHeaderUnit::Correlation { .. }
+               | MTPHeaderUnit::PartitionKey { .. }
+          )).collect();
+
+          encoder.put_u32(units.len() as u32);
+          for unit in units {
+               match unit {
+                    MTPHeaderUnit::Source { source } => {
+                         encoder.put_u8(0);
+                         encoder.put_str(&source.to_string());
+                    },
+                    MTPHeaderUnit::Message { id, timestamp, priority, category, content_type } => {
+                         encoder.put_u8(1);
+                         encoder.put_str(id);
+                         encoder.put_option(*timestamp, Encoder::put_time);
+                         encoder.put(priority);
+                         encoder.put(category);
+                         encoder.put(content_type);
+                    },
+                    MTPHeaderUnit::MessagePublish { queue, to } => {
+                         encoder.put_u8(2);
+                         encoder.put_str(queue);
+                         encoder.put(to);
+                    },
+                    MTPHeaderUnit::TimeToLive { ttl } => {
+                         encoder.put_u8(3);
+                         encoder.put_duration(*ttl);
+                    },
+                    MTPHeaderUnit::NotBefore { at } => {
+                         encoder.put_u8(4);
+                         encoder.put_time(*at);
+                    },
+                    MTPHeaderUnit::Delay { delay } => {
+                         encoder.put_u8(5);
+                         encoder.put_duration(*delay);
+                    },
+                    MTPHeaderUnit::Key { key } => {
+                         encoder.put_u8(6);
+                         encoder.put_str(key);
+                    },
+                    MTPHeaderUnit::Tombstone => encoder.put_u8(7),
+                    MTPHeaderUnit::ReplyTo { queue } => {
+                         encoder.put_u8(8);
+                         encoder.put_str(queue);
+                    },
+                    MTPHeaderUnit::Correlation { id } => {
+                         encoder.put_u8(9);
+                         encoder.put_str(id);
+                    },
+                    MTPHeaderUnit::PartitionKey { key } => {
+                         encoder.put_u8(10);
+                         encoder.put_str(key);
+                    },
+                    _ => unreachable!("filtered above"),
+               }
+          }
+
+          encoder.put(self.local());
+          encoder.put_option(self.timestamp(), Encoder::put_time);
+     }
+}
+
+impl Decode for MTPHeaders {
+     fn decode(decoder: &mut Decoder) -> Result<Self, StorageError> {
+          let count = decoder.get_u32()?;
+          let mut units = Vec::new();
+          for _ in 0..count {
+               let unit = match decoder.get_u8()? {
+                    0 => {
+                         let source: SocketAddr = decoder.get_str()?.parse()
+                              .map_err(|_| StorageError::Corrupted { message: "Invalid source address".to_string() })?;
+                         MTPHeaderUnit::Source { source }
+                    },
+                    1 => MTPHeaderUnit::Message {
+                         id: decoder.get_str()?,
+                         timestamp: decoder.get_option(Decoder::get_time)?,
+                         priority: decoder.get()?,
+                         category: decoder.get()?,
+                         content_type: decoder.get()?,
+                    },
+                    2 => MTPHeaderUnit::MessagePublish { queue: decoder.get_str()?, to: decoder.get()? },
+                    3 => MTPHeaderUnit::TimeToLive { ttl: decoder.get_duration()? },
+                    4 => MTPHeaderUnit::NotBefore { at: decoder.get_time()? },
+                    5 => MTPHeaderUnit::Delay { delay: decoder.get_duration()? },
+                    6 => MTPHeaderUnit::Key { key: decoder.get_str()? },
+                    7 => MTPHeaderUnit::Tombstone,
+                    8 => MTPHeaderUnit::ReplyTo { queue: decoder.get_str()? },
+                    9 => MTPHeaderUnit::Correlation { id: decoder.get_str()? },
+                    10 => MTPHeaderUnit::PartitionKey { key: decoder.get_str()? },
+                    tag => return Err(corrupted("header unit", tag)),
+               };
+               units.push(unit);
+          }
+
+          let local = decoder.get()?;
+          let timestamp = decoder.get_option(Decoder::get_time)?;
+
+          Ok(MTPHeaders::new(units, local, timestamp))
+     }
+}
+
+impl Encode for MTPStorage {
+     fn encode(&self, encoder: &mut Encoder) {
+          encoder.put_u32(self.items().len() as u32);
+          for cell in self.items() {
+               encoder.put_str(cell.key());
+               encoder.put_str(cell.value());
+          }
+     }
+}
+
+impl Decode for MTPStorage {
+     fn decode(decoder: &mut Decoder) -> Result<Self, StorageError> {
+          let count = decoder.get_u32()?;
+          let mut items = Vec::new();
+          for _ in 0..count {
+               items.push(StorageCell::new(decoder.get_str()?, decoder.get_str()?));
+          }
+          Ok(MTPStorage::new(items))
+     }
+}
+
+impl Encode for MTPMessage {
+     fn encode(&self, encoder: &mut Encoder) {
+          encoder.put(self.content_type());
+          encoder.put(self.priority());
+          encoder.put(self.category());
+          encoder.put(self.publish());
+          encoder.put_str(self.message());
+     }
+}
+
+impl Decode for MTPMessage {
+     fn decode(decoder: &mut Decoder) -> Result<Self, StorageError> {
+          Ok(MTPMessage::new(decoder.get()?, decoder.get()?, decoder.get()?, decoder.get()?, decoder.get_str()?))
+     }
+}
+
+impl Encode for ContentType {
+     fn encode(&self, encoder: &mut Encoder) {
+          encoder.put_u8(match self {
+               ContentType::JSON => 0,
+               ContentType::XML => 1,
+          });
+     }
+}
+
+impl Decode for ContentType {
+     fn decode(decoder: &mut Decoder) -> Result<Self, StorageError> {
+          match decoder.get_u8()? {
+               0 => Ok(ContentType::JSON),
+               1 => Ok(ContentType::XML),
+               tag => Err(corrupted("content type", tag)),
+          }
+     }
+}
+
+impl Encode for MessagePriority {
+     fn encode(&self, encoder: &mut Encoder) {
+          encoder.put_u8(match self {
+               MessagePriority::Low => 0,
+               MessagePriority::Medium => 1,
+               MessagePriority::High => 2,
+               MessagePriority::Critical => 3,
+          });
+     }
+}
+
+impl Decode for MessagePriority {
+     fn decode(decoder: &mut Decoder) -> Result<Self, StorageError> {
+          match decoder.get_u8()? {
+               0 => Ok(MessagePriority::Low),
+               1 => Ok(MessagePriority::Medium),
+               2 => Ok(MessagePriority::High),
+               3 => Ok(MessagePriority::Critical),
+               tag => Err(corrupted("priority", tag)),
+          }
+     }
+}
+
+impl Encode for MessageCategory {
+     fn encode(&self, encoder: &mut Encoder) {
+          encoder.put_u8(match self {
+               MessageCategory::EVENT => 0,
+               MessageCategory::COMMAND => 1,
+               MessageCategory::REQUEST => 2,
+               MessageCategory::RESPONSE => 3,
+               MessageCategory::ACKNOWLEDGEMENT => 4,
+               MessageCategory::ERROR => 5,
+               MessageCategory::NOTIFICATION => 6,
+               MessageCategory::STATUS => 7,
+          });
+     }
+}
+
+impl Decode for MessageCategory {
+     fn decode(decoder: &mut Decoder) -> Result<Self, StorageError> {
+          match decoder.get_u8()? {
+               0 => Ok(MessageCategory::EVENT),
+               1 => Ok(MessageCategory::COMMAND),
+               2 => Ok(MessageCategory::REQUEST),
+               3 => Ok(MessageCategory::RESPONSE),
+               4 => Ok(MessageCategory::ACKNOWLEDGEMENT),
+               5 => Ok(MessageCategory::ERROR),
+               6 => Ok(MessageCategory::NOTIFICATION),
+               7 => Ok(MessageCategory::STATUS),
+               tag => Err(corrupted("category", tag)),
+          }
+     }
+}
+
+impl Encode for MessagePublish {
+     fn encode(&self, encoder: &mut Encoder) {
+          match self {
+               MessagePublish::ALL => encoder.put_u8(0),
+               MessagePublish::TO(client) => {
+                    encoder.put_u8(1);
+                    encoder.put_str(client);
+               },
+               MessagePublish::GROUP(clients) => {
+                    encoder.put_u8(2);
+                    encoder.put_u32(clients.len() as u32);
+                    for client in clients {
+                         encoder.put_str(client);
+                    }
+               },
+          }
+     }
+}
+
+impl Decode for MessagePublish {
+     fn decode(decoder: &mut Decoder) -> Result<Self, StorageError> {
+          match decoder.get_u8()? {
+               0 => Ok(MessagePublish::ALL),
+               1 => Ok(MessagePublish::TO(decoder.get_str()?)),
+               2 => {
+                    let count = decoder.get_u32()?;
+                    let mut clients = Vec::new();
+                    for _ in 0..count {
+                         clients.push(decoder.get_str()?);
+                    }
+                    Ok(MessagePublish::GROUP(clients))
+               },
+               tag => Err(corrupted("publish", tag)),
+          }
+     }
+}
+
+impl Encode for Distribution {
+     fn encode(&self, encoder: &mut Encoder) {
+          encoder.put_u8(match self {
+               Distribution::RoundRobin => 0,
+               Distribution::LeastLoaded => 1,
+          });
+     }
+}
+
+impl Decode for Distribution {
+     fn decode(decoder: &mut Decoder) -> Result<Self, StorageError> {
+          match decoder.get_u8()? {
+               0 => Ok(Distribution::RoundRobin),
+               1 => Ok(Distribution::LeastLoaded),
+               tag => Err(corrupted("distribution", tag)),
+          }
+     }
+}
+
+impl Encode for QueueAccess {
+     fn encode(&self, encoder: &mut Encoder) {
+          encoder.put_u8(match self {
+               QueueAccess::Public => 0,
+               QueueAccess::Private => 1,
+               QueueAccess::Protected => 2,
+          });
+     }
+}
+
+impl Decode for QueueAccess {
+     fn decode(decoder: &mut Decoder) -> Result<Self, StorageError> {
+          match decoder.get_u8()? {
+               0 => Ok(QueueAccess::Public),
+               1 => Ok(QueueAccess::Private),
+               2 => Ok(QueueAccess::Protected),
+               tag => Err(corrupted("access", tag)),
+          }
+     }
+}
+
+impl Encode for QueueRoles {
+     fn encode(&self, encoder: &mut Encoder) {
+          encoder.put_u8(match self {
+               QueueRoles::Moderator => 0,
+               QueueRoles::Manager => 1,
+               QueueRoles::Producer => 2,
+               QueueRoles::Consumer => 3,
+               QueueRoles::Couple => 4,
+          });
+     }
+}
+
+impl Decode for QueueRoles {
+     fn decode(decoder: &mut Decoder) -> Result<Self, StorageError> {
+          match decoder.get_u8()? {
+               0 => Ok(QueueRoles::Moderator),
+               1 => Ok(QueueRoles::Manager),
+               2 => Ok(QueueRoles::Producer),
+               3 => Ok(QueueRoles::Consumer),
+               4 => Ok(QueueRoles::Couple),
+               tag => Err(corrupted("role", tag)),
+          }
+     }
+}
+
+impl Encode for Overflow {
+     fn encode(&self, encoder: &mut Encoder) {
+          encoder.put_u8(match self {
+               Overflow::Reject => 0,
+               Overflow::DropHead => 1,
+               Overflow::DeadLetter => 2,
+          });
+     }
+}
+
+impl Decode for Overflow {
+     fn decode(decoder: &mut Decoder) -> Result<Self, StorageError> {
+          match decoder.get_u8()? {
+               0 => Ok(Overflow::Reject),
+               1 => Ok(Overflow::DropHead),
+               2 => Ok(Overflow::DeadLetter),
+               tag => Err(corrupted("overflow", tag)),
+          }
+     }
+}
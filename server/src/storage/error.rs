@@ -0,0 +1,34 @@
+use std::io::Error;
+
+use net::protocol::error::{Error as ProtocolErrorInfo, ProtocolError};
+
+/// An enum to represent the different errors that can occur while persisting broker state
+pub enum StorageError{
+     /// Indicates that the error caused is due to I/O operations on the storage files
+     IoError{
+          /// The underlying I/O Error
+          source:Error
+     },
+
+     /// Indicates that persisted data could not be decoded
+     Corrupted{
+          message:String
+     }
+}
+
+/// From implementation to typecast [std::io::Error] to [StorageError]
+impl From<Error> for StorageError{
+     fn from(value: Error) -> Self {
+          StorageError::IoError { source: value }
+     }
+}
+
+/// From implementation to report a [StorageError] to the client as a [ProtocolError]
+impl From<StorageError> for ProtocolError{
+     fn from(value: StorageError) -> Self {
+          match value {
+               StorageError::IoError { source } => ProtocolError::InsufficientStorage126(ProtocolErrorInfo::new(source.to_string())),
+               StorageError::Corrupted { message } => ProtocolError::InternalServerError120(ProtocolErrorInfo::new(message)),
+          }
+     }
+}
@@ -0,0 +1,172 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use super::error::StorageError;
+use super::sync_parent;
+
+/// Length of the header framing a record: record length and checksum
+const FRAME_HEADER: usize = 4 + 4;
+
+/// An append-only file of length prefixed records.
+///
+/// Every record is framed with its length (`u32`) and a CRC-32 checksum (`u32`) of its content.
+/// The journal is truncated before the first record that is incomplete or does not match its
+/// checksum when it is opened again, as such a record was being written when the broker stopped.
+///
+/// # Fields
+///
+/// ~ `path`: Location of the journal file
+/// ~ `file`: The journal file, opened for appending
+/// ~ `dirty`: Whether records were appended since the journal was last flushed to disk
+pub struct Journal {
+     path: PathBuf,
+     file: File,
+     dirty: bool,
+}
+
+impl Journal {
+     /// Opens the journal at `path`, creating it if it does not exist
+     ///
+     /// # Returns
+     /// The journal along with the records it holds, in the order they were appended
+     pub fn open(path: &Path) -> Result<(Self, Vec<Vec<u8>>), StorageError> {
+          let mut bytes = Vec::new();
+          if path.exists() {
+               File::open(path)?.read_to_end(&mut bytes)?;
+          }
+
+          let mut records = Vec::new();
+          let mut pos = 0;
+          while bytes.len() - pos >= FRAME_HEADER {
+               let len = u32::from_be_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]]) as usize;
+               let checksum = u32::from_be_bytes([bytes[pos + 4], bytes[pos + 5], bytes[pos + 6], bytes[pos + 7]]);
+               if bytes.len() - pos - FRAME_HEADER < len {
+                    break;
+               }
+
+               let record = &bytes[pos + FRAME_HEADER..pos + FRAME_HEADER + len];
+               if crc32fast::hash(record) != checksum {
+                    break;
+               }
+               records.push(record.to_vec());
+               pos += FRAME_HEADER + len;
+          }
+
+          let created = !path.exists();
+          let file = OpenOptions::new().create(true).append(true).open(path)?;
+          if created {
+               sync_parent(path)?;
+          }
+          if pos < bytes.len() {
+               // torn trailing records
+               file.set_len(pos as u64)?;
+               file.sync_data()?;
+          }
+
+          Ok((Self { path: path.to_path_buf(), file, dirty: false }, records))
+     }
+
+     /// Appends a record. The record is only guaranteed to be on disk once the journal is
+     /// flushed with [`Journal::sync`].
+     pub fn append(&mut self, record: &[u8]) -> Result<(), StorageError> {
+          self.file.write_all(&frame(record))?;
+          self.dirty = true;
+
+          Ok(())
+     }
+
+     /// Checks whether records were appended since the journal was last flushed to disk
+     pub fn is_dirty(&self) -> bool {
+          self.dirty
+     }
+
+     /// Flushes the records appended to the journal to disk
+     pub fn sync(&mut self) -> Result<(), StorageError> {
+          if self.dirty {
+               self.file.sync_data()?;
+               self.dirty = false;
+          }
+          Ok(())
+     }
+
+     /// Hands out a handle to the journal file to flush the records appended to it to disk
+     /// without holding on to the journal. The records count as flushed from then on.
+     ///
+     /// # Returns
+     /// The handle, or `None` if no record was appended since the journal was last flushed
+     pub fn detach_sync(&mut self) -> Result<Option<File>, StorageError> {
+          if !self.dirty {
+               return Ok(None);
+          }
+          let file = self.file.try_clone()?;
+          self.dirty = false;
+          Ok(Some(file))
+     }
+
+     /// Replaces the content of the journal with `records`.
+     /// The journal is rewritten to a temporary file which then atomically takes its place.
+     pub fn rewrite(&mut self, records: &[Vec<u8>]) -> Result<(), StorageError> {
+          let temp = self.path.with_extension("compact");
+          {
+               let mut file = File::create(&temp)?;
+               for record in records {
+                    file.write_all(&frame(record))?;
+               }
+               file.sync_all()?;
+          }
+          fs::rename(&temp, &self.path)?;
+          sync_parent(&self.path)?;
+
+          self.file = OpenOptions::new().append(true).open(&self.path)?;
+          self.dirty = false;
+
+          Ok(())
+     }
+}
+
+/// Frames `record` with its length and checksum
+fn frame(record: &[u8]) -> Vec<u8> {
+     let mut frame = Vec::with_capacity(FRAME_HEADER + record.len());
+     frame.extend_from_slice(&(record.len() as u32).to_be_bytes());
+     frame.extend_from_slice(&crc32fast::hash(record).to_be_bytes());
+     frame.extend_from_slice(record);
+     frame
+}
+
+#[cfg(test)]
+mod tests {
+     use std::fs::{self, OpenOptions};
+     use std::io::Write;
+
+     use super::{frame, Journal};
+
+     #[test]
+     fn torn_tail_is_truncated_on_reopen() {
+          let path = std::env::temp_dir().join(format!("journal-torn-{}", std::process::id()));
+          let _ = fs::remove_file(&path);
+
+          let (mut journal, records) = Journal::open(&path).ok().unwrap();
+          assert!(records.is_empty());
+          journal.append(b"first").ok().unwrap();
+          journal.append(b"second").ok().unwrap();
+          journal.sync().ok().unwrap();
+          drop(journal);
+          let intact = fs::metadata(&path).unwrap().len();
+
+          let torn = frame(b"third");
+          OpenOptions::new().append(true).open(&path).unwrap().write_all(&torn[..torn.len() - 1]).unwrap();
+          let (mut journal, records) = Journal::open(&path).ok().unwrap();
+          assert_eq!(records, vec![b"first".to_vec(), b"second".to_vec()]);
+          assert_eq!(fs::metadata(&path).unwrap().len(), intact);
+
+          journal.rewrite(&[b"second".to_vec()]).ok().unwrap();
+          journal.append(b"third").ok().unwrap();
+          journal.sync().ok().unwrap();
+          drop(journal);
+          let (_, records) = Journal::open(&path).ok().unwrap();
+          assert_eq!(records, vec![b"second".to_vec(), b"third".to_vec()]);
+
+          fs::remove_file(&path).unwrap();
+     }
+}
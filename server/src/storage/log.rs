@@ -0,0 +1,387 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use super::create_dir;
+use super::error::StorageError;
+use super::segment::{Record, Segment, LOG_EXTENSION};
+
+/// Configuration of a [`Log`].
+///
+/// # Fields
+///
+/// ~ `segment_bytes`: Size past which the active segment is rolled
+/// ~ `segment_age`: Age past which the active segment is rolled, measured from its first record
+pub struct LogConfig {
+     segment_bytes: u64,
+     segment_age: Duration,
+}
+
+impl LogConfig {
+     /// Sets the size past which the active segment is rolled
+     pub fn with_segment_bytes(mut self, bytes: u64) -> Self {
+          self.segment_bytes = bytes;
+          self
+     }
+
+     /// Sets the age past which the active segment is rolled
+     pub fn with_segment_age(mut self, age: Duration) -> Self {
+          self.segment_age = age;
+          self
+     }
+
+     /// Retrieves the size past which the active segment is rolled
+     pub fn segment_bytes(&self) -> u64 {
+          self.segment_bytes
+     }
+
+     /// Retrieves the age past which the active segment is rolled
+     pub fn segment_age(&self) -> Duration {
+          self.segment_age
+     }
+}
+
+/// Default implementation for [LogConfig]
+/// Segments are rolled once they reach 64 MiB or an hour of age
+impl Default for LogConfig {
+     fn default() -> Self {
+          Self {
+               segment_bytes: 64 * 1024 * 1024,
+               segment_age: Duration::from_secs(3600),
+          }
+     }
+}
+
+/// Clone implementation for [LogConfig]
+impl Clone for LogConfig {
+     fn clone(&self) -> Self {
+          Self { segment_bytes: self.segment_bytes, segment_age: self.segment_age }
+     }
+}
+
+/// A durable, append-only sequence of records split into [`Segment`]s.
+///
+/// Records are only ever appended to the last segment, the active one. Once the active
+/// segment grows past the configured size or age a new segment is started at the next
+/// offset, so that older records can be discarded a whole segment at a time through
+/// [`Log::retain`] and rewritten through [`Log::compact`].
+///
+/// # Fields
+///
+/// ~ `dir`: Directory holding the segments of the log
+/// ~ `config`: Configuration of the log
+/// ~ `segments`: Segments of the log, by base offset
+/// ~ `compacted`: Base offset of the active segment when the log was last compacted
+pub struct Log {
+     dir: PathBuf,
+     config: LogConfig,
+     segments: BTreeMap<u64, Segment>,
+     compacted: Option<u64>,
+}
+
+impl Log {
+     /// Opens the log stored in `dir`, creating the directory if it does not exist
+     pub fn open(dir: &Path, config: LogConfig) -> Result<Self, StorageError> {
+          create_dir(dir)?;
+
+          let mut segments = BTreeMap::new();
+          for entry in fs::read_dir(dir)? {
+               let path = entry?.path();
+               if path.extension().and_then(|extension| extension.to_str()) != Some(LOG_EXTENSION) {
+                    continue;
+               }
+
+               let base = path.file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.parse::<u64>().ok())
+                    .ok_or_else(|| StorageError::Corrupted { message: format!("Unexpected segment {}", path.display()) })?;
+               segments.insert(base, Segment::open(dir, base)?);
+          }
+
+          if segments.is_empty() {
+               segments.insert(0, Segment::open(dir, 0)?);
+          }
+
+          Ok(Self { dir: dir.to_path_buf(), config, segments, compacted: None })
+     }
+
+     /// Retrieves the directory holding the segments of the log
+     pub fn dir(&self) -> &Path {
+          &self.dir
+     }
+
+     /// Retrieves the segments of the log, by base offset
+     pub fn segments(&self) -> &BTreeMap<u64, Segment> {
+          &self.segments
+     }
+
+     /// Retrieves the offset of the first record the log may hold
+     pub fn start_offset(&self) -> u64 {
+          self.segments.keys().next().copied().unwrap_or(0)
+     }
+
+     /// Total length of the segments of the log in bytes
+     pub fn size(&self) -> u64 {
+          self.segments.values().map(Segment::size).sum()
+     }
+
+     /// Retrieves the offset following the last record of the log
+     pub fn next_offset(&self) -> u64 {
+          self.active().next_offset()
+     }
+
+     /// Appends a record, rolling the active segment beforehand if it is due
+     ///
+     /// # Arguments
+     /// * `offset`: Offset of the record, at least [`Log::next_offset`]
+     /// * `timestamp`: Time at which the record is appended
+     /// * `body`: Encoded value held by the record
+     pub fn append(&mut self, offset: u64, timestamp: SystemTime, body: &[u8]) -> Result<(), StorageError> {
+          if offset < self.next_offset() {
+               return Err(StorageError::Corrupted { message: format!("Offset {} is behind the end of the log", offset) });
+          }
+
+          if self.roll_due(timestamp) {
+               self.segments.insert(offset, Segment::open(&self.dir, offset)?);
+          }
+
+          self.active_mut().append(offset, timestamp, body)
+     }
+
+     /// Checks whether records were appended since the log was last flushed to disk
+     pub fn is_dirty(&self) -> bool {
+          self.segments.values().any(Segment::is_dirty)
+     }
+
+     /// Flushes the records appended to the log to disk, including those of segments rolled
+     /// since the last flush
+     pub fn sync(&mut self) -> Result<(), StorageError> {
+          for segment in self.segments.values_mut().filter(|segment| segment.is_dirty()) {
+               segment.sync()?;
+          }
+          Ok(())
+     }
+
+     /// Hands out handles to the segments records were appended to since the last flush, to
+     /// flush them to disk without holding on to the log, see [`Segment::detach_sync`]
+     pub fn detach_sync(&mut self) -> Result<Vec<File>, StorageError> {
+          let mut files = Vec::new();
+          for segment in self.segments.values_mut() {
+               files.extend(segment.detach_sync()?);
+          }
+          Ok(files)
+     }
+
+     /// Reads the record at `offset`
+     pub fn read(&self, offset: u64) -> Result<Option<Record>, StorageError> {
+          match self.segments.range(..=offset).next_back() {
+               Some((_, segment)) => segment.read(offset),
+               None => Ok(None),
+          }
+     }
+
+     /// Reads every record at or after `from`, in offset order
+     pub fn records(&self, from: u64) -> Result<Vec<Record>, StorageError> {
+          let first = self.segments.range(..=from).next_back().map(|(base, _)| *base).unwrap_or(0);
+
+          let mut records = Vec::new();
+          for segment in self.segments.range(first..).map(|(_, segment)| segment) {
+               records.extend(segment.records(from)?);
+          }
+
+          Ok(records)
+     }
+
+     /// Deletes the oldest segments, other than the active one, while their last record was
+     /// appended before `before` or the log is larger than `max_bytes`
+     ///
+     /// # Returns
+     /// The offset of the first record the log may still hold, if segments were deleted
+     pub fn retain(&mut self, before: Option<SystemTime>, max_bytes: Option<u64>) -> Result<Option<u64>, StorageError> {
+          let mut size = self.size();
+          let mut deleted = false;
+
+          while self.segments.len() > 1 {
+               let oldest = match self.segments.values().next() {
+                    Some(segment) => segment,
+                    None => break,
+               };
+
+               let aged = matches!((before, oldest.last()), (Some(before), Some(last)) if last < before);
+               let oversized = max_bytes.is_some_and(|max| size > max);
+               if !(aged || oversized || oldest.is_empty()) {
+                    break;
+               }
+
+               if let Some((_, segment)) = self.segments.pop_first() {
+                    size -= segment.size();
+                    segment.delete()?;
+                    deleted = true;
+               }
+          }
+
+          Ok(deleted.then(|| self.start_offset()))
+     }
+
+     /// Checks whether segments were rolled since the log was last compacted
+     pub fn compaction_due(&self) -> bool {
+          self.segments.len() > 1 && self.compacted != Some(self.active().base())
+     }
+
+     /// Rewrites every segment other than the active one with only the records `keep` accepts.
+     /// Segments left without any record are deleted.
+     pub fn compact(&mut self, keep: impl Fn(&Record) -> bool) -> Result<(), StorageError> {
+          let active = self.active().base();
+          let closed: Vec<u64> = self.segments.range(..active).map(|(base, _)| *base).collect();
+
+          for base in closed {
+               if let Some(segment) = self.segments.remove(&base) {
+                    let segment = segment.compact(&keep)?;
+                    if segment.is_empty() {
+                         segment.delete()?;
+                    } else {
+                         self.segments.insert(base, segment);
+                    }
+               }
+          }
+          self.compacted = Some(active);
+
+          Ok(())
+     }
+
+     /// Checks whether the active segment has grown past the configured size or age
+     fn roll_due(&self, now: SystemTime) -> bool {
+          let active = self.active();
+          if active.is_empty() {
+               return false;
+          }
+
+          let aged = active.created()
+               .and_then(|created| now.duration_since(created).ok())
+               .is_some_and(|age| age >= self.config.segment_age);
+
+          active.size() >= self.config.segment_bytes || aged
+     }
+
+     /// Retrieves the segment records are appended to
+     fn active(&self) -> &Segment {
+          self.segments.values().next_back().expect("a log always holds a segment")
+     }
+
+     /// Retrieves the segment records are appended to, mutably
+     fn active_mut(&mut self) -> &mut Segment {
+          self.segments.values_mut().next_back().expect("a log always holds a segment")
+     }
+}
+
+#[cfg(test)]
+mod tests {
+     use std::fs;
+     use std::path::PathBuf;
+     use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+     use super::{Log, LogConfig};
+
+     fn scratch(name: &str) -> PathBuf {
+          let dir = std::env::temp_dir().join(format!("log-{}-{}", name, std::process::id()));
+          let _ = fs::remove_dir_all(&dir);
+          dir
+     }
+
+     fn at(secs: u64) -> SystemTime {
+          UNIX_EPOCH + Duration::from_secs(secs)
+     }
+
+     fn bodies(log: &Log, from: u64) -> Vec<String> {
+          log.records(from).ok().unwrap().into_iter()
+               .map(|record| String::from_utf8(record.into_body()).unwrap())
+               .collect()
+     }
+
+     #[test]
+     fn segments_roll_by_size_and_age() {
+          let dir = scratch("roll");
+          let mut log = Log::open(&dir, LogConfig::default().with_segment_bytes(64).with_segment_age(Duration::from_secs(60))).ok().unwrap();
+
+          // every frame takes 24 bytes of header and 15 of body: the second one fills the segment
+          for offset in 0..4 {
+               log.append(offset, at(offset), format!("record {:>8}", offset).as_bytes()).ok().unwrap();
+          }
+          assert_eq!(log.segments().keys().copied().collect::<Vec<u64>>(), vec![0, 2]);
+
+          // the active segment is rolled once its first record is a minute old
+          log.append(4, at(62), b"record        4").ok().unwrap();
+          assert_eq!(log.segments().keys().copied().collect::<Vec<u64>>(), vec![0, 2, 4]);
+          assert_eq!(log.next_offset(), 5);
+          assert!(log.append(3, at(63), b"behind").is_err());
+
+          fs::remove_dir_all(&dir).unwrap();
+     }
+
+     #[test]
+     fn records_survive_reopen() {
+          let dir = scratch("reopen");
+          {
+               let mut log = Log::open(&dir, LogConfig::default().with_segment_bytes(1)).ok().unwrap();
+               for offset in 0..3 {
+                    log.append(offset, at(offset), format!("{}", offset).as_bytes()).ok().unwrap();
+               }
+               log.sync().ok().unwrap();
+               assert!(!log.is_dirty());
+          }
+
+          let mut log = Log::open(&dir, LogConfig::default().with_segment_bytes(1)).ok().unwrap();
+          assert_eq!(log.segments().len(), 3);
+          assert_eq!(log.next_offset(), 3);
+          assert_eq!(bodies(&log, 0), vec!["0", "1", "2"]);
+          assert_eq!(bodies(&log, 1), vec!["1", "2"]);
+          assert_eq!(log.read(2).ok().unwrap().unwrap().body(), b"2");
+          assert!(log.read(3).ok().unwrap().is_none());
+
+          log.append(3, at(3), b"3").ok().unwrap();
+          assert_eq!(bodies(&log, 2), vec!["2", "3"]);
+
+          fs::remove_dir_all(&dir).unwrap();
+     }
+
+     #[test]
+     fn retention_deletes_whole_closed_segments() {
+          let dir = scratch("retain");
+          let mut log = Log::open(&dir, LogConfig::default().with_segment_bytes(1)).ok().unwrap();
+          for offset in 0..4 {
+               log.append(offset, at(offset * 10), b"payload").ok().unwrap();
+          }
+
+          // the segments whose last record is older than 15 seconds past the epoch go
+          assert_eq!(log.retain(Some(at(15)), None).ok().unwrap(), Some(2));
+          assert_eq!(log.start_offset(), 2);
+          assert_eq!(log.retain(Some(at(15)), None).ok().unwrap(), None);
+
+          // the active segment is kept however large the log is
+          assert_eq!(log.retain(None, Some(0)).ok().unwrap(), Some(3));
+          assert_eq!(log.segments().len(), 1);
+          assert_eq!(bodies(&log, 0).len(), 1);
+
+          fs::remove_dir_all(&dir).unwrap();
+     }
+
+     #[test]
+     fn compaction_rewrites_closed_segments() {
+          let dir = scratch("compact");
+          let mut log = Log::open(&dir, LogConfig::default().with_segment_bytes(1)).ok().unwrap();
+          assert!(!log.compaction_due());
+          for offset in 0..4 {
+               log.append(offset, at(offset), format!("{}", offset).as_bytes()).ok().unwrap();
+          }
+          assert!(log.compaction_due());
+
+          log.compact(|record| record.offset() % 2 == 1).ok().unwrap();
+          assert!(!log.compaction_due());
+          assert_eq!(log.segments().keys().copied().collect::<Vec<u64>>(), vec![1, 3]);
+          assert_eq!(bodies(&log, 0), vec!["1", "3"]);
+          assert!(log.read(2).ok().unwrap().is_none());
+
+          fs::remove_dir_all(&dir).unwrap();
+     }
+}
@@ -0,0 +1,59 @@
+use std::fs::{self, File};
+use std::io::ErrorKind;
+use std::path::Path;
+
+use error::StorageError;
+
+/// Module containing [`error::StorageError`], the errors raised while persisting broker state.
+pub mod error;
+
+/// Module containing the binary encoding of persisted values.
+///
+/// Values are written through an [`codec::Encoder`] and read back through a [`codec::Decoder`].
+/// Protocol types persisted by the broker, such as [`net::protocol::MTPHeaders`] and
+/// [`net::protocol::MTPMessage`], implement [`codec::Encode`] and [`codec::Decode`].
+pub mod codec;
+
+/// Module containing [`journal::Journal`], an append-only file of length prefixed records.
+pub mod journal;
+
+/// Module containing [`segment::Segment`], a file of framed records indexed by offset.
+pub mod segment;
+
+/// Module containing [`log::Log`], the segmented append-only log durable queues are stored in.
+///
+/// A log is a directory of segments named after the offset of their first record. Records
+/// are appended to the last segment until it grows past the size or age configured through
+/// [`log::LogConfig`], at which point a new segment is started.
+pub mod log;
+
+/// Module containing [`policy::SyncPolicy`], deciding when persisted records are flushed to disk.
+pub mod policy;
+
+/// Flushes to disk the directory holding `path`, so that the creation, renaming or removal of
+/// `path` survives a crash along with the content of the files already flushed
+pub fn sync_parent(path: &Path) -> Result<(), StorageError> {
+     let parent = match path.parent() {
+          Some(parent) if !parent.as_os_str().is_empty() => parent,
+          _ => Path::new("."),
+     };
+     File::open(parent)?.sync_all()?;
+     Ok(())
+}
+
+/// Creates the directory `dir` along with its missing parents, flushing every directory one
+/// of them is added to
+pub fn create_dir(dir: &Path) -> Result<(), StorageError> {
+     if dir.is_dir() {
+          return Ok(());
+     }
+     if let Some(parent) = dir.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+          create_dir(parent)?;
+     }
+
+     match fs::create_dir(dir) {
+          Err(err) if err.kind() != ErrorKind::AlreadyExists => return Err(err.into()),
+          _ => {},
+     }
+     sync_parent(dir)
+}
@@ -0,0 +1,39 @@
+use std::time::Duration;
+
+/// Policy deciding when the records written by the broker are flushed to disk, and therefore
+/// what a successful response to a request guarantees.
+///
+/// A request modifying persisted state, such as a publish to a durable queue, is only answered
+/// once its records reached the durability level of the policy. The response to a publish
+/// therefore guarantees:
+///
+/// - [`SyncPolicy::Always`] and [`SyncPolicy::GroupCommit`]: The message was flushed to disk
+///   and survives both a crash of the broker and a loss of power.
+/// - [`SyncPolicy::Os`]: The message was handed to the operating system. It survives a crash of
+///   the broker but may be lost along with the last writes on a loss of power.
+pub enum SyncPolicy {
+     /// Flushes the records of every request before it is answered
+     Always,
+
+     /// Flushes the records written by every request at once every `interval`.
+     /// Requests are answered once the flush that follows them completes, trading latency for
+     /// fewer flushes under load. Requires the broker to run its flusher.
+     GroupCommit {
+          /// Time between two flushes
+          interval: Duration,
+     },
+
+     /// Leaves flushing to the operating system
+     Os,
+}
+
+/// Clone implementation for [SyncPolicy]
+impl Clone for SyncPolicy {
+     fn clone(&self) -> Self {
+          match self {
+               SyncPolicy::Always => SyncPolicy::Always,
+               SyncPolicy::GroupCommit { interval } => SyncPolicy::GroupCommit { interval: *interval },
+               SyncPolicy::Os => SyncPolicy::Os,
+          }
+     }
+}
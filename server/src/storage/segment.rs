@@ -0,0 +1,522 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::error::StorageError;
+use super::sync_parent;
+
+/// Extension of the files holding the records of a segment
+pub const LOG_EXTENSION: &str = "log";
+
+/// Extension of the files indexing the records of a segment
+pub const INDEX_EXTENSION: &str = "index";
+
+/// Length of the header framing a record: body length, checksum, offset and timestamp
+const FRAME_HEADER: usize = 4 + 4 + 8 + 8;
+
+/// Length of an index entry: offset and position
+const INDEX_ENTRY: usize = 8 + 8;
+
+/// A record of a [`Segment`].
+///
+/// On disk a record is framed as the length of its body (`u32`), a CRC-32 checksum (`u32`) of
+/// everything that follows it, its offset (`u64`) and the time it was appended at (`u64`
+/// nanoseconds since the unix epoch), followed by its body. Every integer is written in big
+/// endian.
+///
+/// # Fields
+///
+/// ~ `offset`: Position of the record within its log
+/// ~ `timestamp`: Time at which the record was appended
+/// ~ `body`: The encoded value held by the record
+pub struct Record {
+     offset: u64,
+     timestamp: SystemTime,
+     body: Vec<u8>,
+}
+
+impl Record {
+     /// Retrieves the position of the record within its log
+     pub fn offset(&self) -> u64 {
+          self.offset
+     }
+
+     /// Retrieves the time at which the record was appended
+     pub fn timestamp(&self) -> SystemTime {
+          self.timestamp
+     }
+
+     /// Retrieves the encoded value held by the record
+     pub fn body(&self) -> &[u8] {
+          &self.body
+     }
+
+     /// Consumes the record, returning its body
+     pub fn into_body(self) -> Vec<u8> {
+          self.body
+     }
+}
+
+/// A file of records with consecutive offsets, starting at the base offset of the segment.
+///
+/// Each segment is stored as two files named after its base offset: the records themselves
+/// and an index mapping the offset of every record to its position in the first file. The
+/// index is checked against the records when the segment is opened and rebuilt where it
+/// falls behind them.
+///
+/// When a segment is opened its records are verified against their checksum, starting from
+/// the last one indexed. The segment is truncated before the first record that is incomplete
+/// or does not match its checksum, as such a record was being written when the broker stopped.
+///
+/// # Fields
+///
+/// ~ `base`: Offset of the first record the segment may hold
+/// ~ `log_path`: Location of the records
+/// ~ `index_path`: Location of the index
+/// ~ `log`: The record file, opened for reading and appending
+/// ~ `index`: The index file, opened for appending
+/// ~ `entries`: Offset and position of every record, in offset order
+/// ~ `size`: Length of the record file in bytes
+/// ~ `created`: Time at which the first record was appended
+/// ~ `last`: Time at which the last record was appended
+/// ~ `dirty`: Whether records were appended since the segment was last flushed to disk
+pub struct Segment {
+     base: u64,
+     log_path: PathBuf,
+     index_path: PathBuf,
+     log: File,
+     index: File,
+     entries: Vec<(u64, u64)>,
+     size: u64,
+     created: Option<SystemTime>,
+     last: Option<SystemTime>,
+     dirty: bool,
+}
+
+impl Segment {
+     /// Opens the segment starting at `base` within `dir`, creating it if it does not exist.
+     /// Records that were only partially written are discarded.
+     pub fn open(dir: &Path, base: u64) -> Result<Self, StorageError> {
+          let log_path = dir.join(format!("{:020}.{}", base, LOG_EXTENSION));
+          let index_path = dir.join(format!("{:020}.{}", base, INDEX_EXTENSION));
+          let created = !log_path.exists() || !index_path.exists();
+
+          let mut log = OpenOptions::new().create(true).read(true).append(true).open(&log_path)?;
+          let index = OpenOptions::new().create(true).read(true).append(true).open(&index_path)?;
+          if created {
+               sync_parent(&log_path)?;
+          }
+          let size = log.metadata()?.len();
+
+          // keep the index entries pointing within the record file
+          let mut entries = read_index(&index)?;
+          entries.retain(|(_, position)| *position + FRAME_HEADER as u64 <= size);
+
+          // scan the records past the last one indexed, starting from that one
+          let start = entries.last().map(|(_, position)| *position).unwrap_or(0);
+          log.seek(SeekFrom::Start(start))?;
+          let mut bytes = Vec::new();
+          log.read_to_end(&mut bytes)?;
+
+          let mut scanned = Vec::new();
+          let mut pos = 0;
+          while let Some((record, len)) = parse_frame(&bytes[pos..]) {
+               scanned.push((record.offset, start + pos as u64));
+               pos += len;
+          }
+
+          let valid = start + pos as u64;
+          if valid < size {
+               // torn trailing records
+               log.set_len(valid)?;
+               log.sync_data()?;
+          }
+
+          let indexed = entries.len().saturating_sub(1);
+          entries.truncate(indexed);
+          entries.extend(scanned);
+          index.set_len(0)?;
+          let mut segment = Self { base, log_path, index_path, log, index, entries, size: valid, created: None, last: None, dirty: false };
+          segment.write_index()?;
+          segment.created = match segment.entries.first() {
+               Some((offset, _)) => segment.read(*offset)?.map(|record| record.timestamp),
+               None => None,
+          };
+          segment.last = match segment.entries.last() {
+               Some((offset, _)) => segment.read(*offset)?.map(|record| record.timestamp),
+               None => None,
+          };
+
+          Ok(segment)
+     }
+
+     /// Retrieves the offset of the first record the segment may hold
+     pub fn base(&self) -> u64 {
+          self.base
+     }
+
+     /// Retrieves the offset following the last record of the segment
+     pub fn next_offset(&self) -> u64 {
+          self.entries.last().map(|(offset, _)| offset + 1).unwrap_or(self.base)
+     }
+
+     /// Length of the record file in bytes
+     pub fn size(&self) -> u64 {
+          self.size
+     }
+
+     /// Number of records held by the segment
+     pub fn len(&self) -> usize {
+          self.entries.len()
+     }
+
+     /// Checks whether the segment holds no records
+     pub fn is_empty(&self) -> bool {
+          self.entries.is_empty()
+     }
+
+     /// Retrieves the time at which the first record of the segment was appended
+     pub fn created(&self) -> Option<SystemTime> {
+          self.created
+     }
+
+     /// Retrieves the time at which the last record of the segment was appended
+     pub fn last(&self) -> Option<SystemTime> {
+          self.last
+     }
+
+     /// Appends a record to the segment
+     ///
+     /// # Arguments
+     /// * `offset`: Offset of the record, greater than the offset of every record held
+     /// * `timestamp`: Time at which the record is appended
+     /// * `body`: Encoded value held by the record
+     pub fn append(&mut self, offset: u64, timestamp: SystemTime, body: &[u8]) -> Result<(), StorageError> {
+          let frame = frame(offset, timestamp, body);
+          self.log.write_all(&frame)?;
+
+          let mut entry = [0u8; INDEX_ENTRY];
+          entry[..8].copy_from_slice(&offset.to_be_bytes());
+          entry[8..].copy_from_slice(&self.size.to_be_bytes());
+          self.index.write_all(&entry)?;
+
+          self.entries.push((offset, self.size));
+          self.size += frame.len() as u64;
+          self.created.get_or_insert(timestamp);
+          self.last = Some(timestamp);
+          self.dirty = true;
+
+          Ok(())
+     }
+
+     /// Checks whether records were appended since the segment was last flushed to disk
+     pub fn is_dirty(&self) -> bool {
+          self.dirty
+     }
+
+     /// Flushes the records appended to the segment to disk
+     pub fn sync(&mut self) -> Result<(), StorageError> {
+          if self.dirty {
+               self.log.sync_data()?;
+               self.dirty = false;
+          }
+          Ok(())
+     }
+
+     /// Hands out a handle to the segment file to flush the records appended to it to disk
+     /// without holding on to the segment. The records count as flushed from then on.
+     ///
+     /// # Returns
+     /// The handle, or `None` if no record was appended since the segment was last flushed
+     pub fn detach_sync(&mut self) -> Result<Option<File>, StorageError> {
+          if !self.dirty {
+               return Ok(None);
+          }
+          let file = self.log.try_clone()?;
+          self.dirty = false;
+          Ok(Some(file))
+     }
+
+     /// Reads the record at `offset`
+     ///
+     /// # Returns
+     /// The record, or `None` if the segment holds no record at this offset
+     pub fn read(&self, offset: u64) -> Result<Option<Record>, StorageError> {
+          let position = match self.entries.binary_search_by_key(&offset, |(offset, _)| *offset) {
+               Ok(index) => self.entries[index].1,
+               Err(_) => return Ok(None),
+          };
+
+          let mut file = &self.log;
+          file.seek(SeekFrom::Start(position))?;
+          let mut header = [0u8; FRAME_HEADER];
+          file.read_exact(&mut header)?;
+          let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+
+          let mut frame = header.to_vec();
+          frame.resize(FRAME_HEADER + len, 0);
+          file.read_exact(&mut frame[FRAME_HEADER..])?;
+
+          match parse_frame(&frame) {
+               Some((record, _)) if record.offset == offset => Ok(Some(record)),
+               _ => Err(StorageError::Corrupted { message: format!("Index of {} does not match record {}", self.log_path.display(), offset) }),
+          }
+     }
+
+     /// Reads every record at or after `from`, in offset order
+     pub fn records(&self, from: u64) -> Result<Vec<Record>, StorageError> {
+          let start = self.entries.partition_point(|(offset, _)| *offset < from);
+          let position = match self.entries.get(start) {
+               Some((_, position)) => *position,
+               None => return Ok(Vec::new()),
+          };
+
+          let mut file = &self.log;
+          file.seek(SeekFrom::Start(position))?;
+          let mut bytes = Vec::with_capacity((self.size - position) as usize);
+          file.take(self.size - position).read_to_end(&mut bytes)?;
+
+          let mut records = Vec::new();
+          let mut pos = 0;
+          while let Some((record, len)) = parse_frame(&bytes[pos..]) {
+               records.push(record);
+               pos += len;
+          }
+
+          Ok(records)
+     }
+
+     /// Rewrites the segment with only the records `keep` accepts.
+     /// The records are written to a temporary file which then atomically takes the place of
+     /// the record file, after which the index is rebuilt.
+     pub fn compact(self, keep: impl Fn(&Record) -> bool) -> Result<Self, StorageError> {
+          let records = self.records(self.base)?;
+          let dir = self.log_path.parent().map(Path::to_path_buf).unwrap_or_default();
+          let temp = self.log_path.with_extension("compact");
+          {
+               let mut file = File::create(&temp)?;
+               for record in records.iter().filter(|record| keep(record)) {
+                    file.write_all(&frame(record.offset, record.timestamp, &record.body))?;
+               }
+               file.sync_all()?;
+          }
+
+          let Self { base, log_path, index_path, .. } = self;
+          fs::rename(&temp, &log_path)?;
+          fs::remove_file(&index_path)?;
+          sync_parent(&log_path)?;
+
+          Self::open(&dir, base)
+     }
+
+     /// Deletes the files of the segment
+     pub fn delete(self) -> Result<(), StorageError> {
+          let Self { log_path, index_path, .. } = self;
+          fs::remove_file(&log_path)?;
+          fs::remove_file(&index_path)?;
+          Ok(())
+     }
+
+     /// Writes every entry of the index to the index file
+     fn write_index(&mut self) -> Result<(), StorageError> {
+          let mut bytes = Vec::with_capacity(self.entries.len() * INDEX_ENTRY);
+          for (offset, position) in &self.entries {
+               bytes.extend_from_slice(&offset.to_be_bytes());
+               bytes.extend_from_slice(&position.to_be_bytes());
+          }
+          self.index.write_all(&bytes)?;
+          Ok(())
+     }
+}
+
+/// Frames the record at `offset` appended at `timestamp`
+fn frame(offset: u64, timestamp: SystemTime, body: &[u8]) -> Vec<u8> {
+     let mut frame = Vec::with_capacity(FRAME_HEADER + body.len());
+     frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+     frame.extend_from_slice(&[0u8; 4]);
+     frame.extend_from_slice(&offset.to_be_bytes());
+     frame.extend_from_slice(&(timestamp.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64).to_be_bytes());
+     frame.extend_from_slice(body);
+
+     let checksum = crc32fast::hash(&frame[8..]);
+     frame[4..8].copy_from_slice(&checksum.to_be_bytes());
+     frame
+}
+
+/// Reads the complete entries of an index file
+fn read_index(mut file: &File) -> Result<Vec<(u64, u64)>, StorageError> {
+     let mut bytes = Vec::new();
+     file.seek(SeekFrom::Start(0))?;
+     file.read_to_end(&mut bytes)?;
+
+     Ok(bytes.chunks_exact(INDEX_ENTRY).map(|entry| {
+          let mut offset = [0u8; 8];
+          let mut position = [0u8; 8];
+          offset.copy_from_slice(&entry[..8]);
+          position.copy_from_slice(&entry[8..]);
+          (u64::from_be_bytes(offset), u64::from_be_bytes(position))
+     }).collect())
+}
+
+/// Parses the record framed at the start of `bytes`
+///
+/// # Returns
+/// The record and the length of its frame, or `None` if `bytes` does not hold a complete frame
+/// matching its checksum
+fn parse_frame(bytes: &[u8]) -> Option<(Record, usize)> {
+     if bytes.len() < FRAME_HEADER {
+          return None;
+     }
+
+     let len = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+     if bytes.len() - FRAME_HEADER < len {
+          return None;
+     }
+
+     let checksum = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+     if crc32fast::hash(&bytes[8..FRAME_HEADER + len]) != checksum {
+          return None;
+     }
+
+     let mut offset = [0u8; 8];
+     let mut timestamp = [0u8; 8];
+     offset.copy_from_slice(&bytes[8..16]);
+     timestamp.copy_from_slice(&bytes[16..24]);
+
+     let record = Record {
+          offset: u64::from_be_bytes(offset),
+          timestamp: UNIX_EPOCH + Duration::from_nanos(u64::from_be_bytes(timestamp)),
+          body: bytes[FRAME_HEADER..FRAME_HEADER + len].to_vec(),
+     };
+
+     Some((record, FRAME_HEADER + len))
+}
+
+#[cfg(test)]
+mod tests {
+     use std::fs::{self, OpenOptions};
+     use std::io::Write;
+     use std::path::{Path, PathBuf};
+     use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+     use super::{Segment, FRAME_HEADER, INDEX_ENTRY};
+
+     fn scratch(name: &str) -> PathBuf {
+          let dir = std::env::temp_dir().join(format!("segment-{}-{}", name, std::process::id()));
+          let _ = fs::remove_dir_all(&dir);
+          fs::create_dir_all(&dir).unwrap();
+          dir
+     }
+
+     fn filled(dir: &Path, count: u64) -> Segment {
+          let mut segment = Segment::open(dir, 10).ok().unwrap();
+          for offset in 10..10 + count {
+               segment.append(offset, UNIX_EPOCH + Duration::from_secs(offset), format!("record {}", offset).as_bytes()).ok().unwrap();
+          }
+          segment.sync().ok().unwrap();
+          segment
+     }
+
+     #[test]
+     fn torn_tail_is_truncated_on_reopen() {
+          let dir = scratch("torn");
+          let segment = filled(&dir, 3);
+          let intact = segment.size();
+          let log_path = segment.log_path.clone();
+          drop(segment);
+
+          // half of a fourth frame, as left by a crash while appending it
+          let mut torn = super::frame(13, SystemTime::now(), b"record 13");
+          torn.truncate(FRAME_HEADER + 2);
+          OpenOptions::new().append(true).open(&log_path).unwrap().write_all(&torn).unwrap();
+
+          let mut segment = Segment::open(&dir, 10).ok().unwrap();
+          assert_eq!(segment.size(), intact);
+          assert_eq!(fs::metadata(&log_path).unwrap().len(), intact);
+          assert_eq!(segment.next_offset(), 13);
+
+          segment.append(13, SystemTime::now(), b"record 13").ok().unwrap();
+          let bodies: Vec<Vec<u8>> = segment.records(10).ok().unwrap().into_iter().map(|record| record.into_body()).collect();
+          assert_eq!(bodies, vec![b"record 10".to_vec(), b"record 11".to_vec(), b"record 12".to_vec(), b"record 13".to_vec()]);
+
+          fs::remove_dir_all(&dir).unwrap();
+     }
+
+     #[test]
+     fn index_is_rebuilt_from_records() {
+          let dir = scratch("index");
+          let segment = filled(&dir, 4);
+          let index_path = segment.index_path.clone();
+          drop(segment);
+
+          // an index that lost its last entries and half of another
+          let entries = fs::read(&index_path).unwrap();
+          fs::write(&index_path, &entries[..INDEX_ENTRY + INDEX_ENTRY / 2]).unwrap();
+          let segment = Segment::open(&dir, 10).ok().unwrap();
+          assert_eq!(segment.len(), 4);
+          assert_eq!(segment.read(12).ok().unwrap().unwrap().body(), b"record 12");
+          assert_eq!(fs::read(&index_path).unwrap(), entries);
+          drop(segment);
+
+          fs::remove_file(&index_path).unwrap();
+          let segment = Segment::open(&dir, 10).ok().unwrap();
+          assert_eq!(segment.read(13).ok().unwrap().unwrap().body(), b"record 13");
+          assert_eq!(fs::read(&index_path).unwrap(), entries);
+
+          fs::remove_dir_all(&dir).unwrap();
+     }
+
+     #[test]
+     fn records_failing_their_checksum_are_detected() {
+          let dir = scratch("crc");
+          let segment = filled(&dir, 3);
+          let log_path = segment.log_path.clone();
+          let second = segment.entries[1].1 as usize;
+          let third = segment.entries[2].1 as usize;
+          drop(segment);
+
+          // a record read through the index is checked when read
+          let mut bytes = fs::read(&log_path).unwrap();
+          bytes[second + FRAME_HEADER] ^= 1;
+          fs::write(&log_path, &bytes).unwrap();
+          let segment = Segment::open(&dir, 10).ok().unwrap();
+          assert!(segment.read(11).is_err());
+          assert_eq!(segment.read(10).ok().unwrap().unwrap().body(), b"record 10");
+          drop(segment);
+
+          // the records from the last one indexed are checked on open, discarding the first one
+          // failing its checksum along with those after it
+          bytes[second + FRAME_HEADER] ^= 1;
+          bytes[third + FRAME_HEADER] ^= 1;
+          fs::write(&log_path, &bytes).unwrap();
+          let segment = Segment::open(&dir, 10).ok().unwrap();
+          assert_eq!(segment.len(), 2);
+          assert_eq!(segment.size(), third as u64);
+          assert!(segment.read(12).ok().unwrap().is_none());
+          assert_eq!(segment.read(11).ok().unwrap().unwrap().body(), b"record 11");
+
+          fs::remove_dir_all(&dir).unwrap();
+     }
+
+     #[test]
+     fn index_looks_records_up_by_offset() {
+          let dir = scratch("lookup");
+          let segment = filled(&dir, 5);
+
+          assert_eq!(segment.base(), 10);
+          assert_eq!(segment.next_offset(), 15);
+          assert!(segment.read(9).ok().unwrap().is_none());
+          assert!(segment.read(15).ok().unwrap().is_none());
+          for offset in 10..15 {
+               let record = segment.read(offset).ok().unwrap().unwrap();
+               assert_eq!(record.offset(), offset);
+               assert_eq!(record.timestamp(), UNIX_EPOCH + Duration::from_secs(offset));
+          }
+          assert_eq!(segment.records(13).ok().unwrap().iter().map(|record| record.offset()).collect::<Vec<u64>>(), vec![13, 14]);
+          assert_eq!(segment.created(), Some(UNIX_EPOCH + Duration::from_secs(10)));
+          assert_eq!(segment.last(), Some(UNIX_EPOCH + Duration::from_secs(14)));
+
+          fs::remove_dir_all(&dir).unwrap();
+     }
+}